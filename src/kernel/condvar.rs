@@ -1,11 +1,16 @@
 use crate::{proc, spinlock::MutexGuard};
 
+// The address is the sleep channel, so a Condvar must not be zero-sized:
+// ZST fields next to each other can share an address and wake each other's
+// sleepers.
 #[derive(Debug)]
-pub struct Condvar;
+pub struct Condvar {
+    _chan: u8,
+}
 
 impl Condvar {
     pub const fn new() -> Self {
-        Self
+        Self { _chan: 0 }
     }
     pub fn wait<'a, T>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
         proc::sleep(self as *const _ as usize, guard)
@@ -20,3 +25,21 @@ impl Default for Condvar {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Condvar;
+
+    #[test_case]
+    fn adjacent_condvars_have_distinct_channels() {
+        struct Pair {
+            a: Condvar,
+            b: Condvar,
+        }
+        let pair = Pair {
+            a: Condvar::new(),
+            b: Condvar::new(),
+        };
+        assert_ne!(&pair.a as *const Condvar, &pair.b as *const Condvar);
+    }
+}
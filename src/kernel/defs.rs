@@ -26,7 +26,10 @@ pub unsafe fn as_bytes_mut<T: ?Sized>(refs: &mut T) -> &mut [u8] {
 pub use core::mem::{ManuallyDrop, MaybeUninit};
 use core::net::Ipv4Addr;
 
-use crate::{fs::DirEnt, stat::Stat};
+use crate::{
+    fs::DirEnt,
+    stat::{Stat, TcpBufInfo, TcpListenStats},
+};
 
 #[repr(C)]
 pub union _transmuter<T, const N: usize> {
@@ -118,6 +121,8 @@ pub unsafe trait FromBytes: Sized {
 
 // u8, [u8; N], [u8], stats
 unsafe impl AsBytes for Stat {}
+unsafe impl AsBytes for TcpListenStats {}
+unsafe impl AsBytes for TcpBufInfo {}
 unsafe impl AsBytes for str {}
 unsafe impl AsBytes for u8 {}
 unsafe impl AsBytes for usize {}
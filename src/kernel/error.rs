@@ -61,6 +61,12 @@ pub enum Error {
     ConnectionAborted = -54,
     BufferFull = -55,
     Unaddressable = -56,
+    InvalidFlags = -57,
+    DnsFormErr = -58,
+    DnsServFail = -59,
+    DnsNxDomain = -60,
+    DnsRefused = -61,
+    NetStackUninitialized = -62,
 }
 
 impl Error {
@@ -122,6 +128,12 @@ impl Error {
             ConnectionAborted => "connection aborted",
             BufferFull => "buffer full",
             Unaddressable => "unaddressable",
+            InvalidFlags => "invalid flags",
+            DnsFormErr => "dns format error",
+            DnsServFail => "dns server failure",
+            DnsNxDomain => "no such domain",
+            DnsRefused => "dns query refused",
+            NetStackUninitialized => "network stack not initialized",
             Uncategorized => "uncategorized error",
         }
     }
@@ -183,6 +195,12 @@ impl Error {
             -54 => ConnectionAborted,
             -55 => BufferFull,
             -56 => Unaddressable,
+            -57 => InvalidFlags,
+            -58 => DnsFormErr,
+            -59 => DnsServFail,
+            -60 => DnsNxDomain,
+            -61 => DnsRefused,
+            -62 => NetStackUninitialized,
             _ => Uncategorized,
         }
     }
@@ -8,6 +8,12 @@ pub mod omode {
     pub const CLOEXEC: usize = 0x1000;
 }
 
+pub mod whence {
+    pub const SEEK_SET: usize = 0;
+    pub const SEEK_CUR: usize = 1;
+    pub const SEEK_END: usize = 2;
+}
+
 pub struct OMode {
     read: bool,
     write: bool,
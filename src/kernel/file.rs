@@ -3,7 +3,7 @@ use crate::array;
 #[cfg(all(target_os = "none", feature = "kernel"))]
 use crate::error::{Error::*, Result};
 #[cfg(all(target_os = "none", feature = "kernel"))]
-use crate::fcntl::{FcntlCmd, OMode};
+use crate::fcntl::{whence, FcntlCmd, OMode};
 #[cfg(all(target_os = "none", feature = "kernel"))]
 use crate::fs::{create, IData, Inode, Path, BSIZE};
 #[cfg(all(target_os = "none", feature = "kernel"))]
@@ -116,6 +116,27 @@ impl FNod {
         *off += r as u32;
         Ok(r)
     }
+    fn seek(&self, offset: isize, from: usize) -> Result<usize> {
+        let ip = self.ip.lock();
+        let off = unsafe { &mut *self.off.get() };
+
+        let base = match from {
+            whence::SEEK_SET => 0,
+            whence::SEEK_CUR => *off as isize,
+            whence::SEEK_END => {
+                let mut stat: Stat = Default::default();
+                ip.stat(&mut stat);
+                stat.size as isize
+            }
+            _ => return Err(InvalidArgument),
+        };
+        let new = base
+            .checked_add(offset)
+            .filter(|n| (0..=u32::MAX as isize).contains(n))
+            .ok_or(InvalidArgument)?;
+        *off = new as u32;
+        Ok(new as usize)
+    }
     fn write(&self, src: VirtAddr, n: usize) -> Result<usize> {
         // write a few blocks at a time to avoid exceeding the maximum
         // log transaction size, including i-node, indirect block,
@@ -216,6 +237,15 @@ impl File {
         self.cloexec = false;
     }
 
+    // Move the offset of an inode-backed file. Devices and pipes have no
+    // offset to move.
+    pub fn seek(&mut self, offset: isize, from: usize) -> Result<usize> {
+        match self.f.as_deref() {
+            Some(VFile::Inode(f)) => f.seek(offset, from),
+            _ => Err(InvalidArgument),
+        }
+    }
+
     pub fn do_fcntl(&mut self, cmd: FcntlCmd) -> Result<usize> {
         use FcntlCmd::*;
         match cmd {
@@ -3,10 +3,13 @@ pub mod device;
 pub mod dns;
 pub mod driver;
 pub mod ethernet;
+pub mod event;
 pub mod icmp;
+pub mod igmp;
 pub mod interface;
 pub mod ip;
 pub mod protocol;
+pub mod raw;
 pub mod route;
 pub mod socket;
 pub mod tcp;
@@ -14,29 +17,139 @@ pub mod trace;
 pub mod udp;
 pub mod util;
 
-use crate::println;
-use core::sync::atomic::{AtomicBool, Ordering};
+use crate::{
+    error::{Error, Result},
+    println,
+};
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
 
 static NET_POLL_PENDING: AtomicBool = AtomicBool::new(false);
 static NET_POLL_RUNNING: AtomicBool = AtomicBool::new(false);
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum NetStackState {
+    Uninitialized = 0,
+    IpInitialized = 1,
+    DriverInitialized = 2,
+    InterfacesUp = 3,
+}
+
+impl NetStackState {
+    fn from_u8(value: u8) -> Self {
+        match value {
+            1 => Self::IpInitialized,
+            2 => Self::DriverInitialized,
+            3 => Self::InterfacesUp,
+            _ => Self::Uninitialized,
+        }
+    }
+}
+
+struct StackState(AtomicU8);
+
+impl StackState {
+    const fn new() -> Self {
+        Self(AtomicU8::new(NetStackState::Uninitialized as u8))
+    }
+
+    fn get(&self) -> NetStackState {
+        NetStackState::from_u8(self.0.load(Ordering::Acquire))
+    }
+
+    fn advance(&self, next: NetStackState) {
+        self.0.fetch_max(next as u8, Ordering::AcqRel);
+    }
+
+    fn require(&self, required: NetStackState) -> Result<()> {
+        let current = self.get();
+        if current < required {
+            println!(
+                "[net] stack is {:?}, operation requires {:?}",
+                current, required
+            );
+            return Err(Error::NetStackUninitialized);
+        }
+        Ok(())
+    }
+}
+
+static NET_STACK_STATE: StackState = StackState::new();
+
+pub fn stack_state() -> NetStackState {
+    NET_STACK_STATE.get()
+}
+
+pub fn require_state(required: NetStackState) -> Result<()> {
+    NET_STACK_STATE.require(required)
+}
+
 pub fn init() {
     println!("[kernel] Network stack init");
 
     ip::ip_init();
+    tcp::init_iss_secret();
+    NET_STACK_STATE.advance(NetStackState::IpInitialized);
 
     driver::loopback::init().expect("loopback init failed");
-    driver::loopback::setup_iface().expect("loopback setup failed");
-
     driver::virtio_net::init().expect("virtio-net init failed");
+    NET_STACK_STATE.advance(NetStackState::DriverInitialized);
+
+    driver::loopback::setup_iface().expect("loopback setup failed");
     driver::virtio_net::setup_iface().expect("virtio-net iface failed");
+    NET_STACK_STATE.advance(NetStackState::InterfacesUp);
 
     println!("[kernel] Network stack initialized");
 }
 
+// Tests drive devices and sockets directly, so only the protocol handlers
+// are registered before they run.
+#[cfg(test)]
+pub fn init_for_tests() {
+    ip::ip_init();
+    NET_STACK_STATE.advance(NetStackState::IpInitialized);
+}
+
 pub fn poll() {
     driver::virtio_net::poll_rx();
     let _ = tcp::poll();
+    arp::expire();
+    flush_tx();
+}
+
+// Kicks devices that queued frames without notifying. Anything that
+// transmits outside of poll() must call this once it is done sending.
+pub fn flush_tx() {
+    driver::virtio_net::flush_tx_notify();
+}
+
+static PANIC_DUMPING: AtomicBool = AtomicBool::new(false);
+
+// Best-effort summary for the panic handler. The heap may be inconsistent
+// at this point, so everything here formats straight to the console and
+// skips any table whose lock is already held.
+pub fn net_panic_dump() {
+    // a panic inside the dump must not recurse into another dump
+    if PANIC_DUMPING.swap(true, Ordering::AcqRel) {
+        return;
+    }
+
+    println!("--- net state ({:?}) ---", stack_state());
+    println!("tcp sockets:");
+    tcp::panic_dump();
+    println!("udp sockets:");
+    udp::panic_dump();
+    println!("arp table:");
+    arp::panic_dump();
+    println!("recent events:");
+    let mut events = [None; event::NET_EVENT_COUNT];
+    event::recent(&mut events);
+    for ev in events.iter().flatten() {
+        println!("  {}", ev);
+    }
+    println!("--- end net state ---");
+
+    PANIC_DUMPING.store(false, Ordering::Release);
 }
 
 pub fn request_poll() {
@@ -62,3 +175,67 @@ pub fn poll_if_pending() {
 
     NET_POLL_RUNNING.store(false, Ordering::Release);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{arp, ethernet::MacAddr, event, ip::IpAddr, ip::IpEndpoint, tcp, udp};
+    use super::{net_panic_dump, NetStackState, StackState, NET_STACK_STATE};
+    use crate::error::Error;
+    use core::sync::atomic::Ordering;
+
+    #[test_case]
+    fn require_state_before_init_fails() {
+        let state = StackState::new();
+        assert_eq!(state.get(), NetStackState::Uninitialized);
+        let err = state.require(NetStackState::IpInitialized).unwrap_err();
+        assert_eq!(err, Error::NetStackUninitialized);
+
+        state.advance(NetStackState::IpInitialized);
+        state.require(NetStackState::IpInitialized).unwrap();
+        assert!(state.require(NetStackState::InterfacesUp).is_err());
+
+        state.advance(NetStackState::InterfacesUp);
+        state.advance(NetStackState::DriverInitialized);
+        assert_eq!(state.get(), NetStackState::InterfacesUp);
+    }
+
+    #[test_case]
+    fn socket_alloc_before_init_fails() {
+        // tests run after init_for_tests, so wind the real state back and
+        // restore it before asserting
+        let saved = NET_STACK_STATE.get();
+        NET_STACK_STATE
+            .0
+            .store(NetStackState::Uninitialized as u8, Ordering::Release);
+        let tcp_result = tcp::socket_alloc();
+        let udp_result = udp::socket_alloc();
+        NET_STACK_STATE.0.store(saved as u8, Ordering::Release);
+
+        assert_eq!(tcp_result, Err(Error::NetStackUninitialized));
+        assert_eq!(udp_result, Err(Error::NetStackUninitialized));
+    }
+
+    #[test_case]
+    fn panic_dump_walks_live_state() {
+        let listener = tcp::socket_alloc().unwrap();
+        tcp::socket_listen(listener, IpEndpoint::new(IpAddr(0), 7301)).unwrap();
+        let idle = tcp::socket_alloc().unwrap();
+        let udp_sock = udp::socket_alloc().unwrap();
+        udp::socket_bind(udp_sock, IpEndpoint::new(IpAddr(0), 7302)).unwrap();
+        arp::insert(IpAddr::new(10, 9, 8, 7), MacAddr([2, 0, 0, 0, 0, 7]));
+
+        let mut events = [None; event::NET_EVENT_COUNT];
+        event::recent(&mut events);
+        let recorded = |kind| events.iter().flatten().any(|ev| ev.kind == kind);
+        assert!(recorded(event::EventKind::TcpState));
+        assert!(recorded(event::EventKind::ArpInsert));
+
+        net_panic_dump();
+        // a second dump must still run, the reentrancy guard is released
+        net_panic_dump();
+
+        tcp::socket_free(listener).unwrap();
+        tcp::socket_free(idle).unwrap();
+        udp::socket_free(udp_sock).unwrap();
+    }
+}
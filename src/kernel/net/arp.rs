@@ -1,13 +1,14 @@
 extern crate alloc;
 use crate::condvar::Condvar;
 use crate::error::{Error, Result};
-use crate::net::device::{NetDevice, NetDeviceFlags};
+use crate::net::device::{NetDevice, NetDeviceFlags, NetDeviceType};
 use crate::net::ethernet::{egress as eth_egress, MacAddr, ETHERTYPE_ARP};
+use crate::net::event;
 use crate::net::ip::IpAddr;
 use crate::net::poll;
 use crate::spinlock::Mutex;
 use crate::trace;
-use alloc::vec::Vec;
+use alloc::{string::String, vec::Vec};
 
 const ARP_HTYPE_ETHERNET: u16 = 1;
 const ARP_PTYPE_IPV4: u16 = 0x0800;
@@ -15,6 +16,14 @@ const ARP_HLEN_ETH: u8 = 6;
 const ARP_PLEN_IPV4: u8 = 4;
 const ARP_OP_REQUEST: u16 = 1;
 const ARP_OP_REPLY: u16 = 2;
+const ARP_PENDING_MAX_PACKETS: usize = 16;
+const ARP_RETRY_TICKS: usize = crate::param::TICK_HZ;
+// Give up on an unanswered request after three retries.
+const ARP_PENDING_TIMEOUT_TICKS: usize = 3 * ARP_RETRY_TICKS;
+// entries are forgotten after 300 s and must be resolved again
+pub const ARP_ENTRY_TTL_TICKS: usize = 300 * crate::param::TICK_HZ;
+// timestamp of an entry added by hand; it never expires
+const STATIC_TIMESTAMP: usize = usize::MAX;
 
 mod wire {
     use crate::error::{Error, Result};
@@ -144,15 +153,51 @@ mod wire {
         data[..4].copy_from_slice(&value.to_be_bytes());
     }
 }
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Debug)]
 struct ArpEntry {
     ip: IpAddr,
     mac: MacAddr,
     valid: bool,
+    dev_name: String,
+    timestamp: usize,
+}
+
+impl ArpEntry {
+    fn is_static(&self) -> bool {
+        self.timestamp == STATIC_TIMESTAMP
+    }
+
+    fn is_live(&self, now: usize) -> bool {
+        self.valid && (self.is_static() || now.saturating_sub(self.timestamp) < ARP_ENTRY_TTL_TICKS)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArpResolveResult {
+    Found(MacAddr),
+    Pending,
+    NotFound,
+}
+
+struct PendingArp {
+    target_ip: IpAddr,
+    dev_name: String,
+    created_at: usize,
+    requested_at: usize,
+    queued_packets: Vec<(u16, Vec<u8>)>,
+}
+
+impl PendingArp {
+    // The same address can be unresolved on two links at once, so a
+    // request is keyed by target and device together.
+    fn is_for(&self, target_ip: IpAddr, dev_name: &str) -> bool {
+        self.target_ip.0 == target_ip.0 && self.dev_name == dev_name
+    }
 }
 
 struct ArpCache {
     table: Mutex<Vec<ArpEntry>>,
+    pending: Mutex<Vec<PendingArp>>,
     cv: Condvar,
 }
 
@@ -160,34 +205,204 @@ impl ArpCache {
     const fn new() -> Self {
         Self {
             table: Mutex::new(Vec::new(), "arp_table"),
+            pending: Mutex::new(Vec::new(), "arp_pending"),
             cv: Condvar::new(),
         }
     }
 
     fn lookup(&self, ip: IpAddr) -> Option<MacAddr> {
+        self.lookup_at(ip, *crate::trap::TICKS.lock())
+    }
+
+    fn lookup_at(&self, ip: IpAddr, now: usize) -> Option<MacAddr> {
         let table = self.table.lock();
         table
             .iter()
-            .find(|e| e.valid && e.ip.0 == ip.0)
+            .filter(|e| e.is_live(now) && e.ip.0 == ip.0)
+            .max_by_key(|e| e.is_static())
             .map(|e| e.mac)
     }
 
-    fn insert(&self, ip: IpAddr, mac: MacAddr) {
+    fn insert(&self, dev_name: &str, ip: IpAddr, mac: MacAddr) {
+        self.insert_at(dev_name, ip, mac, *crate::trap::TICKS.lock());
+        event::record_arp_insert(ip);
+        trace!(ARP, "[arp] insert {:?} -> {}", ip.to_bytes(), mac);
+        self.cv.notify_all();
+        self.flush_pending(ip, mac, Some(dev_name));
+    }
+
+    fn insert_at(&self, dev_name: &str, ip: IpAddr, mac: MacAddr, now: usize) {
+        let mut table = self.table.lock();
+        if let Some(e) = table.iter_mut().find(|e| e.ip.0 == ip.0 && !e.is_static()) {
+            e.mac = mac;
+            e.valid = true;
+            e.timestamp = now;
+            if e.dev_name != dev_name {
+                e.dev_name = String::from(dev_name);
+            }
+        } else {
+            table.push(ArpEntry {
+                ip,
+                mac,
+                valid: true,
+                dev_name: String::from(dev_name),
+                timestamp: now,
+            });
+        }
+    }
+
+    fn add_static(&self, ip: IpAddr, mac: MacAddr) {
         {
             let mut table = self.table.lock();
-            if let Some(e) = table.iter_mut().find(|e| e.ip.0 == ip.0) {
-                e.mac = mac;
-                e.valid = true;
-            } else {
-                table.push(ArpEntry {
-                    ip,
-                    mac,
-                    valid: true,
-                });
-            }
+            table.retain(|e| e.ip.0 != ip.0 || !e.is_static());
+            table.push(ArpEntry {
+                ip,
+                mac,
+                valid: true,
+                dev_name: String::new(),
+                timestamp: STATIC_TIMESTAMP,
+            });
         }
-        trace!(ARP, "[arp] insert {:?} -> {}", ip.to_bytes(), mac);
+        trace!(ARP, "[arp] static {:?} -> {}", ip.to_bytes(), mac);
         self.cv.notify_all();
+        // a static entry is not tied to a device, so every queue drains
+        self.flush_pending(ip, mac, None);
+    }
+
+    fn remove_static(&self, ip: IpAddr) -> Result<()> {
+        let mut table = self.table.lock();
+        let idx = table
+            .iter()
+            .position(|e| e.ip.0 == ip.0 && e.is_static())
+            .ok_or(Error::NotFound)?;
+        table.remove(idx);
+        Ok(())
+    }
+
+    fn expire(&self, now: usize) {
+        self.table.lock().retain(|e| e.is_live(now));
+        // unresolved destinations give up and drop whatever was queued
+        self.pending.lock().retain(|p| {
+            let live = now.saturating_sub(p.created_at) < ARP_PENDING_TIMEOUT_TICKS;
+            if !live {
+                trace!(
+                    ARP,
+                    "[arp] {:?} unresolved, dropped {} queued packets",
+                    p.target_ip.to_bytes(),
+                    p.queued_packets.len()
+                );
+            }
+            live
+        });
+    }
+
+    fn flush(&self, dev_name: &str) {
+        self.table.lock().retain(|e| e.dev_name != dev_name);
+        // nothing queued for the device can go out any more
+        self.pending.lock().retain(|p| p.dev_name != dev_name);
+        trace!(ARP, "[arp] flushed entries for {}", dev_name);
+    }
+
+    // Sends what was queued for `ip` on `dev_name`, or on every device when
+    // it is None.
+    fn flush_pending(&self, ip: IpAddr, mac: MacAddr, dev_name: Option<&str>) {
+        let entries: Vec<PendingArp> = {
+            let mut pending = self.pending.lock();
+            let (ready, waiting) = core::mem::take(&mut *pending)
+                .into_iter()
+                .partition(|p| p.is_for(ip, dev_name.unwrap_or(&p.dev_name)));
+            *pending = waiting;
+            ready
+        };
+        for entry in entries {
+            let Some(mut dev) = crate::net::device::net_device_by_name(&entry.dev_name) else {
+                continue;
+            };
+            trace!(
+                ARP,
+                "[arp] flush {} queued packets to {:?} on {}",
+                entry.queued_packets.len(),
+                ip.to_bytes(),
+                entry.dev_name
+            );
+            for (ethertype, packet) in entry.queued_packets {
+                let _ = eth_egress(&mut dev, mac, ethertype, &packet);
+            }
+        }
+    }
+
+    fn resolve_nb(&self, dev_name: &str, target_ip: IpAddr, sender_ip: IpAddr) -> ArpResolveResult {
+        if let Some(mac) = self.lookup(target_ip) {
+            return ArpResolveResult::Found(mac);
+        }
+
+        let now = *crate::trap::TICKS.lock();
+        {
+            let mut pending = self.pending.lock();
+            match pending.iter_mut().find(|p| p.is_for(target_ip, dev_name)) {
+                Some(p) if now.saturating_sub(p.requested_at) < ARP_RETRY_TICKS => {
+                    return ArpResolveResult::Pending;
+                }
+                Some(p) => p.requested_at = now,
+                None => {}
+            }
+        }
+
+        let sent = crate::net::device::net_device_with_mut(dev_name, |dev| {
+            if !dev.flags().contains(NetDeviceFlags::UP) {
+                return Err(Error::NotConnected);
+            }
+            self.send_request(dev, target_ip, sender_ip)
+        });
+        let Ok(Ok(())) = sent else {
+            return ArpResolveResult::NotFound;
+        };
+
+        let mut pending = self.pending.lock();
+        if !pending.iter().any(|p| p.is_for(target_ip, dev_name)) {
+            pending.push(PendingArp {
+                target_ip,
+                dev_name: String::from(dev_name),
+                created_at: now,
+                requested_at: now,
+                queued_packets: Vec::new(),
+            });
+        }
+        ArpResolveResult::Pending
+    }
+
+    fn enqueue(
+        &self,
+        dev_name: &str,
+        target_ip: IpAddr,
+        ethertype: u16,
+        packet: Vec<u8>,
+    ) -> Result<()> {
+        let mut pending = self.pending.lock();
+        if let Some(entry) = pending.iter_mut().find(|p| p.is_for(target_ip, dev_name)) {
+            if entry.queued_packets.len() >= ARP_PENDING_MAX_PACKETS {
+                return Err(Error::StorageFull);
+            }
+            entry.queued_packets.push((ethertype, packet));
+            return Ok(());
+        }
+
+        // The reply may have arrived between resolve_nb and here.
+        if let Some(mac) = self.lookup(target_ip) {
+            drop(pending);
+            let mut dev =
+                crate::net::device::net_device_by_name(dev_name).ok_or(Error::DeviceNotFound)?;
+            return eth_egress(&mut dev, mac, ethertype, &packet);
+        }
+        let now = *crate::trap::TICKS.lock();
+        pending.push(PendingArp {
+            target_ip,
+            dev_name: String::from(dev_name),
+            created_at: now,
+            requested_at: now,
+            queued_packets: alloc::vec![(ethertype, packet)],
+        });
+        Ok(())
     }
 
     fn ingress(&self, dev: &NetDevice, data: &[u8]) -> Result<()> {
@@ -215,7 +430,7 @@ impl ArpCache {
         match oper {
             ARP_OP_REPLY => {
                 trace!(ARP, "[arp] reply from {:?}", sender_ip.to_bytes());
-                self.insert(sender_ip, sender_mac);
+                self.insert(dev.name(), sender_ip, sender_mac);
             }
             ARP_OP_REQUEST => {
                 if let Some(iface) = dev.interfaces.iter().find(|i| i.addr.0 == target_ip.0) {
@@ -271,6 +486,21 @@ impl ArpCache {
         eth_egress(dev, MacAddr::BROADCAST, ETHERTYPE_ARP, &buf)
     }
 
+    // A request for our own address (SPA == TPA, THA zero) so that peers
+    // refresh stale entries and a host already using the address notices.
+    fn send_gratuitous(&self, dev_name: &str, ip: IpAddr) -> Result<()> {
+        crate::net::device::net_device_with_mut(dev_name, |dev| {
+            if dev.dev_type != NetDeviceType::Ethernet {
+                return Ok(());
+            }
+            if !dev.flags().contains(NetDeviceFlags::UP) {
+                return Err(Error::NotConnected);
+            }
+            trace!(ARP, "[arp] gratuitous {:?} on {}", ip.to_bytes(), dev_name);
+            self.send_request(dev, ip, ip)
+        })?
+    }
+
     fn resolve(
         &self,
         dev_name: &str,
@@ -320,10 +550,61 @@ impl ArpCache {
 
 static ARP: ArpCache = ArpCache::new();
 
+// Called while panicking; see tcp::panic_dump.
+pub fn panic_dump() {
+    let Some(table) = ARP.table.try_lock() else {
+        crate::println!("  arp: table locked");
+        return;
+    };
+    for entry in table.iter().filter(|e| e.valid) {
+        crate::println!("  arp {} -> {}", entry.ip, entry.mac);
+    }
+}
+
 pub fn ingress(dev: &NetDevice, data: &[u8]) -> Result<()> {
     ARP.ingress(dev, data)
 }
 
+#[cfg(test)]
+pub(crate) fn insert(ip: IpAddr, mac: MacAddr) {
+    ARP.insert("", ip, mac)
+}
+
+#[cfg(test)]
+pub(crate) fn lookup(ip: IpAddr) -> Option<MacAddr> {
+    ARP.lookup(ip)
+}
+
+// Drops entries older than ARP_ENTRY_TTL_TICKS and pending requests older
+// than ARP_PENDING_TIMEOUT_TICKS; run from the poll loop.
+pub fn expire() {
+    ARP.expire(*crate::trap::TICKS.lock())
+}
+
+pub fn add_static(ip: IpAddr, mac: MacAddr) {
+    ARP.add_static(ip, mac)
+}
+
+pub fn remove_static(ip: IpAddr) -> Result<()> {
+    ARP.remove_static(ip)
+}
+
+pub fn flush(dev_name: &str) {
+    ARP.flush(dev_name)
+}
+
+pub fn resolve_nb(dev_name: &str, target_ip: IpAddr, sender_ip: IpAddr) -> ArpResolveResult {
+    ARP.resolve_nb(dev_name, target_ip, sender_ip)
+}
+
+pub fn enqueue(dev_name: &str, target_ip: IpAddr, ethertype: u16, packet: Vec<u8>) -> Result<()> {
+    ARP.enqueue(dev_name, target_ip, ethertype, packet)
+}
+
+pub fn send_gratuitous_arp(dev_name: &str, ip: IpAddr) -> Result<()> {
+    ARP.send_gratuitous(dev_name, ip)
+}
+
 pub fn resolve(
     dev_name: &str,
     target_ip: IpAddr,
@@ -335,12 +616,15 @@ pub fn resolve(
 
 #[cfg(test)]
 mod tests {
-    use super::wire;
-    use crate::error::{Error, Result};
-    use crate::net::device::{
-        NetDevice, NetDeviceConfig, NetDeviceFlags, NetDeviceOps, NetDeviceType,
+    use super::{
+        wire, ArpCache, ARP_ENTRY_TTL_TICKS, ARP_PENDING_MAX_PACKETS, ARP_PENDING_TIMEOUT_TICKS,
+    };
+    use crate::error::Error;
+    use crate::net::device::tests::{
+        capture_transmit, captured_frames, dummy_device, register_test_device,
     };
-    use crate::net::ethernet::MacAddr;
+    use crate::net::ethernet::{EthHeader, MacAddr};
+    use crate::net::ip::IpAddr;
 
     #[test_case]
     fn packet_too_short() {
@@ -349,38 +633,144 @@ mod tests {
         assert_eq!(err, Error::PacketTooShort);
     }
 
-    fn ok_transmit(_dev: &mut NetDevice, _data: &[u8]) -> Result<()> {
-        Ok(())
+    #[test_case]
+    fn ingress_rejects_unsupported_protocol() {
+        let dev = dummy_device("dummy");
+        let data = [0u8; wire::PACKET_LEN];
+        let err = super::ingress(&dev, &data).unwrap_err();
+        assert_eq!(err, Error::UnsupportedProtocol);
     }
-    fn ok_open(_dev: &mut NetDevice) -> Result<()> {
-        Ok(())
+
+    #[test_case]
+    fn entries_expire_after_ttl() {
+        let cache = ArpCache::new();
+        let ip = IpAddr::new(10, 1, 2, 3);
+        let mac = MacAddr([0x02, 0, 0, 0, 0, 0x11]);
+        cache.insert_at("eth0", ip, mac, 100);
+        assert_eq!(
+            cache.lookup_at(ip, 100 + ARP_ENTRY_TTL_TICKS - 1),
+            Some(mac)
+        );
+        assert_eq!(cache.lookup_at(ip, 100 + ARP_ENTRY_TTL_TICKS), None);
+
+        // refreshing restarts the clock
+        cache.insert_at("eth0", ip, mac, 100 + ARP_ENTRY_TTL_TICKS);
+        assert_eq!(
+            cache.lookup_at(ip, 100 + ARP_ENTRY_TTL_TICKS + 1),
+            Some(mac)
+        );
+
+        cache.expire(100 + 2 * ARP_ENTRY_TTL_TICKS);
+        assert!(cache.table.lock().is_empty());
     }
-    fn ok_close(_dev: &mut NetDevice) -> Result<()> {
-        Ok(())
+
+    #[test_case]
+    fn pending_requests_are_capped_and_time_out() {
+        let cache = ArpCache::new();
+        let ip = IpAddr::new(10, 1, 2, 7);
+        for _ in 0..ARP_PENDING_MAX_PACKETS {
+            cache
+                .enqueue("eth0", ip, 0x0800, alloc::vec![0; 20])
+                .unwrap();
+        }
+        assert_eq!(
+            cache.enqueue("eth0", ip, 0x0800, alloc::vec![0; 20]),
+            Err(Error::StorageFull)
+        );
+
+        let created_at = cache.pending.lock()[0].created_at;
+        cache.expire(created_at + ARP_PENDING_TIMEOUT_TICKS - 1);
+        assert_eq!(
+            cache.pending.lock()[0].queued_packets.len(),
+            ARP_PENDING_MAX_PACKETS
+        );
+
+        cache.expire(created_at + ARP_PENDING_TIMEOUT_TICKS);
+        assert!(cache.pending.lock().is_empty());
     }
 
-    fn dummy_dev() -> NetDevice {
-        NetDevice::new(NetDeviceConfig {
-            name: "dummy",
-            dev_type: NetDeviceType::Ethernet,
-            mtu: 1500,
-            flags: NetDeviceFlags::UP,
-            header_len: 14,
-            addr_len: 6,
-            hw_addr: MacAddr::ZERO,
-            ops: NetDeviceOps {
-                transmit: ok_transmit,
-                open: ok_open,
-                close: ok_close,
-            },
-        })
+    #[test_case]
+    fn pending_is_kept_per_device() {
+        register_test_device(
+            "arpq0",
+            MacAddr([0x02, 0, 0, 0, 0, 0x20]),
+            IpAddr::new(10, 20, 0, 2),
+            capture_transmit,
+        );
+        register_test_device(
+            "arpq1",
+            MacAddr([0x02, 0, 0, 0, 0, 0x21]),
+            IpAddr::new(10, 21, 0, 2),
+            capture_transmit,
+        );
+        // drop the gratuitous announcements
+        captured_frames("arpq0");
+        captured_frames("arpq1");
+        let cache = ArpCache::new();
+        let ip = IpAddr::new(10, 1, 2, 8);
+        cache
+            .enqueue("arpq0", ip, 0x0800, alloc::vec![0xaa; 20])
+            .unwrap();
+        cache
+            .enqueue("arpq1", ip, 0x0800, alloc::vec![0xbb; 20])
+            .unwrap();
+        cache
+            .enqueue("arpq1", ip, 0x0800, alloc::vec![0xbb; 20])
+            .unwrap();
+        assert_eq!(cache.pending.lock().len(), 2);
+
+        // a reply on arpq0 releases only what was queued there
+        let mac0 = MacAddr([0x02, 0, 0, 0, 0, 0x30]);
+        cache.insert("arpq0", ip, mac0);
+        let sent = captured_frames("arpq0");
+        assert_eq!(sent.len(), 1);
+        assert_eq!(&sent[0][..6], &mac0.0);
+        assert_eq!(&sent[0][EthHeader::LEN..], &[0xaa; 20]);
+        assert!(captured_frames("arpq1").is_empty());
+        {
+            let pending = cache.pending.lock();
+            assert_eq!(pending.len(), 1);
+            assert_eq!(pending[0].dev_name, "arpq1");
+            assert_eq!(pending[0].queued_packets.len(), 2);
+        }
+
+        let mac1 = MacAddr([0x02, 0, 0, 0, 0, 0x31]);
+        cache.insert("arpq1", ip, mac1);
+        let sent = captured_frames("arpq1");
+        assert_eq!(sent.len(), 2);
+        assert!(sent.iter().all(|f| f[..6] == mac1.0));
+        assert!(cache.pending.lock().is_empty());
     }
 
     #[test_case]
-    fn ingress_rejects_unsupported_protocol() {
-        let dev = dummy_dev();
-        let data = [0u8; wire::PACKET_LEN];
-        let err = super::ingress(&dev, &data).unwrap_err();
-        assert_eq!(err, Error::UnsupportedProtocol);
+    fn flush_drops_only_that_device() {
+        let cache = ArpCache::new();
+        let a = IpAddr::new(10, 1, 2, 4);
+        let b = IpAddr::new(10, 1, 2, 5);
+        cache.insert_at("eth0", a, MacAddr([0x02, 0, 0, 0, 0, 0x12]), 0);
+        cache.insert_at("eth1", b, MacAddr([0x02, 0, 0, 0, 0, 0x13]), 0);
+        cache.flush("eth0");
+        assert_eq!(cache.lookup_at(a, 1), None);
+        assert!(cache.lookup_at(b, 1).is_some());
+    }
+
+    #[test_case]
+    fn static_entries_win_and_never_expire() {
+        let cache = ArpCache::new();
+        let ip = IpAddr::new(10, 1, 2, 6);
+        let fixed = MacAddr([0x02, 0, 0, 0, 0, 0x14]);
+        let learned = MacAddr([0x02, 0, 0, 0, 0, 0x15]);
+        cache.insert_at("eth0", ip, learned, 0);
+        cache.add_static(ip, fixed);
+        cache.insert_at("eth0", ip, learned, 1);
+        assert_eq!(cache.lookup_at(ip, 2), Some(fixed));
+
+        cache.expire(10 * ARP_ENTRY_TTL_TICKS);
+        cache.flush("eth0");
+        assert_eq!(cache.lookup_at(ip, 10 * ARP_ENTRY_TTL_TICKS), Some(fixed));
+
+        cache.remove_static(ip).unwrap();
+        assert_eq!(cache.lookup_at(ip, 10 * ARP_ENTRY_TTL_TICKS), None);
+        assert_eq!(cache.remove_static(ip), Err(Error::NotFound));
     }
 }
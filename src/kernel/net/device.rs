@@ -2,9 +2,14 @@ use crate::{
     error::{Error, Result},
     net::{ethernet::MacAddr, interface::NetInterface},
     spinlock::Mutex,
+    stat::NetDeviceStats,
+};
+use alloc::{boxed::Box, string::String, sync::Arc, vec::Vec};
+use core::{
+    ops::{BitAnd, BitAndAssign, BitOr, BitOrAssign, Not},
+    ptr,
+    sync::atomic::{AtomicPtr, AtomicU16, AtomicU64, AtomicUsize, Ordering},
 };
-use alloc::{string::String, vec::Vec};
-use core::ops::{BitAnd, BitAndAssign, BitOr, BitOrAssign, Not};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum NetDeviceType {
@@ -19,6 +24,7 @@ impl NetDeviceFlags {
     pub const BROADCAST: Self = Self(0x0002);
     pub const LOOPBACK: Self = Self(0x0008);
     pub const RUNNING: Self = Self(0x0040);
+    pub const VLAN: Self = Self(0x1000);
 
     pub fn contains(self, other: NetDeviceFlags) -> bool {
         (self.0 & other.0) == other.0
@@ -71,6 +77,16 @@ pub struct NetDeviceConfig<'a> {
     pub ops: NetDeviceOps,
 }
 
+#[derive(Default)]
+struct NetDeviceCounters {
+    rx_packets: AtomicU64,
+    rx_bytes: AtomicU64,
+    rx_errors: AtomicU64,
+    tx_packets: AtomicU64,
+    tx_bytes: AtomicU64,
+    tx_errors: AtomicU64,
+}
+
 pub struct NetDevice {
     name: [u8; 16],
     pub dev_type: NetDeviceType,
@@ -81,6 +97,12 @@ pub struct NetDevice {
     pub hw_addr: MacAddr,
     ops: NetDeviceOps,
     pub interfaces: Vec<NetInterface>,
+    // IPv4 identification counter, shared by every snapshot of the device
+    ip_id: Arc<AtomicU16>,
+    // 802.1Q VLAN the device sends on and accepts tagged frames for
+    vlan_id: Option<u16>,
+    // shared like ip_id, so counting through a snapshot is not lost
+    counters: Arc<NetDeviceCounters>,
 }
 impl NetDevice {
     pub fn new(config: NetDeviceConfig<'_>) -> Self {
@@ -98,6 +120,9 @@ impl NetDevice {
             hw_addr: config.hw_addr,
             ops: config.ops,
             interfaces: Vec::new(),
+            ip_id: Arc::new(AtomicU16::new(0)),
+            vlan_id: None,
+            counters: Arc::new(NetDeviceCounters::default()),
         }
     }
 
@@ -110,6 +135,14 @@ impl NetDevice {
         core::str::from_utf8(&self.name[..end]).unwrap_or("")
     }
 
+    pub fn mtu(&self) -> u16 {
+        self.mtu
+    }
+
+    pub fn next_ip_id(&self) -> u16 {
+        self.ip_id.fetch_add(1, Ordering::Relaxed)
+    }
+
     pub fn flags(&self) -> NetDeviceFlags {
         self.flags
     }
@@ -118,8 +151,61 @@ impl NetDevice {
         self.flags = flags;
     }
 
+    pub fn vlan_id(&self) -> Option<u16> {
+        if self.flags.contains(NetDeviceFlags::VLAN) {
+            self.vlan_id
+        } else {
+            None
+        }
+    }
+
+    // VIDs 0 and 4095 are reserved by 802.1Q.
+    pub fn set_vlan_id(&mut self, vlan_id: Option<u16>) -> Result<()> {
+        match vlan_id {
+            Some(id) if id == 0 || id >= 4095 => return Err(Error::InvalidArgument),
+            Some(_) => self.flags |= NetDeviceFlags::VLAN,
+            None => self.flags &= !NetDeviceFlags::VLAN,
+        }
+        self.vlan_id = vlan_id;
+        Ok(())
+    }
+
     pub fn transmit(&mut self, data: &[u8]) -> Result<()> {
-        (self.ops.transmit)(self, data)
+        let result = (self.ops.transmit)(self, data);
+        let c = &self.counters;
+        if result.is_ok() {
+            c.tx_packets.fetch_add(1, Ordering::Relaxed);
+            c.tx_bytes.fetch_add(data.len() as u64, Ordering::Relaxed);
+        } else {
+            c.tx_errors.fetch_add(1, Ordering::Relaxed);
+        }
+        result
+    }
+
+    // Called by the ingress path for every frame a driver hands up; `ok`
+    // is false only for frames dropped as malformed.
+    pub fn record_rx(&self, len: usize, ok: bool) {
+        let c = &self.counters;
+        c.rx_packets.fetch_add(1, Ordering::Relaxed);
+        c.rx_bytes.fetch_add(len as u64, Ordering::Relaxed);
+        if !ok {
+            c.rx_errors.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn stats(&self) -> NetDeviceStats {
+        let c = &self.counters;
+        let mut name = [0u8; 16];
+        name.copy_from_slice(&self.name);
+        NetDeviceStats {
+            name,
+            rx_packets: c.rx_packets.load(Ordering::Relaxed),
+            rx_bytes: c.rx_bytes.load(Ordering::Relaxed),
+            rx_errors: c.rx_errors.load(Ordering::Relaxed),
+            tx_packets: c.tx_packets.load(Ordering::Relaxed),
+            tx_bytes: c.tx_bytes.load(Ordering::Relaxed),
+            tx_errors: c.tx_errors.load(Ordering::Relaxed),
+        }
     }
 
     pub fn open(&mut self) -> Result<()> {
@@ -127,7 +213,9 @@ impl NetDevice {
     }
 
     pub fn close(&mut self) -> Result<()> {
-        (self.ops.close)(self)
+        (self.ops.close)(self)?;
+        crate::net::arp::flush(self.name());
+        Ok(())
     }
 
     pub fn add_interface(&mut self, iface: NetInterface) {
@@ -166,24 +254,130 @@ impl Clone for NetDevice {
                 close: self.ops.close,
             },
             interfaces: self.interfaces.clone(),
+            ip_id: self.ip_id.clone(),
+            vlan_id: self.vlan_id,
+            counters: self.counters.clone(),
         }
     }
 }
 
+// One registered device. Slots outlive list republishes, so a write to a
+// single device swaps only that device.
+struct DeviceSlot {
+    current: AtomicPtr<NetDevice>,
+}
+
+impl DeviceSlot {
+    fn new(device: NetDevice) -> Self {
+        Self {
+            current: AtomicPtr::new(Box::into_raw(Box::new(device))),
+        }
+    }
+
+    fn device(&self) -> &NetDevice {
+        // SAFETY: the pointer is always live; a replaced device is only
+        // freed by a writer that observed no pinned readers.
+        unsafe { &*self.current.load(Ordering::SeqCst) }
+    }
+}
+
+impl Drop for DeviceSlot {
+    fn drop(&mut self) {
+        drop(unsafe { Box::from_raw(*self.current.get_mut()) });
+    }
+}
+
+struct Registry {
+    slots: Vec<Arc<DeviceSlot>>,
+    // Boxed so the list header and devices stay put while a pinned reader
+    // uses them.
+    #[allow(clippy::vec_box)]
+    retired: Vec<Box<Vec<Arc<DeviceSlot>>>>,
+    #[allow(clippy::vec_box)]
+    retired_devices: Vec<Box<NetDevice>>,
+}
+
+impl Registry {
+    fn reclaim(&mut self, readers: &AtomicUsize) {
+        if readers.load(Ordering::SeqCst) == 0 {
+            self.retired.clear();
+            self.retired_devices.clear();
+        }
+    }
+}
+
+/// Device list with a lock-free read path.
+///
+/// Writers serialize on `inner`. Registering publishes a fresh copy of the
+/// slot list and bumps `seqcount` around the swap; `with_mut` only swaps the
+/// one device in its slot. Readers never take the lock: they pin the
+/// published list via `readers`, clone what they need and retry if the list
+/// changed under them. Replaced lists and devices are freed once no reader
+/// is pinned.
 struct NetDeviceRegistry {
-    devices: Mutex<Vec<NetDevice>>,
+    inner: Mutex<Registry>,
+    published: AtomicPtr<Vec<Arc<DeviceSlot>>>,
+    seqcount: AtomicU64,
+    readers: AtomicUsize,
 }
 
 impl NetDeviceRegistry {
     const fn new() -> Self {
         Self {
-            devices: Mutex::new(Vec::new(), "net_devices"),
+            inner: Mutex::new(
+                Registry {
+                    slots: Vec::new(),
+                    retired: Vec::new(),
+                    retired_devices: Vec::new(),
+                },
+                "net_devices",
+            ),
+            published: AtomicPtr::new(ptr::null_mut()),
+            seqcount: AtomicU64::new(0),
+            readers: AtomicUsize::new(0),
+        }
+    }
+
+    fn publish(&self, inner: &mut Registry) {
+        self.seqcount.fetch_add(1, Ordering::SeqCst);
+        let fresh = Box::into_raw(Box::new(inner.slots.clone()));
+        let old = self.published.swap(fresh, Ordering::SeqCst);
+        if !old.is_null() {
+            inner.retired.push(unsafe { Box::from_raw(old) });
+        }
+        self.seqcount.fetch_add(1, Ordering::SeqCst);
+        inner.reclaim(&self.readers);
+    }
+
+    fn read<F, R>(&self, mut f: F) -> R
+    where
+        F: FnMut(&[Arc<DeviceSlot>]) -> R,
+    {
+        loop {
+            let seq = self.seqcount.load(Ordering::Acquire);
+            if seq & 1 != 0 {
+                core::hint::spin_loop();
+                continue;
+            }
+            self.readers.fetch_add(1, Ordering::SeqCst);
+            let list = self.published.load(Ordering::SeqCst);
+            // SAFETY: a published list is only freed by a writer that
+            // observed no pinned readers after swapping it out.
+            let result = match unsafe { list.as_ref() } {
+                Some(list) => f(list),
+                None => f(&[]),
+            };
+            self.readers.fetch_sub(1, Ordering::SeqCst);
+            if self.seqcount.load(Ordering::Acquire) == seq {
+                return result;
+            }
         }
     }
 
     fn register(&self, device: NetDevice) -> Result<()> {
-        let mut list = self.devices.lock();
-        list.push(device);
+        let mut inner = self.inner.lock();
+        inner.slots.push(Arc::new(DeviceSlot::new(device)));
+        self.publish(&mut inner);
         Ok(())
     }
 
@@ -191,35 +385,56 @@ impl NetDeviceRegistry {
     where
         F: FnMut(&mut NetDevice) -> R,
     {
-        let mut list = self.devices.lock();
-        let dev = list
-            .iter_mut()
-            .find(|d| d.name() == name)
+        let mut inner = self.inner.lock();
+        let slot = inner
+            .slots
+            .iter()
+            .find(|s| s.device().name() == name)
             .ok_or(Error::DeviceNotFound)?;
-        Ok(f(dev))
+        let mut dev = slot.device().clone();
+        let result = f(&mut dev);
+        let old = slot
+            .current
+            .swap(Box::into_raw(Box::new(dev)), Ordering::SeqCst);
+        inner.retired_devices.push(unsafe { Box::from_raw(old) });
+        inner.reclaim(&self.readers);
+        Ok(result)
     }
 
-    fn by_name(&self, name: &str) -> Option<NetDevice> {
-        let list = self.devices.lock();
-        list.iter().find(|d| d.name() == name).cloned()
+    fn snapshot(&self, name: &str) -> Option<NetDevice> {
+        self.read(|list| {
+            list.iter()
+                .map(|s| s.device())
+                .find(|d| d.name() == name)
+                .cloned()
+        })
     }
 
     fn by_index(&self, index: usize) -> Option<NetDevice> {
-        let list = self.devices.lock();
-        list.get(index).cloned()
+        self.read(|list| list.get(index).map(|s| s.device().clone()))
     }
 
     fn foreach<F>(&self, mut f: F)
     where
         F: FnMut(&NetDevice),
     {
-        let list = self.devices.lock();
-        for dev in list.iter() {
+        let devices: Vec<NetDevice> =
+            self.read(|list| list.iter().map(|s| s.device().clone()).collect());
+        for dev in devices.iter() {
             f(dev);
         }
     }
 }
 
+impl Drop for NetDeviceRegistry {
+    fn drop(&mut self) {
+        let list = *self.published.get_mut();
+        if !list.is_null() {
+            drop(unsafe { Box::from_raw(list) });
+        }
+    }
+}
+
 static NET_DEVICES: NetDeviceRegistry = NetDeviceRegistry::new();
 
 pub fn net_device_register(device: NetDevice) -> Result<()> {
@@ -234,13 +449,22 @@ where
 }
 
 pub fn net_device_by_name(name: &str) -> Option<NetDevice> {
-    NET_DEVICES.by_name(name)
+    NET_DEVICES.snapshot(name)
+}
+
+// Lock-free copy of a device, read without taking the registry lock.
+pub fn net_device_snapshot(name: &str) -> Option<NetDevice> {
+    NET_DEVICES.snapshot(name)
 }
 
 pub fn net_device_by_index(index: usize) -> Option<NetDevice> {
     NET_DEVICES.by_index(index)
 }
 
+pub fn net_device_stats(name: &str) -> Option<NetDeviceStats> {
+    NET_DEVICES.snapshot(name).map(|dev| dev.stats())
+}
+
 pub fn net_device_foreach<F>(f: F)
 where
     F: FnMut(&NetDevice),
@@ -249,37 +473,109 @@ where
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
-    use crate::net::ethernet::MacAddr;
-    use crate::net::interface::NetInterface;
+    use crate::net::ethernet::{EthHeader, MacAddr};
+    use crate::net::interface::{net_interface_setup, NetInterface};
     use crate::net::ip::IpAddr;
+    use crate::net::route::{self, Route};
 
-    fn ok_transmit(_dev: &mut NetDevice, _data: &[u8]) -> Result<()> {
+    // Transmit op for test devices whose output nobody looks at.
+    pub(crate) fn discard_transmit(_dev: &mut NetDevice, _data: &[u8]) -> Result<()> {
         Ok(())
     }
-    fn ok_open(_dev: &mut NetDevice) -> Result<()> {
+
+    // Frames sent through capture_transmit, tagged with the sending device.
+    static CAPTURED_FRAMES: Mutex<Vec<(String, Vec<u8>)>> =
+        Mutex::new(Vec::new(), "test_captured_frames");
+
+    // Transmit op for test devices whose output a test inspects.
+    pub(crate) fn capture_transmit(dev: &mut NetDevice, data: &[u8]) -> Result<()> {
+        CAPTURED_FRAMES
+            .lock()
+            .push((String::from(dev.name()), data.to_vec()));
         Ok(())
     }
-    fn ok_close(_dev: &mut NetDevice) -> Result<()> {
-        Ok(())
+
+    // Takes the frames `name` has sent since the last call, oldest first.
+    pub(crate) fn captured_frames(name: &str) -> Vec<Vec<u8>> {
+        let mut captured = CAPTURED_FRAMES.lock();
+        let mut frames = Vec::new();
+        captured.retain(|(dev, frame)| {
+            if dev != name {
+                return true;
+            }
+            frames.push(frame.clone());
+            false
+        });
+        frames
     }
 
-    fn dummy_device(name: &str) -> NetDevice {
-        NetDevice::new(NetDeviceConfig {
+    // The newest frame `name` has sent; older ones are dropped with it.
+    pub(crate) fn last_captured_frame(name: &str) -> Option<Vec<u8>> {
+        captured_frames(name).pop()
+    }
+
+    // An unregistered device that discards what it sends.
+    pub(crate) fn dummy_device(name: &str) -> NetDevice {
+        NetDevice::new(test_config(name, MacAddr::ZERO, discard_transmit))
+    }
+
+    // An UP Ethernet device whose frames go to `transmit`; tests that need
+    // something else override fields with struct update syntax.
+    pub(crate) fn test_config(
+        name: &str,
+        hw_addr: MacAddr,
+        transmit: fn(&mut NetDevice, &[u8]) -> Result<()>,
+    ) -> NetDeviceConfig<'_> {
+        NetDeviceConfig {
             name,
             dev_type: NetDeviceType::Ethernet,
             mtu: 1500,
             flags: NetDeviceFlags::UP,
-            header_len: 14,
+            header_len: EthHeader::LEN as u16,
             addr_len: 6,
-            hw_addr: MacAddr::ZERO,
+            hw_addr,
             ops: NetDeviceOps {
-                transmit: ok_transmit,
-                open: ok_open,
-                close: ok_close,
+                transmit,
+                open: |_dev| Ok(()),
+                close: |_dev| Ok(()),
             },
+        }
+    }
+
+    // Registers a test device holding `addr`/24 and returns it as
+    // registered.
+    pub(crate) fn register_test_device(
+        name: &'static str,
+        hw_addr: MacAddr,
+        addr: IpAddr,
+        transmit: fn(&mut NetDevice, &[u8]) -> Result<()>,
+    ) -> NetDevice {
+        net_device_register(NetDevice::new(test_config(name, hw_addr, transmit))).unwrap();
+        net_interface_setup(name, addr, IpAddr::new(255, 255, 255, 0)).unwrap();
+        net_device_by_name(name).unwrap()
+    }
+
+    // register_test_device plus a route for the device's subnet. The route
+    // table is small and shared by every test, so only tests that go
+    // through routing should use this.
+    pub(crate) fn attach_test_device(
+        name: &'static str,
+        hw_addr: MacAddr,
+        addr: IpAddr,
+        transmit: fn(&mut NetDevice, &[u8]) -> Result<()>,
+    ) -> NetDevice {
+        let dev = register_test_device(name, hw_addr, addr, transmit);
+        let mask = IpAddr::new(255, 255, 255, 0);
+        route::add_route(Route {
+            dest: IpAddr(addr.0 & mask.0),
+            mask,
+            gateway: None,
+            dev: name,
         })
+        .unwrap();
+        dev
     }
 
     #[test_case]
@@ -296,4 +592,85 @@ mod tests {
         let found = dev.interface_by_addr(iface.addr.0).unwrap();
         assert_eq!(found.addr, iface.addr);
     }
+
+    #[test_case]
+    fn snapshot_tracks_registration() {
+        let registry = NetDeviceRegistry::new();
+        assert!(registry.snapshot("snap0").is_none());
+
+        registry.register(dummy_device("snap0")).unwrap();
+        assert_eq!(registry.snapshot("snap0").unwrap().name(), "snap0");
+        assert_eq!(registry.seqcount.load(Ordering::SeqCst), 2);
+
+        // a write swaps the device alone; the list is not republished
+        let list = registry.published.load(Ordering::SeqCst);
+        registry
+            .with_mut("snap0", |dev| dev.set_flags(NetDeviceFlags::LOOPBACK))
+            .unwrap();
+        let dev = registry.snapshot("snap0").unwrap();
+        assert_eq!(dev.flags(), NetDeviceFlags::LOOPBACK);
+        assert_eq!(registry.seqcount.load(Ordering::SeqCst), 2);
+        assert_eq!(registry.published.load(Ordering::SeqCst), list);
+        assert!(registry.inner.lock().retired_devices.is_empty());
+    }
+
+    #[test_case]
+    fn snapshot_stays_valid_across_register() {
+        let registry = NetDeviceRegistry::new();
+        registry.register(dummy_device("snap0")).unwrap();
+
+        // Pin the published list as an in-flight reader would.
+        registry.readers.fetch_add(1, Ordering::SeqCst);
+        let pinned = registry.published.load(Ordering::SeqCst);
+        registry.register(dummy_device("snap1")).unwrap();
+
+        registry
+            .with_mut("snap0", |dev| dev.header_len = 18)
+            .unwrap();
+
+        let old = unsafe { &*pinned };
+        assert_eq!(old.len(), 1);
+        assert_eq!(old[0].device().name(), "snap0");
+        assert_eq!(registry.inner.lock().retired.len(), 1);
+        assert_eq!(registry.inner.lock().retired_devices.len(), 1);
+        registry.readers.fetch_sub(1, Ordering::SeqCst);
+
+        assert!(registry.snapshot("snap1").is_some());
+        registry.register(dummy_device("snap2")).unwrap();
+        assert!(registry.inner.lock().retired.is_empty());
+        assert!(registry.inner.lock().retired_devices.is_empty());
+        assert_eq!(registry.by_index(0).unwrap().header_len, 18);
+        assert_eq!(registry.by_index(2).unwrap().name(), "snap2");
+    }
+
+    fn failing_transmit(_dev: &mut NetDevice, _data: &[u8]) -> Result<()> {
+        Err(Error::NoBufferSpace)
+    }
+
+    #[test_case]
+    fn stats_count_traffic_across_snapshots() {
+        let mut dev = dummy_device("stat0");
+        let mut snapshot = dev.clone();
+        dev.transmit(&[0; 60]).unwrap();
+        snapshot.transmit(&[0; 40]).unwrap();
+        dev.record_rx(100, true);
+        dev.record_rx(10, false);
+
+        let mut broken = dummy_device("stat1");
+        broken.ops.transmit = failing_transmit;
+        assert!(broken.transmit(&[0; 60]).is_err());
+        assert_eq!(broken.stats().tx_errors, 1);
+        assert_eq!(broken.stats().tx_packets, 0);
+
+        let stats = dev.stats();
+        assert_eq!(&stats.name[..6], b"stat0\0");
+        assert_eq!(
+            (stats.tx_packets, stats.tx_bytes, stats.tx_errors),
+            (2, 100, 0)
+        );
+        assert_eq!(
+            (stats.rx_packets, stats.rx_bytes, stats.rx_errors),
+            (2, 110, 1)
+        );
+    }
 }
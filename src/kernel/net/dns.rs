@@ -1,19 +1,81 @@
 use super::{
-    ip::{IpAddr, IpEndpoint},
-    udp,
+    ip::{IpAddr, IpAddr6, IpEndpoint},
+    tcp, udp,
 };
 use crate::{
     error::{Error, Result},
     net::poll,
+    spinlock::Mutex,
     trace,
 };
 extern crate alloc;
-use alloc::{vec, vec::Vec};
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
 
-const DNS_TYPE_A: u16 = 1; // IPv4 address
+pub const DNS_TYPE_A: u16 = 1; // IPv4 address
+pub const DNS_TYPE_CNAME: u16 = 5; // Canonical name
+pub const DNS_TYPE_PTR: u16 = 12; // Domain name pointer
+pub const DNS_TYPE_MX: u16 = 15; // Mail exchange
+pub const DNS_TYPE_TXT: u16 = 16; // Text strings
+pub const DNS_TYPE_AAAA: u16 = 28; // IPv6 address
 const DNS_CLASS_IN: u16 = 1; // Internet class
+const DNS_FLAG_QR: u16 = 0x8000; // Response bit
+const DNS_FLAG_TC: u16 = 0x0200; // Truncated, retry over TCP
+const DNS_RCODE_MASK: u16 = 0x000F; // Response code
+const DNS_RCODE_FORMERR: u16 = 1; // Format error
+const DNS_RCODE_SERVFAIL: u16 = 2; // Server failure
+const DNS_RCODE_NXDOMAIN: u16 = 3; // Non-existent domain
+const DNS_RCODE_REFUSED: u16 = 5; // Query refused
 const DNS_SERVER: IpAddr = IpAddr(0x0808_0808);
 const DNS_PORT: u16 = 53;
+const MAX_POINTER_JUMPS: usize = 16;
+const DNS_TCP_TIMEOUT_TICKS: usize = 5 * crate::param::TICK_HZ;
+
+static DNS_SEARCH_DOMAINS: Mutex<Vec<String>> = Mutex::new(Vec::new(), "dns_search");
+static DNS_QUERY_ID_STATE: Mutex<u32> = Mutex::new(0, "dns_query_id");
+
+// LCG (Numerical Recipes constants) stirred with the tick count on every
+// call, so IDs are not guessable from boot alone. The high half is used;
+// the low bits of an LCG cycle with a short period.
+fn next_query_id_from(state: &mut u32, ticks: usize) -> u16 {
+    *state = state
+        .wrapping_add(ticks as u32)
+        .wrapping_mul(1_664_525)
+        .wrapping_add(1_013_904_223);
+    (*state >> 16) as u16
+}
+
+fn next_query_id() -> u16 {
+    let ticks = *crate::trap::TICKS.lock();
+    next_query_id_from(&mut DNS_QUERY_ID_STATE.lock(), ticks)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DnsRecord {
+    A(IpAddr),
+    Aaaa(IpAddr6),
+    Cname(String),
+    Ptr(String),
+    Mx { preference: u16, exchange: String },
+    Txt(Vec<u8>),
+}
+
+impl DnsRecord {
+    pub fn rtype(&self) -> u16 {
+        match self {
+            DnsRecord::A(_) => DNS_TYPE_A,
+            DnsRecord::Aaaa(_) => DNS_TYPE_AAAA,
+            DnsRecord::Cname(_) => DNS_TYPE_CNAME,
+            DnsRecord::Ptr(_) => DNS_TYPE_PTR,
+            DnsRecord::Mx { .. } => DNS_TYPE_MX,
+            DnsRecord::Txt(_) => DNS_TYPE_TXT,
+        }
+    }
+}
 
 mod wire {
     use crate::error::{Error, Result};
@@ -117,7 +179,7 @@ fn encode_domain_name(domain: &str, buf: &mut Vec<u8>) {
     buf.push(0);
 }
 
-fn build_dns_query(domain: &str, id: u16) -> Vec<u8> {
+fn build_dns_query(domain: &str, qtype: u16, id: u16) -> Vec<u8> {
     let mut packet = vec![0u8; wire::HEADER_LEN];
     {
         let mut header = wire::HeaderMut::new_unchecked(&mut packet);
@@ -129,13 +191,111 @@ fn build_dns_query(domain: &str, id: u16) -> Vec<u8> {
         header.set_arcount(0);
     }
     encode_domain_name(domain, &mut packet);
-    packet.extend_from_slice(&DNS_TYPE_A.to_be_bytes());
+    packet.extend_from_slice(&qtype.to_be_bytes());
     packet.extend_from_slice(&DNS_CLASS_IN.to_be_bytes());
 
     packet
 }
 
-fn parse_dns_response(data: &[u8]) -> Result<IpAddr> {
+fn dns_query_id_matches(data: &[u8], expected_id: u16) -> bool {
+    match wire::Header::new_checked(data) {
+        Ok(header) => header.id() == expected_id && header.flags() & DNS_FLAG_QR != 0,
+        Err(_) => false,
+    }
+}
+
+fn is_truncated(data: &[u8]) -> bool {
+    wire::Header::new_checked(data).is_ok_and(|h| h.flags() & DNS_FLAG_TC != 0)
+}
+
+// RFC 1035 4.2.2: over TCP each message is prefixed with its length.
+fn frame_tcp_message(message: &[u8]) -> Vec<u8> {
+    let mut framed = Vec::with_capacity(2 + message.len());
+    framed.extend_from_slice(&(message.len() as u16).to_be_bytes());
+    framed.extend_from_slice(message);
+    framed
+}
+
+// The whole message once `buf` holds its length prefix and body.
+fn tcp_message(buf: &[u8]) -> Option<&[u8]> {
+    let len = u16::from_be_bytes([*buf.first()?, *buf.get(1)?]) as usize;
+    buf.get(2..2 + len)
+}
+
+fn decode_domain_name(data: &[u8], mut offset: usize) -> Result<(String, usize)> {
+    let mut name = String::new();
+    let mut next = None;
+    let mut jumps = 0;
+    loop {
+        let len = *data.get(offset).ok_or(Error::PacketTooShort)? as usize;
+        if len & 0xC0 == 0xC0 {
+            let low = *data.get(offset + 1).ok_or(Error::PacketTooShort)? as usize;
+            next.get_or_insert(offset + 2);
+            jumps += 1;
+            if jumps > MAX_POINTER_JUMPS {
+                return Err(Error::InvalidLength);
+            }
+            offset = ((len & 0x3F) << 8) | low;
+            continue;
+        }
+
+        offset += 1;
+        if len == 0 {
+            break;
+        }
+
+        let label = data
+            .get(offset..offset + len)
+            .ok_or(Error::PacketTooShort)?;
+        if !name.is_empty() {
+            name.push('.');
+        }
+        name.push_str(&String::from_utf8_lossy(label));
+        offset += len;
+    }
+    Ok((name, next.unwrap_or(offset)))
+}
+
+fn parse_rdata(
+    data: &[u8],
+    rtype: u16,
+    offset: usize,
+    rdlength: usize,
+) -> Result<Option<DnsRecord>> {
+    let rdata = &data[offset..offset + rdlength];
+    let record = match rtype {
+        DNS_TYPE_A if rdlength == 4 => DnsRecord::A(IpAddr(u32::from_be_bytes([
+            rdata[0], rdata[1], rdata[2], rdata[3],
+        ]))),
+        DNS_TYPE_AAAA if rdlength == 16 => {
+            let mut addr = [0u8; 16];
+            addr.copy_from_slice(rdata);
+            DnsRecord::Aaaa(IpAddr6(addr))
+        }
+        DNS_TYPE_CNAME => DnsRecord::Cname(decode_domain_name(data, offset)?.0),
+        DNS_TYPE_PTR => DnsRecord::Ptr(decode_domain_name(data, offset)?.0),
+        DNS_TYPE_MX if rdlength >= 3 => DnsRecord::Mx {
+            preference: u16::from_be_bytes([rdata[0], rdata[1]]),
+            exchange: decode_domain_name(data, offset + 2)?.0,
+        },
+        DNS_TYPE_TXT => DnsRecord::Txt(rdata.to_vec()),
+        _ => return Ok(None),
+    };
+    Ok(Some(record))
+}
+
+fn rcode_error(flags: u16) -> Option<Error> {
+    match flags & DNS_RCODE_MASK {
+        0 => None,
+        DNS_RCODE_FORMERR => Some(Error::DnsFormErr),
+        DNS_RCODE_SERVFAIL => Some(Error::DnsServFail),
+        DNS_RCODE_NXDOMAIN => Some(Error::DnsNxDomain),
+        DNS_RCODE_REFUSED => Some(Error::DnsRefused),
+        _ => Some(Error::Uncategorized),
+    }
+}
+
+fn parse_dns_records(data: &[u8]) -> Result<Vec<DnsRecord>> {
     let header = wire::Header::new_checked(data)?;
     let ancount = header.ancount();
 
@@ -148,63 +308,22 @@ fn parse_dns_response(data: &[u8]) -> Result<IpAddr> {
         ancount
     );
 
+    if let Some(err) = rcode_error(header.flags()) {
+        trace!(DNS, "[dns] Response error: {}", err);
+        return Err(err);
+    }
     if ancount == 0 {
         return Err(Error::NotFound);
     }
 
     let mut offset = wire::HEADER_LEN;
-
-    let qdcount = header.qdcount();
-    for _ in 0..qdcount {
-        loop {
-            if offset >= data.len() {
-                return Err(Error::PacketTooShort);
-            }
-
-            let len = data[offset];
-            if len & 0xC0 == 0xC0 {
-                offset += 2;
-                break;
-            }
-
-            offset += 1;
-
-            if len == 0 {
-                break;
-            }
-
-            offset += len as usize;
-        }
-
-        offset += 4;
+    for _ in 0..header.qdcount() {
+        offset = decode_domain_name(data, offset)?.1 + 4;
     }
 
+    let mut records = Vec::new();
     for i in 0..ancount {
-        if offset >= data.len() {
-            return Err(Error::PacketTooShort);
-        }
-
-        loop {
-            if offset >= data.len() {
-                return Err(Error::PacketTooShort);
-            }
-
-            let len = data[offset];
-
-            if len & 0xC0 == 0xC0 {
-                offset += 2;
-                break;
-            }
-
-            offset += 1;
-
-            if len == 0 {
-                break;
-            }
-
-            offset += len as usize;
-        }
-
+        offset = decode_domain_name(data, offset)?.1;
         if offset + 10 > data.len() {
             return Err(Error::PacketTooShort);
         }
@@ -217,7 +336,7 @@ fn parse_dns_response(data: &[u8]) -> Result<IpAddr> {
             data[offset + 6],
             data[offset + 7],
         ]);
-        let rdlength = u16::from_be_bytes([data[offset + 8], data[offset + 9]]);
+        let rdlength = u16::from_be_bytes([data[offset + 8], data[offset + 9]]) as usize;
 
         offset += 10;
 
@@ -231,30 +350,140 @@ fn parse_dns_response(data: &[u8]) -> Result<IpAddr> {
             rdlength
         );
 
-        if rtype == DNS_TYPE_A && rclass == DNS_CLASS_IN && rdlength == 4 {
-            if offset + 4 > data.len() {
-                return Err(Error::PacketTooShort);
+        if offset + rdlength > data.len() {
+            return Err(Error::PacketTooShort);
+        }
+        if rclass == DNS_CLASS_IN {
+            if let Some(record) = parse_rdata(data, rtype, offset, rdlength)? {
+                records.push(record);
+            }
+        }
+
+        offset += rdlength;
+    }
+
+    Ok(records)
+}
+
+fn parse_dns_response(data: &[u8]) -> Result<IpAddr> {
+    parse_dns_records(data)?
+        .into_iter()
+        .find_map(|r| match r {
+            DnsRecord::A(addr) => Some(addr),
+            _ => None,
+        })
+        .ok_or(Error::NotFound)
+}
+
+/// Serializes records for userland as `type(u16) len(u16) data` entries.
+/// Addresses are raw bytes, names are dotted text and MX data is the
+/// preference followed by the exchange name. Records that do not fit in
+/// `limit` bytes are left out.
+pub fn encode_records(records: &[DnsRecord], limit: usize) -> Vec<u8> {
+    let mut out = Vec::new();
+    for record in records {
+        let mut data = Vec::new();
+        match record {
+            DnsRecord::A(addr) => data.extend_from_slice(&addr.0.to_be_bytes()),
+            DnsRecord::Aaaa(addr) => data.extend_from_slice(&addr.0),
+            DnsRecord::Cname(name) | DnsRecord::Ptr(name) => {
+                data.extend_from_slice(name.as_bytes())
             }
+            DnsRecord::Mx {
+                preference,
+                exchange,
+            } => {
+                data.extend_from_slice(&preference.to_be_bytes());
+                data.extend_from_slice(exchange.as_bytes());
+            }
+            DnsRecord::Txt(text) => data.extend_from_slice(text),
+        }
+        if out.len() + 4 + data.len() > limit {
+            break;
+        }
+        out.extend_from_slice(&record.rtype().to_be_bytes());
+        out.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        out.extend_from_slice(&data);
+    }
+    out
+}
+
+pub fn default_server() -> IpEndpoint {
+    IpEndpoint::new(DNS_SERVER, DNS_PORT)
+}
+
+fn wait_tick() {
+    let mut ticks = crate::trap::TICKS.lock();
+    let ticks0 = *ticks;
+    while *ticks - ticks0 < 1 {
+        ticks = crate::proc::sleep(&(*ticks) as *const _ as usize, ticks);
+    }
+}
 
-            let addr = u32::from_be_bytes([
-                data[offset],
-                data[offset + 1],
-                data[offset + 2],
-                data[offset + 3],
-            ]);
+fn exchange_tcp(server: IpEndpoint, domain: &str, qtype: u16, query_id: u16) -> Result<Vec<u8>> {
+    let query = frame_tcp_message(&build_dns_query(domain, qtype, query_id));
+    let sock = tcp::socket_alloc()?;
+    let result = tcp_round_trip(sock, server, &query);
+    let _ = tcp::socket_get_mut(sock, |socket| socket.close());
+    poll();
+    let _ = tcp::socket_free(sock);
+
+    let response = result?;
+    if !dns_query_id_matches(&response, query_id) {
+        trace!(DNS, "[dns] Unmatched response over TCP");
+        return Err(Error::NotFound);
+    }
+    Ok(response)
+}
 
-            return Ok(IpAddr(addr));
+fn tcp_round_trip(sock: usize, server: IpEndpoint, query: &[u8]) -> Result<Vec<u8>> {
+    use tcp::State;
+
+    tcp::socket_get_mut(sock, |socket| socket.connect(IpEndpoint::any(0), server))??;
+    let start = *crate::trap::TICKS.lock();
+    let expired = || *crate::trap::TICKS.lock() - start > DNS_TCP_TIMEOUT_TICKS;
+
+    loop {
+        poll();
+        match tcp::socket_get(sock, |s| s.state())? {
+            State::Established => break,
+            State::SynSent | State::SynReceived if !expired() => wait_tick(),
+            State::SynSent | State::SynReceived => return Err(Error::Timeout),
+            State::Closed => return Err(Error::ConnectionRefused),
+            _ => return Err(Error::ConnectionAborted),
         }
+    }
 
-        offset += rdlength as usize;
+    let mut sent = 0;
+    while sent < query.len() {
+        match tcp::socket_get_mut(sock, |socket| socket.send_slice(&query[sent..]))? {
+            Ok(n) => sent += n,
+            Err(Error::BufferFull) if !expired() => wait_tick(),
+            Err(Error::BufferFull) => return Err(Error::Timeout),
+            Err(err) => return Err(err),
+        }
+        poll();
     }
 
-    Err(Error::NotFound)
+    let mut response = Vec::new();
+    let mut chunk = [0u8; 512];
+    loop {
+        if let Some(message) = tcp_message(&response) {
+            trace!(DNS, "[dns] Received {} bytes over TCP", message.len());
+            return Ok(message.to_vec());
+        }
+        poll();
+        match tcp::socket_get_mut(sock, |socket| socket.recv_slice(&mut chunk))? {
+            Ok(0) => return Err(Error::PacketTruncated),
+            Ok(n) => response.extend_from_slice(&chunk[..n]),
+            Err(Error::WouldBlock) if !expired() => wait_tick(),
+            Err(Error::WouldBlock) => return Err(Error::Timeout),
+            Err(err) => return Err(err),
+        }
+    }
 }
 
-pub fn resolve(domain: &str) -> Result<IpAddr> {
-    trace!(DNS, "[dns] Resolving: {}", domain);
-    trace!(DNS, "[dns] Querying upstream DNS server...");
+fn send_query(server: IpEndpoint, domain: &str, qtype: u16, query_id: u16) -> Result<usize> {
     let sockfd = udp::socket_alloc()?;
     let local = IpEndpoint::any(0);
     if let Err(err) = udp::socket_bind(sockfd, local) {
@@ -262,24 +491,28 @@ pub fn resolve(domain: &str) -> Result<IpAddr> {
         return Err(err);
     }
 
-    let query_id = 0x1234; // TODO: ランダムIDを使用
-    let query = build_dns_query(domain, query_id);
+    let query = build_dns_query(domain, qtype, query_id);
 
     trace!(
         DNS,
-        "[dns] Sending query to {}.{}.{}.{}:53 ({} bytes)",
-        (DNS_SERVER.0 >> 24) & 0xFF,
-        (DNS_SERVER.0 >> 16) & 0xFF,
-        (DNS_SERVER.0 >> 8) & 0xFF,
-        DNS_SERVER.0 & 0xFF,
+        "[dns] Sending query to {}:{} ({} bytes)",
+        server.addr,
+        server.port,
         query.len()
     );
 
-    let dns_endpoint = IpEndpoint::new(DNS_SERVER, DNS_PORT);
-    if let Err(err) = udp::socket_sendto(sockfd, dns_endpoint, &query) {
+    if let Err(err) = udp::socket_sendto(sockfd, server, &query) {
         let _ = udp::socket_free(sockfd);
         return Err(err);
     }
+    Ok(sockfd)
+}
+
+fn exchange(server: IpEndpoint, domain: &str, qtype: u16) -> Result<Vec<u8>> {
+    trace!(DNS, "[dns] Resolving: {} (type {})", domain, qtype);
+    trace!(DNS, "[dns] Querying upstream DNS server...");
+    let query_id = next_query_id();
+    let sockfd = send_query(server, domain, qtype, query_id)?;
 
     let mut buf = alloc::vec![0u8; 512];
     let max_attempts = 100;
@@ -297,32 +530,20 @@ pub fn resolve(domain: &str) -> Result<IpAddr> {
                     attempt + 1
                 );
 
-                match parse_dns_response(&buf[..len]) {
-                    Ok(addr) => {
-                        udp::socket_free(sockfd)?;
-                        trace!(
-                            DNS,
-                            "[dns] Resolved {} to {}.{}.{}.{}",
-                            domain,
-                            (addr.0 >> 24) & 0xFF,
-                            (addr.0 >> 16) & 0xFF,
-                            (addr.0 >> 8) & 0xFF,
-                            addr.0 & 0xFF
-                        );
-                        return Ok(addr);
-                    }
-                    Err(e) => {
-                        trace!(DNS, "[dns] Failed to parse response: {:?}", e);
-                    }
+                if !dns_query_id_matches(&buf[..len], query_id) {
+                    trace!(DNS, "[dns] Discarding unmatched response");
+                    continue;
                 }
-            }
-            Err(Error::WouldBlock) => {
-                let mut ticks = crate::trap::TICKS.lock();
-                let ticks0 = *ticks;
-                while *ticks - ticks0 < 1 {
-                    ticks = crate::proc::sleep(&(*ticks) as *const _ as usize, ticks);
+
+                udp::socket_free(sockfd)?;
+                buf.truncate(len);
+                if is_truncated(&buf) {
+                    trace!(DNS, "[dns] Response truncated, retrying over TCP");
+                    return exchange_tcp(server, domain, qtype, query_id);
                 }
+                return Ok(buf);
             }
+            Err(Error::WouldBlock) => wait_tick(),
             Err(e) => {
                 udp::socket_free(sockfd)?;
                 return Err(e);
@@ -334,11 +555,257 @@ pub fn resolve(domain: &str) -> Result<IpAddr> {
     Err(Error::Timeout)
 }
 
+pub fn set_search(domains: &[&str]) {
+    let domains = domains
+        .iter()
+        .map(|d| d.trim_matches('.'))
+        .filter(|d| !d.is_empty())
+        .map(|d| d.to_string())
+        .collect();
+    *DNS_SEARCH_DOMAINS.lock() = domains;
+}
+
+pub fn search_domains() -> Vec<String> {
+    DNS_SEARCH_DOMAINS.lock().clone()
+}
+
+// Bare labels try every search domain before the label itself, dotted names
+// are tried as given first, and a trailing dot disables the search.
+fn search_candidates(name: &str) -> Vec<String> {
+    if let Some(absolute) = name.strip_suffix('.') {
+        return vec![absolute.to_string()];
+    }
+
+    let domains = DNS_SEARCH_DOMAINS.lock();
+    let qualified = domains.iter().map(|d| format!("{}.{}", name, d));
+    let mut candidates = Vec::with_capacity(domains.len() + 1);
+    if name.contains('.') {
+        candidates.push(name.to_string());
+        candidates.extend(qualified);
+    } else {
+        candidates.extend(qualified);
+        candidates.push(name.to_string());
+    }
+    candidates
+}
+
+pub fn resolve(domain: &str) -> Result<IpAddr> {
+    resolve_with_search(domain)
+}
+
+pub fn resolve_with_search(name: &str) -> Result<IpAddr> {
+    resolve_with_search_server(name, default_server()).map(|(_, addr)| addr)
+}
+
+pub fn resolve_with_search_server(name: &str, server: IpEndpoint) -> Result<(String, IpAddr)> {
+    search(name, |candidate| resolve_with_server(candidate, server))
+}
+
+// Returns the first expansion of `name` with records of any of `qtypes`,
+// together with everything those queries answered. A type without records
+// does not stop the others from being asked.
+pub fn query_with_search_server(
+    name: &str,
+    qtypes: &[u16],
+    server: IpEndpoint,
+) -> Result<(String, Vec<DnsRecord>)> {
+    search(name, |candidate| {
+        let mut records = Vec::new();
+        for &qtype in qtypes {
+            match query_with_server(candidate, qtype, server) {
+                Ok(found) => records.extend(found),
+                Err(Error::NotFound) => {}
+                Err(err) => return Err(err),
+            }
+        }
+        if !records.iter().any(|r| qtypes.contains(&r.rtype())) {
+            return Err(Error::NotFound);
+        }
+        Ok(records)
+    })
+}
+
+// NotFound and NXDOMAIN move on to the next search candidate; any other
+// error ends the search.
+fn search<T>(name: &str, mut lookup: impl FnMut(&str) -> Result<T>) -> Result<(String, T)> {
+    let mut last_err = Error::NotFound;
+    for candidate in search_candidates(name) {
+        match lookup(&candidate) {
+            Ok(found) => return Ok((candidate, found)),
+            Err(err @ (Error::NotFound | Error::DnsNxDomain)) => last_err = err,
+            Err(err) => return Err(err),
+        }
+    }
+    Err(last_err)
+}
+
+pub fn resolve_with_server(domain: &str, server: IpEndpoint) -> Result<IpAddr> {
+    let response = exchange(server, domain, DNS_TYPE_A)?;
+    let addr = parse_dns_response(&response)?;
+    trace!(DNS, "[dns] Resolved {} to {}", domain, addr);
+    Ok(addr)
+}
+
+// Skips UDP and asks over TCP straight away.
+pub fn resolve_tcp(domain: &str) -> Result<IpAddr> {
+    let response = exchange_tcp(default_server(), domain, DNS_TYPE_A, next_query_id())?;
+    parse_dns_response(&response)
+}
+
+pub fn resolve_dual(domain: &str) -> Result<(Vec<IpAddr>, Vec<IpAddr6>)> {
+    resolve_dual_with_server(domain, default_server())
+}
+
+pub fn resolve_dual_with_server(
+    domain: &str,
+    server: IpEndpoint,
+) -> Result<(Vec<IpAddr>, Vec<IpAddr6>)> {
+    dual(|qtype| query_with_server(domain, qtype, server))
+}
+
+// The A and AAAA queries are sent one after the other. A family without
+// records is left empty; only when both are empty is the lookup NotFound.
+fn dual(
+    mut query: impl FnMut(u16) -> Result<Vec<DnsRecord>>,
+) -> Result<(Vec<IpAddr>, Vec<IpAddr6>)> {
+    let mut records = Vec::new();
+    for qtype in [DNS_TYPE_A, DNS_TYPE_AAAA] {
+        match query(qtype) {
+            Ok(found) => records.extend(found),
+            Err(Error::NotFound) => {}
+            Err(err) => return Err(err),
+        }
+    }
+    let (v4, v6) = split_addresses(records);
+    if v4.is_empty() && v6.is_empty() {
+        return Err(Error::NotFound);
+    }
+    Ok((v4, v6))
+}
+
+fn split_addresses(records: Vec<DnsRecord>) -> (Vec<IpAddr>, Vec<IpAddr6>) {
+    let mut v4 = Vec::new();
+    let mut v6 = Vec::new();
+    for record in records {
+        match record {
+            DnsRecord::A(addr) => v4.push(addr),
+            DnsRecord::Aaaa(addr) => v6.push(addr),
+            _ => {}
+        }
+    }
+    (v4, v6)
+}
+
+pub fn resolve_aaaa(domain: &str) -> Result<IpAddr6> {
+    resolve_aaaa_with_server(domain, default_server())
+}
+
+pub fn resolve_aaaa_with_server(domain: &str, server: IpEndpoint) -> Result<IpAddr6> {
+    let answer = query_with_server(domain, DNS_TYPE_AAAA, server);
+    let addr = aaaa_or_mapped(answer, || resolve_with_server(domain, server))?;
+    trace!(DNS, "[dns] Resolved {} to {}", domain, addr);
+    Ok(addr)
+}
+
+// Without an AAAA record the A record is returned as an IPv4-mapped
+// address.
+fn aaaa_or_mapped(
+    answer: Result<Vec<DnsRecord>>,
+    resolve_a: impl FnOnce() -> Result<IpAddr>,
+) -> Result<IpAddr6> {
+    let records = match answer {
+        Ok(records) => records,
+        Err(Error::NotFound) => Vec::new(),
+        Err(err) => return Err(err),
+    };
+    match first_aaaa(&records) {
+        Some(addr) => Ok(addr),
+        None => Ok(IpAddr6::from_ipv4_mapped(resolve_a()?)),
+    }
+}
+
+fn first_aaaa(records: &[DnsRecord]) -> Option<IpAddr6> {
+    records.iter().find_map(|r| match r {
+        DnsRecord::Aaaa(addr) => Some(*addr),
+        _ => None,
+    })
+}
+
+// Octets are reversed under in-addr.arpa, so 8.8.4.4 becomes
+// 4.4.8.8.in-addr.arpa.
+fn reverse_name(ip: IpAddr) -> String {
+    format!(
+        "{}.{}.{}.{}.in-addr.arpa",
+        ip.0 & 0xFF,
+        (ip.0 >> 8) & 0xFF,
+        (ip.0 >> 16) & 0xFF,
+        (ip.0 >> 24) & 0xFF
+    )
+}
+
+pub fn resolve_ptr(ip: IpAddr) -> Result<String> {
+    resolve_ptr_with_server(ip, default_server())
+}
+
+pub fn resolve_ptr_with_server(ip: IpAddr, server: IpEndpoint) -> Result<String> {
+    let records = query_with_server(&reverse_name(ip), DNS_TYPE_PTR, server)?;
+    let name = first_ptr(records).ok_or(Error::NotFound)?;
+    trace!(DNS, "[dns] Reverse lookup of {} gave {}", ip, name);
+    Ok(name)
+}
+
+fn first_ptr(records: Vec<DnsRecord>) -> Option<String> {
+    records.into_iter().find_map(|r| match r {
+        DnsRecord::Ptr(name) => Some(name),
+        _ => None,
+    })
+}
+
+pub fn query(domain: &str, qtype: u16) -> Result<Vec<DnsRecord>> {
+    query_with_server(domain, qtype, default_server())
+}
+
+pub fn query_with_server(domain: &str, qtype: u16, server: IpEndpoint) -> Result<Vec<DnsRecord>> {
+    let response = exchange(server, domain, qtype)?;
+    parse_dns_records(&response)
+}
+
 #[cfg(test)]
 mod tests {
-    use super::{encode_domain_name, parse_dns_response, wire, IpAddr};
+    use super::{
+        aaaa_or_mapped, decode_domain_name, dns_query_id_matches, dual, encode_domain_name,
+        encode_records, first_ptr, frame_tcp_message, is_truncated, next_query_id_from,
+        parse_dns_records, parse_dns_response, reverse_name, search, search_candidates, send_query,
+        set_search, tcp_message, wire, DnsRecord, IpAddr, IpAddr6, IpEndpoint, DNS_TYPE_A,
+        DNS_TYPE_AAAA, DNS_TYPE_MX, DNS_TYPE_PTR, DNS_TYPE_TXT,
+    };
     use crate::error::Error;
-    use alloc::vec;
+    use crate::net::device::tests::{attach_test_device, capture_transmit, last_captured_frame};
+    use crate::net::ethernet::{self, MacAddr};
+    use crate::net::{arp, udp};
+    use alloc::{string::String, vec, vec::Vec};
+
+    fn response_with_answer(name: &str, rtype: u16, rdata: &[u8]) -> Vec<u8> {
+        let mut data = vec![0u8; wire::HEADER_LEN];
+        {
+            let mut header = wire::HeaderMut::new_unchecked(&mut data);
+            header.set_id(0x1234);
+            header.set_flags(0x8180);
+            header.set_qdcount(1);
+            header.set_ancount(1);
+        }
+        encode_domain_name(name, &mut data);
+        data.extend_from_slice(&rtype.to_be_bytes());
+        data.extend_from_slice(&1u16.to_be_bytes());
+
+        data.extend_from_slice(&[0xC0, 0x0C]);
+        data.extend_from_slice(&rtype.to_be_bytes());
+        data.extend_from_slice(&1u16.to_be_bytes());
+        data.extend_from_slice(&60u32.to_be_bytes());
+        data.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+        data.extend_from_slice(rdata);
+        data
+    }
 
     #[test_case]
     fn header_too_short() {
@@ -370,6 +837,117 @@ mod tests {
         assert_eq!(err, Error::NotFound);
     }
 
+    #[test_case]
+    fn parse_combined_a_and_aaaa_answers() {
+        let v6 = [
+            0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x01,
+        ];
+        let mut data = response_with_answer("example.com", DNS_TYPE_A, &[192, 0, 2, 1]);
+        {
+            let mut header = wire::HeaderMut::new_unchecked(&mut data);
+            header.set_ancount(2);
+        }
+        data.extend_from_slice(&[0xC0, 0x0C]);
+        data.extend_from_slice(&DNS_TYPE_AAAA.to_be_bytes());
+        data.extend_from_slice(&1u16.to_be_bytes());
+        data.extend_from_slice(&60u32.to_be_bytes());
+        data.extend_from_slice(&(v6.len() as u16).to_be_bytes());
+        data.extend_from_slice(&v6);
+
+        let (addrs4, addrs6) = dual(|qtype| match qtype {
+            DNS_TYPE_A => parse_dns_records(&data),
+            _ => Err(Error::NotFound),
+        })
+        .unwrap();
+        assert_eq!(addrs4, vec![IpAddr::new(192, 0, 2, 1)]);
+        assert_eq!(addrs6, vec![IpAddr6(v6)]);
+
+        assert_eq!(dual(|_| Err(Error::NotFound)), Err(Error::NotFound));
+        assert_eq!(dual(|_| Err(Error::Timeout)), Err(Error::Timeout));
+    }
+
+    #[test_case]
+    fn aaaa_falls_back_to_mapped_a() {
+        let v6 = IpAddr6([
+            0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x01,
+        ]);
+        let v4 = IpAddr::new(192, 0, 2, 1);
+        let answer = vec![DnsRecord::A(v4), DnsRecord::Aaaa(v6)];
+        let found = aaaa_or_mapped(Ok(answer), || panic!("A record asked for"));
+        assert_eq!(found, Ok(v6));
+
+        let mapped = IpAddr6::from_ipv4_mapped(v4);
+        assert_eq!(aaaa_or_mapped(Ok(Vec::new()), || Ok(v4)), Ok(mapped));
+        assert_eq!(aaaa_or_mapped(Err(Error::NotFound), || Ok(v4)), Ok(mapped));
+        assert_eq!(
+            aaaa_or_mapped(Err(Error::NotFound), || Err(Error::DnsNxDomain)),
+            Err(Error::DnsNxDomain)
+        );
+        assert_eq!(
+            aaaa_or_mapped(Err(Error::Timeout), || Ok(v4)),
+            Err(Error::Timeout)
+        );
+    }
+
+    #[test_case]
+    fn search_domains_expand_bare_labels() {
+        set_search(&["local", ".corp."]);
+        assert_eq!(
+            search_candidates("mybox"),
+            vec!["mybox.local", "mybox.corp", "mybox"]
+        );
+        assert_eq!(
+            search_candidates("www.example.com"),
+            vec![
+                "www.example.com",
+                "www.example.com.local",
+                "www.example.com.corp"
+            ]
+        );
+        assert_eq!(search_candidates("mybox."), vec!["mybox"]);
+        set_search(&[]);
+        assert_eq!(search_candidates("mybox"), vec!["mybox"]);
+    }
+
+    #[test_case]
+    fn search_stops_at_first_answer() {
+        set_search(&["local", "corp"]);
+        let mut asked = Vec::new();
+        let found = search("mybox", |candidate| {
+            asked.push(String::from(candidate));
+            match candidate {
+                "mybox.local" => Err(Error::DnsNxDomain),
+                _ => Ok(candidate.len()),
+            }
+        });
+        assert_eq!(found, Ok((String::from("mybox.corp"), 10)));
+        assert_eq!(asked, vec!["mybox.local", "mybox.corp"]);
+
+        let failed = search("mybox", |_| Err::<(), _>(Error::Timeout));
+        assert_eq!(failed, Err(Error::Timeout));
+        set_search(&[]);
+    }
+
+    #[test_case]
+    fn rcode_maps_to_error() {
+        let cases = [
+            (1, Error::DnsFormErr),
+            (2, Error::DnsServFail),
+            (3, Error::DnsNxDomain),
+            (5, Error::DnsRefused),
+        ];
+        for (rcode, expected) in cases {
+            let mut data = response_with_answer("example.com", DNS_TYPE_A, &[192, 0, 2, 1]);
+            {
+                let mut header = wire::HeaderMut::new_unchecked(&mut data);
+                header.set_flags(0x8180 | rcode);
+                header.set_ancount(0);
+            }
+            let err = parse_dns_records(&data).unwrap_err();
+            assert_eq!(err, expected);
+        }
+    }
+
     #[test_case]
     fn parse_a_record_response() {
         let mut data = vec![0u8; wire::HEADER_LEN];
@@ -397,4 +975,145 @@ mod tests {
         let addr = parse_dns_response(&data).unwrap();
         assert_eq!(addr, IpAddr::new(1, 2, 3, 4));
     }
+
+    #[test_case]
+    fn query_id_mismatch_is_discarded() {
+        let mut data = vec![0u8; wire::HEADER_LEN];
+        {
+            let mut header = wire::HeaderMut::new_unchecked(&mut data);
+            header.set_id(0x4321);
+            header.set_flags(0x8180);
+        }
+        assert!(!dns_query_id_matches(&data, 0x1234));
+        assert!(dns_query_id_matches(&data, 0x4321));
+    }
+
+    #[test_case]
+    fn query_without_qr_is_discarded() {
+        let mut data = vec![0u8; wire::HEADER_LEN];
+        {
+            let mut header = wire::HeaderMut::new_unchecked(&mut data);
+            header.set_id(0x1234);
+            header.set_flags(0x0100);
+        }
+        assert!(!dns_query_id_matches(&data, 0x1234));
+        assert!(!dns_query_id_matches(&data[..4], 0x1234));
+    }
+
+    #[test_case]
+    fn decode_compressed_name() {
+        let mut data = vec![0u8; wire::HEADER_LEN];
+        encode_domain_name("example.com", &mut data);
+        data.extend_from_slice(&[3, b'w', b'w', b'w', 0xC0, 0x0C]);
+        let (name, next) = decode_domain_name(&data, wire::HEADER_LEN + 13).unwrap();
+        assert_eq!(name, "www.example.com");
+        assert_eq!(next, data.len());
+    }
+
+    #[test_case]
+    fn decode_pointer_loop_fails() {
+        let mut data = vec![0u8; wire::HEADER_LEN];
+        data.extend_from_slice(&[0xC0, 0x0C]);
+        let err = decode_domain_name(&data, wire::HEADER_LEN).unwrap_err();
+        assert_eq!(err, Error::InvalidLength);
+    }
+
+    #[test_case]
+    fn parse_ptr_record() {
+        let mut rdata = Vec::new();
+        encode_domain_name("dns.google", &mut rdata);
+        let data = response_with_answer("8.8.8.8.in-addr.arpa", DNS_TYPE_PTR, &rdata);
+        let records = parse_dns_records(&data).unwrap();
+        assert_eq!(records, vec![DnsRecord::Ptr(String::from("dns.google"))]);
+    }
+
+    #[test_case]
+    fn reverse_lookup_name_and_answer() {
+        let ip = IpAddr((192 << 24) | (2 << 8) | 10);
+        assert_eq!(reverse_name(ip), "10.2.0.192.in-addr.arpa");
+
+        let mut rdata = Vec::new();
+        encode_domain_name("host.example", &mut rdata);
+        let data = response_with_answer(&reverse_name(ip), DNS_TYPE_PTR, &rdata);
+        let records = parse_dns_records(&data).unwrap();
+        assert_eq!(first_ptr(records), Some(String::from("host.example")));
+        assert_eq!(first_ptr(vec![DnsRecord::A(ip)]), None);
+    }
+
+    #[test_case]
+    fn parse_mx_and_txt_records() {
+        let mut rdata = vec![0x00, 0x0a];
+        encode_domain_name("mx.example.com", &mut rdata);
+        let data = response_with_answer("example.com", DNS_TYPE_MX, &rdata);
+        let records = parse_dns_records(&data).unwrap();
+        assert_eq!(
+            records,
+            vec![DnsRecord::Mx {
+                preference: 10,
+                exchange: String::from("mx.example.com"),
+            }]
+        );
+
+        let data = response_with_answer("example.com", DNS_TYPE_TXT, b"\x05hello");
+        let records = parse_dns_records(&data).unwrap();
+        assert_eq!(records, vec![DnsRecord::Txt(b"\x05hello".to_vec())]);
+    }
+
+    #[test_case]
+    fn query_ids_vary() {
+        let mut state = 0;
+        let ids: Vec<u16> = (0..8).map(|_| next_query_id_from(&mut state, 5)).collect();
+        for (i, id) in ids.iter().enumerate() {
+            assert!(!ids[i + 1..].contains(id));
+        }
+        let mut other = 0;
+        assert_ne!(next_query_id_from(&mut other, 6), ids[0]);
+    }
+
+    #[test_case]
+    fn tcp_framing_and_truncation_flag() {
+        let mut response = response_with_answer("example.com", DNS_TYPE_A, &[192, 0, 2, 1]);
+        assert!(!is_truncated(&response));
+        response[2] |= 0x02;
+        assert!(is_truncated(&response));
+
+        let framed = frame_tcp_message(&response);
+        assert_eq!(&framed[..2], &(response.len() as u16).to_be_bytes());
+        assert_eq!(tcp_message(&framed[..1]), None);
+        assert_eq!(tcp_message(&framed[..framed.len() - 1]), None);
+        assert_eq!(tcp_message(&framed), Some(&response[..]));
+    }
+
+    #[test_case]
+    fn encode_records_respects_limit() {
+        let records = [
+            DnsRecord::A(IpAddr::new(1, 2, 3, 4)),
+            DnsRecord::A(IpAddr::new(5, 6, 7, 8)),
+        ];
+        let out = encode_records(&records, 12);
+        assert_eq!(out, vec![0, 1, 0, 4, 1, 2, 3, 4]);
+    }
+
+    #[test_case]
+    fn query_goes_to_configured_server() {
+        attach_test_device(
+            "dnstest0",
+            MacAddr([0x02, 0, 0, 0, 0, 0x53]),
+            IpAddr::new(10, 53, 0, 2),
+            capture_transmit,
+        );
+        let server = IpEndpoint::new(IpAddr::new(10, 53, 0, 53), 5353);
+        arp::insert(server.addr, MacAddr([0x02, 0, 0, 0, 0, 0x35]));
+
+        let sockfd = send_query(server, "example.com", DNS_TYPE_A, 0x4242).unwrap();
+        udp::socket_free(sockfd).unwrap();
+
+        let frame = last_captured_frame("dnstest0").unwrap();
+        let ip = &frame[ethernet::EthHeader::LEN..];
+        assert_eq!(&ip[16..20], &server.addr.to_bytes());
+        let udp_header = &ip[20..];
+        assert_eq!(&udp_header[2..4], &5353u16.to_be_bytes());
+        let query = &udp_header[8..];
+        assert_eq!(&query[wire::field::ID], &0x4242u16.to_be_bytes());
+    }
 }
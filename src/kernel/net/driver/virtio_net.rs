@@ -11,20 +11,25 @@ use crate::{
         },
         ethernet,
         ip::IpAddr,
-        request_poll,
+        protocol, request_poll,
     },
     println,
     spinlock::Mutex,
     trace,
 };
-use alloc::vec::Vec;
-use core::sync::atomic::{fence, Ordering};
+use alloc::collections::VecDeque;
+use core::{
+    cell::UnsafeCell,
+    sync::atomic::{fence, Ordering},
+};
 
 const VIRTIO_NET_F_MAC: u32 = 1 << 5;
 const VIRTIO_NET_F_STATUS: u32 = 1 << 16;
 const VIRTIO_NET_HDR_LEN: usize = 10;
 
 const NUM: usize = 32;
+const RX_POOL_SIZE: usize = 64;
+const RX_BUF_SIZE: usize = 2048;
 
 #[repr(usize)]
 enum Mmio {
@@ -106,24 +111,118 @@ struct VirtioNetHdr {
     csum_offset: u16,
 }
 
+/// One RX pool buffer, either posted to the device or holding a received
+/// frame until the stack has consumed it.
+#[derive(Debug, PartialEq, Eq)]
+pub struct PooledBuffer {
+    pool_idx: usize,
+    len: usize,
+}
+
+/// Fixed set of RX buffers the device receives into directly, so a frame is
+/// neither copied nor heap allocated on its way to the stack. Each buffer
+/// starts with the virtio-net header the device writes; the frame follows.
+/// A buffer index is owned exclusively by its `PooledBuffer` until it is
+/// released, so the payload can be read without the pool lock.
+struct RxPool<const N: usize> {
+    bufs: UnsafeCell<[[u8; RX_BUF_SIZE]; N]>,
+    free_list: Mutex<VecDeque<usize>>,
+    dropped: Mutex<usize>,
+}
+
+unsafe impl<const N: usize> Sync for RxPool<N> {}
+
+impl<const N: usize> RxPool<N> {
+    const fn new() -> Self {
+        Self {
+            bufs: UnsafeCell::new([[0u8; RX_BUF_SIZE]; N]),
+            free_list: Mutex::new(VecDeque::new(), "rx_pool"),
+            dropped: Mutex::new(0, "rx_pool_dropped"),
+        }
+    }
+
+    fn init(&self) {
+        let mut free_list = self.free_list.lock();
+        free_list.clear();
+        free_list.extend(0..N);
+    }
+
+    /// A free buffer to post in place of one that just received a frame.
+    /// Failing means the pool is exhausted and that frame is dropped instead;
+    /// buffers already handed out are never reclaimed.
+    fn alloc(&self) -> Option<PooledBuffer> {
+        let Some(pool_idx) = self.free_list.lock().pop_front() else {
+            *self.dropped.lock() += 1;
+            return None;
+        };
+        Some(PooledBuffer { pool_idx, len: 0 })
+    }
+
+    // Address and size the device writes header and frame to.
+    fn dma_region(&self, buf: &PooledBuffer) -> (u64, u32) {
+        let bytes = unsafe { &(&*self.bufs.get())[buf.pool_idx] };
+        (bytes.as_ptr() as u64, RX_BUF_SIZE as u32)
+    }
+
+    fn as_slice(&self, buf: &PooledBuffer) -> &[u8] {
+        let frame = VIRTIO_NET_HDR_LEN..VIRTIO_NET_HDR_LEN + buf.len;
+        unsafe { &(&*self.bufs.get())[buf.pool_idx][frame] }
+    }
+
+    fn release(&self, buf: PooledBuffer) {
+        self.free_list.lock().push_back(buf.pool_idx);
+    }
+
+    fn available(&self) -> usize {
+        self.free_list.lock().len()
+    }
+
+    fn dropped(&self) -> usize {
+        *self.dropped.lock()
+    }
+}
+
+// transmit only posts descriptors; the device is kicked once per flush so a
+// burst of small frames costs a single QueueNotify write.
+struct TxNotify {
+    pending: bool,
+}
+
+impl TxNotify {
+    const fn new() -> Self {
+        Self { pending: false }
+    }
+
+    fn post(&mut self) {
+        self.pending = true;
+    }
+
+    fn take(&mut self) -> bool {
+        core::mem::take(&mut self.pending)
+    }
+}
+
 pub struct VirtioNet {
     desc_rx: [VirtqDesc; NUM],
     avail_rx: VirtqAvail,
     used_rx: VirtqUsed,
     free_rx: [bool; NUM],
     used_idx_rx: u16,
+    // pool buffer posted in each RX descriptor
+    rx_slots: [Option<PooledBuffer>; NUM],
     desc_tx: [VirtqDesc; NUM],
     avail_tx: VirtqAvail,
     used_tx: VirtqUsed,
     free_tx: [bool; NUM],
     used_idx_tx: u16,
-    rx_bufs: [[u8; 2048]; NUM],
     tx_bufs: [[u8; 2048]; NUM],
     tx_hdr: VirtioNetHdr,
+    tx_notify: TxNotify,
     mac: [u8; 6],
 }
 
 static NET: Mutex<VirtioNet> = Mutex::new(VirtioNet::new(), "virtio_net");
+static RX_POOL: RxPool<RX_POOL_SIZE> = RxPool::new();
 
 impl VirtioNet {
     const fn new() -> Self {
@@ -147,6 +246,7 @@ impl VirtioNet {
             },
             free_rx: [true; NUM],
             used_idx_rx: 0,
+            rx_slots: [const { None }; NUM],
             desc_tx: [VirtqDesc {
                 addr: 0,
                 len: 0,
@@ -166,7 +266,6 @@ impl VirtioNet {
             },
             free_tx: [true; NUM],
             used_idx_tx: 0,
-            rx_bufs: [[0u8; 2048]; NUM],
             tx_bufs: [[0u8; 2048]; NUM],
             tx_hdr: VirtioNetHdr {
                 flags: 0,
@@ -176,6 +275,7 @@ impl VirtioNet {
                 csum_start: 0,
                 csum_offset: 0,
             },
+            tx_notify: TxNotify::new(),
             mac: [0; 6],
         }
     }
@@ -242,7 +342,8 @@ impl VirtioNet {
         }
 
         for i in 0..NUM {
-            self.alloc_rx_buf(i);
+            let buf = RX_POOL.alloc().ok_or(Error::NoBufferSpace)?;
+            self.post_rx_buf(i, buf);
         }
 
         status |= 0x4; // DRIVER_OK
@@ -269,10 +370,11 @@ impl VirtioNet {
         self.desc_tx[idx].next = 0;
     }
 
-    fn alloc_rx_buf(&mut self, slot: usize) {
-        let hdr_len = core::mem::size_of::<VirtioNetHdr>();
-        self.desc_rx[slot].addr = self.rx_bufs[slot].as_ptr() as u64;
-        self.desc_rx[slot].len = self.rx_bufs[slot].len() as u32;
+    fn post_rx_buf(&mut self, slot: usize, buf: PooledBuffer) {
+        let (addr, len) = RX_POOL.dma_region(&buf);
+        self.rx_slots[slot] = Some(buf);
+        self.desc_rx[slot].addr = addr;
+        self.desc_rx[slot].len = len;
         self.desc_rx[slot].flags = VIRTQ_DESC_F_WRITE;
         self.desc_rx[slot].next = 0;
         let ring_idx = (self.avail_rx.idx as usize) % NUM;
@@ -281,9 +383,6 @@ impl VirtioNet {
         self.avail_rx.idx = self.avail_rx.idx.wrapping_add(1);
         fence(Ordering::SeqCst);
         unsafe { Mmio::QueueNotify.write(0) };
-        for b in &mut self.rx_bufs[slot][..hdr_len] {
-            *b = 0;
-        }
     }
 
     fn transmit(&mut self, data: &[u8]) -> Result<()> {
@@ -309,34 +408,50 @@ impl VirtioNet {
         fence(Ordering::SeqCst);
         self.avail_tx.idx = self.avail_tx.idx.wrapping_add(1);
         fence(Ordering::SeqCst);
-        unsafe { Mmio::QueueNotify.write(1) };
+        self.tx_notify.post();
         Ok(())
     }
 
-    fn handle_used(&mut self) -> Result<Vec<Vec<u8>>> {
-        let mut packets = Vec::new();
+    fn flush_tx_notify(&mut self) {
+        if self.tx_notify.take() {
+            unsafe { Mmio::QueueNotify.write(1) };
+        }
+    }
+
+    // Takes the next received frame off the used ring. Its buffer goes to
+    // the caller and a fresh pool buffer is posted in its place; with the
+    // pool exhausted the frame is dropped and its buffer posted again.
+    fn next_rx(&mut self) -> Option<PooledBuffer> {
         while self.used_idx_rx != self.used_rx.idx {
             let used_elem = self.used_rx.ring[(self.used_idx_rx as usize) % NUM];
+            self.used_idx_rx = self.used_idx_rx.wrapping_add(1);
             let id = used_elem.id as usize;
-            if id >= NUM {
+            let Some(mut buf) = self.rx_slots.get_mut(id).and_then(Option::take) else {
                 trace!(DRIVER, "[virtio-net] invalid RX descriptor id: {}", id);
-                self.used_idx_rx = self.used_idx_rx.wrapping_add(1);
                 continue;
-            }
-            let hdr_len = core::mem::size_of::<VirtioNetHdr>();
+            };
             let total_len = used_elem.len as usize;
-            if total_len > hdr_len {
-                let data_len = total_len.saturating_sub(hdr_len);
-                let buf_len = self.rx_bufs[id].len();
-                if hdr_len + data_len <= buf_len {
-                    let mut buf = Vec::with_capacity(data_len);
-                    buf.extend_from_slice(&self.rx_bufs[id][hdr_len..hdr_len + data_len]);
-                    packets.push(buf);
-                }
+            if total_len <= VIRTIO_NET_HDR_LEN || total_len > RX_BUF_SIZE {
+                self.post_rx_buf(id, buf);
+                continue;
             }
-            self.alloc_rx_buf(id);
-            self.used_idx_rx = self.used_idx_rx.wrapping_add(1);
+            let Some(fresh) = RX_POOL.alloc() else {
+                trace!(
+                    DRIVER,
+                    "[virtio-net] RX pool exhausted, dropped {} frames",
+                    RX_POOL.dropped()
+                );
+                self.post_rx_buf(id, buf);
+                continue;
+            };
+            self.post_rx_buf(id, fresh);
+            buf.len = total_len - VIRTIO_NET_HDR_LEN;
+            return Some(buf);
         }
+        None
+    }
+
+    fn reap_tx(&mut self) {
         while self.used_idx_tx != self.used_tx.idx {
             let used_elem = self.used_tx.ring[(self.used_idx_tx as usize) % NUM];
             let id = used_elem.id as usize;
@@ -348,7 +463,6 @@ impl VirtioNet {
             self.free_desc_chain_tx(id);
             self.used_idx_tx = self.used_idx_tx.wrapping_add(1);
         }
-        Ok(packets)
     }
 
     fn free_desc_chain_tx(&mut self, mut idx: usize) {
@@ -366,6 +480,7 @@ impl VirtioNet {
 }
 
 pub fn init() -> Result<()> {
+    RX_POOL.init();
     let mut guard = NET.lock();
     guard.mmio_init()?;
 
@@ -417,21 +532,30 @@ fn transmit(_dev: &mut NetDevice, data: &[u8]) -> Result<()> {
     guard.transmit(data)
 }
 
+pub fn flush_tx_notify() {
+    NET.lock().flush_tx_notify();
+}
+
+// Frames are handed to the stack one at a time, without the driver lock
+// held, so a reply sent from the handler can take it for transmit.
 pub fn poll_rx() {
-    let mut guard = NET.lock();
-    if let Ok(pkts) = guard.handle_used() {
-        if !pkts.is_empty() {
-            trace!(
-                DRIVER,
-                "[virtio-net] poll_rx: received {} packets",
-                pkts.len()
-            );
-        }
-        drop(guard);
-        for p in pkts {
-            let dev = net_device_by_name("eth0").unwrap();
-            let _ = ethernet::ingress(&dev, p.as_slice());
-        }
+    NET.lock().reap_tx();
+    let dev = net_device_by_name("eth0").unwrap();
+    let mut received = 0;
+    loop {
+        let Some(p) = NET.lock().next_rx() else {
+            break;
+        };
+        let _ = protocol::net_ingress_handler(&dev, RX_POOL.as_slice(&p));
+        RX_POOL.release(p);
+        received += 1;
+    }
+    if received > 0 {
+        trace!(
+            DRIVER,
+            "[virtio-net] poll_rx: received {} packets",
+            received
+        );
     }
 }
 
@@ -440,3 +564,61 @@ pub fn intr() {
     unsafe { Mmio::InterruptAck.write(intr_stat & 0x3) };
     request_poll();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn tx_notify_coalesces_posts() {
+        let mut notify = TxNotify::new();
+        assert!(!notify.take());
+        notify.post();
+        notify.post();
+        assert!(notify.take());
+        assert!(!notify.take());
+    }
+
+    // Writes `frame` where the device would, after the virtio-net header.
+    fn receive(pool: &RxPool<4>, mut buf: PooledBuffer, frame: &[u8]) -> PooledBuffer {
+        let (addr, len) = pool.dma_region(&buf);
+        assert_eq!(len as usize, RX_BUF_SIZE);
+        let dst = (addr as usize + VIRTIO_NET_HDR_LEN) as *mut u8;
+        unsafe { core::ptr::copy_nonoverlapping(frame.as_ptr(), dst, frame.len()) };
+        buf.len = frame.len();
+        buf
+    }
+
+    #[test_case]
+    fn rx_pool_alloc_and_release() {
+        let pool: RxPool<4> = RxPool::new();
+        pool.init();
+        let buf = receive(&pool, pool.alloc().unwrap(), &[1, 2, 3]);
+        assert_eq!(pool.as_slice(&buf), &[1, 2, 3]);
+        assert_eq!(pool.available(), 3);
+        pool.release(buf);
+        assert_eq!(pool.available(), 4);
+    }
+
+    #[test_case]
+    fn rx_pool_exhaustion_drops_new_frames() {
+        let pool: RxPool<4> = RxPool::new();
+        pool.init();
+        let mut held = alloc::vec::Vec::new();
+        for i in 0..6u8 {
+            if let Some(buf) = pool.alloc() {
+                held.push(receive(&pool, buf, &[i]));
+            }
+        }
+        assert_eq!(held.len(), 4);
+        assert_eq!(pool.dropped(), 2);
+        assert_eq!(pool.as_slice(&held[0]), &[0]);
+        assert_eq!(pool.as_slice(&held[3]), &[3]);
+
+        let first = held.remove(0);
+        pool.release(first);
+        let buf = receive(&pool, pool.alloc().unwrap(), &[9]);
+        assert_eq!(pool.as_slice(&buf), &[9]);
+        assert_eq!(pool.as_slice(&held[0]), &[1]);
+    }
+}
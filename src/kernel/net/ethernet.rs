@@ -1,7 +1,7 @@
 extern crate alloc;
 use crate::error::{Error, Result};
 use crate::net::device::{NetDevice, NetDeviceFlags};
-use crate::net::protocol::{net_protocol_handler, ProtocolType};
+use crate::net::protocol::{net_eth_protocol_handler, net_protocol_handler, ProtocolType};
 use crate::trace;
 use core::fmt;
 
@@ -21,6 +21,25 @@ impl MacAddr {
     }
 }
 
+// 802.1Q tag control information: 3 bits of priority, the drop eligible
+// bit and a 12-bit VLAN id.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VlanTag(pub u16);
+
+impl VlanTag {
+    pub fn new(vlan_id: u16) -> Self {
+        VlanTag(vlan_id & 0x0fff)
+    }
+
+    pub fn vlan_id(&self) -> u16 {
+        self.0 & 0x0fff
+    }
+
+    pub fn priority(&self) -> u8 {
+        (self.0 >> 13) as u8
+    }
+}
+
 impl fmt::Display for MacAddr {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
@@ -41,9 +60,14 @@ mod wire {
         pub const DST: Field = 0..6;
         pub const SRC: Field = 6..12;
         pub const ETHERTYPE: Field = 12..14;
+        // present only when ETHERTYPE is 0x8100
+        pub const VLAN_TCI: Field = 14..16;
+        pub const VLAN_ETHERTYPE: Field = 16..18;
     }
 
     pub const HEADER_LEN: usize = field::ETHERTYPE.end;
+    pub const VLAN_HEADER_LEN: usize = field::VLAN_ETHERTYPE.end;
+    pub const TPID_VLAN: u16 = 0x8100;
 
     pub struct Frame<'a> {
         buffer: &'a [u8],
@@ -54,7 +78,28 @@ mod wire {
             if buffer.len() < HEADER_LEN {
                 return Err(Error::PacketTooShort);
             }
-            Ok(Self { buffer })
+            let frame = Self { buffer };
+            if frame.is_tagged() && buffer.len() < VLAN_HEADER_LEN {
+                return Err(Error::PacketTooShort);
+            }
+            Ok(frame)
+        }
+
+        fn is_tagged(&self) -> bool {
+            read_u16(&self.buffer[field::ETHERTYPE]) == TPID_VLAN
+        }
+
+        fn header_len(&self) -> usize {
+            if self.is_tagged() {
+                VLAN_HEADER_LEN
+            } else {
+                HEADER_LEN
+            }
+        }
+
+        pub fn vlan_tci(&self) -> Option<u16> {
+            self.is_tagged()
+                .then(|| read_u16(&self.buffer[field::VLAN_TCI]))
         }
 
         #[allow(dead_code)]
@@ -71,12 +116,17 @@ mod wire {
             src
         }
 
+        // the inner type for a tagged frame
         pub fn ethertype(&self) -> u16 {
-            read_u16(&self.buffer[field::ETHERTYPE])
+            if self.is_tagged() {
+                read_u16(&self.buffer[field::VLAN_ETHERTYPE])
+            } else {
+                read_u16(&self.buffer[field::ETHERTYPE])
+            }
         }
 
         pub fn payload(&self) -> &'a [u8] {
-            &self.buffer[HEADER_LEN..]
+            &self.buffer[self.header_len()..]
         }
     }
 
@@ -101,8 +151,21 @@ mod wire {
             write_u16(&mut self.buffer[field::ETHERTYPE], value);
         }
 
+        // Call after set_ethertype; the buffer must have room for the tag.
+        pub fn set_vlan(&mut self, tci: u16) {
+            let inner = read_u16(&self.buffer[field::ETHERTYPE]);
+            write_u16(&mut self.buffer[field::ETHERTYPE], TPID_VLAN);
+            write_u16(&mut self.buffer[field::VLAN_TCI], tci);
+            write_u16(&mut self.buffer[field::VLAN_ETHERTYPE], inner);
+        }
+
         pub fn payload_mut(&mut self) -> &mut [u8] {
-            &mut self.buffer[HEADER_LEN..]
+            let start = if read_u16(&self.buffer[field::ETHERTYPE]) == TPID_VLAN {
+                VLAN_HEADER_LEN
+            } else {
+                HEADER_LEN
+            };
+            &mut self.buffer[start..]
         }
     }
 }
@@ -121,46 +184,80 @@ impl EthHeader {
 
 pub const ETHERTYPE_ARP: u16 = 0x0806;
 pub const ETHERTYPE_IPV4: u16 = 0x0800;
+pub const ETHERTYPE_VLAN: u16 = wire::TPID_VLAN;
 
 pub fn ingress(dev: &NetDevice, data: &[u8]) -> Result<()> {
     let frame = wire::Frame::new_checked(data)?;
     let etype = frame.ethertype();
+    let vlan = frame.vlan_tci().map(VlanTag);
 
     trace!(
         ETHER,
-        "[ether] ingress: ethertype=0x{:04x}, len={}",
+        "[ether] ingress: ethertype=0x{:04x}, vlan={:?}, len={}",
         etype,
+        vlan.map(|t| t.vlan_id()),
         data.len()
     );
 
+    // Tagged frames are only taken for the device's own VLAN; the upper
+    // layers find the id through dev.vlan_id().
+    if let Some(tag) = vlan {
+        if dev.vlan_id() != Some(tag.vlan_id()) {
+            trace!(ETHER, "[ether] drop frame for vlan {}", tag.vlan_id());
+            return Err(Error::UnsupportedProtocol);
+        }
+    }
+
     let payload = frame.payload();
     match etype {
         ETHERTYPE_ARP => crate::net::arp::ingress(dev, payload),
         ETHERTYPE_IPV4 => net_protocol_handler(dev, ProtocolType::IP, payload),
-        _ => {
-            trace!(ETHER, "[ether] unsupported ethertype: 0x{:04x}", etype);
-            Err(Error::UnsupportedProtocol)
-        }
+        _ => match net_eth_protocol_handler(dev, etype, payload) {
+            Err(Error::ProtocolNotFound) => {
+                trace!(ETHER, "[ether] unsupported ethertype: 0x{:04x}", etype);
+                Err(Error::UnsupportedProtocol)
+            }
+            result => result,
+        },
     }
 }
 
 pub fn egress(dev: &mut NetDevice, dst_mac: MacAddr, ethertype: u16, payload: &[u8]) -> Result<()> {
+    let vlan_id = dev.vlan_id();
+    egress_vlan(dev, dst_mac, ethertype, payload, vlan_id)
+}
+
+pub fn egress_vlan(
+    dev: &mut NetDevice,
+    dst_mac: MacAddr,
+    ethertype: u16,
+    payload: &[u8],
+    vlan_id: Option<u16>,
+) -> Result<()> {
     if !dev.flags().contains(NetDeviceFlags::UP) {
         return Err(Error::NotConnected);
     }
-    let mut frame = alloc::vec![0u8; wire::HEADER_LEN + payload.len()];
+    let header_len = match vlan_id {
+        Some(_) => wire::VLAN_HEADER_LEN,
+        None => wire::HEADER_LEN,
+    };
+    let mut frame = alloc::vec![0u8; header_len + payload.len()];
     {
         let mut hdr = wire::FrameMut::new_unchecked(&mut frame);
         hdr.set_dst(dst_mac.0);
         hdr.set_src(dev.hw_addr.0);
         hdr.set_ethertype(ethertype);
+        if let Some(id) = vlan_id {
+            hdr.set_vlan(VlanTag::new(id).0);
+        }
         hdr.payload_mut().copy_from_slice(payload);
     }
     trace!(
         ETHER,
-        "[ether] egress: dst={:02x?} type=0x{:04x} len={}",
+        "[ether] egress: dst={:02x?} type=0x{:04x} vlan={:?} len={}",
         dst_mac.0,
         ethertype,
+        vlan_id,
         frame.len()
     );
     dev.transmit(&frame)
@@ -169,11 +266,12 @@ pub fn egress(dev: &mut NetDevice, dst_mac: MacAddr, ethertype: u16, payload: &[
 #[cfg(test)]
 mod tests {
     use super::wire;
-    use crate::error::{Error, Result};
-    use crate::net::device::{
-        NetDevice, NetDeviceConfig, NetDeviceFlags, NetDeviceOps, NetDeviceType,
+    use crate::error::Error;
+    use crate::net::device::tests::{
+        capture_transmit, dummy_device, last_captured_frame, test_config,
     };
-    use crate::net::ethernet::{ingress, MacAddr};
+    use crate::net::device::{NetDevice, NetDeviceFlags};
+    use crate::net::ethernet::{egress, ingress, MacAddr, ETHERTYPE_VLAN};
 
     #[test_case]
     fn frame_too_short() {
@@ -182,40 +280,46 @@ mod tests {
         assert_eq!(err, Error::PacketTooShort);
     }
 
-    fn ok_transmit(_dev: &mut NetDevice, _data: &[u8]) -> Result<()> {
-        Ok(())
-    }
-    fn ok_open(_dev: &mut NetDevice) -> Result<()> {
-        Ok(())
-    }
-    fn ok_close(_dev: &mut NetDevice) -> Result<()> {
-        Ok(())
-    }
-
-    fn dummy_dev() -> NetDevice {
-        NetDevice::new(NetDeviceConfig {
-            name: "dummy",
-            dev_type: NetDeviceType::Ethernet,
-            mtu: 1500,
-            flags: NetDeviceFlags::UP,
-            header_len: wire::HEADER_LEN as u16,
-            addr_len: 6,
-            hw_addr: MacAddr::ZERO,
-            ops: NetDeviceOps {
-                transmit: ok_transmit,
-                open: ok_open,
-                close: ok_close,
-            },
-        })
-    }
-
     #[test_case]
     fn ingress_unsupported_ethertype() {
-        let dev = dummy_dev();
+        let dev = dummy_device("dummy");
         let mut frame = [0u8; wire::HEADER_LEN];
         frame[12] = 0x12;
         frame[13] = 0x34;
         let err = ingress(&dev, &frame).unwrap_err();
         assert_eq!(err, Error::UnsupportedProtocol);
     }
+
+    #[test_case]
+    fn vlan_tag_inserted_and_stripped() {
+        let mut dev = NetDevice::new(test_config("vlan0", MacAddr::ZERO, capture_transmit));
+        assert_eq!(dev.set_vlan_id(Some(4095)), Err(Error::InvalidArgument));
+        dev.set_vlan_id(Some(42)).unwrap();
+        assert!(dev.flags().contains(NetDeviceFlags::VLAN));
+
+        egress(&mut dev, MacAddr::BROADCAST, 0x1234, &[0xaa, 0xbb]).unwrap();
+        let frame = last_captured_frame("vlan0").unwrap();
+        assert_eq!(&frame[12..14], &ETHERTYPE_VLAN.to_be_bytes());
+        assert_eq!(&frame[14..16], &42u16.to_be_bytes());
+        assert_eq!(&frame[16..18], &[0x12, 0x34]);
+        assert_eq!(&frame[18..], &[0xaa, 0xbb]);
+
+        let parsed = wire::Frame::new_checked(&frame).unwrap();
+        assert_eq!(parsed.vlan_tci(), Some(42));
+        assert_eq!(parsed.ethertype(), 0x1234);
+        assert_eq!(parsed.payload(), &[0xaa, 0xbb]);
+
+        // a frame tagged for another VLAN never reaches the handlers
+        let mut other = frame.clone();
+        other[15] = 43;
+        assert_eq!(ingress(&dev, &other), Err(Error::UnsupportedProtocol));
+        let truncated = &frame[..wire::HEADER_LEN + 2];
+        assert_eq!(
+            wire::Frame::new_checked(truncated).err(),
+            Some(Error::PacketTooShort)
+        );
+
+        dev.set_vlan_id(None).unwrap();
+        assert_eq!(dev.vlan_id(), None);
+    }
 }
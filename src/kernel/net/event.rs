@@ -0,0 +1,175 @@
+use crate::net::ip::IpAddr;
+use crate::net::tcp::State;
+use core::fmt;
+use core::sync::atomic::{AtomicU32, AtomicU8, AtomicUsize, Ordering};
+
+// Small ring of recent stack events, dumped by net_panic_dump(). Writers
+// never take a lock, so recording is safe from any context, and readers
+// only need the stack.
+pub const NET_EVENT_COUNT: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum EventKind {
+    TcpState = 1,
+    ArpInsert = 2,
+    IpSend = 3,
+}
+
+impl EventKind {
+    fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            1 => Some(EventKind::TcpState),
+            2 => Some(EventKind::ArpInsert),
+            3 => Some(EventKind::IpSend),
+            _ => None,
+        }
+    }
+}
+
+pub struct EventEntry {
+    // 0 while empty or being written, otherwise the event's index + 1
+    seq: AtomicUsize,
+    kind: AtomicU8,
+    a: AtomicU32,
+    b: AtomicU32,
+}
+
+impl EventEntry {
+    const fn new() -> Self {
+        Self {
+            seq: AtomicUsize::new(0),
+            kind: AtomicU8::new(0),
+            a: AtomicU32::new(0),
+            b: AtomicU32::new(0),
+        }
+    }
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY_EVENT: EventEntry = EventEntry::new();
+
+static NET_EVENTS: [EventEntry; NET_EVENT_COUNT] = [EMPTY_EVENT; NET_EVENT_COUNT];
+static NET_EVENT_NEXT: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NetEvent {
+    pub seq: usize,
+    pub kind: EventKind,
+    a: u32,
+    b: u32,
+}
+
+fn record(kind: EventKind, a: u32, b: u32) {
+    let index = NET_EVENT_NEXT.fetch_add(1, Ordering::Relaxed);
+    let entry = &NET_EVENTS[index % NET_EVENT_COUNT];
+    entry.seq.store(0, Ordering::Release);
+    entry.kind.store(kind as u8, Ordering::Relaxed);
+    entry.a.store(a, Ordering::Relaxed);
+    entry.b.store(b, Ordering::Relaxed);
+    entry.seq.store(index + 1, Ordering::Release);
+}
+
+pub fn record_tcp_state(local_port: u16, remote_port: u16, from: State, to: State) {
+    record(
+        EventKind::TcpState,
+        ((local_port as u32) << 16) | remote_port as u32,
+        ((from.as_u8() as u32) << 8) | to.as_u8() as u32,
+    );
+}
+
+pub fn record_arp_insert(ip: IpAddr) {
+    record(EventKind::ArpInsert, ip.0, 0);
+}
+
+pub fn record_ip_send(dst: IpAddr, protocol: u8, len: usize) {
+    record(
+        EventKind::IpSend,
+        dst.0,
+        ((protocol as u32) << 16) | (len.min(0xffff) as u32),
+    );
+}
+
+// Copies the most recent events, oldest first, into `out`. Slots that are
+// mid-write or already overwritten come back as None.
+pub fn recent(out: &mut [Option<NetEvent>; NET_EVENT_COUNT]) {
+    let next = NET_EVENT_NEXT.load(Ordering::Acquire);
+    let start = next.saturating_sub(NET_EVENT_COUNT);
+    for (slot, index) in out.iter_mut().zip(start..start + NET_EVENT_COUNT) {
+        *slot = None;
+        if index >= next {
+            continue;
+        }
+        let entry = &NET_EVENTS[index % NET_EVENT_COUNT];
+        if entry.seq.load(Ordering::Acquire) != index + 1 {
+            continue;
+        }
+        let kind = entry.kind.load(Ordering::Relaxed);
+        let a = entry.a.load(Ordering::Relaxed);
+        let b = entry.b.load(Ordering::Relaxed);
+        if entry.seq.load(Ordering::Acquire) != index + 1 {
+            continue;
+        }
+        if let Some(kind) = EventKind::from_u8(kind) {
+            *slot = Some(NetEvent {
+                seq: index,
+                kind,
+                a,
+                b,
+            });
+        }
+    }
+}
+
+impl fmt::Display for NetEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            EventKind::TcpState => {
+                let from = State::from_u8((self.b >> 8) as u8);
+                let to = State::from_u8(self.b as u8);
+                write!(f, "#{} tcp {}:{} ", self.seq, self.a >> 16, self.a & 0xffff)?;
+                match (from, to) {
+                    (Some(from), Some(to)) => write!(f, "{} -> {}", from, to),
+                    _ => write!(f, "state {:#06x}", self.b),
+                }
+            }
+            EventKind::ArpInsert => {
+                write!(f, "#{} arp insert {}", self.seq, IpAddr(self.a))
+            }
+            EventKind::IpSend => write!(
+                f,
+                "#{} ip send {} proto {} len {}",
+                self.seq,
+                IpAddr(self.a),
+                self.b >> 16,
+                self.b & 0xffff
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{recent, record_ip_send, EventKind, NET_EVENT_COUNT};
+    use crate::net::ip::IpAddr;
+
+    #[test_case]
+    fn ring_keeps_latest_events_in_order() {
+        let dst = IpAddr::new(192, 0, 2, 1);
+        for len in 0..NET_EVENT_COUNT + 3 {
+            record_ip_send(dst, 17, len);
+        }
+
+        let mut events = [None; NET_EVENT_COUNT];
+        recent(&mut events);
+        let mut prev = None;
+        for ev in events.iter().map(|ev| ev.unwrap()) {
+            assert_eq!(ev.kind, EventKind::IpSend);
+            assert!(prev.is_none_or(|p| p < ev.seq));
+            prev = Some(ev.seq);
+        }
+        let last = events[NET_EVENT_COUNT - 1].unwrap();
+        assert_eq!(last.b & 0xffff, (NET_EVENT_COUNT + 2) as u32);
+        assert_eq!(events[0].unwrap().b & 0xffff, 3);
+    }
+}
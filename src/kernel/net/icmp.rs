@@ -1,14 +1,17 @@
 use super::{
-    ip::{egress_route, IpAddr, IpHeader},
+    ip::{self, egress_route, egress_route_ttl, IpAddr, IpHeader},
+    tcp::timer::get_time_cycles,
     util::{checksum, verify_checksum, write_u16},
 };
 use crate::{
     error::{Error, Result},
-    net::{socket::SocketHandle, socket::SocketSet},
+    net::{flush_tx, socket::SocketHandle, socket::SocketSet},
     spinlock::Mutex,
+    stat::IcmpEchoStats,
     trace,
 };
 use alloc::{collections::VecDeque, vec, vec::Vec};
+use core::cmp;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
@@ -105,15 +108,72 @@ mod wire {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IcmpReplyKind {
+    EchoReply,
+    TimeExceeded,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct IcmpReply {
+    kind: IcmpReplyKind,
+    id: u16,
+    seq: u16,
+}
+
+impl IcmpReply {
+    fn parse(data: &[u8]) -> Option<Self> {
+        let echo = wire::Echo::new_checked(data).ok()?;
+        match echo.msg_type() {
+            t if t == IcmpType::EchoReply as u8 => Some(Self {
+                kind: IcmpReplyKind::EchoReply,
+                id: echo.id(),
+                seq: echo.seq(),
+            }),
+            t if t == IcmpType::TimeExceeded as u8 => {
+                let (id, seq) = Self::quoted_echo(&data[wire::ECHO_HEADER_LEN..])?;
+                Some(Self {
+                    kind: IcmpReplyKind::TimeExceeded,
+                    id,
+                    seq,
+                })
+            }
+            _ => None,
+        }
+    }
+
+    // Time Exceeded quotes the original IP header plus the first 8 bytes of
+    // its payload, which for our probes is the echo request header.
+    fn quoted_echo(inner: &[u8]) -> Option<(u16, u16)> {
+        let ip = ip::wire::Packet::new_checked(inner).ok()?;
+        let header_len = ip.header_len();
+        if ip.version() != 4
+            || ip.protocol() != IpHeader::ICMP
+            || header_len < ip::wire::MIN_HEADER_LEN
+            || header_len > inner.len()
+        {
+            return None;
+        }
+        let echo = wire::Echo::new_checked(&inner[header_len..]).ok()?;
+        if echo.msg_type() != IcmpType::EchoRequest as u8 {
+            return None;
+        }
+        Some((echo.id(), echo.seq()))
+    }
+}
+
 #[derive(Debug, Clone)]
 struct RawPacket {
     src: IpAddr,
+    ttl: u8,
+    reply: Option<IcmpReply>,
     data: Vec<u8>,
 }
 
 pub struct RawSocket {
     protocol: u8,
     recv_queue: VecDeque<RawPacket>,
+    echo: EchoTracker,
 }
 
 impl RawSocket {
@@ -121,10 +181,104 @@ impl RawSocket {
         Self {
             protocol,
             recv_queue: VecDeque::new(),
+            echo: EchoTracker::new(),
         }
     }
 }
 
+// Echo requests a socket sent and the round trips of the answered ones.
+// Raw ICMP sockets keep one too, so ping's summary comes from here.
+pub(crate) struct EchoTracker {
+    stats: IcmpEchoStats,
+    // (id, seq, sent at in timebase cycles) of echo requests still waiting
+    // for a reply
+    outstanding: VecDeque<(u16, u16, u64)>,
+}
+
+impl EchoTracker {
+    const MAX_OUTSTANDING: usize = 16;
+
+    pub(crate) const fn new() -> Self {
+        Self {
+            stats: IcmpEchoStats {
+                sent: 0,
+                received: 0,
+                min_rtt_us: 0,
+                max_rtt_us: 0,
+                sum_rtt_us: 0,
+            },
+            outstanding: VecDeque::new(),
+        }
+    }
+
+    pub(crate) fn stats(&self) -> IcmpEchoStats {
+        self.stats
+    }
+
+    pub(crate) fn is_waiting(&self) -> bool {
+        !self.outstanding.is_empty()
+    }
+
+    pub(crate) fn sent(&mut self, id: u16, seq: u16, now: u64) {
+        self.stats.sent += 1;
+        if self.outstanding.len() == Self::MAX_OUTSTANDING {
+            self.outstanding.pop_front();
+        }
+        self.outstanding.push_back((id, seq, now));
+    }
+
+    // The request never left; forget it again.
+    pub(crate) fn unsent(&mut self, id: u16, seq: u16) {
+        if let Some(pos) = self
+            .outstanding
+            .iter()
+            .rposition(|&(i, s, _)| i == id && s == seq)
+        {
+            self.outstanding.remove(pos);
+            self.stats.sent -= 1;
+        }
+    }
+
+    pub(crate) fn replied(&mut self, id: u16, seq: u16, now: u64) {
+        let Some(pos) = self
+            .outstanding
+            .iter()
+            .position(|&(i, s, _)| i == id && s == seq)
+        else {
+            return;
+        };
+        let (_, _, sent) = self.outstanding.remove(pos).unwrap();
+        let rtt = cycles_to_us(now.saturating_sub(sent));
+        let stats = &mut self.stats;
+        if stats.received == 0 || rtt < stats.min_rtt_us {
+            stats.min_rtt_us = rtt;
+        }
+        stats.max_rtt_us = stats.max_rtt_us.max(rtt);
+        stats.sum_rtt_us += rtt;
+        stats.received += 1;
+    }
+}
+
+// (id, seq) when `data` is an ICMP message of type `msg_type`
+fn echo_id_seq(data: &[u8], msg_type: IcmpType) -> Option<(u16, u16)> {
+    wire::Echo::new_checked(data)
+        .ok()
+        .filter(|echo| echo.msg_type() == msg_type as u8)
+        .map(|echo| (echo.id(), echo.seq()))
+}
+
+pub(crate) fn echo_request_id_seq(data: &[u8]) -> Option<(u16, u16)> {
+    echo_id_seq(data, IcmpType::EchoRequest)
+}
+
+pub(crate) fn echo_reply_id_seq(data: &[u8]) -> Option<(u16, u16)> {
+    echo_id_seq(data, IcmpType::EchoReply)
+}
+
+fn cycles_to_us(cycles: u64) -> u64 {
+    (cycles as u128 * 1_000_000 / crate::param::TIMEBASE_FREQ as u128) as u64
+}
+
 struct Icmp {
     sockets: Mutex<SocketSet<RawSocket>>,
 }
@@ -157,7 +311,7 @@ impl Icmp {
         }
     }
 
-    fn socket_sendto(&self, index: usize, dst: IpAddr, data: &[u8]) -> Result<usize> {
+    fn socket_sendto(&self, index: usize, dst: IpAddr, data: &[u8], ttl: u8) -> Result<usize> {
         let sockets = self.sockets.lock();
         let socket = sockets.get(SocketHandle::new(index))?;
         let protocol = socket.protocol;
@@ -174,28 +328,65 @@ impl Icmp {
 
         trace!(
             ICMP,
-            "[icmp] sending raw: {} bytes -> {:?}",
+            "[icmp] sending raw: {} bytes -> {:?} ttl={}",
             packet.len(),
-            dst.to_bytes()
+            dst.to_bytes(),
+            ttl
         );
 
-        egress_route(dst, protocol, &packet)?;
+        // Loopback answers from inside egress, so the request has to be on
+        // record before it goes out.
+        let echo = echo_request_id_seq(&packet);
+        if let Some((id, seq)) = echo {
+            let mut sockets = self.sockets.lock();
+            sockets
+                .get_mut(SocketHandle::new(index))?
+                .echo
+                .sent(id, seq, get_time_cycles());
+        }
+
+        let result = egress_route_ttl(dst, protocol, &packet, ttl);
+        flush_tx();
+        if let (Err(_), Some((id, seq))) = (&result, echo) {
+            let mut sockets = self.sockets.lock();
+            if let Ok(socket) = sockets.get_mut(SocketHandle::new(index)) {
+                socket.echo.unsent(id, seq);
+            }
+        }
+        result?;
         Ok(packet.len())
     }
 
-    fn socket_recvfrom(&self, index: usize, buf: &mut [u8]) -> Result<(usize, IpAddr)> {
+    fn echo_stats(&self, index: usize) -> Result<IcmpEchoStats> {
+        let sockets = self.sockets.lock();
+        let socket = sockets.get(SocketHandle::new(index))?;
+        Ok(socket.echo.stats())
+    }
+
+    fn socket_recvfrom(&self, index: usize, buf: &mut [u8]) -> Result<(usize, IpAddr, u8)> {
         let mut sockets = self.sockets.lock();
         let socket = sockets.get_mut(SocketHandle::new(index))?;
         let Some(packet) = socket.recv_queue.pop_front() else {
             return Err(Error::WouldBlock);
         };
 
+        if let Some(reply) = packet.reply {
+            trace!(
+                ICMP,
+                "[icmp] recv {:?} from {:?}, id={}, seq={}",
+                reply.kind,
+                packet.src.to_bytes(),
+                reply.id,
+                reply.seq
+            );
+        }
+
         let len = packet.data.len().min(buf.len());
         buf[..len].copy_from_slice(&packet.data[..len]);
-        Ok((len, packet.src))
+        Ok((len, packet.src, packet.ttl))
     }
 
-    fn ingress(&self, src: IpAddr, dst: IpAddr, data: &[u8]) -> Result<()> {
+    fn ingress(&self, src: IpAddr, dst: IpAddr, ttl: u8, data: &[u8]) -> Result<()> {
         if !verify_checksum(data) {
             return Err(Error::ChecksumError);
         }
@@ -208,15 +399,41 @@ impl Icmp {
             self.echo_reply(dst, src, id, seq, payload)?;
         }
 
-        self.enqueue_to_all(src, data);
+        match IcmpReply::parse(data) {
+            Some(reply) => self.notify_reply(src, ttl, reply, data),
+            None => self.enqueue_to_all(src, ttl, None, data),
+        }
         Ok(())
     }
 
-    fn enqueue_to_all(&self, src: IpAddr, data: &[u8]) {
+    fn notify_reply(&self, src: IpAddr, ttl: u8, reply: IcmpReply, data: &[u8]) {
+        trace!(
+            ICMP,
+            "[icmp] {:?} from {:?}, id={}, seq={}",
+            reply.kind,
+            src.to_bytes(),
+            reply.id,
+            reply.seq
+        );
+        self.enqueue_to_all(src, ttl, Some(reply), data);
+    }
+
+    fn enqueue_to_all(&self, src: IpAddr, ttl: u8, reply: Option<IcmpReply>, data: &[u8]) {
+        let echo_reply = reply.filter(|r| r.kind == IcmpReplyKind::EchoReply);
+        let mut now = None;
         let mut sockets = self.sockets.lock();
         for (_, socket) in sockets.iter_mut() {
+            if let Some(r) = echo_reply {
+                if socket.echo.is_waiting() {
+                    socket
+                        .echo
+                        .replied(r.id, r.seq, *now.get_or_insert_with(get_time_cycles));
+                }
+            }
             socket.recv_queue.push_back(RawPacket {
                 src,
+                ttl,
+                reply,
                 data: data.to_vec(),
             });
         }
@@ -267,23 +484,94 @@ pub fn socket_free(index: usize) -> Result<()> {
     ICMP.socket_free(index)
 }
 
-pub fn socket_sendto(index: usize, dst: IpAddr, data: &[u8]) -> Result<usize> {
-    ICMP.socket_sendto(index, dst, data)
+pub fn socket_sendto(index: usize, dst: IpAddr, data: &[u8], ttl: u8) -> Result<usize> {
+    ICMP.socket_sendto(index, dst, data, ttl)
 }
 
-pub fn socket_recvfrom(index: usize, buf: &mut [u8]) -> Result<(usize, IpAddr)> {
+pub fn socket_recvfrom(index: usize, buf: &mut [u8]) -> Result<(usize, IpAddr, u8)> {
     ICMP.socket_recvfrom(index, buf)
 }
 
-pub fn ingress(src: IpAddr, dst: IpAddr, data: &[u8]) -> Result<()> {
-    ICMP.ingress(src, dst, data)
+pub fn echo_stats(index: usize) -> Result<IcmpEchoStats> {
+    ICMP.echo_stats(index)
+}
+
+pub fn ingress(src: IpAddr, dst: IpAddr, ttl: u8, data: &[u8]) -> Result<()> {
+    ICMP.ingress(src, dst, ttl, data)
+}
+
+// Time Exceeded: TTL exceeded in transit
+pub const CODE_TTL_EXCEEDED: u8 = 0;
+// Destination Unreachable: no transport listener on the port
+pub const CODE_PORT_UNREACHABLE: u8 = 3;
+
+// Quotes the original header and the first 8 bytes of its payload. Per
+// RFC 1122 3.2.2 nothing is sent about ICMP errors, non-initial fragments
+// or non-unicast sources.
+fn error_message(kind: IcmpType, code: u8, original: &[u8]) -> Option<Vec<u8>> {
+    let ip = ip::wire::Packet::new_checked(original).ok()?;
+    let header_len = ip.header_len();
+    if header_len < ip::wire::MIN_HEADER_LEN || header_len > original.len() {
+        return None;
+    }
+    let src = IpAddr(ip.src());
+    if src.0 == 0 || src.0 == 0xFFFF_FFFF || src.is_multicast() {
+        return None;
+    }
+    let (_, _, offset) = ip::ip_header_flags(ip.flags_offset());
+    if offset != 0 {
+        return None;
+    }
+    if ip.protocol() == IpHeader::ICMP {
+        let kind = original.get(header_len).copied();
+        if kind != Some(IcmpType::EchoRequest as u8) && kind != Some(IcmpType::EchoReply as u8) {
+            return None;
+        }
+    }
+
+    let quoted = &original[..cmp::min(original.len(), header_len + 8)];
+    let mut packet = vec![0u8; wire::ECHO_HEADER_LEN + quoted.len()];
+    packet[0] = kind as u8;
+    packet[1] = code;
+    packet[wire::ECHO_HEADER_LEN..].copy_from_slice(quoted);
+    let csum = checksum(&packet);
+    write_u16(&mut packet[2..4], csum);
+    Some(packet)
+}
+
+pub fn send_time_exceeded(src: IpAddr, original: &[u8]) -> Result<()> {
+    let Some(packet) = error_message(IcmpType::TimeExceeded, CODE_TTL_EXCEEDED, original) else {
+        return Ok(());
+    };
+    trace!(ICMP, "[icmp] Sending Time Exceeded to {:?}", src.to_bytes());
+    egress_route(src, IpHeader::ICMP, &packet)
+}
+
+pub fn send_port_unreachable(src: IpAddr, original: &[u8]) -> Result<()> {
+    let Some(packet) = error_message(
+        IcmpType::DestinationUnreachable,
+        CODE_PORT_UNREACHABLE,
+        original,
+    ) else {
+        return Ok(());
+    };
+    trace!(
+        ICMP,
+        "[icmp] Sending Port Unreachable to {:?}",
+        src.to_bytes()
+    );
+    egress_route(src, IpHeader::ICMP, &packet)
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{wire, Icmp, IpAddr, RawPacket, SocketHandle};
+    use super::{
+        error_message, wire, EchoTracker, Icmp, IcmpReply, IcmpReplyKind, IcmpType, IpAddr,
+        RawPacket, SocketHandle, CODE_PORT_UNREACHABLE,
+    };
     use crate::error::Error;
-    use alloc::vec;
+    use crate::net::util::{checksum, verify_checksum, write_u16};
+    use alloc::{vec, vec::Vec};
 
     #[test_case]
     fn echo_too_short() {
@@ -320,14 +608,145 @@ mod tests {
             let socket = sockets.get_mut(SocketHandle::new(idx)).unwrap();
             socket.recv_queue.push_back(RawPacket {
                 src,
+                ttl: 57,
+                reply: None,
                 data: vec![1, 2, 3, 4],
             });
         }
 
         let mut buf = [0u8; 8];
-        let (len, recv_src) = icmp.socket_recvfrom(idx, &mut buf).unwrap();
+        let (len, recv_src, ttl) = icmp.socket_recvfrom(idx, &mut buf).unwrap();
         assert_eq!(len, 4);
         assert_eq!(recv_src, src);
+        assert_eq!(ttl, 57);
         assert_eq!(&buf[..len], &[1, 2, 3, 4]);
     }
+
+    fn time_exceeded(id: u16, seq: u16) -> Vec<u8> {
+        let mut packet = vec![0u8; wire::ECHO_HEADER_LEN];
+        packet[0] = IcmpType::TimeExceeded as u8;
+
+        let mut inner_ip = [0u8; 20];
+        inner_ip[0] = 0x45;
+        inner_ip[9] = 1;
+        inner_ip[12..16].copy_from_slice(&[10, 0, 0, 1]);
+        inner_ip[16..20].copy_from_slice(&[198, 51, 100, 7]);
+        packet.extend_from_slice(&inner_ip);
+
+        let mut inner_echo = [0u8; wire::ECHO_HEADER_LEN];
+        inner_echo[0] = IcmpType::EchoRequest as u8;
+        inner_echo[4..6].copy_from_slice(&id.to_be_bytes());
+        inner_echo[6..8].copy_from_slice(&seq.to_be_bytes());
+        packet.extend_from_slice(&inner_echo);
+
+        let csum = checksum(&packet);
+        write_u16(&mut packet[wire::field::CHECKSUM], csum);
+        packet
+    }
+
+    #[test_case]
+    fn time_exceeded_recovers_echo_id_seq() {
+        let icmp = Icmp::new();
+        let idx = icmp.socket_alloc().unwrap();
+        let router = IpAddr::new(192, 0, 2, 254);
+        let data = time_exceeded(0x1234, 7);
+
+        icmp.ingress(router, IpAddr::new(10, 0, 0, 1), 63, &data)
+            .unwrap();
+
+        let sockets = icmp.sockets.lock();
+        let socket = sockets.get(SocketHandle::new(idx)).unwrap();
+        let packet = socket.recv_queue.front().unwrap();
+        assert_eq!(packet.src, router);
+        assert_eq!(
+            packet.reply,
+            Some(IcmpReply {
+                kind: IcmpReplyKind::TimeExceeded,
+                id: 0x1234,
+                seq: 7,
+            })
+        );
+        assert_eq!(packet.data, data);
+    }
+
+    fn udp_datagram(flags_offset: u16) -> Vec<u8> {
+        let mut packet = vec![0u8; 20 + 24];
+        packet[0] = 0x45;
+        packet[2..4].copy_from_slice(&44u16.to_be_bytes());
+        packet[6..8].copy_from_slice(&flags_offset.to_be_bytes());
+        packet[8] = 1;
+        packet[9] = 17;
+        packet[12..16].copy_from_slice(&[10, 0, 0, 1]);
+        packet[16..20].copy_from_slice(&[198, 51, 100, 7]);
+        for (i, byte) in packet[20..].iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+        packet
+    }
+
+    #[test_case]
+    fn time_exceeded_quotes_header_and_8_bytes() {
+        let original = udp_datagram(0);
+        let msg = error_message(IcmpType::TimeExceeded, 0, &original).unwrap();
+        assert_eq!(msg.len(), wire::ECHO_HEADER_LEN + 28);
+        assert_eq!((msg[0], msg[1]), (IcmpType::TimeExceeded as u8, 0));
+        assert_eq!(&msg[4..8], &[0; 4]);
+        assert_eq!(&msg[8..], &original[..28]);
+        assert!(verify_checksum(&msg));
+
+        // no errors about non-initial fragments or about ICMP errors
+        assert_eq!(
+            error_message(IcmpType::TimeExceeded, 0, &udp_datagram(0x2000 | 3)),
+            None
+        );
+        let mut error = udp_datagram(0);
+        error[9] = 1;
+        error[20] = IcmpType::TimeExceeded as u8;
+        assert_eq!(error_message(IcmpType::TimeExceeded, 0, &error), None);
+        error[20] = IcmpType::EchoRequest as u8;
+        assert!(error_message(IcmpType::TimeExceeded, 0, &error).is_some());
+    }
+
+    #[test_case]
+    fn port_unreachable_quotes_udp_header() {
+        let original = udp_datagram(0);
+        let msg = error_message(
+            IcmpType::DestinationUnreachable,
+            CODE_PORT_UNREACHABLE,
+            &original,
+        )
+        .unwrap();
+        assert_eq!((msg[0], msg[1]), (3, 3));
+        assert_eq!(&msg[8..], &original[..28]);
+        assert!(verify_checksum(&msg));
+
+        let mut broadcast = udp_datagram(0);
+        broadcast[12..16].copy_from_slice(&[255; 4]);
+        assert_eq!(
+            error_message(IcmpType::DestinationUnreachable, 3, &broadcast),
+            None
+        );
+    }
+
+    #[test_case]
+    fn echo_stats_track_round_trips() {
+        let mut echo = EchoTracker::new();
+        let us = |us: u64| us * crate::param::TIMEBASE_FREQ as u64 / 1_000_000;
+        echo.sent(7, 0, us(1_000));
+        echo.sent(7, 1, us(2_000));
+        echo.sent(7, 2, us(3_000));
+        echo.sent(7, 3, us(3_500));
+        echo.unsent(7, 3);
+        echo.replied(7, 1, us(2_500));
+        echo.replied(7, 0, us(4_000));
+        // duplicates and replies to someone else's probes are ignored
+        echo.replied(7, 0, us(5_000));
+        echo.replied(8, 2, us(5_000));
+
+        let stats = echo.stats();
+        assert_eq!((stats.sent, stats.received), (3, 2));
+        assert_eq!((stats.min_rtt_us, stats.max_rtt_us), (500, 3_000));
+        assert_eq!(stats.sum_rtt_us, 3_500);
+        assert_eq!(echo.outstanding.len(), 1);
+    }
 }
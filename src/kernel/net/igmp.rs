@@ -0,0 +1,57 @@
+use super::{
+    ip::{egress_multicast, IpAddr, IpHeader},
+    util::{checksum, write_u16},
+};
+use crate::{error::Result, trace};
+
+pub const IGMP_PROTOCOL: u8 = IpHeader::IGMP;
+
+// IGMPv2 (RFC 2236). Only the host side of joining and leaving is
+// implemented; queries are not answered.
+const MEMBERSHIP_REPORT_V2: u8 = 0x16;
+const LEAVE_GROUP: u8 = 0x17;
+const MESSAGE_LEN: usize = 8;
+// RFC 2236 2: IGMP messages are never forwarded
+const IGMP_TTL: u8 = 1;
+const ALL_ROUTERS: IpAddr = IpAddr(0xE000_0002);
+
+fn message(kind: u8, group: IpAddr) -> [u8; MESSAGE_LEN] {
+    let mut msg = [0u8; MESSAGE_LEN];
+    msg[0] = kind;
+    msg[4..8].copy_from_slice(&group.to_bytes());
+    let csum = checksum(&msg);
+    write_u16(&mut msg[2..4], csum);
+    msg
+}
+
+pub fn send_report(iface: &str, group: IpAddr) -> Result<()> {
+    trace!(IP, "[igmp] report {:?} on {}", group.to_bytes(), iface);
+    let msg = message(MEMBERSHIP_REPORT_V2, group);
+    egress_multicast(iface, group, IGMP_PROTOCOL, &msg, IGMP_TTL)
+}
+
+pub fn send_leave(iface: &str, group: IpAddr) -> Result<()> {
+    trace!(IP, "[igmp] leave {:?} on {}", group.to_bytes(), iface);
+    let msg = message(LEAVE_GROUP, group);
+    egress_multicast(iface, ALL_ROUTERS, IGMP_PROTOCOL, &msg, IGMP_TTL)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{message, IpAddr, LEAVE_GROUP, MEMBERSHIP_REPORT_V2};
+    use crate::net::util::verify_checksum;
+
+    #[test_case]
+    fn messages_carry_group_and_checksum() {
+        let group = IpAddr::new(239, 1, 2, 3);
+        let report = message(MEMBERSHIP_REPORT_V2, group);
+        assert_eq!(report[0], 0x16);
+        assert_eq!(report[1], 0);
+        assert_eq!(&report[4..], &[239, 1, 2, 3]);
+        assert!(verify_checksum(&report));
+
+        let leave = message(LEAVE_GROUP, group);
+        assert_eq!(leave[0], 0x17);
+        assert!(verify_checksum(&leave));
+    }
+}
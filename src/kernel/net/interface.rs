@@ -1,6 +1,6 @@
 use super::ip::IpAddr;
 use crate::error::Result;
-use crate::net::device::net_device_with_mut;
+use crate::net::{arp, device::net_device_with_mut};
 
 #[derive(Debug, Clone)]
 pub struct NetInterface {
@@ -26,7 +26,10 @@ pub fn net_interface_setup(dev_name: &str, addr: IpAddr, netmask: IpAddr) -> Res
     net_device_with_mut(dev_name, |dev| {
         let iface = NetInterface::new(addr, netmask);
         dev.add_interface(iface);
-    })
+    })?;
+    // best effort; a device that is still down just stays quiet
+    let _ = arp::send_gratuitous_arp(dev_name, addr);
+    Ok(())
 }
 
 #[cfg(test)]
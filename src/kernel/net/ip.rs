@@ -5,14 +5,22 @@ use super::{
 use crate::{
     error::{Error, Result},
     net::{
-        arp,
-        device::{net_device_by_name, NetDevice},
-        ethernet, icmp, route, tcp, udp,
+        arp::{self, ArpResolveResult},
+        device::{net_device_by_name, net_device_foreach, NetDevice, NetDeviceType},
+        ethernet::{self, MacAddr},
+        event, icmp, raw, require_state, route, tcp, udp, NetStackState,
     },
-    println, trace,
+    param::TICK_HZ,
+    println,
+    spinlock::Mutex,
+    trace,
 };
 extern crate alloc;
-use core::mem::size_of;
+use alloc::vec::Vec;
+use core::{
+    mem::size_of,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
 
 #[repr(C, packed)]
 #[derive(Debug, Clone, Copy)]
@@ -30,6 +38,7 @@ pub struct IpHeader {
 }
 impl IpHeader {
     pub const ICMP: u8 = 1;
+    pub const IGMP: u8 = 2;
     pub const TCP: u8 = 6;
     pub const UDP: u8 = 17;
 
@@ -46,7 +55,7 @@ impl IpHeader {
     }
 }
 
-mod wire {
+pub(super) mod wire {
     use crate::error::{Error, Result};
     use crate::net::util::{read_u16, write_u16};
 
@@ -91,6 +100,22 @@ mod wire {
             read_u16(&self.buffer[field::TOTAL_LEN])
         }
 
+        pub fn id(&self) -> u16 {
+            read_u16(&self.buffer[field::ID])
+        }
+
+        pub fn flags_offset(&self) -> u16 {
+            read_u16(&self.buffer[field::FLAGS_OFFSET])
+        }
+
+        pub fn tos(&self) -> u8 {
+            self.buffer[field::TOS.start]
+        }
+
+        pub fn ttl(&self) -> u8 {
+            self.buffer[field::TTL.start]
+        }
+
         pub fn protocol(&self) -> u8 {
             self.buffer[field::PROTOCOL.start]
         }
@@ -176,7 +201,7 @@ mod wire {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
 pub struct IpAddr(pub u32);
 
 impl IpAddr {
@@ -189,6 +214,124 @@ impl IpAddr {
     pub fn to_bytes(self) -> [u8; 4] {
         self.0.to_be_bytes()
     }
+
+    // 224.0.0.0/4
+    pub fn is_multicast(self) -> bool {
+        self.0 >> 28 == 0b1110
+    }
+
+    pub fn subnet_contains(self, addr: IpAddr, mask: IpAddr) -> bool {
+        (addr.0 & mask.0) == (self.0 & mask.0)
+    }
+
+    pub fn subnet_of(self, network: IpAddr, mask: IpAddr) -> bool {
+        network.subnet_contains(self, mask)
+    }
+
+    pub fn prefix_len_to_mask(prefix: u8) -> IpAddr {
+        match prefix {
+            0 => IpAddr(0),
+            p => IpAddr(u32::MAX << (32 - p.min(32) as u32)),
+        }
+    }
+
+    pub fn from_cidr(cidr: &str) -> Result<(IpAddr, IpAddr)> {
+        let (addr, prefix) = cidr.split_once('/').ok_or(Error::InvalidAddress)?;
+        let addr = parse_ip_str(addr)?;
+        let prefix = prefix
+            .parse::<u8>()
+            .ok()
+            .filter(|&p| p <= 32)
+            .ok_or(Error::InvalidAddress)?;
+        let mask = Self::prefix_len_to_mask(prefix);
+        Ok((IpAddr(addr.0 & mask.0), mask))
+    }
+}
+
+impl core::fmt::Display for IpAddr {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let [a, b, c, d] = self.to_bytes();
+        write!(f, "{}.{}.{}.{}", a, b, c, d)
+    }
+}
+
+impl core::str::FromStr for IpAddr {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        parse_ip_str(s)
+    }
+}
+
+// IPv6 address in network byte order. The stack does not speak IPv6;
+// this only carries addresses such as DNS AAAA answers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct IpAddr6(pub [u8; 16]);
+
+impl IpAddr6 {
+    // ::ffff:a.b.c.d (RFC 4291 2.5.5.2)
+    pub fn from_ipv4_mapped(addr: IpAddr) -> Self {
+        let mut bytes = [0u8; 16];
+        bytes[10..12].copy_from_slice(&[0xff, 0xff]);
+        bytes[12..].copy_from_slice(&addr.to_bytes());
+        IpAddr6(bytes)
+    }
+
+    pub fn to_ipv4_mapped(self) -> Option<IpAddr> {
+        if self.0[..10] != [0; 10] || self.0[10..12] != [0xff, 0xff] {
+            return None;
+        }
+        Some(IpAddr::new(self.0[12], self.0[13], self.0[14], self.0[15]))
+    }
+
+    pub fn segments(self) -> [u16; 8] {
+        let mut segments = [0u16; 8];
+        for (i, seg) in segments.iter_mut().enumerate() {
+            *seg = u16::from_be_bytes([self.0[2 * i], self.0[2 * i + 1]]);
+        }
+        segments
+    }
+}
+
+// RFC 5952: lowercase hex, the longest run of two or more zero groups
+// collapsed to "::".
+impl core::fmt::Display for IpAddr6 {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        if let Some(v4) = self.to_ipv4_mapped() {
+            return write!(f, "::ffff:{}", v4);
+        }
+        let segments = self.segments();
+        let (mut best_start, mut best_len) = (0, 0);
+        let mut i = 0;
+        while i < 8 {
+            let start = i;
+            while i < 8 && segments[i] == 0 {
+                i += 1;
+            }
+            if i - start > best_len {
+                (best_start, best_len) = (start, i - start);
+            }
+            i += 1;
+        }
+        if best_len < 2 {
+            best_len = 0;
+        }
+
+        let mut i = 0;
+        while i < 8 {
+            if best_len > 0 && i == best_start {
+                f.write_str("::")?;
+                i += best_len;
+                continue;
+            }
+            if i > 0 && !(best_len > 0 && i == best_start + best_len) {
+                f.write_str(":")?;
+            }
+            write!(f, "{:x}", segments[i])?;
+            i += 1;
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
@@ -218,7 +361,212 @@ impl IpEndpoint {
     }
 }
 
-pub fn ingress(_dev: &NetDevice, data: &[u8]) -> Result<()> {
+impl core::fmt::Display for IpEndpoint {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}:{}", self.addr, self.port)
+    }
+}
+
+const FLAG_RESERVED: u16 = 0x8000;
+const FLAG_DONT_FRAGMENT: u16 = 0x4000;
+const FLAG_MORE_FRAGMENTS: u16 = 0x2000;
+const FRAGMENT_OFFSET_MASK: u16 = 0x1fff;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct IpFlags {
+    pub dont_fragment: bool,
+    pub more_fragments: bool,
+    pub fragment_offset: u16, // in bytes, a multiple of 8
+    pub tos: u8,              // DSCP (6bit) + ECN (2bit)
+}
+
+impl IpFlags {
+    pub const fn none() -> Self {
+        Self {
+            dont_fragment: false,
+            more_fragments: false,
+            fragment_offset: 0,
+            tos: 0,
+        }
+    }
+
+    pub const fn df() -> Self {
+        Self {
+            dont_fragment: true,
+            more_fragments: false,
+            fragment_offset: 0,
+            tos: 0,
+        }
+    }
+
+    pub const fn with_tos(self, tos: u8) -> Self {
+        Self { tos, ..self }
+    }
+
+    fn flags_offset(&self) -> u16 {
+        let mut value = (self.fragment_offset / 8) & FRAGMENT_OFFSET_MASK;
+        if self.dont_fragment {
+            value |= FLAG_DONT_FRAGMENT;
+        }
+        if self.more_fragments {
+            value |= FLAG_MORE_FRAGMENTS;
+        }
+        value
+    }
+}
+
+pub fn ip_header_flags(flags_offset: u16) -> (bool, bool, u16) {
+    let dont_fragment = flags_offset & FLAG_DONT_FRAGMENT != 0;
+    let more_fragments = flags_offset & FLAG_MORE_FRAGMENTS != 0;
+    let offset_bytes = (flags_offset & FRAGMENT_OFFSET_MASK) * 8;
+    (dont_fragment, more_fragments, offset_bytes)
+}
+
+const REASSEMBLY_MAX_ENTRIES: usize = 8;
+// RFC 791 suggests 15 s as a lower bound; Linux uses 30 s
+const REASSEMBLY_DEFAULT_TIMEOUT_TICKS: usize = 30 * TICK_HZ;
+const FRAGMENT_BLOCK: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FragmentKey {
+    src: IpAddr,
+    dst: IpAddr,
+    id: u16,
+    protocol: u8,
+}
+
+struct ReassemblyEntry {
+    key: FragmentKey,
+    data: Vec<u8>,
+    // one bit per 8-byte block of `data` received so far
+    received: Vec<u64>,
+    // known once the fragment without MF arrives
+    total_len: Option<usize>,
+    started_at: usize,
+}
+
+impl ReassemblyEntry {
+    fn new(key: FragmentKey, now: usize) -> Self {
+        Self {
+            key,
+            data: Vec::new(),
+            received: Vec::new(),
+            total_len: None,
+            started_at: now,
+        }
+    }
+
+    fn mark(&mut self, first: usize, end: usize) {
+        for block in first..end {
+            let word = block / 64;
+            if word >= self.received.len() {
+                self.received.resize(word + 1, 0);
+            }
+            self.received[word] |= 1 << (block % 64);
+        }
+    }
+
+    fn complete(&self) -> bool {
+        let Some(total) = self.total_len else {
+            return false;
+        };
+        (0..total.div_ceil(FRAGMENT_BLOCK)).all(|block| {
+            self.received
+                .get(block / 64)
+                .is_some_and(|word| word & (1 << (block % 64)) != 0)
+        })
+    }
+}
+
+// Fragments wait here, keyed by (src, dst, id, protocol) as RFC 791
+// requires, until the datagram is whole. Overlapping fragments are
+// copied over what is already there. An incomplete datagram is dropped
+// once it is older than the timeout, and the oldest one makes room when
+// the table is full.
+struct Reassembler {
+    entries: Mutex<Vec<ReassemblyEntry>>,
+    timeout_ticks: AtomicUsize,
+}
+
+impl Reassembler {
+    const fn new() -> Self {
+        Self {
+            entries: Mutex::new(Vec::new(), "ip_reassembly"),
+            timeout_ticks: AtomicUsize::new(REASSEMBLY_DEFAULT_TIMEOUT_TICKS),
+        }
+    }
+
+    // Returns the whole payload once the last gap is filled.
+    fn insert(
+        &self,
+        key: FragmentKey,
+        offset: usize,
+        more_fragments: bool,
+        payload: &[u8],
+        now: usize,
+    ) -> Result<Option<Vec<u8>>> {
+        let end = offset + payload.len();
+        if more_fragments && (payload.is_empty() || !payload.len().is_multiple_of(FRAGMENT_BLOCK)) {
+            return Err(Error::InvalidLength);
+        }
+
+        let timeout = self.timeout_ticks.load(Ordering::Relaxed);
+        let mut entries = self.entries.lock();
+        entries.retain(|e| now.wrapping_sub(e.started_at) < timeout);
+        let index = match entries.iter().position(|e| e.key == key) {
+            Some(index) => index,
+            None => {
+                if entries.len() >= REASSEMBLY_MAX_ENTRIES {
+                    entries.remove(0);
+                }
+                entries.push(ReassemblyEntry::new(key, now));
+                entries.len() - 1
+            }
+        };
+
+        let entry = &mut entries[index];
+        let inconsistent = match entry.total_len {
+            Some(total) => end > total || (!more_fragments && end != total),
+            None => !more_fragments && entry.data.len() > end,
+        };
+        if inconsistent {
+            entries.remove(index);
+            return Err(Error::InvalidLength);
+        }
+        if !more_fragments {
+            entry.total_len = Some(end);
+        }
+        if entry.data.len() < end {
+            entry.data.resize(end, 0);
+        }
+        entry.data[offset..end].copy_from_slice(payload);
+        entry.mark(offset / FRAGMENT_BLOCK, end.div_ceil(FRAGMENT_BLOCK));
+
+        if !entry.complete() {
+            return Ok(None);
+        }
+        let entry = entries.remove(index);
+        trace!(
+            IP,
+            "[ip] reassembled {} bytes, id={}",
+            entry.data.len(),
+            key.id
+        );
+        Ok(Some(entry.data))
+    }
+}
+
+static REASSEMBLY: Reassembler = Reassembler::new();
+
+pub fn set_reassembly_timeout(ticks: usize) -> Result<()> {
+    if ticks == 0 {
+        return Err(Error::InvalidArgument);
+    }
+    REASSEMBLY.timeout_ticks.store(ticks, Ordering::Relaxed);
+    Ok(())
+}
+
+pub fn ingress(dev: &NetDevice, data: &[u8]) -> Result<()> {
     let header = wire::Packet::new_checked(data)?;
     if header.version() != 4 {
         return Err(Error::InvalidVersion);
@@ -241,27 +589,112 @@ pub fn ingress(_dev: &NetDevice, data: &[u8]) -> Result<()> {
         return Err(Error::InvalidLength);
     }
 
+    let flags_offset = header.flags_offset();
+    if flags_offset & FLAG_RESERVED != 0 {
+        return Err(Error::InvalidFlags);
+    }
+    let (dont_fragment, more_fragments, offset_bytes) = ip_header_flags(flags_offset);
+    if offset_bytes as usize + (total_len - hlen) > 65535 {
+        return Err(Error::PacketTooLarge);
+    }
+
     let src = IpAddr(header.src());
     let dst = IpAddr(header.dst());
 
     trace!(
         IP,
-        "[ip] received packet: {:?} -> {:?}, proto={}",
+        "[ip] received packet: {:?} -> {:?}, proto={} df={} mf={} off={}",
         src.to_bytes(),
         dst.to_bytes(),
-        header.protocol()
+        header.protocol(),
+        dont_fragment,
+        more_fragments,
+        offset_bytes
     );
 
-    let payload = &data[hlen..total_len];
-    match header.protocol() {
-        IpHeader::ICMP => icmp::ingress(src, dst, payload),
-        IpHeader::TCP => tcp::ingress(src, dst, payload),
-        IpHeader::UDP => udp::ingress(src, dst, payload),
+    if FORWARDING.load(Ordering::Relaxed) && !is_local_destination(dst) {
+        return forward(src, dst, &data[..total_len]);
+    }
+
+    let mut payload = &data[hlen..total_len];
+    let reassembled;
+    if more_fragments || offset_bytes != 0 {
+        let key = FragmentKey {
+            src,
+            dst,
+            id: header.id(),
+            protocol: header.protocol(),
+        };
+        let now = *crate::trap::TICKS.lock();
+        match REASSEMBLY.insert(key, offset_bytes as usize, more_fragments, payload, now)? {
+            Some(whole) => reassembled = whole,
+            None => return Ok(()),
+        }
+        payload = &reassembled;
+    }
+    let protocol = header.protocol();
+    let raw_listening = raw::wants(protocol);
+    if raw_listening {
+        raw::ingress(
+            protocol,
+            &raw_datagram(&data[..hlen], payload, dont_fragment),
+        );
+    }
+    match protocol {
+        IpHeader::ICMP => icmp::ingress(src, dst, header.ttl(), payload),
+        IpHeader::TCP => {
+            let result = tcp::ingress(src, dst, payload);
+            report_unreachable_port(result, src, dst, &data[..total_len])
+        }
+        IpHeader::UDP => {
+            let result = udp::ingress(dev, src, dst, payload);
+            report_unreachable_port(result, src, dst, &data[..total_len])
+        }
+        _ if raw_listening => Ok(()),
         _ => Err(Error::UnsupportedProtocol),
     }
 }
 
-pub fn egress(dev: &NetDevice, protocol: u8, src: IpAddr, dst: IpAddr, data: &[u8]) -> Result<()> {
+// The datagram as raw sockets see it: the received header in front of the
+// (possibly reassembled) payload, lengths and fragment fields fixed up.
+fn raw_datagram(header: &[u8], payload: &[u8], dont_fragment: bool) -> Vec<u8> {
+    let mut datagram = Vec::with_capacity(header.len() + payload.len());
+    datagram.extend_from_slice(header);
+    datagram.extend_from_slice(payload);
+    let mut packet = wire::PacketMut::new_unchecked(&mut datagram);
+    packet.set_total_len((header.len() + payload.len()) as u16);
+    packet.set_flags_offset(if dont_fragment { FLAG_DONT_FRAGMENT } else { 0 });
+    packet.fill_checksum();
+    datagram
+}
+
+// RFC 1122 3.2.2: no ICMP error for datagrams sent to a broadcast or
+// multicast address.
+fn report_unreachable_port(
+    result: Result<()>,
+    src: IpAddr,
+    dst: IpAddr,
+    packet: &[u8],
+) -> Result<()> {
+    if let Err(Error::NoMatchingSocket) = result {
+        if !is_broadcast_destination(dst) {
+            icmp::send_port_unreachable(src, packet)?;
+        }
+    }
+    result
+}
+
+pub const DEFAULT_TTL: u8 = 64;
+
+fn build_packet(
+    protocol: u8,
+    src: IpAddr,
+    dst: IpAddr,
+    data: &[u8],
+    ttl: u8,
+    id: u16,
+    flags: IpFlags,
+) -> Result<Vec<u8>> {
     let total_len = size_of::<IpHeader>() + data.len();
     if total_len > 65535 {
         return Err(Error::PacketTooLarge);
@@ -270,11 +703,11 @@ pub fn egress(dev: &NetDevice, protocol: u8, src: IpAddr, dst: IpAddr, data: &[u
     {
         let mut header = wire::PacketMut::new_unchecked(&mut packet);
         header.set_version_ihl(4, 5);
-        header.set_tos(0);
+        header.set_tos(flags.tos);
         header.set_total_len(total_len as u16);
-        header.set_id(0);
-        header.set_flags_offset(0);
-        header.set_ttl(64);
+        header.set_id(id);
+        header.set_flags_offset(flags.flags_offset());
+        header.set_ttl(ttl);
         header.set_protocol(protocol);
         header.set_checksum(0);
         header.set_src(src.0);
@@ -282,17 +715,101 @@ pub fn egress(dev: &NetDevice, protocol: u8, src: IpAddr, dst: IpAddr, data: &[u
         header.fill_checksum();
     }
     packet[size_of::<IpHeader>()..].copy_from_slice(data);
+    Ok(packet)
+}
+
+// Splits `data` into fragments that fit the device MTU (RFC 791 3.2).
+// Every fragment but the last carries a multiple of 8 payload bytes; all
+// of them share one identification. DF makes an oversized datagram an
+// error instead.
+fn build_packets(
+    dev: &NetDevice,
+    protocol: u8,
+    src: IpAddr,
+    dst: IpAddr,
+    data: &[u8],
+    ttl: u8,
+    flags: IpFlags,
+) -> Result<Vec<Vec<u8>>> {
+    let hlen = size_of::<IpHeader>();
+    if hlen + data.len() > 65535 {
+        return Err(Error::PacketTooLarge);
+    }
+    let id = dev.next_ip_id();
+    let mtu = dev.mtu() as usize;
+    if hlen + data.len() <= mtu {
+        return Ok(alloc::vec![build_packet(
+            protocol, src, dst, data, ttl, id, flags
+        )?]);
+    }
+    if flags.dont_fragment {
+        return Err(Error::PacketTooLarge);
+    }
+    let chunk = mtu.saturating_sub(hlen) & !7;
+    if chunk == 0 {
+        return Err(Error::PacketTooLarge);
+    }
 
+    let mut packets = Vec::with_capacity(data.len().div_ceil(chunk));
+    for (i, part) in data.chunks(chunk).enumerate() {
+        let offset = i * chunk;
+        let fragment = IpFlags {
+            more_fragments: offset + part.len() < data.len(),
+            fragment_offset: offset as u16,
+            ..flags
+        };
+        packets.push(build_packet(protocol, src, dst, part, ttl, id, fragment)?);
+    }
     trace!(
         IP,
-        "[ip] sending packet: {:?} -> {:?}, {} bytes",
-        src.to_bytes(),
-        dst.to_bytes(),
-        total_len
+        "[ip] fragmented {} bytes into {} packets, id={}",
+        data.len(),
+        packets.len(),
+        id
     );
+    Ok(packets)
+}
+
+pub fn egress(dev: &NetDevice, protocol: u8, src: IpAddr, dst: IpAddr, data: &[u8]) -> Result<()> {
+    egress_ttl(dev, protocol, src, dst, data, DEFAULT_TTL, IpFlags::none())
+}
+
+// Like egress, with the DF policy up to the caller.
+pub fn egress_frag(
+    dev: &NetDevice,
+    protocol: u8,
+    src: IpAddr,
+    dst: IpAddr,
+    data: &[u8],
+    flags: IpFlags,
+) -> Result<()> {
+    egress_ttl(dev, protocol, src, dst, data, DEFAULT_TTL, flags)
+}
+
+fn egress_ttl(
+    dev: &NetDevice,
+    protocol: u8,
+    src: IpAddr,
+    dst: IpAddr,
+    data: &[u8],
+    ttl: u8,
+    flags: IpFlags,
+) -> Result<()> {
+    let packets = build_packets(dev, protocol, src, dst, data, ttl, flags)?;
 
     let mut dev_clone = dev.clone();
-    dev_clone.transmit(&packet)
+    for packet in &packets {
+        trace!(
+            IP,
+            "[ip] sending packet: {:?} -> {:?}, {} bytes",
+            src.to_bytes(),
+            dst.to_bytes(),
+            packet.len()
+        );
+        event::record_ip_send(dst, protocol, packet.len());
+        dev_clone.transmit(packet)?;
+    }
+    Ok(())
 }
 
 pub fn get_source_address(dst: IpAddr) -> Option<IpAddr> {
@@ -306,7 +823,7 @@ pub fn get_source_address(dst: IpAddr) -> Option<IpAddr> {
     if let Some(iface) = dev
         .interfaces
         .iter()
-        .find(|i| (dst.0 & i.netmask.0) == (i.addr.0 & i.netmask.0))
+        .find(|i| dst.subnet_of(i.addr, i.netmask))
     {
         return Some(iface.addr);
     }
@@ -315,40 +832,191 @@ pub fn get_source_address(dst: IpAddr) -> Option<IpAddr> {
 }
 
 pub fn egress_route(dst: IpAddr, protocol: u8, payload: &[u8]) -> Result<()> {
+    egress_route_ttl(dst, protocol, payload, DEFAULT_TTL)
+}
+
+pub fn egress_route_df(dst: IpAddr, protocol: u8, payload: &[u8]) -> Result<()> {
+    egress_route_flags(dst, protocol, payload, DEFAULT_TTL, IpFlags::df())
+}
+
+pub fn egress_route_ttl(dst: IpAddr, protocol: u8, payload: &[u8], ttl: u8) -> Result<()> {
+    egress_route_flags(dst, protocol, payload, ttl, IpFlags::none())
+}
+
+pub fn egress_route_flags(
+    dst: IpAddr,
+    protocol: u8,
+    payload: &[u8],
+    ttl: u8,
+    flags: IpFlags,
+) -> Result<()> {
+    require_state(NetStackState::IpInitialized)?;
     if dst.0 == IpAddr::LOOPBACK.0 {
         let dev = net_device_by_name("lo").ok_or(Error::DeviceNotFound)?;
-        return egress(&dev, protocol, IpAddr::LOOPBACK, dst, payload);
+        return egress_ttl(&dev, protocol, IpAddr::LOOPBACK, dst, payload, ttl, flags);
     }
 
-    if let Some(route) = route::lookup(dst) {
-        let dev = net_device_by_name(route.dev).ok_or(Error::DeviceNotFound)?;
-        let src = get_source_address(dst).unwrap_or(IpAddr::LOOPBACK);
+    let route = route::lookup(dst).ok_or(Error::NoSuchNode)?;
+    let mut dev = net_device_by_name(route.dev).ok_or(Error::DeviceNotFound)?;
+    let src = get_source_address(dst).ok_or(Error::Unaddressable)?;
+    if dev.dev_type == NetDeviceType::Loopback {
+        return egress_ttl(&dev, protocol, src, dst, payload, ttl, flags);
+    }
 
-        let next_hop = route.gateway.unwrap_or(dst);
-        let mac = arp::resolve(dev.name(), next_hop, src, crate::param::TICK_HZ)
-            .map_err(|_| Error::Timeout)?;
-        let mut dev_clone = dev.clone();
-        let total_len = core::mem::size_of::<super::ip::IpHeader>() + payload.len();
-        let mut ip_packet = alloc::vec![0u8; total_len];
-        {
-            let mut hdr = wire::PacketMut::new_unchecked(&mut ip_packet);
-            hdr.set_version_ihl(4, 5);
-            hdr.set_tos(0);
-            hdr.set_total_len(total_len as u16);
-            hdr.set_id(0);
-            hdr.set_flags_offset(0);
-            hdr.set_ttl(64);
-            hdr.set_protocol(protocol);
-            hdr.set_checksum(0);
-            hdr.set_src(src.0);
-            hdr.set_dst(dst.0);
-            hdr.fill_checksum();
+    let next_hop = route.gateway.unwrap_or(dst);
+    let packets = build_packets(&dev, protocol, src, dst, payload, ttl, flags)?;
+    trace!(
+        IP,
+        "[ip] routing packet: {:?} -> {:?} via {:?} dev {}",
+        src.to_bytes(),
+        dst.to_bytes(),
+        next_hop.to_bytes(),
+        dev.name()
+    );
+    transmit_to_next_hop(&mut dev, next_hop, src, packets)
+}
+
+// Sends finished packets to `next_hop`, parking them on the ARP queue
+// until its address resolves.
+fn transmit_to_next_hop(
+    dev: &mut NetDevice,
+    next_hop: IpAddr,
+    src: IpAddr,
+    packets: Vec<Vec<u8>>,
+) -> Result<()> {
+    let mac = match arp::resolve_nb(dev.name(), next_hop, src) {
+        ArpResolveResult::Found(mac) => mac,
+        ArpResolveResult::Pending => {
+            trace!(
+                IP,
+                "[ip] queued packet for {:?} awaiting arp",
+                next_hop.to_bytes()
+            );
+            for packet in packets {
+                arp::enqueue(dev.name(), next_hop, ethernet::ETHERTYPE_IPV4, packet)?;
+            }
+            return Ok(());
         }
-        ip_packet[core::mem::size_of::<super::ip::IpHeader>()..].copy_from_slice(payload);
-        return ethernet::egress(&mut dev_clone, mac, ethernet::ETHERTYPE_IPV4, &ip_packet);
+        ArpResolveResult::NotFound => return Err(Error::NotConnected),
+    };
+    for packet in &packets {
+        ethernet::egress(dev, mac, ethernet::ETHERTYPE_IPV4, packet)?;
+    }
+    Ok(())
+}
+
+// Off by default: as a host the stack hands every datagram it receives
+// to the upper layers. With forwarding on, datagrams for other hosts are
+// routed onward instead (RFC 1812 5.3).
+static FORWARDING: AtomicBool = AtomicBool::new(false);
+
+pub fn set_forwarding(enable: bool) {
+    FORWARDING.store(enable, Ordering::Relaxed);
+}
+
+fn is_broadcast_destination(dst: IpAddr) -> bool {
+    if dst.is_multicast() || dst.0 == 0xFFFF_FFFF {
+        return true;
+    }
+    let mut broadcast = false;
+    net_device_foreach(|dev| {
+        broadcast |= dev.interfaces.iter().any(|i| i.broadcast == dst);
+    });
+    broadcast
+}
+
+fn is_local_destination(dst: IpAddr) -> bool {
+    if dst.0 >> 24 == 127 || dst.is_multicast() || dst.0 == 0xFFFF_FFFF {
+        return true;
+    }
+    let mut local = false;
+    net_device_foreach(|dev| {
+        local |= dev
+            .interfaces
+            .iter()
+            .any(|i| i.addr == dst || i.broadcast == dst);
+    });
+    local
+}
+
+// `packet` is the received datagram, header included. A datagram whose TTL
+// would reach zero is answered with Time Exceeded instead (RFC 792). Too
+// large a datagram for the outgoing link is dropped; refragmenting is not
+// supported.
+fn forward(src: IpAddr, dst: IpAddr, packet: &[u8]) -> Result<()> {
+    let ttl = wire::Packet::new_checked(packet)?.ttl();
+    if ttl <= 1 {
+        trace!(
+            IP,
+            "[ip] ttl expired forwarding {:?} -> {:?}",
+            src.to_bytes(),
+            dst.to_bytes()
+        );
+        return icmp::send_time_exceeded(src, packet);
+    }
+
+    let route = route::lookup(dst).ok_or(Error::NoSuchNode)?;
+    let mut dev = net_device_by_name(route.dev).ok_or(Error::DeviceNotFound)?;
+    if packet.len() > dev.mtu() as usize {
+        return Err(Error::PacketTooLarge);
+    }
+    let mut out = packet.to_vec();
+    {
+        let mut header = wire::PacketMut::new_unchecked(&mut out);
+        header.set_ttl(ttl - 1);
+        header.fill_checksum();
+    }
+    let next_hop = route.gateway.unwrap_or(dst);
+    let arp_src = get_source_address(next_hop).ok_or(Error::Unaddressable)?;
+    trace!(
+        IP,
+        "[ip] forwarding packet: {:?} -> {:?} via {:?} dev {}",
+        src.to_bytes(),
+        dst.to_bytes(),
+        next_hop.to_bytes(),
+        dev.name()
+    );
+    transmit_to_next_hop(&mut dev, next_hop, arp_src, alloc::vec![out])
+}
+
+// Multicast skips the routing table and ARP: the caller names the
+// interface, and the group maps straight onto an Ethernet address
+// (RFC 1112 6.4).
+pub fn egress_multicast(
+    dev_name: &str,
+    group: IpAddr,
+    protocol: u8,
+    payload: &[u8],
+    ttl: u8,
+) -> Result<()> {
+    require_state(NetStackState::IpInitialized)?;
+    if !group.is_multicast() {
+        return Err(Error::InvalidAddress);
+    }
+    let mut dev = net_device_by_name(dev_name).ok_or(Error::DeviceNotFound)?;
+    let src = dev
+        .interfaces
+        .first()
+        .map(|i| i.addr)
+        .ok_or(Error::Unaddressable)?;
+    if dev.dev_type == NetDeviceType::Loopback {
+        return egress_ttl(&dev, protocol, src, group, payload, ttl, IpFlags::none());
     }
 
-    Err(Error::NoSuchNode)
+    let packets = build_packets(&dev, protocol, src, group, payload, ttl, IpFlags::none())?;
+    let [_, b, c, d] = group.to_bytes();
+    let mac = MacAddr([0x01, 0x00, 0x5e, b & 0x7f, c, d]);
+    trace!(
+        IP,
+        "[ip] multicast packet: {:?} -> {:?} dev {}",
+        src.to_bytes(),
+        group.to_bytes(),
+        dev.name()
+    );
+    for packet in &packets {
+        ethernet::egress(&mut dev, mac, ethernet::ETHERTYPE_IPV4, packet)?;
+    }
+    Ok(())
 }
 
 pub fn ip_init() {
@@ -370,35 +1038,46 @@ pub fn parse_ip_str(s: &str) -> Result<IpAddr> {
 
 #[cfg(test)]
 mod tests {
-    use super::{egress, ingress, parse_ip_str, wire, IpAddr, IpHeader};
+    use super::{
+        build_packet, egress, egress_frag, egress_route, egress_route_ttl, get_source_address,
+        ingress, ip_header_flags, parse_ip_str, raw_datagram, set_forwarding, wire, FragmentKey,
+        IpAddr, IpAddr6, IpEndpoint, IpFlags, IpHeader, Reassembler, DEFAULT_TTL,
+    };
     use crate::error::Error;
-    use crate::net::device::{
-        NetDevice, NetDeviceConfig, NetDeviceFlags, NetDeviceOps, NetDeviceType,
+    use crate::net::device::tests::{
+        attach_test_device, capture_transmit, captured_frames, discard_transmit, dummy_device,
+        last_captured_frame, register_test_device, test_config,
     };
-    use crate::net::ethernet::MacAddr;
-    use crate::net::util::checksum;
-    use alloc::vec;
-
-    fn dummy_dev() -> NetDevice {
-        NetDevice::new(NetDeviceConfig {
-            name: "dummy",
-            dev_type: NetDeviceType::Ethernet,
-            mtu: 1500,
-            flags: NetDeviceFlags::UP,
-            header_len: wire::MIN_HEADER_LEN as u16,
-            addr_len: 6,
-            hw_addr: MacAddr::ZERO,
-            ops: NetDeviceOps {
-                transmit: |_dev, _data| Ok(()),
-                open: |_dev| Ok(()),
-                close: |_dev| Ok(()),
-            },
-        })
+    use crate::net::device::{net_device_register, NetDevice, NetDeviceConfig};
+    use crate::net::ethernet::{self, MacAddr};
+    use crate::net::interface::net_interface_setup;
+    use crate::net::util::{checksum, verify_checksum};
+    use crate::net::{arp, icmp, route, tcp, udp};
+    use alloc::{format, vec, vec::Vec};
+
+    #[test_case]
+    fn raw_datagram_covers_reassembled_payload() {
+        use crate::net::util::verify_checksum;
+
+        let src = IpAddr::new(192, 0, 2, 1);
+        let dst = IpAddr::new(192, 0, 2, 2);
+        let flags = IpFlags::none();
+        let first = build_packet(IpHeader::UDP, src, dst, &[0u8; 8], 64, 7, flags).unwrap();
+        let mut header = first[..wire::MIN_HEADER_LEN].to_vec();
+        // first fragment: MF set, total length covering only 8 bytes
+        header[wire::field::FLAGS_OFFSET].copy_from_slice(&0x2000u16.to_be_bytes());
+
+        let datagram = raw_datagram(&header, &[0xaa; 24], false);
+        let packet = wire::Packet::new_checked(&datagram).unwrap();
+        assert_eq!(packet.total_len() as usize, wire::MIN_HEADER_LEN + 24);
+        assert_eq!(packet.flags_offset(), 0);
+        assert!(verify_checksum(&datagram[..wire::MIN_HEADER_LEN]));
+        assert_eq!(&datagram[wire::MIN_HEADER_LEN..], &[0xaa; 24]);
     }
 
     #[test_case]
     fn invalid_version() {
-        let dev = dummy_dev();
+        let dev = dummy_device("dummy");
         let mut data = [0u8; wire::MIN_HEADER_LEN];
         data[0] = 0x55; // version=5, ihl=5
         let err = ingress(&dev, &data).unwrap_err();
@@ -407,7 +1086,7 @@ mod tests {
 
     #[test_case]
     fn invalid_header_len() {
-        let dev = dummy_dev();
+        let dev = dummy_device("dummy");
         let mut data = [0u8; wire::MIN_HEADER_LEN];
         data[0] = 0x44; // version=4, ihl=4 -> 16 bytes
         let err = ingress(&dev, &data).unwrap_err();
@@ -416,7 +1095,7 @@ mod tests {
 
     #[test_case]
     fn total_len_too_large() {
-        let dev = dummy_dev();
+        let dev = dummy_device("dummy");
         let mut data = [0u8; wire::MIN_HEADER_LEN];
         data[0] = 0x45; // version=4, ihl=5
         data[9] = IpHeader::UDP;
@@ -431,7 +1110,7 @@ mod tests {
 
     #[test_case]
     fn total_len_smaller_than_header() {
-        let dev = dummy_dev();
+        let dev = dummy_device("dummy");
         let mut data = [0u8; wire::MIN_HEADER_LEN];
         data[0] = 0x45; // version=4, ihl=5
         data[9] = IpHeader::UDP;
@@ -446,7 +1125,7 @@ mod tests {
 
     #[test_case]
     fn checksum_error() {
-        let dev = dummy_dev();
+        let dev = dummy_device("dummy");
         let mut data = [0u8; wire::MIN_HEADER_LEN];
         {
             let mut hdr = wire::PacketMut::new_unchecked(&mut data);
@@ -462,12 +1141,99 @@ mod tests {
         assert_eq!(err, Error::ChecksumError);
     }
 
+    fn flagged_packet(flags_offset: u16, payload_len: usize) -> Vec<u8> {
+        let total_len = wire::MIN_HEADER_LEN + payload_len;
+        let mut data = vec![0u8; total_len];
+        {
+            let mut hdr = wire::PacketMut::new_unchecked(&mut data);
+            hdr.set_version_ihl(4, 5);
+            hdr.set_total_len(total_len as u16);
+            hdr.set_flags_offset(flags_offset);
+            hdr.set_protocol(IpHeader::UDP);
+            hdr.set_src(IpAddr::new(10, 0, 0, 1).0);
+            hdr.set_dst(IpAddr::new(10, 0, 0, 2).0);
+            hdr.fill_checksum();
+        }
+        data
+    }
+
+    #[test_case]
+    fn header_flags_decode() {
+        assert_eq!(ip_header_flags(0x0000), (false, false, 0));
+        assert_eq!(ip_header_flags(0x4000), (true, false, 0));
+        assert_eq!(ip_header_flags(0x2001), (false, true, 8));
+        assert_eq!(ip_header_flags(0x1fff), (false, false, 65528));
+    }
+
+    #[test_case]
+    fn reserved_flag_rejected() {
+        let dev = dummy_device("dummy");
+        for flags_offset in [0x8000, 0xc000, 0xa000, 0x8001] {
+            let data = flagged_packet(flags_offset, 0);
+            assert_eq!(ingress(&dev, &data).unwrap_err(), Error::InvalidFlags);
+        }
+    }
+
+    #[test_case]
+    fn fragment_offset_overflow_rejected() {
+        let dev = dummy_device("dummy");
+        let data = flagged_packet(0x1fff, 8);
+        assert_eq!(ingress(&dev, &data).unwrap_err(), Error::PacketTooLarge);
+        let data = flagged_packet(0x3fff, 8);
+        assert_eq!(ingress(&dev, &data).unwrap_err(), Error::PacketTooLarge);
+    }
+
+    #[test_case]
+    fn subnet_membership() {
+        let net = IpAddr::new(192, 168, 1, 0);
+        let mask = IpAddr::new(255, 255, 255, 0);
+        assert!(IpAddr::new(192, 168, 1, 1).subnet_of(net, mask));
+        assert!(!IpAddr::new(192, 168, 2, 1).subnet_of(net, mask));
+        assert!(net.subnet_contains(IpAddr::new(192, 168, 1, 254), mask));
+        assert!(IpAddr::new(10, 0, 0, 1).subnet_of(IpAddr(0), IpAddr(0)));
+    }
+
+    #[test_case]
+    fn cidr_and_prefix_masks() {
+        assert_eq!(IpAddr::prefix_len_to_mask(0), IpAddr(0));
+        assert_eq!(
+            IpAddr::prefix_len_to_mask(24),
+            IpAddr::new(255, 255, 255, 0)
+        );
+        assert_eq!(IpAddr::prefix_len_to_mask(32), IpAddr(u32::MAX));
+        assert_eq!(
+            IpAddr::from_cidr("192.168.1.77/24").unwrap(),
+            (IpAddr::new(192, 168, 1, 0), IpAddr::new(255, 255, 255, 0))
+        );
+        assert_eq!(
+            IpAddr::from_cidr("10.1.2.3/33").unwrap_err(),
+            Error::InvalidAddress
+        );
+        assert_eq!(
+            IpAddr::from_cidr("10.1.2.3").unwrap_err(),
+            Error::InvalidAddress
+        );
+    }
+
     #[test_case]
     fn parse_ip_str_valid() {
         let ip = parse_ip_str("192.168.1.10").unwrap();
         assert_eq!(ip, IpAddr::new(192, 168, 1, 10));
     }
 
+    #[test_case]
+    fn ip_addr_display_parse_and_order() {
+        use alloc::format;
+
+        let addr: IpAddr = "10.0.2.15".parse().unwrap();
+        assert_eq!(addr, IpAddr::new(10, 0, 2, 15));
+        assert_eq!(format!("{}", addr), "10.0.2.15");
+        assert_eq!("10.0.2".parse::<IpAddr>(), Err(Error::InvalidAddress));
+        assert!(IpAddr::new(10, 0, 2, 2) < addr);
+        assert!(IpAddr::new(192, 0, 2, 1) > addr);
+        assert_eq!(format!("{}", IpEndpoint::new(addr, 8080)), "10.0.2.15:8080");
+    }
+
     #[test_case]
     fn parse_ip_str_invalid() {
         let err = parse_ip_str("256.1.2.3").unwrap_err();
@@ -476,7 +1242,7 @@ mod tests {
 
     #[test_case]
     fn egress_packet_too_large() {
-        let dev = dummy_dev();
+        let dev = dummy_device("dummy");
         let payload = vec![0u8; 65516];
         let err = egress(
             &dev,
@@ -488,4 +1254,470 @@ mod tests {
         .unwrap_err();
         assert_eq!(err, Error::PacketTooLarge);
     }
+
+    #[test_case]
+    fn egress_route_resolves_next_hop() {
+        register_test_device(
+            "iptest0",
+            MacAddr([0x02, 0, 0, 0, 0, 0x01]),
+            IpAddr::new(198, 51, 100, 2),
+            capture_transmit,
+        );
+        route::add_route(route::Route {
+            dest: IpAddr::new(203, 0, 113, 0),
+            mask: IpAddr::new(255, 255, 255, 0),
+            gateway: Some(IpAddr::new(198, 51, 100, 1)),
+            dev: "iptest0",
+        })
+        .unwrap();
+        let gw_mac = MacAddr([0x02, 0, 0, 0, 0, 0xfe]);
+        arp::insert(IpAddr::new(198, 51, 100, 1), gw_mac);
+
+        let dst = IpAddr::new(203, 0, 113, 9);
+        egress_route(dst, IpHeader::UDP, &[0xde, 0xad]).unwrap();
+
+        let frame = last_captured_frame("iptest0").unwrap();
+        let eth = ethernet::EthHeader::LEN;
+        assert_eq!(&frame[0..6], gw_mac.as_bytes());
+        assert_eq!(&frame[12..14], &ethernet::ETHERTYPE_IPV4.to_be_bytes());
+        let pkt = wire::Packet::new_checked(&frame[eth..]).unwrap();
+        assert_eq!(pkt.protocol(), IpHeader::UDP);
+        assert_eq!(pkt.src(), IpAddr::new(198, 51, 100, 2).0);
+        assert_eq!(pkt.dst(), dst.0);
+        assert_eq!(&frame[eth + wire::MIN_HEADER_LEN..], &[0xde, 0xad]);
+        assert_eq!(pkt.ttl(), DEFAULT_TTL);
+
+        egress_route_ttl(dst, IpHeader::ICMP, &[0x08, 0x00], 3).unwrap();
+        let frame = last_captured_frame("iptest0").unwrap();
+        let pkt = wire::Packet::new_checked(&frame[eth..]).unwrap();
+        assert_eq!(pkt.ttl(), 3);
+        assert_eq!(pkt.protocol(), IpHeader::ICMP);
+
+        // an ICMP socket's per-request TTL, as ping -t sends it
+        let sock = icmp::socket_alloc().unwrap();
+        let echo = [8, 0, 0, 0, 0x12, 0x34, 0, 1];
+        icmp::socket_sendto(sock, dst, &echo, 5).unwrap();
+        icmp::socket_free(sock).unwrap();
+        let frame = last_captured_frame("iptest0").unwrap();
+        let pkt = wire::Packet::new_checked(&frame[eth..]).unwrap();
+        assert_eq!((pkt.protocol(), pkt.ttl()), (IpHeader::ICMP, 5));
+        assert_eq!(&frame[eth + wire::MIN_HEADER_LEN + 4..][..4], &echo[4..]);
+    }
+
+    #[test_case]
+    fn egress_fragments_to_mtu() {
+        let dev = NetDevice::new(NetDeviceConfig {
+            mtu: 100,
+            header_len: 0,
+            ..test_config("fragtest0", MacAddr::ZERO, capture_transmit)
+        });
+        let src = IpAddr::new(10, 0, 0, 1);
+        let dst = IpAddr::new(10, 0, 0, 2);
+        let payload: Vec<u8> = (0..200u8).collect();
+        egress(&dev, IpHeader::UDP, src, dst, &payload).unwrap();
+
+        let frames = captured_frames("fragtest0");
+        assert_eq!(frames.len(), 3);
+        let id = u16::from_be_bytes([frames[0][4], frames[0][5]]);
+        let mut reassembled = Vec::new();
+        for (frame, (mf, offset, len)) in
+            frames
+                .iter()
+                .zip([(true, 0, 80), (true, 80, 80), (false, 160, 40)])
+        {
+            let pkt = wire::Packet::new_checked(frame).unwrap();
+            assert_eq!(checksum(pkt.header_bytes()), 0);
+            assert_eq!(pkt.total_len() as usize, wire::MIN_HEADER_LEN + len);
+            assert_eq!(u16::from_be_bytes([frame[4], frame[5]]), id);
+            assert_eq!(ip_header_flags(pkt.flags_offset()), (false, mf, offset));
+            reassembled.extend_from_slice(&frame[wire::MIN_HEADER_LEN..]);
+        }
+        assert_eq!(reassembled, payload);
+
+        // the next datagram gets a new identification
+        egress(&dev, IpHeader::UDP, src, dst, &[1, 2]).unwrap();
+        let frame = last_captured_frame("fragtest0").unwrap();
+        assert_eq!(frame.len(), wire::MIN_HEADER_LEN + 2);
+        assert_eq!(u16::from_be_bytes([frame[4], frame[5]]), id.wrapping_add(1));
+
+        let err = egress_frag(&dev, IpHeader::UDP, src, dst, &payload, IpFlags::df()).unwrap_err();
+        assert_eq!(err, Error::PacketTooLarge);
+        assert!(captured_frames("fragtest0").is_empty());
+    }
+
+    fn fragment_key(id: u16) -> FragmentKey {
+        FragmentKey {
+            src: IpAddr::new(10, 0, 0, 1),
+            dst: IpAddr::new(10, 0, 0, 2),
+            id,
+            protocol: IpHeader::UDP,
+        }
+    }
+
+    #[test_case]
+    fn reassembly_in_order() {
+        let r = Reassembler::new();
+        let data: Vec<u8> = (0..40u8).collect();
+        let key = fragment_key(1);
+        assert_eq!(r.insert(key, 0, true, &data[..16], 0), Ok(None));
+        assert_eq!(r.insert(key, 16, true, &data[16..32], 0), Ok(None));
+        assert_eq!(r.insert(key, 32, false, &data[32..], 0), Ok(Some(data)));
+        assert!(r.entries.lock().is_empty());
+    }
+
+    #[test_case]
+    fn reassembly_out_of_order() {
+        let r = Reassembler::new();
+        let data: Vec<u8> = (0..40u8).collect();
+        let (a, b) = (fragment_key(2), fragment_key(3));
+        assert_eq!(r.insert(a, 32, false, &data[32..], 0), Ok(None));
+        assert_eq!(r.insert(b, 0, true, &data[..8], 0), Ok(None));
+        assert_eq!(r.insert(a, 16, true, &data[16..32], 0), Ok(None));
+        assert_eq!(r.insert(a, 0, true, &data[..16], 0), Ok(Some(data)));
+        // the other datagram is still waiting
+        assert_eq!(r.entries.lock().len(), 1);
+    }
+
+    #[test_case]
+    fn reassembly_overlapping_fragments() {
+        let r = Reassembler::new();
+        let data: Vec<u8> = (0..48u8).collect();
+        let key = fragment_key(4);
+        assert_eq!(r.insert(key, 0, true, &data[..24], 0), Ok(None));
+        assert_eq!(r.insert(key, 16, true, &data[16..32], 0), Ok(None));
+        assert_eq!(r.insert(key, 40, false, &data[40..], 0), Ok(None));
+        assert_eq!(r.insert(key, 8, true, &data[8..48], 0), Ok(Some(data)));
+
+        // a last fragment that contradicts the known length drops the datagram
+        assert_eq!(r.insert(key, 0, true, &[0; 16], 0), Ok(None));
+        assert_eq!(r.insert(key, 24, false, &[0; 4], 0), Ok(None));
+        assert_eq!(
+            r.insert(key, 8, false, &[0; 4], 0),
+            Err(Error::InvalidLength)
+        );
+        assert!(r.entries.lock().is_empty());
+        assert_eq!(
+            r.insert(key, 0, true, &[0; 12], 0),
+            Err(Error::InvalidLength)
+        );
+    }
+
+    #[test_case]
+    fn reassembly_expires_stale_fragments() {
+        let r = Reassembler::new();
+        r.timeout_ticks
+            .store(300, core::sync::atomic::Ordering::Relaxed);
+        let key = fragment_key(5);
+        assert_eq!(r.insert(key, 0, true, &[1; 8], 100), Ok(None));
+        // the first half timed out, so this alone is not enough
+        assert_eq!(r.insert(key, 8, false, &[2; 4], 400), Ok(None));
+        assert_eq!(r.entries.lock().len(), 1);
+        assert_eq!(r.entries.lock()[0].started_at, 400);
+
+        for id in 10..20 {
+            r.insert(fragment_key(id), 0, true, &[0; 8], 400).unwrap();
+        }
+        assert_eq!(r.entries.lock().len(), 8);
+        assert!(r.entries.lock().iter().all(|e| e.key.id >= 12));
+    }
+
+    #[test_case]
+    fn ip_flags_encode() {
+        assert_eq!(IpFlags::none().flags_offset(), 0);
+        assert_eq!(IpFlags::df().flags_offset(), 0x4000);
+        let fragment = IpFlags {
+            dont_fragment: false,
+            more_fragments: true,
+            fragment_offset: 1480,
+            tos: 0,
+        };
+        assert_eq!(fragment.flags_offset(), 0x2000 | 185);
+        assert_eq!(
+            ip_header_flags(fragment.flags_offset()),
+            (false, true, 1480)
+        );
+    }
+
+    #[test_case]
+    fn tcp_segments_set_dont_fragment() {
+        attach_test_device(
+            "dftest0",
+            MacAddr([0x02, 0, 0, 0, 0, 0x41]),
+            IpAddr::new(172, 16, 50, 2),
+            capture_transmit,
+        );
+        let peer = IpAddr::new(172, 16, 50, 9);
+        arp::insert(peer, MacAddr([0x02, 0, 0, 0, 0, 0x42]));
+
+        let sock = tcp::socket_alloc().unwrap();
+        tcp::socket_get_mut(sock, |s| {
+            s.connect(IpEndpoint::any(0), IpEndpoint::new(peer, 80))
+        })
+        .unwrap()
+        .unwrap();
+        let _ = tcp::poll();
+        tcp::socket_free(sock).unwrap();
+
+        let frames = captured_frames("dftest0");
+        let eth = ethernet::EthHeader::LEN;
+        let pkt = frames
+            .iter()
+            .map(|frame| wire::Packet::new_checked(&frame[eth..]).unwrap())
+            .find(|pkt| pkt.protocol() == IpHeader::TCP && pkt.dst() == peer.0)
+            .unwrap();
+        assert_eq!(pkt.flags_offset(), 0x4000);
+
+        egress_route(peer, IpHeader::UDP, &[0x01]).unwrap();
+        let frame = last_captured_frame("dftest0").unwrap();
+        let pkt = wire::Packet::new_checked(&frame[eth..]).unwrap();
+        assert_eq!(pkt.flags_offset(), 0);
+    }
+
+    #[test_case]
+    fn socket_ttl_and_tos_reach_header() {
+        attach_test_device(
+            "tostest0",
+            MacAddr([0x02, 0, 0, 0, 0, 0x51]),
+            IpAddr::new(172, 16, 60, 2),
+            capture_transmit,
+        );
+        let peer = IpAddr::new(172, 16, 60, 9);
+        arp::insert(peer, MacAddr([0x02, 0, 0, 0, 0, 0x52]));
+        let eth = ethernet::EthHeader::LEN;
+
+        let sock = tcp::socket_alloc().unwrap();
+        tcp::socket_get_mut(sock, |s| {
+            assert_eq!(s.set_ttl(0), Err(Error::InvalidArgument));
+            s.set_ttl(7).unwrap();
+            s.set_tos(0xb8);
+            s.connect(IpEndpoint::any(0), IpEndpoint::new(peer, 80))
+        })
+        .unwrap()
+        .unwrap();
+        let _ = tcp::poll();
+        tcp::socket_free(sock).unwrap();
+        let frame = last_captured_frame("tostest0").unwrap();
+        let pkt = wire::Packet::new_checked(&frame[eth..]).unwrap();
+        assert_eq!(pkt.protocol(), IpHeader::TCP);
+        assert_eq!(
+            (pkt.ttl(), pkt.tos(), pkt.flags_offset()),
+            (7, 0xb8, 0x4000)
+        );
+
+        let sock = udp::socket_alloc().unwrap();
+        udp::socket_set_ttl(sock, 2).unwrap();
+        udp::socket_set_tos(sock, 0x10).unwrap();
+        udp::socket_sendto(sock, IpEndpoint::new(peer, 53), &[1]).unwrap();
+        udp::socket_free(sock).unwrap();
+        let frame = last_captured_frame("tostest0").unwrap();
+        let pkt = wire::Packet::new_checked(&frame[eth..]).unwrap();
+        assert_eq!(pkt.protocol(), IpHeader::UDP);
+        assert_eq!((pkt.ttl(), pkt.tos()), (2, 0x10));
+    }
+
+    #[test_case]
+    fn ipv6_addresses_format_per_rfc5952() {
+        let mut addr = [0u8; 16];
+        addr[..4].copy_from_slice(&[0x20, 0x01, 0x0d, 0xb8]);
+        addr[15] = 1;
+        assert_eq!(format!("{}", IpAddr6(addr)), "2001:db8::1");
+        assert_eq!(format!("{}", IpAddr6([0; 16])), "::");
+        addr[5] = 1;
+        addr[11] = 1;
+        assert_eq!(format!("{}", IpAddr6(addr)), "2001:db8:1::1:0:1");
+        let mut loopback = [0u8; 16];
+        loopback[15] = 1;
+        assert_eq!(format!("{}", IpAddr6(loopback)), "::1");
+
+        let mapped = IpAddr6::from_ipv4_mapped(IpAddr::new(192, 0, 2, 1));
+        assert_eq!(mapped.to_ipv4_mapped(), Some(IpAddr::new(192, 0, 2, 1)));
+        assert_eq!(format!("{}", mapped), "::ffff:192.0.2.1");
+        assert_eq!(IpAddr6(addr).to_ipv4_mapped(), None);
+    }
+
+    #[test_case]
+    fn forwarding_decrements_ttl_or_reports_expiry() {
+        let dev = attach_test_device(
+            "fwdtest0",
+            MacAddr([0x02, 0, 0, 0, 0, 0x61]),
+            IpAddr::new(172, 16, 70, 1),
+            capture_transmit,
+        );
+        let sender = IpAddr::new(172, 16, 70, 20);
+        let target = IpAddr::new(172, 16, 70, 9);
+        arp::insert(sender, MacAddr([0x02, 0, 0, 0, 0, 0x62]));
+        arp::insert(target, MacAddr([0x02, 0, 0, 0, 0, 0x63]));
+        let eth = ethernet::EthHeader::LEN;
+
+        set_forwarding(true);
+        let packet = build_packet(
+            IpHeader::UDP,
+            sender,
+            target,
+            &[1, 2, 3],
+            5,
+            9,
+            IpFlags::none(),
+        )
+        .unwrap();
+        ingress(&dev, &packet).unwrap();
+        let frame = last_captured_frame("fwdtest0").unwrap();
+        assert_eq!(&frame[0..6], &[0x02, 0, 0, 0, 0, 0x63]);
+        let fwd = wire::Packet::new_checked(&frame[eth..]).unwrap();
+        assert_eq!(fwd.ttl(), 4);
+        assert!(verify_checksum(fwd.header_bytes()));
+        assert_eq!((fwd.src(), fwd.dst(), fwd.id()), (sender.0, target.0, 9));
+
+        let packet = build_packet(
+            IpHeader::UDP,
+            sender,
+            target,
+            &[1, 2, 3],
+            1,
+            10,
+            IpFlags::none(),
+        )
+        .unwrap();
+        ingress(&dev, &packet).unwrap();
+        set_forwarding(false);
+        let frame = last_captured_frame("fwdtest0").unwrap();
+        assert_eq!(&frame[0..6], &[0x02, 0, 0, 0, 0, 0x62]);
+        let reply = wire::Packet::new_checked(&frame[eth..]).unwrap();
+        assert_eq!(reply.protocol(), IpHeader::ICMP);
+        assert_eq!(reply.dst(), sender.0);
+        let icmp = &frame[eth + wire::MIN_HEADER_LEN..];
+        assert_eq!((icmp[0], icmp[1]), (11, 0));
+        assert_eq!(&icmp[8..8 + packet.len()], &packet[..]);
+    }
+
+    #[test_case]
+    fn unmatched_udp_port_reports_unreachable() {
+        let dev = attach_test_device(
+            "unrtest0",
+            MacAddr([0x02, 0, 0, 0, 0, 0x71]),
+            IpAddr::new(172, 16, 80, 1),
+            capture_transmit,
+        );
+        captured_frames("unrtest0");
+        let sender = IpAddr::new(172, 16, 80, 20);
+        arp::insert(sender, MacAddr([0x02, 0, 0, 0, 0, 0x72]));
+        let eth = ethernet::EthHeader::LEN;
+
+        // src port 4000, dst port 9, length 11, no checksum
+        let datagram = [0x0f, 0xa0, 0, 9, 0, 11, 0, 0, 1, 2, 3];
+        let packet = build_packet(
+            IpHeader::UDP,
+            sender,
+            IpAddr::new(172, 16, 80, 1),
+            &datagram,
+            DEFAULT_TTL,
+            1,
+            IpFlags::none(),
+        )
+        .unwrap();
+        let err = ingress(&dev, &packet).unwrap_err();
+        assert_eq!(err, Error::NoMatchingSocket);
+        let frame = last_captured_frame("unrtest0").unwrap();
+        let reply = wire::Packet::new_checked(&frame[eth..]).unwrap();
+        assert_eq!(reply.protocol(), IpHeader::ICMP);
+        assert_eq!(reply.dst(), sender.0);
+        let icmp = &frame[eth + wire::MIN_HEADER_LEN..];
+        assert_eq!((icmp[0], icmp[1]), (3, 3));
+        assert_eq!(&icmp[8..], &packet[..wire::MIN_HEADER_LEN + 8]);
+
+        // nothing is sent back for a subnet broadcast
+        let packet = build_packet(
+            IpHeader::UDP,
+            sender,
+            IpAddr::new(172, 16, 80, 255),
+            &datagram,
+            DEFAULT_TTL,
+            2,
+            IpFlags::none(),
+        )
+        .unwrap();
+        assert!(ingress(&dev, &packet).is_err());
+        assert!(captured_frames("unrtest0").is_empty());
+    }
+
+    #[test_case]
+    fn egress_route_queues_until_arp_reply() {
+        let dev = attach_test_device(
+            "arpwait0",
+            MacAddr([0x02, 0, 0, 0, 0, 0x21]),
+            IpAddr::new(100, 64, 0, 2),
+            capture_transmit,
+        );
+        {
+            // the new address is announced with a gratuitous request
+            let announce = last_captured_frame("arpwait0").unwrap();
+            let arp = &announce[ethernet::EthHeader::LEN..];
+            assert_eq!(&announce[0..6], &[0xff; 6]);
+            assert_eq!(&arp[6..8], &[0, 1]);
+            assert_eq!(&arp[14..18], &[100, 64, 0, 2]);
+            assert_eq!(&arp[18..24], &[0; 6]);
+            assert_eq!(&arp[24..28], &[100, 64, 0, 2]);
+        }
+        let dst = IpAddr::new(100, 64, 0, 9);
+        egress_route(dst, IpHeader::UDP, &[0xbe, 0xef]).unwrap();
+        {
+            let mut frames = captured_frames("arpwait0");
+            assert_eq!(frames.len(), 1);
+            let request = frames.pop().unwrap();
+            assert_eq!(&request[12..14], &ethernet::ETHERTYPE_ARP.to_be_bytes());
+        }
+
+        let peer_mac = [0x02, 0, 0, 0, 0, 0x99];
+        let mut reply = [0u8; 28];
+        reply[0..2].copy_from_slice(&1u16.to_be_bytes());
+        reply[2..4].copy_from_slice(&0x0800u16.to_be_bytes());
+        reply[4] = 6;
+        reply[5] = 4;
+        reply[6..8].copy_from_slice(&2u16.to_be_bytes());
+        reply[8..14].copy_from_slice(&peer_mac);
+        reply[14..18].copy_from_slice(&dst.to_bytes());
+        reply[18..24].copy_from_slice(&[0x02, 0, 0, 0, 0, 0x21]);
+        reply[24..28].copy_from_slice(&IpAddr::new(100, 64, 0, 2).to_bytes());
+        // the reply comes in on the link the request went out on
+        arp::ingress(&dev, &reply).unwrap();
+
+        let frame = last_captured_frame("arpwait0").unwrap();
+        let eth = ethernet::EthHeader::LEN;
+        assert_eq!(&frame[0..6], &peer_mac);
+        assert_eq!(&frame[12..14], &ethernet::ETHERTYPE_IPV4.to_be_bytes());
+        let pkt = wire::Packet::new_checked(&frame[eth..]).unwrap();
+        assert_eq!(pkt.dst(), dst.0);
+        assert_eq!(&frame[eth + wire::MIN_HEADER_LEN..], &[0xbe, 0xef]);
+    }
+
+    #[test_case]
+    fn get_source_address_matches_subnet() {
+        let dev = NetDevice::new(test_config("srctest0", MacAddr::ZERO, discard_transmit));
+        net_device_register(dev).unwrap();
+        let mask = IpAddr::new(255, 255, 255, 0);
+        net_interface_setup("srctest0", IpAddr::new(10, 10, 1, 2), mask).unwrap();
+        net_interface_setup("srctest0", IpAddr::new(10, 10, 2, 2), mask).unwrap();
+        route::add_route(route::Route {
+            dest: IpAddr::new(10, 10, 0, 0),
+            mask: IpAddr::new(255, 255, 0, 0),
+            gateway: None,
+            dev: "srctest0",
+        })
+        .unwrap();
+
+        assert_eq!(
+            get_source_address(IpAddr::new(10, 10, 2, 9)),
+            Some(IpAddr::new(10, 10, 2, 2))
+        );
+        assert_eq!(
+            get_source_address(IpAddr::new(10, 10, 1, 9)),
+            Some(IpAddr::new(10, 10, 1, 2))
+        );
+        assert_eq!(
+            get_source_address(IpAddr::new(10, 10, 3, 9)),
+            Some(IpAddr::new(10, 10, 1, 2))
+        );
+        assert_eq!(get_source_address(IpAddr::LOOPBACK), Some(IpAddr::LOOPBACK));
+        assert_eq!(get_source_address(IpAddr::new(172, 16, 0, 1)), None);
+    }
 }
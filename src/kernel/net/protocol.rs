@@ -1,6 +1,9 @@
 use crate::{
     error::{Error, Result},
-    net::{device::NetDevice, device::NetDeviceFlags},
+    net::{
+        device::{NetDevice, NetDeviceFlags, NetDeviceType},
+        ethernet,
+    },
     println,
     spinlock::Mutex,
     trace,
@@ -19,14 +22,21 @@ pub struct Protocol {
     handler: fn(&NetDevice, &[u8]) -> Result<()>,
 }
 
+pub struct EthProtocol {
+    ethertype: u16,
+    handler: fn(&NetDevice, &[u8]) -> Result<()>,
+}
+
 struct ProtocolRegistry {
     protocols: Mutex<Vec<Protocol>>,
+    eth_protocols: Mutex<Vec<EthProtocol>>,
 }
 
 impl ProtocolRegistry {
     const fn new() -> Self {
         Self {
             protocols: Mutex::new(Vec::new(), "net_protocols"),
+            eth_protocols: Mutex::new(Vec::new(), "eth_protocols"),
         }
     }
 
@@ -51,6 +61,27 @@ impl ProtocolRegistry {
         }
     }
 
+    fn eth_register(&self, ethertype: u16, handler: fn(&NetDevice, &[u8]) -> Result<()>) {
+        let mut protocols = self.eth_protocols.lock();
+        protocols.push(EthProtocol { ethertype, handler });
+        drop(protocols);
+        println!("[net] Registered ethertype: 0x{:04x}", ethertype);
+    }
+
+    fn eth_handler(&self, dev: &NetDevice, ethertype: u16, data: &[u8]) -> Result<()> {
+        let handler = {
+            let protocols = self.eth_protocols.lock();
+            protocols
+                .iter()
+                .find(|p| p.ethertype == ethertype)
+                .map(|p| p.handler)
+        };
+        match handler {
+            Some(h) => h(dev, data),
+            None => Err(Error::ProtocolNotFound),
+        }
+    }
+
     fn ingress(&self, dev: &NetDevice, data: &[u8]) -> Result<()> {
         trace!(
             DRIVER,
@@ -62,6 +93,9 @@ impl ProtocolRegistry {
         if dev.flags().contains(NetDeviceFlags::LOOPBACK) {
             return self.handler(dev, ProtocolType::IP, data);
         }
+        if dev.dev_type == NetDeviceType::Ethernet {
+            return ethernet::ingress(dev, data);
+        }
 
         Err(Error::UnsupportedDevice)
     }
@@ -77,6 +111,103 @@ pub fn net_protocol_handler(dev: &NetDevice, ptype: ProtocolType, data: &[u8]) -
     PROTOCOLS.handler(dev, ptype, data)
 }
 
+pub fn eth_protocol_register(ethertype: u16, handler: fn(&NetDevice, &[u8]) -> Result<()>) {
+    PROTOCOLS.eth_register(ethertype, handler)
+}
+
+pub fn net_eth_protocol_handler(dev: &NetDevice, ethertype: u16, data: &[u8]) -> Result<()> {
+    PROTOCOLS.eth_handler(dev, ethertype, data)
+}
+
+// Frames nobody wanted (no protocol, no socket) are not receive errors;
+// only ones dropped as malformed are.
+fn is_malformed(err: &Error) -> bool {
+    matches!(
+        err,
+        Error::PacketTooShort
+            | Error::PacketTruncated
+            | Error::PacketTooLarge
+            | Error::InvalidVersion
+            | Error::InvalidHeaderLen
+            | Error::InvalidLength
+            | Error::ChecksumError
+    )
+}
+
 pub fn net_ingress_handler(dev: &NetDevice, data: &[u8]) -> Result<()> {
-    PROTOCOLS.ingress(dev, data)
+    let result = PROTOCOLS.ingress(dev, data);
+    dev.record_rx(data.len(), !result.as_ref().is_err_and(is_malformed));
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::arp;
+    use crate::net::device::tests::{discard_transmit, test_config};
+    use crate::net::ethernet::{EthHeader, MacAddr, ETHERTYPE_ARP};
+    use crate::net::ip::IpAddr;
+
+    fn eth_dev() -> NetDevice {
+        NetDevice::new(test_config(
+            "prototest0",
+            MacAddr([0x02, 0, 0, 0, 0, 0x01]),
+            discard_transmit,
+        ))
+    }
+
+    #[test_case]
+    fn ingress_dispatches_arp_frames() {
+        let dev = eth_dev();
+        let sender_mac = [0x02, 0, 0, 0, 0, 0x77];
+        let sender_ip = IpAddr::new(192, 0, 2, 77);
+
+        let mut frame = [0u8; EthHeader::LEN + 28];
+        frame[0..6].copy_from_slice(&dev.hw_addr.0);
+        frame[6..12].copy_from_slice(&sender_mac);
+        frame[12..14].copy_from_slice(&ETHERTYPE_ARP.to_be_bytes());
+        let arp = &mut frame[EthHeader::LEN..];
+        arp[0..2].copy_from_slice(&1u16.to_be_bytes()); // htype: ethernet
+        arp[2..4].copy_from_slice(&0x0800u16.to_be_bytes()); // ptype: ipv4
+        arp[4] = 6;
+        arp[5] = 4;
+        arp[6..8].copy_from_slice(&2u16.to_be_bytes()); // oper: reply
+        arp[8..14].copy_from_slice(&sender_mac);
+        arp[14..18].copy_from_slice(&sender_ip.to_bytes());
+        arp[18..24].copy_from_slice(&dev.hw_addr.0);
+        arp[24..28].copy_from_slice(&IpAddr::new(192, 0, 2, 1).to_bytes());
+
+        net_ingress_handler(&dev, &frame).unwrap();
+        assert_eq!(arp::lookup(sender_ip), Some(MacAddr(sender_mac)));
+    }
+
+    #[test_case]
+    fn ingress_dispatches_registered_ethertype() {
+        fn accept(_dev: &NetDevice, data: &[u8]) -> Result<()> {
+            assert_eq!(data, &[0xAB; 4]);
+            Ok(())
+        }
+        eth_protocol_register(0x88B5, accept);
+
+        let dev = eth_dev();
+        let mut frame = [0xABu8; EthHeader::LEN + 4];
+        frame[12..14].copy_from_slice(&0x88B5u16.to_be_bytes());
+        net_ingress_handler(&dev, &frame).unwrap();
+    }
+
+    #[test_case]
+    fn rx_errors_count_only_malformed_frames() {
+        let dev = eth_dev();
+        let mut frame = [0u8; EthHeader::LEN + 4];
+        frame[12..14].copy_from_slice(&0x88B6u16.to_be_bytes());
+        assert_eq!(
+            net_ingress_handler(&dev, &frame),
+            Err(Error::UnsupportedProtocol)
+        );
+        assert_eq!(dev.stats().rx_errors, 0);
+
+        assert!(net_ingress_handler(&dev, &frame[..6]).is_err());
+        let stats = dev.stats();
+        assert_eq!((stats.rx_packets, stats.rx_errors), (2, 1));
+    }
 }
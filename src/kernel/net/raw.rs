@@ -0,0 +1,267 @@
+use super::{
+    icmp::{self, EchoTracker},
+    ip::{self, egress_route_ttl, IpAddr, IpHeader},
+    tcp::timer::get_time_cycles,
+};
+use crate::{
+    error::{Error, Result},
+    net::{flush_tx, socket::SocketHandle, socket::SocketSet},
+    spinlock::Mutex,
+    stat::IcmpEchoStats,
+    trace,
+};
+use alloc::{collections::VecDeque, vec::Vec};
+
+// Raw IP sockets: the user builds everything above the IP header, and
+// every datagram carrying the socket's protocol is queued whole, header
+// included, alongside the kernel's own handling of it.
+pub struct RawSocket {
+    protocol: u8,
+    recv_queue: VecDeque<Vec<u8>>,
+    dropped: u64,
+    // only used by ICMP sockets
+    echo: EchoTracker,
+}
+
+impl RawSocket {
+    const RECV_QUEUE_DEPTH: usize = 32;
+
+    const fn new(protocol: u8) -> Self {
+        Self {
+            protocol,
+            recv_queue: VecDeque::new(),
+            dropped: 0,
+            echo: EchoTracker::new(),
+        }
+    }
+
+    fn enqueue(&mut self, datagram: &[u8]) {
+        if self.recv_queue.len() >= Self::RECV_QUEUE_DEPTH {
+            self.dropped += 1;
+            return;
+        }
+        self.recv_queue.push_back(datagram.to_vec());
+    }
+}
+
+struct Raw {
+    sockets: Mutex<SocketSet<RawSocket>>,
+}
+
+impl Raw {
+    const SOCKET_CAPACITY: usize = 16;
+
+    const fn new() -> Self {
+        Self {
+            sockets: Mutex::new(SocketSet::new(Self::SOCKET_CAPACITY), "raw_sockets"),
+        }
+    }
+
+    fn socket_alloc(&self, protocol: u8) -> Result<usize> {
+        if protocol == 0 {
+            return Err(Error::InvalidArgument);
+        }
+        let mut sockets = self.sockets.lock();
+        let handle = sockets.alloc(RawSocket::new(protocol))?;
+        Ok(handle.index())
+    }
+
+    fn socket_free(&self, index: usize) -> Result<()> {
+        let mut sockets = self.sockets.lock();
+        let handle = SocketHandle::new(index);
+        if handle.index() >= Self::SOCKET_CAPACITY {
+            return Err(Error::InvalidSocketIndex);
+        }
+        match sockets.get(handle) {
+            Ok(_) => sockets.free(handle),
+            Err(Error::InvalidSocketState) => Err(Error::InvalidSocketIndex),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn socket_send(&self, index: usize, dst: IpAddr, data: &[u8], ttl: u8) -> Result<usize> {
+        let sockets = self.sockets.lock();
+        let protocol = sockets.get(SocketHandle::new(index))?.protocol;
+        drop(sockets);
+
+        trace!(
+            IP,
+            "[raw] sending {} bytes -> {:?} proto={} ttl={}",
+            data.len(),
+            dst.to_bytes(),
+            protocol,
+            ttl
+        );
+        // Loopback answers from inside egress, so the request has to be on
+        // record before it goes out.
+        let echo = Some(protocol)
+            .filter(|&p| p == IpHeader::ICMP)
+            .and_then(|_| icmp::echo_request_id_seq(data));
+        if let Some((id, seq)) = echo {
+            let mut sockets = self.sockets.lock();
+            sockets
+                .get_mut(SocketHandle::new(index))?
+                .echo
+                .sent(id, seq, get_time_cycles());
+        }
+
+        let result = egress_route_ttl(dst, protocol, data, ttl);
+        flush_tx();
+        if let (Err(_), Some((id, seq))) = (&result, echo) {
+            let mut sockets = self.sockets.lock();
+            if let Ok(socket) = sockets.get_mut(SocketHandle::new(index)) {
+                socket.echo.unsent(id, seq);
+            }
+        }
+        result?;
+        Ok(data.len())
+    }
+
+    fn echo_stats(&self, index: usize) -> Result<IcmpEchoStats> {
+        let sockets = self.sockets.lock();
+        let socket = sockets.get(SocketHandle::new(index))?;
+        Ok(socket.echo.stats())
+    }
+
+    fn socket_recv(&self, index: usize, buf: &mut [u8]) -> Result<usize> {
+        let mut sockets = self.sockets.lock();
+        let socket = sockets.get_mut(SocketHandle::new(index))?;
+        let datagram = socket.recv_queue.pop_front().ok_or(Error::WouldBlock)?;
+        let len = datagram.len().min(buf.len());
+        buf[..len].copy_from_slice(&datagram[..len]);
+        Ok(len)
+    }
+
+    fn wants(&self, protocol: u8) -> bool {
+        let sockets = self.sockets.lock();
+        let wanted = sockets
+            .iter()
+            .any(|(_, socket)| socket.protocol == protocol);
+        wanted
+    }
+
+    fn ingress(&self, protocol: u8, datagram: &[u8]) {
+        let reply = Some(protocol)
+            .filter(|&p| p == IpHeader::ICMP)
+            .and_then(|_| ip::wire::Packet::new_checked(datagram).ok())
+            .and_then(|pkt| datagram.get(pkt.header_len()..))
+            .and_then(icmp::echo_reply_id_seq);
+        let mut now = None;
+        let mut sockets = self.sockets.lock();
+        for (_, socket) in sockets.iter_mut() {
+            if socket.protocol != protocol {
+                continue;
+            }
+            if let Some((id, seq)) = reply {
+                if socket.echo.is_waiting() {
+                    let now = *now.get_or_insert_with(get_time_cycles);
+                    socket.echo.replied(id, seq, now);
+                }
+            }
+            socket.enqueue(datagram);
+        }
+    }
+}
+
+static RAW: Raw = Raw::new();
+
+pub fn socket_alloc(protocol: u8) -> Result<usize> {
+    RAW.socket_alloc(protocol)
+}
+
+pub fn socket_free(index: usize) -> Result<()> {
+    RAW.socket_free(index)
+}
+
+pub fn socket_send(index: usize, dst: IpAddr, data: &[u8], ttl: u8) -> Result<usize> {
+    RAW.socket_send(index, dst, data, ttl)
+}
+
+pub fn socket_recv(index: usize, buf: &mut [u8]) -> Result<usize> {
+    RAW.socket_recv(index, buf)
+}
+
+// Round trips of the echo requests an ICMP socket sent.
+pub fn echo_stats(index: usize) -> Result<IcmpEchoStats> {
+    RAW.echo_stats(index)
+}
+
+// Lets IP skip rebuilding the datagram when nobody listens.
+pub fn wants(protocol: u8) -> bool {
+    RAW.wants(protocol)
+}
+
+pub fn ingress(protocol: u8, datagram: &[u8]) {
+    RAW.ingress(protocol, datagram)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{get_time_cycles, Raw, RawSocket, SocketHandle};
+    use crate::error::Error;
+    use crate::net::ip::IpHeader;
+    use alloc::vec;
+
+    #[test_case]
+    fn raw_socket_alloc_rejects_protocol_zero() {
+        let raw = Raw::new();
+        assert_eq!(raw.socket_alloc(0), Err(Error::InvalidArgument));
+        let idx = raw.socket_alloc(IpHeader::ICMP).unwrap();
+        raw.socket_free(idx).unwrap();
+        assert_eq!(raw.socket_free(idx), Err(Error::InvalidSocketIndex));
+    }
+
+    #[test_case]
+    fn raw_ingress_matches_protocol() {
+        let raw = Raw::new();
+        let icmp = raw.socket_alloc(IpHeader::ICMP).unwrap();
+        let udp = raw.socket_alloc(IpHeader::UDP).unwrap();
+        assert!(raw.wants(IpHeader::ICMP));
+        assert!(!raw.wants(IpHeader::TCP));
+
+        raw.ingress(IpHeader::ICMP, &[0x45, 1, 2, 3]);
+        let mut buf = [0u8; 2];
+        assert_eq!(raw.socket_recv(icmp, &mut buf), Ok(2));
+        assert_eq!(buf, [0x45, 1]);
+        assert_eq!(raw.socket_recv(icmp, &mut buf), Err(Error::WouldBlock));
+        assert_eq!(raw.socket_recv(udp, &mut buf), Err(Error::WouldBlock));
+    }
+
+    #[test_case]
+    fn raw_icmp_socket_matches_echo_replies() {
+        let raw = Raw::new();
+        let icmp = raw.socket_alloc(IpHeader::ICMP).unwrap();
+        raw.sockets
+            .lock()
+            .get_mut(SocketHandle::new(icmp))
+            .unwrap()
+            .echo
+            .sent(0x1234, 1, get_time_cycles());
+
+        let mut datagram = vec![0x45, 0, 0, 28, 0, 0, 0, 0, 64, IpHeader::ICMP];
+        datagram.resize(20, 0);
+        // a request carrying the outstanding id/seq is not an answer
+        datagram.extend_from_slice(&[8, 0, 0, 0, 0x12, 0x34, 0, 1]);
+        raw.ingress(IpHeader::ICMP, &datagram);
+        assert_eq!(raw.echo_stats(icmp).unwrap().received, 0);
+        datagram[20] = 0; // echo reply
+        raw.ingress(IpHeader::ICMP, &datagram);
+
+        let stats = raw.echo_stats(icmp).unwrap();
+        assert_eq!((stats.sent, stats.received), (1, 1));
+        // both datagrams are still delivered whole
+        let mut buf = [0u8; 64];
+        assert_eq!(raw.socket_recv(icmp, &mut buf), Ok(28));
+        assert_eq!(raw.socket_recv(icmp, &mut buf), Ok(28));
+    }
+
+    #[test_case]
+    fn raw_queue_drops_when_full() {
+        let mut socket = RawSocket::new(IpHeader::ICMP);
+        for _ in 0..RawSocket::RECV_QUEUE_DEPTH + 2 {
+            socket.enqueue(&[0x45]);
+        }
+        assert_eq!(socket.recv_queue.len(), RawSocket::RECV_QUEUE_DEPTH);
+        assert_eq!(socket.dropped, 2);
+    }
+}
@@ -10,6 +10,39 @@ pub struct Route {
     pub dev: &'static str,
 }
 
+impl Route {
+    pub fn prefix_len(&self) -> u32 {
+        mask_len(self.mask)
+    }
+}
+
+impl core::fmt::Display for Route {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        if self.mask.0 == 0 {
+            write!(f, "default")?;
+        } else {
+            write!(f, "{}/{}", self.dest, self.prefix_len())?;
+        }
+        if let Some(gateway) = self.gateway {
+            write!(f, " via {}", gateway)?;
+        }
+        write!(f, " dev {}", self.dev)
+    }
+}
+
+impl core::fmt::Debug for Route {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let mut s = f.debug_struct("Route");
+        s.field("dest", &format_args!("{}", self.dest))
+            .field("mask", &format_args!("{}", self.mask));
+        match self.gateway {
+            Some(gateway) => s.field("gateway", &format_args!("{}", gateway)),
+            None => s.field("gateway", &format_args!("none")),
+        };
+        s.field("dev", &self.dev).finish()
+    }
+}
+
 struct RouteTable {
     routes: Mutex<[Option<Route>; 8]>,
 }
@@ -36,7 +69,7 @@ impl RouteTable {
         let routes = self.routes.lock();
         let mut best: Option<Route> = None;
         for r in routes.iter().flatten() {
-            if (dst.0 & r.mask.0) == (r.dest.0 & r.mask.0)
+            if r.dest.subnet_contains(dst, r.mask)
                 && best
                     .map(|b| mask_len(r.mask) > mask_len(b.mask))
                     .unwrap_or(true)
@@ -66,6 +99,7 @@ fn mask_len(mask: IpAddr) -> u32 {
 mod tests {
     use super::*;
     use crate::error::Error;
+    use alloc::string::ToString;
 
     #[test_case]
     fn mask_len_counts_ones() {
@@ -124,4 +158,23 @@ mod tests {
             .unwrap_err();
         assert_eq!(err, Error::StorageFull);
     }
+
+    #[test_case]
+    fn display_matches_ip_route() {
+        let default = Route {
+            dest: IpAddr(0),
+            mask: IpAddr(0),
+            gateway: Some(IpAddr::new(192, 0, 2, 1)),
+            dev: "eth0",
+        };
+        assert_eq!(default.to_string(), "default via 192.0.2.1 dev eth0");
+
+        let link = Route {
+            dest: IpAddr::new(10, 0, 2, 0),
+            mask: IpAddr::new(255, 255, 255, 0),
+            gateway: None,
+            dev: "eth0",
+        };
+        assert_eq!(link.to_string(), "10.0.2.0/24 dev eth0");
+    }
 }
@@ -2,19 +2,24 @@ mod retransmit;
 mod segment;
 mod socket;
 mod state;
-mod timer;
+pub(crate) mod timer;
 mod wire;
 
 pub use socket::Socket;
 pub use socket::{
-    ingress, poll, socket_accept, socket_alloc, socket_free, socket_get, socket_get_mut,
+    ingress, init_iss_secret, panic_dump, poll, set_reject_with_icmp, socket_accept, socket_alloc,
+    socket_close, socket_free, socket_get, socket_get_mut, socket_listen, wait_for_data,
+    wait_for_send_space, TCP_SOCKET_SIZE,
 };
 pub use state::State;
 
 #[cfg(test)]
 mod tests {
     use super::{
-        segment::SegmentInfo, segment::SegmentProcessor, socket::Socket, state::State, wire,
+        segment::{SegmentEvent, SegmentInfo, SegmentProcessor},
+        socket::Socket,
+        state::State,
+        wire,
     };
     use crate::net::ip::IpAddr;
 
@@ -87,6 +92,121 @@ mod tests {
             }
         }
 
+        #[test_case]
+        fn test_syn_mss_option_roundtrip() {
+            let opts = [wire::TcpOption::Mss(1460)];
+            let header_len = wire::HEADER_LEN + wire::PacketMut::total_option_bytes(&opts);
+            assert_eq!(header_len, 24);
+
+            let mut buffer = [0u8; 24];
+            {
+                let mut packet = wire::PacketMut::new_unchecked(&mut buffer);
+                packet.set_src_port(49152);
+                packet.set_dst_port(80);
+                packet.set_seq_number(1000);
+                packet.set_flags(wire::field::FLG_SYN);
+                packet.set_window_len(65535);
+                packet.set_options(&opts).unwrap();
+            }
+
+            let packet = wire::Packet::new_checked(&buffer).unwrap();
+            assert_eq!(packet.header_len(), 24);
+            assert_eq!(packet.options().unwrap(), opts);
+            assert!(packet.payload().is_empty());
+        }
+
+        #[test_case]
+        fn test_options_padded_to_word() {
+            let opts = [
+                wire::TcpOption::Mss(536),
+                wire::TcpOption::WindowScale(7),
+                wire::TcpOption::SackPermitted,
+            ];
+            assert_eq!(wire::PacketMut::total_option_bytes(&opts), 12);
+
+            let mut buffer = [0xffu8; 32];
+            let mut packet = wire::PacketMut::new_unchecked(&mut buffer);
+            packet.set_options(&opts).unwrap();
+            assert_eq!(packet.header_len(), 32);
+            assert_eq!(packet.options_mut()[9..], [0, 0, 0]);
+
+            let mut short = [0u8; 24];
+            let err = wire::PacketMut::new_unchecked(&mut short)
+                .set_options(&opts)
+                .unwrap_err();
+            assert_eq!(err, Error::InvalidHeaderLen);
+
+            let packet = wire::Packet::new_checked(&buffer).unwrap();
+            assert_eq!(packet.options().unwrap(), opts);
+        }
+
+        #[test_case]
+        fn test_payload_mut_skips_options() {
+            let opts = [
+                wire::TcpOption::Mss(1460),
+                wire::TcpOption::WindowScale(7),
+                wire::TcpOption::SackPermitted,
+            ];
+            let header_len = wire::HEADER_LEN + wire::PacketMut::total_option_bytes(&opts);
+            assert_eq!(header_len, 32);
+
+            let mut buffer = [0u8; 36];
+            {
+                let mut packet = wire::PacketMut::new_unchecked(&mut buffer);
+                packet.set_options(&opts).unwrap();
+                assert_eq!(packet.header_len(), header_len);
+                let payload = packet.payload_mut();
+                assert_eq!(payload.len(), 4);
+                payload.copy_from_slice(&[0xde, 0xad, 0xbe, 0xef]);
+            }
+
+            let packet = wire::Packet::new_checked(&buffer).unwrap();
+            assert_eq!(packet.options().unwrap(), opts);
+            assert_eq!(packet.payload(), [0xde, 0xad, 0xbe, 0xef]);
+        }
+
+        #[test_case]
+        fn test_timestamps_option_roundtrip() {
+            let ts = wire::TsOption {
+                tsval: 0x0102_0304,
+                tsecr: 0x0a0b_0c0d,
+            };
+            let opts = [wire::TcpOption::NoOp, wire::TcpOption::NoOp, ts.into()];
+            assert_eq!(wire::PacketMut::total_option_bytes(&opts), 12);
+
+            let mut buffer = [0u8; 32];
+            wire::PacketMut::new_unchecked(&mut buffer)
+                .set_options(&opts)
+                .unwrap();
+            assert_eq!(&buffer[22..24], &[8, wire::TsOption::LEN as u8]);
+
+            let packet = wire::Packet::new_checked(&buffer).unwrap();
+            assert_eq!(packet.timestamps(), Some(ts));
+        }
+
+        #[test_case]
+        fn test_sack_option_roundtrip() {
+            let mut blocks = wire::SackBlocks::new();
+            assert!(blocks.push(1200, 1300));
+            assert!(blocks.push(1500, 1600));
+            let opts = [
+                wire::TcpOption::NoOp,
+                wire::TcpOption::NoOp,
+                wire::TcpOption::Sack(blocks),
+            ];
+            assert_eq!(wire::PacketMut::total_option_bytes(&opts), 20);
+
+            let mut buffer = [0u8; 40];
+            wire::PacketMut::new_unchecked(&mut buffer)
+                .set_options(&opts)
+                .unwrap();
+            assert_eq!(&buffer[22..24], &[5, 18]);
+
+            let packet = wire::Packet::new_checked(&buffer).unwrap();
+            let parsed = wire::SackBlocks::find(&packet.options().unwrap()).unwrap();
+            assert_eq!(parsed.as_slice(), &[(1200, 1300), (1500, 1600)]);
+        }
+
         #[test_case]
         fn test_checksum_verification() {
             let src_ip = IpAddr(0x0a000001); // 10.0.0.1
@@ -138,14 +258,55 @@ mod tests {
             socket.parent = Some(0);
 
             let seg = SegmentInfo::new(5, 15, 0, 4096, wire::field::FLG_ACK, &[]);
-            let mut proc = SegmentProcessor::new(&mut socket, seg);
+            let mut proc = SegmentProcessor::with_log(&mut socket, seg);
             assert!(proc.handle_ack());
+            let log = proc.take_log();
+            assert_eq!(
+                log.events(),
+                &[
+                    SegmentEvent::AckAdvanced(15),
+                    SegmentEvent::WindowUpdate(4096),
+                    SegmentEvent::StateTransition {
+                        from: State::SynReceived,
+                        to: State::Established,
+                    },
+                ]
+            );
+            assert_eq!(
+                alloc::format!("{}", log),
+                "ack=15 wnd=4096 SynReceived->Established"
+            );
             assert_eq!(socket.state, State::Established);
             assert!(socket.accept_ready);
             assert_eq!(socket.snd_una, 15);
             assert_eq!(socket.snd_wnd, 4096);
         }
 
+        #[test_case]
+        fn state_transitions_record_entry_time() {
+            let mut socket = Socket::new(1, 1);
+            socket.state = State::SynReceived;
+            socket.state_entered_at = u64::MAX;
+            socket.snd_una = 10;
+            socket.snd_nxt = 20;
+
+            let seg = SegmentInfo::new(5, 15, 0, 4096, wire::field::FLG_ACK, &[]);
+            assert!(SegmentProcessor::new(&mut socket, seg).handle_ack());
+            assert_eq!(socket.state, State::Established);
+            let entered = socket.state_entered_at();
+            assert!(entered <= super::super::timer::get_time_ms());
+
+            socket.state_entered_at = 1_000;
+            socket.set_state(State::Established);
+            assert_eq!(socket.state_entered_at(), 1_000);
+            assert_eq!(socket.time_in_state_ms(4_500), 3_500);
+            assert_eq!(socket.time_in_state_ms(500), 0);
+
+            socket.close();
+            assert_eq!(socket.state, State::FinWait1);
+            assert_ne!(socket.state_entered_at(), 1_000);
+        }
+
         #[test_case]
         fn synsent_invalid_ack_sends_rst() {
             let mut socket = Socket::new(1, 1);
@@ -156,8 +317,9 @@ mod tests {
             socket.rcv_wnd = 1024;
 
             let seg = SegmentInfo::new(1, 100, 0, 0, wire::field::FLG_ACK, &[]);
-            let mut proc = SegmentProcessor::new(&mut socket, seg);
+            let mut proc = SegmentProcessor::with_log(&mut socket, seg);
             proc.run();
+            assert_eq!(proc.take_log().events(), &[SegmentEvent::RstSent]);
 
             assert_eq!(socket.state, State::SynSent);
             let req = socket.pending.pop_front().unwrap();
@@ -183,12 +345,1304 @@ mod tests {
                 wire::field::FLG_ACK,
                 &payload,
             );
-            let mut proc = SegmentProcessor::new(&mut socket, seg);
+            let mut proc = SegmentProcessor::with_log(&mut socket, seg);
             proc.run();
+            assert_eq!(
+                proc.take_log().events(),
+                &[
+                    SegmentEvent::AckAdvanced(2),
+                    SegmentEvent::WindowUpdate(1024),
+                    SegmentEvent::DataQueued(3),
+                ]
+            );
 
             assert_eq!(socket.rx_buf.len(), 3);
             assert_eq!(socket.rcv_nxt, 103);
+            assert!(socket.pending.is_empty());
+            assert!(socket.ack_deadline.is_some());
+        }
+
+        #[test_case]
+        fn delayed_ack_coalesces_with_outgoing_data() {
+            let mut socket = nagle_socket(true);
+            let seg = SegmentInfo::new(300, 100, 4, 4096, wire::field::FLG_ACK, &[7; 4]);
+            SegmentProcessor::new(&mut socket, seg).run();
+            assert!(socket.pending.is_empty());
+            assert!(socket.ack_deadline.is_some());
+
+            // reply data carries the ACK, so no ACK-only segment is sent
+            assert_eq!(socket.send_slice(&[1; 8]), Ok(8));
             assert_eq!(socket.pending.len(), 1);
+            let sent = socket.pending.pop_front().unwrap();
+            assert_eq!((sent.ack, sent.payload.len()), (304, 8));
+            assert_eq!(socket.ack_deadline, None);
+
+            let seg = SegmentInfo::new(304, 108, 4, 4096, wire::field::FLG_ACK, &[8; 4]);
+            SegmentProcessor::new(&mut socket, seg).run();
+            let deadline = socket.ack_deadline.unwrap();
+            assert_eq!(socket.next_deadline(), Some(deadline));
+            socket.poll_delayed_ack(deadline - 1);
+            assert!(socket.pending.is_empty());
+            socket.poll_delayed_ack(deadline);
+            let ack = socket.pending.pop_front().unwrap();
+            assert_eq!((ack.ack, ack.payload.len()), (308, 0));
+            assert_eq!(socket.ack_deadline, None);
+        }
+
+        #[test_case]
+        fn second_segment_is_acked_at_once() {
+            let mut socket = nagle_socket(true);
+            let seg = SegmentInfo::new(300, 100, 4, 4096, wire::field::FLG_ACK, &[7; 4]);
+            SegmentProcessor::new(&mut socket, seg).run();
+            let seg = SegmentInfo::new(304, 100, 4, 4096, wire::field::FLG_ACK, &[8; 4]);
+            SegmentProcessor::new(&mut socket, seg).run();
+            let ack = socket.pending.pop_front().unwrap();
+            assert_eq!(ack.ack, 308);
+            assert_eq!(socket.ack_deadline, None);
+        }
+
+        fn dup_ack(socket: &mut Socket) {
+            let seg = SegmentInfo::new(500, 100, 0, 1024, wire::field::FLG_ACK, &[]);
+            SegmentProcessor::new(socket, seg).run();
+        }
+
+        #[test_case]
+        fn triple_dup_ack_triggers_fast_retransmit() {
+            let mut socket = Socket::new(64, 64);
+            socket.state = State::Established;
+            socket.rcv_nxt = 500;
+            socket.rcv_wnd = 64;
+            socket.snd_una = 100;
+            socket.snd_nxt = 100;
+            socket.snd_wnd = 1024;
+            socket.egress(wire::field::FLG_ACK, &[0xaa; 10]).unwrap();
+            socket.snd_nxt = 110;
+            socket.pending.clear();
+
+            socket.cwnd = 8 * socket.mss as u32;
+
+            dup_ack(&mut socket);
+            dup_ack(&mut socket);
+            assert_eq!(socket.dup_ack_count, 2);
+            assert!(socket.pending.is_empty());
+            dup_ack(&mut socket);
+
+            // resent before any timer runs
+            let req = socket.pending.pop_front().unwrap();
+            assert_eq!(req.seq, 100);
+            assert_eq!(req.payload.len(), 10);
+            let mss = socket.mss as u32;
+            assert_eq!((socket.ssthresh, socket.cwnd), (4 * mss, 4 * mss));
+            assert_eq!(socket.retransmit_count, 1);
+
+            let seg = SegmentInfo::new(500, 110, 0, 1024, wire::field::FLG_ACK, &[]);
+            SegmentProcessor::new(&mut socket, seg).run();
+            assert_eq!(socket.snd_una, 110);
+            assert_eq!(socket.dup_ack_count, 0);
+        }
+
+        #[test_case]
+        fn triple_dup_ack_at_ingress_resends_first_unacked() {
+            use super::super::socket::Tcp;
+            use crate::net::ip::IpEndpoint;
+
+            let local = IpEndpoint::new(IpAddr(0xC000_0202), 7083);
+            let peer = IpEndpoint::new(IpAddr(0xC000_0201), 40010);
+            let tcp = Tcp::with_capacity(4);
+            let server = tcp.socket_alloc().unwrap();
+            tcp.socket_get_mut(server, |socket| {
+                socket.listen(IpEndpoint::new(IpAddr(0), 7083)).unwrap()
+            })
+            .unwrap();
+            let child = handshake(&tcp, server, local, peer);
+
+            let una = tcp
+                .socket_get_mut(child, |socket| {
+                    socket.set_nodelay(true);
+                    assert_eq!(socket.send_slice(&[0xaa; 10]), Ok(10));
+                    assert_eq!(socket.send_slice(&[0xbb; 10]), Ok(10));
+                    // both segments went out already
+                    assert_eq!(socket.pending.len(), 2);
+                    socket.pending.clear();
+                    socket.snd_una
+                })
+                .unwrap();
+
+            let dup_ack = segment(peer, local, 1001, una, wire::field::FLG_ACK);
+            for _ in 0..2 {
+                let (sends, _) = tcp
+                    .process_segment(peer.addr, local.addr, &dup_ack)
+                    .unwrap();
+                assert!(sends.is_empty());
+            }
+            let (sends, _) = tcp
+                .process_segment(peer.addr, local.addr, &dup_ack)
+                .unwrap();
+            assert_eq!(sends.len(), 1);
+            assert_eq!(sends[0].seq, una);
+            assert_eq!(sends[0].payload, [0xaa; 10]);
+            let resent = tcp
+                .socket_get(child, |socket| socket.retransmit_count)
+                .unwrap();
+            assert_eq!(resent, 1);
+        }
+
+        fn zero_window_socket() -> Socket {
+            let mut socket = Socket::new(64, 64);
+            socket.state = State::Established;
+            socket.rcv_nxt = 500;
+            socket.rcv_wnd = 64;
+            socket.snd_una = 100;
+            socket.snd_nxt = 100;
+            socket.snd_wl1 = 500;
+            socket.snd_wl2 = 100;
+            socket.snd_wnd = 0;
+            socket.send_slice(&[1, 2, 3, 4, 5]).unwrap();
+            socket
+        }
+
+        #[test_case]
+        fn persist_timer_probes_zero_window() {
+            let mut socket = zero_window_socket();
+            assert!(socket.pending.is_empty());
+            let first = socket.persist_deadline.unwrap();
+            assert_eq!(socket.next_deadline(), Some(first));
+
+            socket.poll_persist(first - 1);
+            assert!(socket.pending.is_empty());
+            socket.poll_persist(first);
+            let probe = socket.pending.pop_front().unwrap();
+            assert_eq!((probe.seq, probe.payload.as_slice()), (100, &[1u8][..]));
+            assert_eq!(socket.snd_nxt, 100);
+            assert_eq!(socket.persist_deadline, Some(first + 400));
+
+            // backoff doubles up to the cap
+            for _ in 0..12 {
+                socket.poll_persist(socket.persist_deadline.unwrap());
+            }
+            assert_eq!(socket.persist_backoff, 60_000);
+
+            let seg = SegmentInfo::new(500, 100, 0, 64, wire::field::FLG_ACK, &[]);
+            SegmentProcessor::new(&mut socket, seg).run();
+            assert_eq!(socket.snd_nxt, 105);
+            assert_eq!(socket.persist_deadline, None);
+        }
+
+        #[test_case]
+        fn accepted_probe_byte_is_acknowledged() {
+            let mut socket = zero_window_socket();
+            socket.poll_persist(socket.persist_deadline.unwrap());
+
+            let seg = SegmentInfo::new(500, 101, 0, 0, wire::field::FLG_ACK, &[]);
+            SegmentProcessor::new(&mut socket, seg).run();
+            assert_eq!((socket.snd_una, socket.snd_nxt), (101, 101));
+            assert_eq!(socket.tx_buf.len(), 4);
+            assert!(socket.persist_deadline.is_some());
+        }
+
+        #[test_case]
+        fn window_update_unblocks_sender() {
+            use crate::error::Error;
+
+            let index = super::super::socket_alloc().unwrap();
+            super::super::socket_get_mut(index, |socket| {
+                socket.state = State::Established;
+                socket.rcv_nxt = 500;
+                socket.rcv_wnd = 64;
+                socket.snd_una = 100;
+                socket.snd_nxt = 100;
+                socket.snd_wl1 = 500;
+                socket.snd_wl2 = 100;
+                socket.snd_wnd = 0;
+                let cap = socket.tx_capacity;
+                assert_eq!(socket.send_slice(&alloc::vec![0x55; cap]), Ok(cap));
+                assert_eq!(socket.send_slice(&[0x55]), Err(Error::BufferFull));
+            })
+            .unwrap();
+
+            assert_eq!(
+                super::super::wait_for_send_space(index, 0),
+                Err(Error::Timeout)
+            );
+
+            super::super::socket_get_mut(index, |socket| {
+                let seg = SegmentInfo::new(500, 100, 0, 1024, wire::field::FLG_ACK, &[]);
+                SegmentProcessor::new(socket, seg).run();
+                assert_eq!(socket.snd_wnd, 1024);
+            })
+            .unwrap();
+
+            assert_eq!(super::super::wait_for_send_space(index, 0), Ok(()));
+            let sent = super::super::socket_get_mut(index, |socket| socket.send_slice(&[0x55]));
+            assert_eq!(sent, Ok(Ok(1)));
+            super::super::socket_free(index).unwrap();
+        }
+
+        #[test_case]
+        fn full_accept_queue_refuses_syn() {
+            use super::super::socket::Tcp;
+            use crate::net::ip::IpEndpoint;
+
+            let local = IpEndpoint::new(IpAddr(0xC000_0202), 7081);
+            let tcp = Tcp::with_capacity(4);
+            let server = tcp.socket_alloc().unwrap();
+            tcp.socket_get_mut(server, |socket| {
+                socket.set_backlog(1);
+                socket.listen(IpEndpoint::new(IpAddr(0), 7081)).unwrap();
+            })
+            .unwrap();
+
+            let peer = IpEndpoint::new(IpAddr(0xC000_0201), 40000);
+            let child = handshake(&tcp, server, local, peer);
+            let (state, foreign) = tcp
+                .socket_get(child, |socket| (socket.state, socket.foreign))
+                .unwrap();
+            assert_eq!(state, State::Established);
+            assert_eq!(foreign, peer);
+
+            for port in [40001u16, 40002] {
+                let syn = syn_segment(peer.addr, local.addr, port, local.port);
+                let (sends, _) = tcp.process_segment(peer.addr, local.addr, &syn).unwrap();
+                assert!(sends
+                    .iter()
+                    .all(|req| req.flags & wire::field::FLG_SYN == 0));
+            }
+
+            let stats = tcp
+                .socket_get(server, |socket| socket.listen_stats())
+                .unwrap();
+            assert_eq!(stats.connections_refused, 2);
+            assert_eq!(stats.current_depth, 1);
+            assert_eq!(stats.max_depth, 1);
+            assert_eq!(tcp.socket_accept(server), Ok(child));
+        }
+
+        #[test_case]
+        fn listen_backlog_is_clamped() {
+            let mut socket = Socket::new(8, 8);
+            assert_eq!(socket.accept_queue_max(), Socket::DEFAULT_BACKLOG);
+            socket.set_backlog(0);
+            assert_eq!(socket.accept_queue_max(), 1);
+            socket.set_backlog(4);
+            assert_eq!(socket.accept_queue_max(), 4);
+            socket.set_backlog(100_000);
+            assert_eq!(socket.accept_queue_max(), Socket::MAX_BACKLOG);
+        }
+
+        #[test_case]
+        fn full_accept_queue_can_drop_syn() {
+            use super::super::socket::Tcp;
+            use crate::net::ip::IpEndpoint;
+
+            let local_ip = IpAddr(0xC000_0202);
+            let remote_ip = IpAddr(0xC000_0201);
+            let tcp = Tcp::with_capacity(4);
+            let server = tcp.socket_alloc().unwrap();
+            let queued = tcp.socket_alloc().unwrap();
+            tcp.socket_get_mut(queued, |socket| {
+                socket.parent = Some(server);
+                socket.state = State::Established;
+            })
+            .unwrap();
+            tcp.socket_get_mut(server, |socket| {
+                socket.set_backlog(1);
+                socket.listen(IpEndpoint::new(IpAddr(0), 7082)).unwrap();
+                socket.push_accept_queue(queued);
+            })
+            .unwrap();
+
+            let rsts = |port| {
+                let syn = syn_segment(remote_ip, local_ip, port, 7082);
+                let (sends, _) = tcp.process_segment(remote_ip, local_ip, &syn).unwrap();
+                sends
+                    .iter()
+                    .filter(|req| req.flags & wire::field::FLG_RST != 0)
+                    .count()
+            };
+            assert_eq!(rsts(40002), 1);
+            tcp.socket_get_mut(server, |socket| socket.set_syn_overflow_rst(false))
+                .unwrap();
+            assert_eq!(rsts(40003), 0);
+
+            let stats = tcp
+                .socket_get(server, |socket| socket.listen_stats())
+                .unwrap();
+            assert_eq!(stats.connections_refused, 2);
+            assert_eq!(stats.current_depth, 1);
+            // no child was created for either SYN
+            assert_eq!(tcp.socket_alloc(), Ok(2));
+        }
+
+        #[test_case]
+        fn buf_info_tracks_queued_and_in_flight_bytes() {
+            let mut socket = Socket::new(64, 64);
+            socket.state = State::Established;
+            socket.snd_una = 100;
+            socket.snd_nxt = 100;
+            socket.snd_wnd = 0;
+
+            assert_eq!(socket.send_slice(&[0x11; 40]), Ok(40));
+            let info = socket.buf_info();
+            assert_eq!(info.tx_capacity, 64);
+            assert_eq!(info.tx_used, 40);
+            assert_eq!(info.tx_in_flight, 0);
+
+            socket.snd_wnd = 16;
+            socket.flush_tx(0);
+            let info = socket.buf_info();
+            assert_eq!(info.tx_used, 24);
+            assert_eq!(info.tx_in_flight, 16);
+            assert_eq!(info.rx_capacity, 64);
+            assert_eq!(info.rx_used, 0);
+        }
+
+        fn nagle_socket(nodelay: bool) -> Socket {
+            let mut socket = Socket::new(64, 4096);
+            socket.state = State::Established;
+            socket.rcv_nxt = 300;
+            socket.rcv_wnd = 64;
+            socket.snd_una = 100;
+            socket.snd_nxt = 100;
+            socket.snd_wnd = 4096;
+            socket.set_nodelay(nodelay);
+            socket
+        }
+
+        #[test_case]
+        fn nagle_holds_small_segments_until_ack() {
+            let mut socket = nagle_socket(false);
+            assert_eq!(socket.send_slice(&[1; 10]), Ok(10));
+            assert_eq!(socket.send_slice(&[2; 10]), Ok(10));
+            assert_eq!(socket.send_slice(&[3; 10]), Ok(10));
+            assert_eq!(socket.pending.len(), 1);
+            assert_eq!(socket.snd_nxt, 110);
+
+            let seg = SegmentInfo::new(300, 110, 0, 4096, wire::field::FLG_ACK, &[]);
+            SegmentProcessor::new(&mut socket, seg).run();
+            socket.flush_tx(0);
+            let sent = socket.pending.back().unwrap();
+            assert_eq!((sent.seq, sent.payload.len()), (110, 20));
+            assert_eq!(socket.snd_nxt, 130);
+        }
+
+        #[test_case]
+        fn nodelay_sends_small_segments_at_once() {
+            let mut socket = nagle_socket(true);
+            assert_eq!(socket.send_slice(&[1; 10]), Ok(10));
+            assert_eq!(socket.send_slice(&[2; 10]), Ok(10));
+            assert_eq!(socket.pending.len(), 2);
+            assert_eq!(socket.snd_nxt, 120);
+        }
+
+        #[test_case]
+        fn close_flushes_data_held_by_nagle() {
+            let mut socket = nagle_socket(false);
+            socket.send_slice(&[1; 10]).unwrap();
+            socket.send_slice(&[2; 10]).unwrap();
+            socket.close();
+            assert_eq!(socket.state, State::FinWait1);
+            let seqs: Vec<(u32, usize)> = socket
+                .pending
+                .iter()
+                .map(|req| (req.seq, req.payload.len()))
+                .collect();
+            assert_eq!(seqs, [(100, 10), (110, 10), (120, 0)]);
+            assert_ne!(socket.pending[2].flags & wire::field::FLG_FIN, 0);
+        }
+
+        #[test_case]
+        fn cwnd_limits_flight_and_grows_per_ack() {
+            let mut socket = Socket::new(64, 8192);
+            socket.state = State::Established;
+            socket.rcv_nxt = 300;
+            socket.snd_una = 100;
+            socket.snd_nxt = 100;
+            socket.snd_wnd = 65535;
+            socket.set_nodelay(true);
+            let mss = socket.mss as u32;
+            assert_eq!(socket.cwnd, mss);
+
+            socket.send_slice(&[0x55; 4 * 1460]).unwrap();
+            assert_eq!(socket.snd_nxt, 100 + mss);
+
+            // slow start: one MSS per ACK
+            let ack = socket.snd_nxt;
+            let seg = SegmentInfo::new(300, ack, 0, 65535, wire::field::FLG_ACK, &[]);
+            SegmentProcessor::new(&mut socket, seg).run();
+            assert_eq!(socket.cwnd, 2 * mss);
+            socket.flush_tx(0);
+            assert_eq!(socket.snd_nxt, ack + 2 * mss);
+
+            // congestion avoidance past ssthresh
+            socket.ssthresh = socket.cwnd;
+            let ack = socket.snd_nxt;
+            let seg = SegmentInfo::new(300, ack, 0, 65535, wire::field::FLG_ACK, &[]);
+            SegmentProcessor::new(&mut socket, seg).run();
+            assert_eq!(socket.cwnd, 2 * mss + mss / 2);
+        }
+
+        #[test_case]
+        fn rto_resets_cwnd_to_one_segment() {
+            let mut socket = Socket::new(64, 8192);
+            socket.state = State::Established;
+            socket.snd_una = 100;
+            socket.snd_nxt = 100;
+            socket.snd_wnd = 65535;
+            socket.cwnd = 10 * socket.mss as u32;
+            socket.set_nodelay(true);
+            socket.send_slice(&[0x55; 100]).unwrap();
+
+            let now = socket.retransmit[0].last_at + socket.rto;
+            socket.poll_retransmit(now);
+            let mss = socket.mss as u32;
+            assert_eq!((socket.cwnd, socket.ssthresh), (mss, 5 * mss));
+        }
+
+        #[test_case]
+        fn stats_report_sequence_state_and_retransmits() {
+            use crate::net::ip::IpEndpoint;
+
+            let mut socket = Socket::new(64, 64);
+            socket.state = State::Established;
+            socket.local = IpEndpoint::new(IpAddr::new(10, 0, 2, 15), 8080);
+            socket.foreign = IpEndpoint::new(IpAddr::new(10, 0, 2, 2), 40000);
+            socket.rcv_nxt = 300;
+            socket.rcv_wnd = 64;
+            socket.snd_una = 100;
+            socket.snd_nxt = 100;
+            socket.snd_wnd = 1024;
+            socket.set_nodelay(true);
+            socket.send_slice(&[0x55; 10]).unwrap();
+
+            let now = socket.retransmit[0].last_at + socket.rto;
+            socket.poll_retransmit(now);
+            let st = socket.stats();
+            assert_eq!(st.state, State::Established.as_u8());
+            assert_eq!((st.local_addr, st.local_port), (0x0a00_020f, 8080));
+            assert_eq!((st.foreign_addr, st.foreign_port), (0x0a00_0202, 40000));
+            assert_eq!((st.snd_una, st.snd_nxt, st.rcv_nxt), (100, 110, 300));
+            assert_eq!((st.rx_buf_len, st.tx_buf_len, st.rcv_wnd), (0, 0, 64));
+            assert_eq!(st.retransmit_count, 1);
+            assert_eq!(st.rto_ms, socket.rto);
+        }
+
+        #[test_case]
+        fn iss_depends_on_tuple_and_secret() {
+            use super::super::socket::{initial_iss, ISS_SECRET};
+            use crate::net::ip::IpEndpoint;
+
+            let local = IpEndpoint::new(IpAddr::new(10, 0, 2, 15), 40000);
+            let server = IpEndpoint::new(IpAddr::new(10, 0, 2, 2), 80);
+            let other = IpEndpoint::new(IpAddr::new(10, 0, 2, 2), 81);
+
+            let saved = *ISS_SECRET.lock();
+            *ISS_SECRET.lock() = [0x0123_4567_89ab_cdef, 0xfedc_ba98_7654_3210];
+            let a = initial_iss(&local, &server);
+            let b = initial_iss(&local, &other);
+            *ISS_SECRET.lock() = [0x0123_4567_89ab_cdef, 0];
+            let c = initial_iss(&local, &server);
+            *ISS_SECRET.lock() = saved;
+
+            assert_ne!(a, b);
+            assert_ne!(a, c);
+            // the old scheme: port * 1000 + 12345
+            assert_ne!(a, 40000u32.wrapping_mul(1000).wrapping_add(12345));
+        }
+
+        #[test_case]
+        fn window_scale_negotiated_on_syn_ack() {
+            let mut socket = Socket::new(256 * 1024, 8);
+            assert_eq!(socket.desired_wscale(), 3);
+            socket.state = State::SynSent;
+            socket.rcv_wnd = socket.rx_capacity as u32;
+            socket.rcv_wscale = socket.desired_wscale();
+            socket.wscale_offer = true;
+            socket.iss = 100;
+            socket.snd_una = 100;
+            socket.snd_nxt = 101;
+            assert!(socket
+                .syn_options()
+                .contains(&wire::TcpOption::WindowScale(3)));
+            assert_eq!(socket.advertised_window(wire::field::FLG_SYN), u16::MAX);
+
+            let flags = wire::field::FLG_SYN | wire::field::FLG_ACK;
+            let seg = SegmentInfo::new(500, 101, 1, 1000, flags, &[]).with_wscale(Some(5));
+            SegmentProcessor::new(&mut socket, seg).run();
+            assert_eq!(socket.state, State::Established);
+            assert_eq!(socket.snd_wscale, 5);
+            // the SYN-ACK's own window is not scaled
+            assert_eq!(socket.snd_wnd, 1000);
+            // 256 KiB >> 3
+            assert_eq!(socket.advertised_window(wire::field::FLG_ACK), 32768);
+
+            let seg = SegmentInfo::new(501, 101, 0, 100, wire::field::FLG_ACK, &[]);
+            SegmentProcessor::new(&mut socket, seg).run();
+            assert_eq!(socket.snd_wnd, 100 << 5);
+        }
+
+        #[test_case]
+        fn peer_mss_clamps_send_segments() {
+            for (announced, expected) in [(Some(536), 536), (Some(9000), 1460), (Some(1), 88)] {
+                let mut socket = Socket::new(64, 4096);
+                socket.state = State::SynSent;
+                socket.iss = 100;
+                socket.snd_una = 100;
+                socket.snd_nxt = 101;
+
+                let opts: Vec<wire::TcpOption> =
+                    announced.map(wire::TcpOption::Mss).into_iter().collect();
+                let flags = wire::field::FLG_SYN | wire::field::FLG_ACK;
+                let seg = SegmentInfo::new(500, 101, 1, 65535, flags, &[]).with_options(&opts);
+                SegmentProcessor::new(&mut socket, seg).run();
+                assert_eq!(socket.state, State::Established);
+                assert_eq!(socket.mss, expected);
+            }
+
+            let mut socket = Socket::new(64, 4096);
+            socket.state = State::SynSent;
+            socket.iss = 100;
+            socket.snd_una = 100;
+            socket.snd_nxt = 101;
+            let flags = wire::field::FLG_SYN | wire::field::FLG_ACK;
+            let opts = [wire::TcpOption::Mss(536)];
+            let seg = SegmentInfo::new(500, 101, 1, 65535, flags, &[]).with_options(&opts);
+            SegmentProcessor::new(&mut socket, seg).run();
+            socket.pending.clear();
+            socket.cwnd = 65535;
+            socket.set_nodelay(true);
+            socket.send_slice(&[0x55; 1000]).unwrap();
+            let sizes: Vec<usize> = socket.pending.iter().map(|req| req.payload.len()).collect();
+            assert_eq!(sizes, [536, 464]);
+        }
+
+        #[test_case]
+        fn window_scale_off_without_peer_option() {
+            let mut socket = Socket::new(256 * 1024, 8);
+            socket.state = State::SynSent;
+            socket.rcv_wnd = socket.rx_capacity as u32;
+            socket.rcv_wscale = socket.desired_wscale();
+            socket.wscale_offer = true;
+            socket.iss = 100;
+            socket.snd_una = 100;
+            socket.snd_nxt = 101;
+
+            let flags = wire::field::FLG_SYN | wire::field::FLG_ACK;
+            let seg = SegmentInfo::new(500, 101, 1, 1000, flags, &[]);
+            SegmentProcessor::new(&mut socket, seg).run();
+            assert_eq!(socket.state, State::Established);
+            assert_eq!((socket.snd_wscale, socket.rcv_wscale), (0, 0));
+            assert_eq!(socket.advertised_window(wire::field::FLG_ACK), u16::MAX);
+
+            let seg = SegmentInfo::new(501, 101, 0, 100, wire::field::FLG_ACK, &[]);
+            SegmentProcessor::new(&mut socket, seg).run();
+            assert_eq!(socket.snd_wnd, 100);
+        }
+
+        #[test_case]
+        fn rtt_samples_drive_rto() {
+            let mut socket = Socket::new(8, 8);
+            socket.update_rtt(100);
+            assert_eq!(
+                (socket.srtt, socket.rttvar, socket.rto),
+                (Some(100), 50, 300)
+            );
+            socket.update_rtt(100);
+            assert_eq!(
+                (socket.srtt, socket.rttvar, socket.rto),
+                (Some(100), 37, 248)
+            );
+            // LAN-scale samples never drop below the floor
+            socket.update_rtt(0);
+            socket.update_rtt(0);
+            assert!(socket.rto >= 200);
+        }
+
+        #[test_case]
+        fn timestamps_negotiated_and_echoed() {
+            let mut socket = Socket::new(64, 64);
+            socket.state = State::SynSent;
+            socket.ts_enabled = true;
+            socket.iss = 100;
+            socket.snd_una = 100;
+            socket.snd_nxt = 101;
+
+            let flags = wire::field::FLG_SYN | wire::field::FLG_ACK;
+            let ts = wire::TsOption {
+                tsval: 7000,
+                tsecr: 0,
+            };
+            let seg = SegmentInfo::new(500, 101, 1, 1000, flags, &[]).with_timestamps(Some(ts));
+            SegmentProcessor::new(&mut socket, seg).run();
+            assert_eq!(socket.state, State::Established);
+            assert!(socket.ts_enabled);
+            assert_eq!(socket.ts_ecr, 7000);
+
+            let ack = socket.pending.back().unwrap();
+            let echoed = wire::TsOption::find(&ack.options).unwrap();
+            assert_eq!(echoed.tsecr, 7000);
+            assert_eq!(echoed.tsval, socket.ts_val);
+        }
+
+        #[test_case]
+        fn paws_drops_stale_timestamp() {
+            let mut socket = Socket::new(64, 64);
+            socket.state = State::Established;
+            socket.ts_enabled = true;
+            socket.ts_ecr = 1000;
+            socket.rcv_nxt = 300;
+            socket.rcv_wnd = 64;
+            socket.snd_una = 10;
+            socket.snd_nxt = 10;
+
+            let stale = wire::TsOption {
+                tsval: 999,
+                tsecr: 0,
+            };
+            let seg = SegmentInfo::new(300, 10, 2, 1024, wire::field::FLG_ACK, &[1, 2])
+                .with_timestamps(Some(stale));
+            SegmentProcessor::new(&mut socket, seg).run();
+            assert_eq!(socket.rcv_nxt, 300);
+            assert!(!socket.has_data());
+            assert_eq!(socket.ts_ecr, 1000);
+
+            let fresh = wire::TsOption {
+                tsval: 1001,
+                tsecr: 0,
+            };
+            let seg = SegmentInfo::new(300, 10, 2, 1024, wire::field::FLG_ACK, &[1, 2])
+                .with_timestamps(Some(fresh));
+            SegmentProcessor::new(&mut socket, seg).run();
+            assert_eq!(socket.rcv_nxt, 302);
+            assert_eq!(socket.ts_ecr, 1001);
+        }
+
+        #[test_case]
+        fn select_readiness_follows_buffers() {
+            let mut socket = Socket::new(8, 4);
+            socket.state = State::Listen;
+            assert!(!socket.is_readable());
+            assert!(!socket.is_writable());
+            socket.state = State::SynSent;
+            assert!(!socket.is_writable());
+
+            socket.state = State::Established;
+            socket.rcv_nxt = 300;
+            socket.rcv_wnd = 8;
+            socket.snd_una = 10;
+            socket.snd_nxt = 10;
+            assert!(!socket.is_readable());
+            assert!(socket.is_writable());
+
+            let seg = SegmentInfo::new(300, 10, 2, 1024, wire::field::FLG_ACK, &[1, 2]);
+            SegmentProcessor::new(&mut socket, seg).run();
+            assert!(socket.is_readable());
+
+            socket.tx_buf.extend([0u8; 4]);
+            assert!(!socket.is_writable());
+            socket.state = State::Closed;
+            assert!(socket.is_writable());
+        }
+
+        #[test_case]
+        fn nonblocking_send_reports_would_block() {
+            use crate::error::Error;
+
+            let mut socket = Socket::new(8, 4);
+            socket.state = State::Established;
+            socket.tx_buf.extend([0u8; 4]);
+            assert_eq!(socket.send_slice(&[1]), Err(Error::BufferFull));
+            socket.set_nonblocking(true);
+            assert_eq!(socket.send_slice(&[1]), Err(Error::WouldBlock));
+        }
+
+        #[test_case]
+        fn buffer_resize_bounds_and_window() {
+            use crate::error::Error;
+
+            let mut socket = Socket::new(8192, 8192);
+            assert_eq!(socket.resize_rx_buf(256), Err(Error::InvalidArgument));
+            assert_eq!(socket.resize_tx_buf(2 << 20), Err(Error::InvalidArgument));
+
+            socket.state = State::Established;
+            socket.rx_buf.extend([0u8; 600]);
+            assert_eq!(socket.resize_rx_buf(512), Err(Error::InvalidArgument));
+            assert_eq!(socket.resize_rx_buf(65536), Ok(()));
+            assert_eq!(socket.rx_capacity(), 65536);
+            assert_eq!(socket.rcv_wnd, 65536 - 600);
+
+            assert_eq!(socket.resize_tx_buf(1024), Ok(()));
+            socket.tx_buf.extend([0u8; 1024]);
+            assert!(!socket.has_send_space());
+        }
+
+        #[test_case]
+        fn sack_skips_retransmit_of_covered_segments() {
+            let mut socket = Socket::new(64, 512);
+            socket.state = State::Established;
+            socket.sack_enabled = true;
+            socket.rcv_nxt = 300;
+            socket.rcv_wnd = 64;
+            socket.snd_una = 1000;
+            socket.snd_nxt = 1000;
+            socket.snd_wnd = 1024;
+            for _ in 0..3 {
+                socket
+                    .egress(wire::field::FLG_ACK | wire::field::FLG_PSH, &[0x55; 100])
+                    .unwrap();
+                socket.snd_nxt = socket.snd_nxt.wrapping_add(100);
+            }
+            socket.pending.clear();
+
+            // first segment acked, third one SACKed: only 1100 is missing
+            let mut sack = wire::SackBlocks::new();
+            sack.push(1200, 1300);
+            let seg = SegmentInfo::new(300, 1100, 0, 1024, wire::field::FLG_ACK, &[])
+                .with_sack(Some(sack));
+            SegmentProcessor::new(&mut socket, seg).run();
+            assert_eq!(socket.snd_una, 1100);
+            assert_eq!(socket.retransmit.len(), 2);
+            socket.pending.clear();
+
+            let now = socket.retransmit[0].last_at + socket.rto;
+            socket.poll_retransmit(now);
+            let resent: Vec<u32> = socket.pending.iter().map(|req| req.seq).collect();
+            assert_eq!(resent, [1100]);
+        }
+
+        #[test_case]
+        fn reversed_segments_reassemble_in_order() {
+            let mut socket = Socket::new(64, 64);
+            socket.state = State::Established;
+            socket.rcv_nxt = 300;
+            socket.rcv_wnd = 64;
+            socket.snd_una = 10;
+            socket.snd_nxt = 10;
+
+            let segments: [(u32, [u8; 4]); 3] = [
+                (308, [9, 10, 11, 12]),
+                (304, [5, 6, 7, 8]),
+                (300, [1, 2, 3, 4]),
+            ];
+            for (seq, payload) in segments.iter() {
+                let seg = SegmentInfo::new(*seq, 10, 4, 1024, wire::field::FLG_ACK, payload);
+                SegmentProcessor::new(&mut socket, seg).run();
+            }
+            assert_eq!(socket.rcv_nxt, 312);
+            assert!(socket.rx_ooo.is_empty());
+            assert_eq!(socket.rcv_wnd, 52);
+
+            let mut buf = [0u8; 16];
+            assert_eq!(socket.recv_slice(&mut buf), Ok(12));
+            assert_eq!(&buf[..12], &[1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12]);
+        }
+
+        #[test_case]
+        fn out_of_order_queue_bounded_by_capacity() {
+            let mut socket = Socket::new(8, 8);
+            socket.state = State::Established;
+            socket.rcv_nxt = 300;
+            socket.rcv_wnd = 8;
+            socket.snd_una = 10;
+            socket.snd_nxt = 10;
+
+            let seg = SegmentInfo::new(304, 10, 8, 1024, wire::field::FLG_ACK, &[5; 8]);
+            SegmentProcessor::new(&mut socket, seg).run();
+            assert_eq!(socket.rx_ooo.len(), 1);
+            assert_eq!(socket.rx_ooo[0].1.len(), 4);
+
+            let seg = SegmentInfo::new(300, 10, 4, 1024, wire::field::FLG_ACK, &[1; 4]);
+            SegmentProcessor::new(&mut socket, seg).run();
+            assert_eq!(socket.rcv_nxt, 308);
+            assert_eq!(socket.rcv_wnd, 0);
+        }
+
+        #[test_case]
+        fn sack_blocks_track_out_of_order_data() {
+            let mut socket = Socket::new(64, 64);
+            socket.state = State::Established;
+            socket.sack_enabled = true;
+            socket.rcv_nxt = 300;
+            socket.rcv_wnd = 64;
+            socket.snd_una = 10;
+            socket.snd_nxt = 10;
+
+            let seg = SegmentInfo::new(300, 10, 4, 1024, wire::field::FLG_ACK, &[1, 2, 3, 4]);
+            SegmentProcessor::new(&mut socket, seg).run();
+            assert_eq!(socket.rcv_nxt, 304);
+
+            let seg = SegmentInfo::new(308, 10, 4, 1024, wire::field::FLG_ACK, &[9, 10, 11, 12]);
+            SegmentProcessor::new(&mut socket, seg).run();
+            assert_eq!(socket.rcv_nxt, 304);
+            let ack = socket.pending.back().unwrap();
+            assert_eq!(ack.ack, 304);
+            let sack = wire::SackBlocks::find(&ack.options).unwrap();
+            assert_eq!(sack.as_slice(), &[(308, 312)]);
+
+            let seg = SegmentInfo::new(304, 10, 4, 1024, wire::field::FLG_ACK, &[5, 6, 7, 8]);
+            SegmentProcessor::new(&mut socket, seg).run();
+            assert_eq!(socket.rcv_nxt, 312);
+            assert!(socket.sack_rx.is_empty());
+            let ack = socket.pending.back().unwrap();
+            assert!(wire::SackBlocks::find(&ack.options).is_none());
+
+            let mut buf = [0u8; 16];
+            assert_eq!(socket.recv_slice(&mut buf), Ok(12));
+            assert_eq!(&buf[..12], &[1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12]);
+        }
+
+        #[test_case]
+        fn keepalive_probes_idle_peer_then_closes() {
+            let mut socket = Socket::new(64, 64);
+            socket.state = State::Established;
+            socket.rcv_nxt = 300;
+            socket.snd_una = 100;
+            socket.snd_nxt = 100;
+            socket.last_recv_time = 0;
+            socket.set_keepalive(true);
+            socket.set_keepalive_params(1000, 100, 2).unwrap();
+            assert_eq!(socket.next_deadline(), Some(1000));
+
+            socket.poll_keepalive(999);
+            assert!(socket.pending.is_empty());
+            socket.poll_keepalive(1000);
+            let probe = socket.pending.back().unwrap();
+            assert_eq!((probe.seq, probe.ack), (99, 300));
+            assert_eq!(probe.flags, wire::field::FLG_ACK);
+            assert!(probe.payload.is_empty());
+
+            socket.poll_keepalive(1050);
+            assert_eq!(socket.pending.len(), 1);
+            socket.poll_keepalive(1100);
+            assert_eq!(socket.pending.len(), 2);
+            assert_eq!(socket.state, State::Established);
+
+            socket.poll_keepalive(1200);
+            assert_eq!(socket.pending.len(), 2);
+            assert_eq!(socket.state, State::Closed);
+        }
+
+        #[test_case]
+        fn keepalive_reply_resets_probes() {
+            use crate::error::Error;
+
+            let mut socket = Socket::new(64, 64);
+            socket.state = State::Established;
+            socket.rcv_nxt = 300;
+            socket.rcv_wnd = 64;
+            socket.snd_una = 100;
+            socket.snd_nxt = 100;
+            socket.last_recv_time = 0;
+            socket.set_keepalive(true);
+            socket.set_keepalive_params(1000, 100, 2).unwrap();
+            assert_eq!(
+                socket.set_keepalive_params(1000, 0, 2),
+                Err(Error::InvalidArgument)
+            );
+
+            socket.poll_keepalive(1000);
+            assert_eq!(socket.keepalive_sent, 1);
+            let seg = SegmentInfo::new(300, 100, 0, 1024, wire::field::FLG_ACK, &[]);
+            SegmentProcessor::new(&mut socket, seg).run();
+            assert_eq!(socket.keepalive_sent, 0);
+            socket.pending.clear();
+            assert_eq!(socket.next_deadline(), Some(socket.last_recv_time + 1000));
+
+            socket.set_keepalive(false);
+            assert_eq!(socket.next_deadline(), None);
+        }
+
+        #[test_case]
+        fn shutdown_write_keeps_receiving() {
+            use crate::error::Error;
+
+            let mut socket = Socket::new(64, 64);
+            assert_eq!(socket.shutdown_write(), Err(Error::SocketNotOpen));
+            socket.state = State::Established;
+            socket.rcv_nxt = 300;
+            socket.rcv_wnd = 64;
+            socket.snd_una = 100;
+            socket.snd_nxt = 100;
+
+            assert_eq!(socket.shutdown_write(), Ok(()));
+            assert_eq!(socket.state, State::FinWait1);
+            assert_eq!(socket.snd_nxt, 101);
+            assert_eq!(socket.send_slice(&[1]), Err(Error::SocketNotOpen));
+            assert_eq!(socket.shutdown_write(), Ok(()));
+            assert_eq!(socket.snd_nxt, 101);
+
+            // the peer acks our FIN and keeps sending
+            let seg = SegmentInfo::new(300, 101, 3, 1024, wire::field::FLG_ACK, &[7, 8, 9]);
+            SegmentProcessor::new(&mut socket, seg).run();
+            assert_eq!(socket.state, State::FinWait2);
+            let mut buf = [0u8; 8];
+            assert_eq!(socket.recv_slice(&mut buf), Ok(3));
+            assert_eq!(&buf[..3], &[7, 8, 9]);
+
+            let flags = wire::field::FLG_ACK | wire::field::FLG_FIN;
+            let seg = SegmentInfo::new(303, 101, 1, 1024, flags, &[]);
+            SegmentProcessor::new(&mut socket, seg).run();
+            assert_eq!(socket.state, State::TimeWait);
+        }
+
+        #[test_case]
+        fn fin_turns_empty_recv_into_eof() {
+            use crate::error::Error;
+
+            let mut socket = Socket::new(8, 8);
+            socket.state = State::Established;
+            socket.rcv_nxt = 200;
+            socket.rcv_wnd = 8;
+            socket.snd_una = 5;
+            socket.snd_nxt = 5;
+
+            let mut buf = [0u8; 4];
+            assert_eq!(socket.recv_slice(&mut buf), Err(Error::WouldBlock));
+
+            let payload = [0x41u8, 0x42];
+            let flags = wire::field::FLG_ACK | wire::field::FLG_FIN;
+            let seg = SegmentInfo::new(200, 5, 3, 1024, flags, &payload);
+            SegmentProcessor::new(&mut socket, seg).run();
+            assert_eq!(socket.state, State::CloseWait);
+            assert!(socket.eof_received());
+
+            assert_eq!(socket.recv_slice(&mut buf), Ok(2));
+            assert_eq!(&buf[..2], &payload);
+            assert_eq!(socket.recv_slice(&mut buf), Ok(0));
+        }
+
+        #[test_case]
+        fn fin_ahead_of_gap_is_not_eof() {
+            use crate::error::Error;
+
+            let mut socket = Socket::new(16, 8);
+            socket.state = State::Established;
+            socket.rcv_nxt = 200;
+            socket.rcv_wnd = 16;
+            socket.snd_una = 5;
+            socket.snd_nxt = 5;
+
+            let flags = wire::field::FLG_ACK | wire::field::FLG_FIN;
+            let seg = SegmentInfo::new(202, 5, 1, 1024, flags, &[]);
+            SegmentProcessor::new(&mut socket, seg).run();
+            assert!(!socket.eof_received());
+            let mut buf = [0u8; 4];
+            assert_eq!(socket.recv_slice(&mut buf), Err(Error::WouldBlock));
+        }
+
+        #[test_case]
+        fn out_of_order_fin_waits_for_gap() {
+            let mut socket = Socket::new(16, 8);
+            socket.state = State::Established;
+            socket.rcv_nxt = 200;
+            socket.rcv_wnd = 16;
+            socket.snd_una = 5;
+            socket.snd_nxt = 5;
+
+            let flags = wire::field::FLG_ACK | wire::field::FLG_FIN;
+            let seg = SegmentInfo::new(202, 5, 3, 1024, flags, &[0x43, 0x44]);
+            SegmentProcessor::new(&mut socket, seg).run();
+            assert_eq!(socket.rcv_nxt, 200);
+            assert_eq!(socket.state, State::Established);
+
+            let seg = SegmentInfo::new(200, 5, 2, 1024, wire::field::FLG_ACK, &[0x41, 0x42]);
+            SegmentProcessor::new(&mut socket, seg).run();
+            assert_eq!(socket.rcv_nxt, 204);
+            assert_eq!(socket.rx_buf.len(), 4);
+
+            // the retransmitted FIN now lands at rcv_nxt
+            let seg = SegmentInfo::new(204, 5, 1, 1024, flags, &[]);
+            SegmentProcessor::new(&mut socket, seg).run();
+            assert_eq!(socket.rcv_nxt, 205);
+            assert_eq!(socket.state, State::CloseWait);
+        }
+
+        #[test_case]
+        fn psh_segment_wakes_receiver() {
+            use crate::error::Error;
+
+            let index = super::super::socket_alloc().unwrap();
+            super::super::socket_get_mut(index, |socket| {
+                socket.state = State::Established;
+                socket.rcv_nxt = 300;
+                socket.rcv_wnd = socket.rx_capacity as u32;
+                socket.snd_una = 10;
+                socket.snd_nxt = 10;
+            })
+            .unwrap();
+
+            assert_eq!(super::super::wait_for_data(index, 0), Err(Error::Timeout));
+
+            super::super::socket_get_mut(index, |socket| {
+                let payload = [0x61u8, 0x62];
+                let flags = wire::field::FLG_ACK | wire::field::FLG_PSH;
+                let seg = SegmentInfo::new(300, 10, 2, 1024, flags, &payload);
+                SegmentProcessor::new(socket, seg).run();
+                assert!(socket.push_pending);
+                assert!(socket.has_data());
+            })
+            .unwrap();
+
+            assert_eq!(super::super::wait_for_data(index, 0), Ok(()));
+            super::super::socket_get_mut(index, |socket| {
+                let mut buf = [0u8; 1];
+                assert_eq!(socket.recv_slice(&mut buf), Ok(1));
+                assert!(!socket.push_pending);
+            })
+            .unwrap();
+            super::super::socket_free(index).unwrap();
+        }
+
+        fn segment(
+            from: crate::net::ip::IpEndpoint,
+            to: crate::net::ip::IpEndpoint,
+            seq: u32,
+            ack: u32,
+            flags: u8,
+        ) -> [u8; wire::HEADER_LEN] {
+            let mut data = [0u8; wire::HEADER_LEN];
+            {
+                let mut packet = wire::PacketMut::new_unchecked(&mut data);
+                packet.set_src_port(from.port);
+                packet.set_dst_port(to.port);
+                packet.set_seq_number(seq);
+                packet.set_ack_number(ack);
+                packet.set_header_len(wire::HEADER_LEN);
+                packet.set_flags(flags);
+                packet.set_window_len(1024);
+                packet.fill_checksum(from.addr, to.addr);
+            }
+            data
+        }
+
+        // Runs SYN, SYN/ACK, ACK from `peer` against the listener and
+        // returns the established child the listener queued.
+        fn handshake(
+            tcp: &super::super::socket::Tcp,
+            listener: usize,
+            local: crate::net::ip::IpEndpoint,
+            peer: crate::net::ip::IpEndpoint,
+        ) -> usize {
+            let syn = segment(peer, local, 1000, 0, wire::field::FLG_SYN);
+            let (sends, _) = tcp.process_segment(peer.addr, local.addr, &syn).unwrap();
+            let syn_ack = sends
+                .iter()
+                .find(|req| req.flags == wire::field::FLG_SYN | wire::field::FLG_ACK)
+                .unwrap();
+            assert_eq!(syn_ack.ack, 1001);
+
+            let ack = segment(
+                peer,
+                local,
+                1001,
+                syn_ack.seq.wrapping_add(1),
+                wire::field::FLG_ACK,
+            );
+            tcp.process_segment(peer.addr, local.addr, &ack).unwrap();
+            tcp.socket_get(listener, |socket| socket.backlog.back().copied())
+                .unwrap()
+                .unwrap()
+        }
+
+        fn syn_segment(
+            src_ip: IpAddr,
+            dst_ip: IpAddr,
+            src_port: u16,
+            dst_port: u16,
+        ) -> [u8; wire::HEADER_LEN] {
+            use crate::net::ip::IpEndpoint;
+            segment(
+                IpEndpoint::new(src_ip, src_port),
+                IpEndpoint::new(dst_ip, dst_port),
+                1000,
+                0,
+                wire::field::FLG_SYN,
+            )
+        }
+
+        #[test_case]
+        fn reuse_port_spreads_syns_across_listeners() {
+            use crate::error::Error;
+            use crate::net::ip::IpEndpoint;
+            use alloc::vec::Vec;
+
+            let local_ip = IpAddr(0xC000_0202);
+            let remote_ip = IpAddr(0xC000_0201);
+            let endpoint = IpEndpoint::new(IpAddr(0), 7082);
+
+            let first = super::super::socket_alloc().unwrap();
+            let second = super::super::socket_alloc().unwrap();
+            super::super::socket_listen(first, endpoint).unwrap();
+            assert_eq!(
+                super::super::socket_listen(second, endpoint),
+                Err(Error::PortInUse)
+            );
+            super::super::socket_free(first).unwrap();
+
+            let first = super::super::socket_alloc().unwrap();
+            for index in [first, second] {
+                super::super::socket_get_mut(index, |socket| socket.set_reuse_port(true)).unwrap();
+                super::super::socket_listen(index, endpoint).unwrap();
+            }
+
+            for port in 40100u16..40104 {
+                let syn = syn_segment(remote_ip, local_ip, port, 7082);
+                let _ = super::super::ingress(remote_ip, local_ip, &syn);
+            }
+
+            let mut children = Vec::new();
+            let mut per_listener = [0usize; 2];
+            for index in 0..16 {
+                if let Ok(Some(parent)) = super::super::socket_get(index, |socket| socket.parent) {
+                    children.push(index);
+                    if parent == first {
+                        per_listener[0] += 1;
+                    } else if parent == second {
+                        per_listener[1] += 1;
+                    }
+                }
+            }
+            assert_eq!(per_listener, [2, 2]);
+
+            for index in children.into_iter().chain([first, second]) {
+                super::super::socket_free(index).unwrap();
+            }
+        }
+
+        #[test_case]
+        fn reuse_addr_hands_new_syn_to_listener() {
+            use super::super::socket::Tcp;
+            use crate::net::ip::IpEndpoint;
+
+            let local_ip = IpAddr(0xC000_0202);
+            let remote_ip = IpAddr(0xC000_0201);
+            let tcp = Tcp::with_capacity(4);
+            let old = tcp.socket_alloc().unwrap();
+            tcp.socket_get_mut(old, |socket| {
+                socket.local = IpEndpoint::new(local_ip, 7083);
+                socket.foreign = IpEndpoint::new(remote_ip, 40200);
+                socket.state = State::TimeWait;
+            })
+            .unwrap();
+
+            // TIME_WAIT never holds the port against a listener
+            let server = tcp.socket_alloc().unwrap();
+            tcp.socket_listen(server, IpEndpoint::new(IpAddr(0), 7083))
+                .unwrap();
+
+            let children = || {
+                (0..4)
+                    .filter(|&i| tcp.socket_get(i, |socket| socket.parent) == Ok(Some(server)))
+                    .count()
+            };
+            let syn = syn_segment(remote_ip, local_ip, 40200, 7083);
+            let _ = tcp.ingress(remote_ip, local_ip, &syn);
+            assert_eq!(children(), 0);
+
+            tcp.socket_get_mut(server, |socket| socket.set_reuse_addr(true))
+                .unwrap();
+            let _ = tcp.ingress(remote_ip, local_ip, &syn);
+            assert_eq!(children(), 1);
+        }
+
+        #[test_case]
+        fn accepted_child_inherits_ip_options() {
+            use super::super::socket::Tcp;
+            use crate::net::ip::IpEndpoint;
+
+            let local_ip = IpAddr(0xC000_0202);
+            let remote_ip = IpAddr(0xC000_0201);
+            let tcp = Tcp::with_capacity(4);
+            let server = tcp.socket_alloc().unwrap();
+            tcp.socket_get_mut(server, |socket| {
+                socket.set_ttl(9).unwrap();
+                socket.set_tos(0x28);
+            })
+            .unwrap();
+            tcp.socket_listen(server, IpEndpoint::new(IpAddr(0), 7084))
+                .unwrap();
+
+            let syn = syn_segment(remote_ip, local_ip, 40300, 7084);
+            let _ = tcp.ingress(remote_ip, local_ip, &syn);
+            let child = (0..4)
+                .find(|&i| tcp.socket_get(i, |socket| socket.parent) == Ok(Some(server)))
+                .unwrap();
+            assert_eq!(
+                tcp.socket_get(child, |socket| (socket.ttl(), socket.tos())),
+                Ok((9, 0x28))
+            );
+        }
+
+        #[test_case]
+        fn poll_skips_idle_sockets_between_deadlines() {
+            use super::super::socket::Tcp;
+            use alloc::vec::Vec;
+
+            let tcp = Tcp::with_capacity(64);
+            let now = 1_000;
+            let sockets: Vec<usize> = (0..64).map(|_| tcp.socket_alloc().unwrap()).collect();
+            // a syscall touching the socket schedules one pass
+            for &index in &sockets {
+                tcp.socket_get_mut(index, |_| ()).unwrap();
+            }
+            assert_eq!(tcp.poll_at(now).unwrap(), 64);
+            assert_eq!(tcp.poll_at(now).unwrap(), 0);
+            assert_eq!(tcp.poll_at(now + 60_000).unwrap(), 0);
+
+            let timed = sockets[7];
+            tcp.socket_get_mut(timed, |socket| {
+                socket.state = State::TimeWait;
+                socket.timewait_deadline = Some(now + 50);
+            })
+            .unwrap();
+            assert_eq!(tcp.poll_at(now).unwrap(), 1);
+            assert_eq!(tcp.poll_at(now + 49).unwrap(), 0);
+            assert_eq!(tcp.poll_at(now + 50).unwrap(), 1);
+            assert_eq!(
+                tcp.socket_get(timed, |socket| socket.state).unwrap(),
+                State::Closed
+            );
+            assert_eq!(tcp.poll_at(now + 60_000).unwrap(), 0);
+        }
+
+        #[test_case]
+        fn finwait2_times_out_and_frees_socket() {
+            use super::super::socket::Tcp;
+
+            let tcp = Tcp::with_capacity(4);
+            let index = tcp.socket_alloc().unwrap();
+            tcp.socket_get_mut(index, |socket| {
+                socket.state = State::Established;
+                socket.rcv_nxt = 500;
+                socket.snd_una = 100;
+                socket.snd_nxt = 100;
+                socket.snd_wnd = 1024;
+                socket.set_finwait2_timeout(5_000).unwrap();
+            })
+            .unwrap();
+            tcp.socket_close(index).unwrap();
+            // the FIN has no route out of the test, so the send error is
+            // ignored
+            let _ = tcp.poll_at(0);
+            tcp.socket_get_mut(index, |socket| {
+                assert_eq!(socket.state, State::FinWait1);
+                let seg = SegmentInfo::new(500, 101, 0, 1024, wire::field::FLG_ACK, &[]);
+                SegmentProcessor::new(socket, seg).run();
+            })
+            .unwrap();
+            let deadline = tcp
+                .socket_get(index, |socket| {
+                    assert_eq!(socket.state, State::FinWait2);
+                    socket.finwait2_deadline.unwrap()
+                })
+                .unwrap();
+
+            tcp.poll_at(deadline - 1).unwrap();
+            assert_eq!(
+                tcp.socket_get(index, |socket| socket.state).unwrap(),
+                State::FinWait2
+            );
+            assert_eq!(tcp.poll_at(deadline).unwrap(), 1);
+            assert!(tcp.socket_get(index, |_| ()).is_err());
         }
     }
 }
@@ -1,4 +1,5 @@
-use crate::net::ip::IpEndpoint;
+use super::wire::TcpOption;
+use crate::net::ip::{IpEndpoint, DEFAULT_TTL};
 use alloc::vec::Vec;
 
 pub(crate) struct RetransmitEntry {
@@ -7,6 +8,7 @@ pub(crate) struct RetransmitEntry {
     pub(crate) rto: u64,
     pub(crate) seq: u32,
     pub(crate) flags: u8,
+    pub(crate) options: Vec<TcpOption>,
     pub(crate) payload: Vec<u8>,
 }
 
@@ -15,7 +17,35 @@ pub(crate) struct SendRequest {
     pub(crate) ack: u32,
     pub(crate) flags: u8,
     pub(crate) wnd: u16,
+    pub(crate) options: Vec<TcpOption>,
     pub(crate) payload: Vec<u8>,
     pub(crate) local: IpEndpoint,
     pub(crate) foreign: IpEndpoint,
+    pub(crate) ttl: u8,
+    pub(crate) tos: u8,
+}
+
+impl SendRequest {
+    // A bare reset for segments that reach no socket, so there are no
+    // socket IP options to honour.
+    pub(crate) fn reset(
+        seq: u32,
+        ack: u32,
+        flags: u8,
+        local: IpEndpoint,
+        foreign: IpEndpoint,
+    ) -> Self {
+        Self {
+            seq,
+            ack,
+            flags,
+            wnd: 0,
+            options: Vec::new(),
+            payload: Vec::new(),
+            local,
+            foreign,
+            ttl: DEFAULT_TTL,
+            tos: 0,
+        }
+    }
 }
@@ -1,8 +1,10 @@
+use crate::net::trace::{self, Flags};
 use alloc::vec::Vec;
-use core::cmp;
+use core::{cmp, fmt};
 
-use super::{retransmit::SendRequest, socket::Socket, state::State, timer, wire};
+use super::{socket::Socket, state::State, timer, wire};
 
+#[derive(Clone, Copy)]
 pub(crate) struct SegmentInfo<'a> {
     pub(crate) seq: u32,
     pub(crate) ack: u32,
@@ -10,6 +12,13 @@ pub(crate) struct SegmentInfo<'a> {
     pub(crate) wnd: u16,
     pub(crate) flags: u8,
     pub(crate) payload: &'a [u8],
+    // Window Scale option, only looked at on SYN segments
+    pub(crate) wscale: Option<u8>,
+    // MSS option, only looked at on SYN segments
+    pub(crate) mss: Option<u16>,
+    pub(crate) ts: Option<wire::TsOption>,
+    pub(crate) sack: Option<wire::SackBlocks>,
+    pub(crate) sack_permitted: bool,
 }
 
 impl<'a> SegmentInfo<'a> {
@@ -28,9 +37,47 @@ impl<'a> SegmentInfo<'a> {
             wnd,
             flags,
             payload,
+            wscale: None,
+            mss: None,
+            ts: None,
+            sack: None,
+            sack_permitted: false,
         }
     }
 
+    // Picks the options this stack acts on out of a parsed option list.
+    pub(crate) fn with_options(mut self, opts: &[wire::TcpOption]) -> Self {
+        self.ts = wire::TsOption::find(opts);
+        self.sack = wire::SackBlocks::find(opts);
+        if self.has_syn() {
+            self.sack_permitted = opts.contains(&wire::TcpOption::SackPermitted);
+            self.wscale = opts.iter().find_map(|opt| match *opt {
+                wire::TcpOption::WindowScale(shift) => Some(shift),
+                _ => None,
+            });
+            self.mss = opts.iter().find_map(|opt| match *opt {
+                wire::TcpOption::Mss(mss) => Some(mss),
+                _ => None,
+            });
+        }
+        self
+    }
+
+    pub(crate) fn with_sack(mut self, sack: Option<wire::SackBlocks>) -> Self {
+        self.sack = sack;
+        self
+    }
+
+    pub(crate) fn with_timestamps(mut self, ts: Option<wire::TsOption>) -> Self {
+        self.ts = ts;
+        self
+    }
+
+    pub(crate) fn with_wscale(mut self, wscale: Option<u8>) -> Self {
+        self.wscale = wscale;
+        self
+    }
+
     pub(crate) fn has_syn(&self) -> bool {
         (self.flags & wire::field::FLG_SYN) != 0
     }
@@ -46,24 +93,121 @@ impl<'a> SegmentInfo<'a> {
     pub(crate) fn has_rst(&self) -> bool {
         (self.flags & wire::field::FLG_RST) != 0
     }
+
+    pub(crate) fn has_psh(&self) -> bool {
+        (self.flags & wire::field::FLG_PSH) != 0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SegmentEvent {
+    StateTransition { from: State, to: State },
+    AckAdvanced(u32),
+    WindowUpdate(u32),
+    RstSent,
+    SynAckSent,
+    DataQueued(usize),
+    FinReceived,
+}
+
+impl fmt::Display for SegmentEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SegmentEvent::StateTransition { from, to } => write!(f, "{:?}->{:?}", from, to),
+            SegmentEvent::AckAdvanced(ack) => write!(f, "ack={}", ack),
+            SegmentEvent::WindowUpdate(wnd) => write!(f, "wnd={}", wnd),
+            SegmentEvent::RstSent => write!(f, "rst"),
+            SegmentEvent::SynAckSent => write!(f, "syn-ack"),
+            SegmentEvent::DataQueued(len) => write!(f, "data={}", len),
+            SegmentEvent::FinReceived => write!(f, "fin"),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct SegmentLog {
+    events: Vec<SegmentEvent>,
+}
+
+impl SegmentLog {
+    pub(crate) fn events(&self) -> &[SegmentEvent] {
+        &self.events
+    }
+}
+
+impl fmt::Display for SegmentLog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.events.is_empty() {
+            return write!(f, "-");
+        }
+        for (i, event) in self.events.iter().enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{}", event)?;
+        }
+        Ok(())
+    }
 }
 
 pub(crate) struct SegmentProcessor<'a> {
     sock: &'a mut Socket,
     seg: SegmentInfo<'a>,
     send_ack: bool,
+    // the only reason to ACK is new in-order data, which may wait
+    delay_ack: bool,
+    log: Option<SegmentLog>,
 }
 
 impl<'a> SegmentProcessor<'a> {
     pub(crate) fn new(sock: &'a mut Socket, seg: SegmentInfo<'a>) -> Self {
+        let log = trace::is_enabled(Flags::TCP).then(SegmentLog::default);
         Self {
             sock,
             seg,
             send_ack: false,
+            delay_ack: false,
+            log,
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn with_log(sock: &'a mut Socket, seg: SegmentInfo<'a>) -> Self {
+        let mut processor = Self::new(sock, seg);
+        processor.log.get_or_insert_with(SegmentLog::default);
+        processor
+    }
+
+    pub(crate) fn take_log(self) -> SegmentLog {
+        self.log.unwrap_or_default()
+    }
+
+    fn record(&mut self, event: SegmentEvent) {
+        if let Some(log) = self.log.as_mut() {
+            log.events.push(event);
         }
     }
 
+    fn transition(&mut self, to: State) {
+        let from = self.sock.state;
+        if from != to {
+            self.record(SegmentEvent::StateTransition { from, to });
+        }
+        self.sock.set_state(to);
+    }
+
+    fn send_syn_ack(&mut self) {
+        let options = self.sock.syn_options();
+        let _ = self.sock.egress_with_options(
+            wire::field::FLG_SYN | wire::field::FLG_ACK,
+            &options,
+            &[],
+        );
+        self.record(SegmentEvent::SynAckSent);
+    }
+
     pub(crate) fn run(&mut self) {
+        self.sock.note_recv(timer::get_time_ms());
         if self.handle_syn_sent() {
             return;
         }
@@ -73,14 +217,15 @@ impl<'a> SegmentProcessor<'a> {
         if !self.validate_window() {
             return;
         }
+        self.update_ts_recent();
 
         if self.seg.has_rst() {
-            self.sock.state = State::Closed;
+            self.transition(State::Closed);
             return;
         }
 
         if self.seg.has_syn() {
-            self.sock.state = State::Closed;
+            self.transition(State::Closed);
             self.send_rst_for_segment(self.seg.has_ack());
             return;
         }
@@ -93,7 +238,12 @@ impl<'a> SegmentProcessor<'a> {
         self.handle_fin();
 
         if self.send_ack {
-            let _ = self.sock.egress(wire::field::FLG_ACK, &[]);
+            if self.delay_ack && self.sock.ack_deadline.is_none() {
+                self.sock.ack_deadline =
+                    Some(timer::get_time_ms().saturating_add(Socket::DELAYED_ACK_MS));
+            } else {
+                let _ = self.sock.egress(wire::field::FLG_ACK, &[]);
+            }
         }
     }
 
@@ -116,7 +266,7 @@ impl<'a> SegmentProcessor<'a> {
 
         if self.seg.has_rst() {
             if acceptable_ack {
-                self.sock.state = State::Closed;
+                self.transition(State::Closed);
             }
             return true;
         }
@@ -124,23 +274,28 @@ impl<'a> SegmentProcessor<'a> {
         if self.seg.has_syn() {
             self.sock.irs = self.seg.seq;
             self.sock.rcv_nxt = self.seg.seq.wrapping_add(1);
+            self.sock.negotiate_mss(self.seg.mss);
+            self.sock.negotiate_wscale(self.seg.wscale);
+            self.sock.negotiate_timestamps(self.seg.ts);
+            self.sock.negotiate_sack(self.seg.sack_permitted);
 
             if self.seg.has_ack() {
                 self.sock.snd_una = self.seg.ack;
+                self.sample_rtt();
                 self.sock.cleanup_retransmit();
-                self.sock.snd_wnd = self.seg.wnd;
+                self.sock.snd_wnd = self.seg_window();
                 self.sock.snd_wl1 = self.seg.seq;
                 self.sock.snd_wl2 = self.seg.ack;
+                self.record(SegmentEvent::AckAdvanced(self.seg.ack));
+                self.record(SegmentEvent::WindowUpdate(self.sock.snd_wnd));
             }
 
             if self.seg.has_ack() && Self::seq_lt(self.sock.iss, self.sock.snd_una) {
-                self.sock.state = State::Established;
+                self.transition(State::Established);
                 let _ = self.sock.egress(wire::field::FLG_ACK, &[]);
             } else {
-                self.sock.state = State::SynReceived;
-                let _ = self
-                    .sock
-                    .egress(wire::field::FLG_SYN | wire::field::FLG_ACK, &[]);
+                self.transition(State::SynReceived);
+                self.send_syn_ack();
             }
         }
 
@@ -151,13 +306,19 @@ impl<'a> SegmentProcessor<'a> {
         if self.sock.state != State::SynReceived || !self.seg.has_syn() {
             return false;
         }
-        let _ = self
-            .sock
-            .egress(wire::field::FLG_SYN | wire::field::FLG_ACK, &[]);
+        self.send_syn_ack();
         true
     }
 
     pub(crate) fn validate_window(&mut self) -> bool {
+        // RFC 7323 5.3 (PAWS): a timestamp older than TS.Recent marks an old
+        // duplicate even if its sequence number looks acceptable.
+        if let (true, Some(ts)) = (self.sock.ts_enabled, self.seg.ts) {
+            if !self.seg.has_rst() && Self::seq_lt(ts.tsval, self.sock.ts_ecr) {
+                return self.accept_or_ack(false);
+            }
+        }
+
         let rcv_nxt = self.sock.rcv_nxt;
         let rcv_wnd = self.sock.rcv_wnd;
         let seg_seq = self.seg.seq;
@@ -167,7 +328,7 @@ impl<'a> SegmentProcessor<'a> {
             if rcv_wnd == 0 {
                 return self.accept_or_ack(seg_seq == rcv_nxt);
             }
-            let end = rcv_nxt.wrapping_add(rcv_wnd as u32);
+            let end = rcv_nxt.wrapping_add(rcv_wnd);
             return self.accept_or_ack(Self::seq_between(rcv_nxt, seg_seq, end));
         }
 
@@ -175,7 +336,7 @@ impl<'a> SegmentProcessor<'a> {
             return self.accept_or_ack(false);
         }
 
-        let end = rcv_nxt.wrapping_add(rcv_wnd as u32);
+        let end = rcv_nxt.wrapping_add(rcv_wnd);
         let seg_end = seg_seq.wrapping_add(seg_len - 1);
         self.accept_or_ack(
             Self::seq_between(rcv_nxt, seg_seq, end) || Self::seq_between(rcv_nxt, seg_end, end),
@@ -187,7 +348,11 @@ impl<'a> SegmentProcessor<'a> {
             return self.sock.state == State::SynReceived;
         }
 
+        self.sock.commit_persist_probe(self.seg.ack);
         let ack_ok = self.ack_in_window();
+        if self.sock.sack_enabled {
+            self.sock.sacked = self.seg.sack.unwrap_or_default();
+        }
 
         if self.sock.state == State::SynReceived {
             if !ack_ok {
@@ -196,48 +361,62 @@ impl<'a> SegmentProcessor<'a> {
             }
 
             self.sock.snd_una = self.seg.ack;
+            self.sample_rtt();
             self.sock.cleanup_retransmit();
-            self.sock.snd_wnd = self.seg.wnd;
+            self.sock.snd_wnd = self.seg_window();
             self.sock.snd_wl1 = self.seg.seq;
             self.sock.snd_wl2 = self.seg.ack;
-            self.sock.state = State::Established;
+            self.record(SegmentEvent::AckAdvanced(self.seg.ack));
+            self.record(SegmentEvent::WindowUpdate(self.sock.snd_wnd));
+            self.transition(State::Established);
             if self.sock.parent.is_some() {
                 self.sock.accept_ready = true;
             }
             return true;
         }
 
+        if self.is_duplicate_ack() {
+            self.sock.dup_ack_count = self.sock.dup_ack_count.saturating_add(1);
+            if self.sock.dup_ack_count == Socket::DUP_ACK_THRESHOLD {
+                self.sock.fast_retransmit(timer::get_time_ms());
+            }
+            return true;
+        }
+
         if !ack_ok {
+            // A window update re-acknowledges snd_una without advancing it.
+            if self.seg.ack == self.sock.snd_una {
+                self.update_send_window();
+            }
             return true;
         }
 
         self.sock.snd_una = self.seg.ack;
+        self.sock.dup_ack_count = 0;
+        self.sample_rtt();
         self.sock.cleanup_retransmit();
-
-        if Self::seq_lt(self.sock.snd_wl1, self.seg.seq)
-            || (self.sock.snd_wl1 == self.seg.seq && Self::seq_le(self.sock.snd_wl2, self.seg.ack))
-        {
-            self.sock.snd_wnd = self.seg.wnd;
-            self.sock.snd_wl1 = self.seg.seq;
-            self.sock.snd_wl2 = self.seg.ack;
-        }
+        self.sock.grow_cwnd();
+        self.record(SegmentEvent::AckAdvanced(self.seg.ack));
+        self.update_send_window();
 
         match self.sock.state {
             State::FinWait1 => {
                 if self.sock.snd_una == self.sock.snd_nxt {
-                    self.sock.state = State::FinWait2;
+                    self.transition(State::FinWait2);
+                    self.sock.finwait2_deadline =
+                        Some(timer::get_time_ms().saturating_add(self.sock.finwait2_timeout_ms));
                 }
             }
             State::Closing => {
                 if self.sock.snd_una == self.sock.snd_nxt {
-                    self.sock.state = State::TimeWait;
+                    self.transition(State::TimeWait);
                     self.sock.timewait_deadline =
                         Some(timer::get_time_ms().saturating_add(Socket::TIMEWAIT_MS));
                 }
             }
             State::LastAck => {
                 if self.sock.snd_una == self.sock.snd_nxt {
-                    self.sock.state = State::Closed;
+                    self.transition(State::Closed);
                     return false;
                 }
             }
@@ -247,6 +426,21 @@ impl<'a> SegmentProcessor<'a> {
         true
     }
 
+    fn update_send_window(&mut self) {
+        if Self::seq_lt(self.sock.snd_wl1, self.seg.seq)
+            || (self.sock.snd_wl1 == self.seg.seq && Self::seq_le(self.sock.snd_wl2, self.seg.ack))
+        {
+            let window_opened = self.sock.snd_wnd == 0 && self.seg.wnd != 0;
+            self.sock.snd_wnd = self.seg_window();
+            self.sock.snd_wl1 = self.seg.seq;
+            self.sock.snd_wl2 = self.seg.ack;
+            self.record(SegmentEvent::WindowUpdate(self.sock.snd_wnd));
+            if window_opened {
+                self.sock.flush_tx(timer::get_time_ms());
+            }
+        }
+    }
+
     fn handle_payload(&mut self) {
         if self.seg.payload.is_empty() {
             return;
@@ -259,50 +453,71 @@ impl<'a> SegmentProcessor<'a> {
         }
 
         if self.seg.seq == self.sock.rcv_nxt {
+            // RFC 5681 4.2: a segment that fills a gap is acked at once
+            let filled_gap = !self.sock.rx_ooo.is_empty();
             let space = self.sock.rx_capacity.saturating_sub(self.sock.rx_buf.len());
             let to_copy = cmp::min(space, self.seg.payload.len());
             for b in self.seg.payload.iter().take(to_copy) {
                 self.sock.rx_buf.push_back(*b);
             }
             self.sock.rcv_nxt = self.sock.rcv_nxt.wrapping_add(to_copy as u32);
+            self.delay_ack = to_copy > 0 && !filled_gap;
+            let to_copy = to_copy + self.sock.drain_ooo();
+            if to_copy > 0 {
+                self.record(SegmentEvent::DataQueued(to_copy));
+                if self.seg.has_psh() {
+                    self.sock.push_pending = true;
+                }
+            }
             self.send_ack = true;
         } else {
+            if Self::seq_lt(self.sock.rcv_nxt, self.seg.seq) {
+                self.sock.queue_ooo(self.seg.seq, self.seg.payload);
+            }
             self.send_ack = true;
         }
 
-        self.sock.rcv_wnd = (self.sock.rx_capacity - self.sock.rx_buf.len()) as u16;
+        self.sock.rcv_wnd = (self.sock.rx_capacity - self.sock.rx_buf.len()) as u32;
     }
 
     fn handle_fin(&mut self) {
         if !self.seg.has_fin() {
             return;
         }
-
-        let fin_end = self
-            .seg
-            .seq
-            .wrapping_add(self.seg.payload.len() as u32)
-            .wrapping_add(1);
-        if Self::seq_lt(self.sock.rcv_nxt, fin_end) {
-            self.sock.rcv_nxt = fin_end;
-        }
         self.send_ack = true;
+        self.delay_ack = false;
+
+        // The FIN only counts once everything before it is in: one ahead
+        // of a gap is dropped and taken again when the peer retransmits.
+        // A retransmission of a FIN already consumed just gets re-acked.
+        let fin_seq = self.seg.seq.wrapping_add(self.seg.payload.len() as u32);
+        if fin_seq != self.sock.rcv_nxt {
+            if fin_seq.wrapping_add(1) == self.sock.rcv_nxt && self.sock.state == State::TimeWait {
+                self.sock.timewait_deadline =
+                    Some(timer::get_time_ms().saturating_add(Socket::TIMEWAIT_MS));
+            }
+            return;
+        }
+        self.sock.rcv_nxt = fin_seq.wrapping_add(1);
+        self.record(SegmentEvent::FinReceived);
+        self.sock.eof_received = true;
 
         match self.sock.state {
             State::SynReceived | State::Established => {
-                self.sock.state = State::CloseWait;
+                self.transition(State::CloseWait);
             }
             State::FinWait1 => {
                 if self.sock.snd_una == self.sock.snd_nxt {
-                    self.sock.state = State::TimeWait;
+                    self.transition(State::TimeWait);
                     self.sock.timewait_deadline =
                         Some(timer::get_time_ms().saturating_add(Socket::TIMEWAIT_MS));
                 } else {
-                    self.sock.state = State::Closing;
+                    self.transition(State::Closing);
                 }
             }
             State::FinWait2 => {
-                self.sock.state = State::TimeWait;
+                self.transition(State::TimeWait);
+                self.sock.finwait2_deadline = None;
                 self.sock.timewait_deadline =
                     Some(timer::get_time_ms().saturating_add(Socket::TIMEWAIT_MS));
             }
@@ -315,26 +530,42 @@ impl<'a> SegmentProcessor<'a> {
     }
 
     fn send_rst_for_segment(&mut self, ack_present: bool) {
-        if ack_present {
-            self.sock.pending.push_back(SendRequest {
-                seq: self.seg.ack,
-                ack: 0,
-                flags: wire::field::FLG_RST,
-                wnd: 0,
-                payload: Vec::new(),
-                local: self.sock.local,
-                foreign: self.sock.foreign,
-            });
+        self.record(SegmentEvent::RstSent);
+        let (seq, ack, flags) = if ack_present {
+            (self.seg.ack, 0, wire::field::FLG_RST)
         } else {
-            self.sock.pending.push_back(SendRequest {
-                seq: 0,
-                ack: self.seg.seq.wrapping_add(self.seg.len),
-                flags: wire::field::FLG_RST | wire::field::FLG_ACK,
-                wnd: 0,
-                payload: Vec::new(),
-                local: self.sock.local,
-                foreign: self.sock.foreign,
-            });
+            (
+                0,
+                self.seg.seq.wrapping_add(self.seg.len),
+                wire::field::FLG_RST | wire::field::FLG_ACK,
+            )
+        };
+        let req = self
+            .sock
+            .send_request(seq, ack, flags, 0, Vec::new(), Vec::new());
+        self.sock.pending.push_back(req);
+    }
+
+    // RFC 7323 4.3: remember the peer's newest TSval from an in-order
+    // segment so the next ACK echoes it.
+    fn update_ts_recent(&mut self) {
+        if let (true, Some(ts)) = (self.sock.ts_enabled, self.seg.ts) {
+            if Self::seq_le(self.seg.seq, self.sock.rcv_nxt)
+                && !Self::seq_lt(ts.tsval, self.sock.ts_ecr)
+            {
+                self.sock.ts_ecr = ts.tsval;
+            }
+        }
+    }
+
+    // The echoed TSecr is our clock at send time, which gives an RTT sample
+    // for every new ACK, retransmitted or not.
+    fn sample_rtt(&mut self) {
+        if let (true, Some(ts)) = (self.sock.ts_enabled, self.seg.ts) {
+            if ts.tsecr != 0 {
+                let now = timer::get_time_ms() as u32;
+                self.sock.update_rtt(now.wrapping_sub(ts.tsecr) as u64);
+            }
         }
     }
 
@@ -350,15 +581,105 @@ impl<'a> SegmentProcessor<'a> {
             && Self::seq_le(self.seg.ack, self.sock.snd_nxt)
     }
 
-    fn seq_lt(a: u32, b: u32) -> bool {
+    // RFC 5681: a pure ACK that acknowledges nothing new while data is in flight.
+    fn is_duplicate_ack(&self) -> bool {
+        self.seg.ack == self.sock.snd_una
+            && self.sock.snd_una != self.sock.snd_nxt
+            && self.seg.payload.is_empty()
+            && !self.seg.has_syn()
+            && !self.seg.has_fin()
+            && self.seg_window() == self.sock.snd_wnd
+    }
+
+    // RFC 7323 2.3: the shift applies to every segment except a SYN.
+    fn seg_window(&self) -> u32 {
+        if self.seg.has_syn() {
+            self.seg.wnd as u32
+        } else {
+            (self.seg.wnd as u32) << self.sock.snd_wscale
+        }
+    }
+
+    pub(super) fn seq_lt(a: u32, b: u32) -> bool {
         (a.wrapping_sub(b) as i32) < 0
     }
 
-    fn seq_le(a: u32, b: u32) -> bool {
+    pub(super) fn seq_le(a: u32, b: u32) -> bool {
         (a.wrapping_sub(b) as i32) <= 0
     }
 
-    fn seq_between(start: u32, seq: u32, end: u32) -> bool {
+    pub(super) fn seq_between(start: u32, seq: u32, end: u32) -> bool {
         !Self::seq_lt(seq, start) && Self::seq_lt(seq, end)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_payload_wraps_seq_space() {
+        let mut socket = Socket::new(8, 8);
+        socket.state = State::Established;
+        socket.rcv_nxt = u32::MAX - 2;
+        socket.rcv_wnd = 8;
+        socket.snd_una = 1;
+        socket.snd_nxt = 2;
+
+        let payload = [0xaau8; 5];
+        let seg = SegmentInfo::new(
+            u32::MAX - 2,
+            2,
+            payload.len() as u32,
+            1024,
+            wire::field::FLG_ACK,
+            &payload,
+        );
+        let mut proc = SegmentProcessor::new(&mut socket, seg);
+        proc.run();
+
+        assert_eq!(socket.rx_buf.len(), 5);
+        assert_eq!(socket.rcv_nxt, 2);
+    }
+
+    #[test_case]
+    fn test_ack_in_window_wrap() {
+        let mut socket = Socket::new(1, 1);
+        socket.snd_una = 0xFFFF_FFFF - 5;
+        socket.snd_nxt = 10;
+
+        let seg = SegmentInfo::new(0, 3, 0, 0, wire::field::FLG_ACK, &[]);
+        let proc = SegmentProcessor::new(&mut socket, seg);
+        assert!(proc.ack_in_window());
+
+        let seg = SegmentInfo::new(0, 11, 0, 0, wire::field::FLG_ACK, &[]);
+        let proc = SegmentProcessor::new(&mut socket, seg);
+        assert!(!proc.ack_in_window());
+    }
+
+    #[test_case]
+    fn test_seq_le_wrap() {
+        assert!(SegmentProcessor::seq_le(u32::MAX, 0));
+        assert!(SegmentProcessor::seq_le(u32::MAX - 10, 5));
+        assert!(SegmentProcessor::seq_le(7, 7));
+        assert!(!SegmentProcessor::seq_le(0, u32::MAX));
+        assert!(SegmentProcessor::seq_lt(u32::MAX, 0));
+        assert!(!SegmentProcessor::seq_lt(0, 0));
+    }
+
+    #[test_case]
+    fn test_seq_between_wrap() {
+        let start = u32::MAX - 3;
+        let end = start.wrapping_add(8);
+        assert!(SegmentProcessor::seq_between(start, start, end));
+        assert!(SegmentProcessor::seq_between(start, u32::MAX, end));
+        assert!(SegmentProcessor::seq_between(start, 0, end));
+        assert!(SegmentProcessor::seq_between(start, 3, end));
+        assert!(!SegmentProcessor::seq_between(start, end, end));
+        assert!(!SegmentProcessor::seq_between(
+            start,
+            start.wrapping_sub(1),
+            end
+        ));
+    }
+}
@@ -1,11 +1,19 @@
+use crate::condvar::Condvar;
 use crate::error::{Error, Result};
-use crate::net::ip::{self, IpAddr, IpEndpoint};
+use crate::net::event;
+use crate::net::ip::{self, IpAddr, IpEndpoint, IpFlags, DEFAULT_TTL};
 use crate::net::socket::{SocketHandle, SocketSet};
+use crate::net::util::siphash13;
+use crate::net::{require_state, NetStackState};
 use crate::spinlock::Mutex;
+use crate::stat::{TcpBufInfo, TcpListenStats, TcpSocketStats};
 use crate::trace;
-use alloc::{collections::VecDeque, vec::Vec};
-use core::cmp;
-use core::sync::atomic::{AtomicU16, Ordering};
+use alloc::{
+    collections::{BinaryHeap, VecDeque},
+    vec::Vec,
+};
+use core::cmp::{self, Reverse};
+use core::sync::atomic::{AtomicBool, AtomicU16, AtomicUsize, Ordering};
 
 use super::{
     retransmit::{RetransmitEntry, SendRequest},
@@ -14,24 +22,55 @@ use super::{
     timer, wire,
 };
 
+pub const TCP_SOCKET_SIZE: usize = 16;
+
 pub struct Socket {
     pub(super) state: State,
+    pub(super) state_entered_at: u64,
     pub(super) local: IpEndpoint,
     pub(super) foreign: IpEndpoint,
 
     pub(super) snd_nxt: u32,
     pub(super) snd_una: u32,
-    pub(super) snd_wnd: u16,
+    pub(super) snd_wnd: u32,
+    pub(super) snd_wscale: u8,
     pub(super) snd_wl1: u32,
     pub(super) snd_wl2: u32,
 
     pub(super) rcv_nxt: u32,
-    pub(super) rcv_wnd: u16,
+    pub(super) rcv_wnd: u32,
+    pub(super) rcv_wscale: u8,
+    pub(super) wscale_offer: bool,
+
+    // RFC 7323 timestamps. ts_enabled means "offered" until the handshake
+    // settles it. ts_val is the TSval we last sent, ts_ecr is TS.Recent,
+    // the peer's TSval we echo back.
+    pub(super) ts_enabled: bool,
+    pub(super) ts_val: u32,
+    pub(super) ts_ecr: u32,
+    // RFC 6298 estimator, fed from echoed timestamps
+    pub(super) srtt: Option<u64>,
+    pub(super) rttvar: u64,
+    pub(super) rto: u64,
+    pub(super) retransmit_count: u64,
+
+    // RFC 2018 SACK, offered and settled like timestamps. sack_rx lists
+    // the ranges held in rx_ooo, sacked the blocks from the peer's latest
+    // ACK.
+    pub(super) sack_enabled: bool,
+    pub(super) sack_rx: wire::SackBlocks,
+    pub(super) sacked: wire::SackBlocks,
+    // segments received beyond rcv_nxt, sorted by seq; together with
+    // rx_buf they never exceed rx_capacity
+    pub(super) rx_ooo: VecDeque<(u32, Vec<u8>)>,
 
     pub(super) iss: u32,
     pub(super) irs: u32,
 
     pub(super) mss: u16,
+    // RFC 5681 congestion window, in bytes
+    pub(super) cwnd: u32,
+    pub(super) ssthresh: u32,
 
     pub(super) rx_buf: VecDeque<u8>,
     pub(super) rx_capacity: usize,
@@ -40,47 +79,162 @@ pub struct Socket {
 
     pub(super) retransmit: VecDeque<RetransmitEntry>,
     pub(super) pending: VecDeque<SendRequest>,
+    pub(super) dup_ack_count: u8,
 
     pub(super) timewait_deadline: Option<u64>,
+    // zero-window probing (RFC 9293 3.8.6.1); persist_backoff is the wait
+    // before the next probe
+    pub(super) persist_deadline: Option<u64>,
+    pub(super) persist_backoff: u64,
+    // pending delayed ACK (RFC 1122 4.2.3.2); any segment we send carries
+    // the ACK and clears it
+    pub(super) ack_deadline: Option<u64>,
+    // FIN-WAIT-2 can last forever if the peer never sends its FIN
+    pub(super) finwait2_deadline: Option<u64>,
+    pub(super) finwait2_timeout_ms: u64,
+    // closed by its owner; the stack frees the slot once the connection
+    // reaches CLOSED
+    pub(super) orphaned: bool,
+    pub(super) send_wait_deadline: Option<u64>,
+    pub(super) recv_wait_deadline: Option<u64>,
+    pub(super) next_poll_at: u64,
+    pub(super) push_pending: bool,
+    pub(super) eof_received: bool,
 
     pub(super) parent: Option<usize>,
     pub(super) backlog: VecDeque<usize>,
+    pub(super) backlog_max: usize,
+    pub(super) peak_accept_queue_depth: usize,
+    pub(super) connections_refused: u64,
+    // full accept queue: answer the SYN with RST, or drop it so the peer
+    // retries later
+    pub(super) syn_overflow_rst: bool,
     pub(super) accept_ready: bool,
+    pub(super) reuse_port: bool,
+    // SO_REUSEADDR: a new SYN on a TIME_WAIT tuple goes to this listener
+    pub(super) reuse_addr: bool,
+    // recv/send/accept fail with WouldBlock instead of waiting in the syscall
+    pub(super) nonblocking: bool,
+    // SO_RCVTIMEO / SO_SNDTIMEO, enforced by the recv/send syscalls
+    pub(super) recv_timeout_ms: Option<u64>,
+    pub(super) send_timeout_ms: Option<u64>,
+    // TCP_NODELAY: send small segments even with data in flight
+    pub(super) nagle_disabled: bool,
+    // IP header fields for everything this socket sends
+    pub(super) ip_ttl: u8,
+    pub(super) ip_tos: u8,
+
+    // Keepalive (RFC 1122 4.2.3.6): after keepalive_idle_ms without hearing
+    // from the peer, probe every keepalive_interval_ms and give up after
+    // keepalive_probes unanswered ones.
+    pub(super) keepalive_enabled: bool,
+    pub(super) keepalive_idle_ms: u64,
+    pub(super) keepalive_interval_ms: u64,
+    pub(super) keepalive_probes: u8,
+    pub(super) keepalive_sent: u8,
+    pub(super) last_recv_time: u64,
 }
 
 impl Socket {
     const RX_BUFFER_SIZE: usize = 8192;
     const TX_BUFFER_SIZE: usize = 8192;
+    // SO_RCVBUF / SO_SNDBUF bounds
+    pub(crate) const MIN_BUFFER_SIZE: usize = 512;
+    pub(crate) const MAX_BUFFER_SIZE: usize = 1 << 20;
     const DEFAULT_MSS: usize = 1460;
+    // floor for a peer-announced MSS, so per-segment options still fit
+    const MIN_MSS: u16 = 88;
     const DEFAULT_RTO_MS: u64 = 200;
+    const MIN_RTO_MS: u64 = Self::DEFAULT_RTO_MS;
+    const MAX_RTO_MS: u64 = 60_000;
     const RETRANSMIT_DEADLINE_MS: u64 = 12_000;
+    const MAX_PERSIST_MS: u64 = 60_000;
+    pub(super) const DELAYED_ACK_MS: u64 = 200;
+    // RFC 7323 2.3
+    pub(crate) const MAX_WSCALE: u8 = 14;
     pub(crate) const TIMEWAIT_MS: u64 = 30_000;
+    const DEFAULT_FINWAIT2_TIMEOUT_MS: u64 = 60_000;
+    const INITIAL_SSTHRESH: u32 = 65535;
+    const DEFAULT_KEEPALIVE_IDLE_MS: u64 = 7_200_000;
+    const DEFAULT_KEEPALIVE_INTERVAL_MS: u64 = 75_000;
+    const DEFAULT_KEEPALIVE_PROBES: u8 = 9;
+    pub(crate) const DUP_ACK_THRESHOLD: u8 = 3;
+    pub(crate) const DEFAULT_BACKLOG: usize = 8;
+    pub(crate) const MAX_BACKLOG: usize = 64;
+    const POLL_NEVER: u64 = u64::MAX;
 
     pub fn new(rx_capacity: usize, tx_capacity: usize) -> Self {
         Self {
             state: State::Closed,
+            state_entered_at: timer::get_time_ms(),
             local: IpEndpoint::unspecified(),
             foreign: IpEndpoint::unspecified(),
             snd_nxt: 0,
             snd_una: 0,
             snd_wnd: 0,
+            snd_wscale: 0,
             snd_wl1: 0,
             snd_wl2: 0,
             rcv_nxt: 0,
             rcv_wnd: 0,
+            rcv_wscale: 0,
+            wscale_offer: false,
+            ts_enabled: false,
+            ts_val: 0,
+            ts_ecr: 0,
+            srtt: None,
+            rttvar: 0,
+            rto: Self::DEFAULT_RTO_MS,
+            retransmit_count: 0,
+            sack_enabled: false,
+            sack_rx: wire::SackBlocks::new(),
+            sacked: wire::SackBlocks::new(),
+            rx_ooo: VecDeque::new(),
             iss: 0,
             irs: 0,
             mss: Self::DEFAULT_MSS as u16,
+            cwnd: Self::DEFAULT_MSS as u32,
+            ssthresh: Self::INITIAL_SSTHRESH,
             rx_buf: VecDeque::with_capacity(rx_capacity),
             rx_capacity,
             tx_buf: VecDeque::with_capacity(tx_capacity),
             tx_capacity,
             retransmit: VecDeque::new(),
             pending: VecDeque::new(),
+            dup_ack_count: 0,
             timewait_deadline: None,
+            persist_deadline: None,
+            persist_backoff: Self::DEFAULT_RTO_MS,
+            ack_deadline: None,
+            finwait2_deadline: None,
+            finwait2_timeout_ms: Self::DEFAULT_FINWAIT2_TIMEOUT_MS,
+            orphaned: false,
+            send_wait_deadline: None,
+            recv_wait_deadline: None,
+            next_poll_at: Self::POLL_NEVER,
+            push_pending: false,
+            eof_received: false,
             parent: None,
             backlog: VecDeque::new(),
+            backlog_max: Self::DEFAULT_BACKLOG,
+            peak_accept_queue_depth: 0,
+            connections_refused: 0,
+            syn_overflow_rst: true,
             accept_ready: false,
+            reuse_port: false,
+            reuse_addr: false,
+            nonblocking: false,
+            recv_timeout_ms: None,
+            send_timeout_ms: None,
+            nagle_disabled: false,
+            ip_ttl: DEFAULT_TTL,
+            ip_tos: 0,
+            keepalive_enabled: false,
+            keepalive_idle_ms: Self::DEFAULT_KEEPALIVE_IDLE_MS,
+            keepalive_interval_ms: Self::DEFAULT_KEEPALIVE_INTERVAL_MS,
+            keepalive_probes: Self::DEFAULT_KEEPALIVE_PROBES,
+            keepalive_sent: 0,
+            last_recv_time: timer::get_time_ms(),
         }
     }
 
@@ -88,6 +242,22 @@ impl Socket {
         self.state
     }
 
+    pub fn state_entered_at(&self) -> u64 {
+        self.state_entered_at
+    }
+
+    pub fn time_in_state_ms(&self, now: u64) -> u64 {
+        now.saturating_sub(self.state_entered_at)
+    }
+
+    pub(super) fn set_state(&mut self, state: State) {
+        if self.state != state {
+            event::record_tcp_state(self.local.port, self.foreign.port, self.state, state);
+            self.state = state;
+            self.state_entered_at = timer::get_time_ms();
+        }
+    }
+
     pub fn local_endpoint(&self) -> IpEndpoint {
         self.local
     }
@@ -104,16 +274,279 @@ impl Socket {
         !self.backlog.is_empty()
     }
 
+    pub fn accept_queue_depth(&self) -> usize {
+        self.backlog.len()
+    }
+
+    pub fn accept_queue_max(&self) -> usize {
+        self.backlog_max
+    }
+
+    // listen(2) backlog; out-of-range values are clamped like Linux does
+    // with somaxconn.
+    pub fn set_backlog(&mut self, backlog: usize) {
+        self.backlog_max = backlog.clamp(1, Self::MAX_BACKLOG);
+    }
+
+    pub fn set_syn_overflow_rst(&mut self, enable: bool) {
+        self.syn_overflow_rst = enable;
+    }
+
+    pub fn peak_accept_queue_depth(&self) -> usize {
+        self.peak_accept_queue_depth
+    }
+
+    pub fn connections_refused(&self) -> u64 {
+        self.connections_refused
+    }
+
+    pub fn listen_stats(&self) -> TcpListenStats {
+        TcpListenStats {
+            current_depth: self.accept_queue_depth(),
+            max_depth: self.accept_queue_max(),
+            peak_depth: self.peak_accept_queue_depth,
+            connections_refused: self.connections_refused,
+        }
+    }
+
+    pub fn buf_info(&self) -> TcpBufInfo {
+        TcpBufInfo {
+            rx_capacity: self.rx_capacity,
+            rx_used: self.rx_buf.len(),
+            tx_capacity: self.tx_capacity,
+            tx_used: self.tx_buf.len(),
+            tx_in_flight: self.snd_nxt.wrapping_sub(self.snd_una) as usize,
+        }
+    }
+
+    pub fn stats(&self) -> TcpSocketStats {
+        TcpSocketStats {
+            local_addr: self.local.addr.0,
+            foreign_addr: self.foreign.addr.0,
+            local_port: self.local.port,
+            foreign_port: self.foreign.port,
+            state: self.state.as_u8(),
+            _pad: [0; 3],
+            rx_buf_len: self.rx_buf.len(),
+            tx_buf_len: self.tx_buf.len(),
+            snd_nxt: self.snd_nxt,
+            snd_una: self.snd_una,
+            rcv_nxt: self.rcv_nxt,
+            rcv_wnd: self.rcv_wnd,
+            retransmit_count: self.retransmit_count,
+            rto_ms: self.rto,
+        }
+    }
+
+    pub(super) fn push_accept_queue(&mut self, index: usize) {
+        self.backlog.push_back(index);
+        self.peak_accept_queue_depth = cmp::max(self.peak_accept_queue_depth, self.backlog.len());
+    }
+
     pub fn may_recv(&self) -> bool {
         self.can_recv() && !self.rx_buf.is_empty()
     }
 
+    pub fn has_data(&self) -> bool {
+        self.push_pending || !self.rx_buf.is_empty()
+    }
+
+    pub fn has_send_space(&self) -> bool {
+        self.tx_buf.len() < self.tx_capacity
+    }
+
+    // Readable when recv/accept would not block: data, EOF or a queued connection.
+    pub fn is_readable(&self) -> bool {
+        if self.is_listening() {
+            self.has_pending_connection()
+        } else {
+            self.recv_wait_done()
+        }
+    }
+
+    // Writable when send would not block, including when it would fail
+    // outright; a handshake still in progress is neither.
+    pub fn is_writable(&self) -> bool {
+        match self.state {
+            State::Listen | State::SynSent | State::SynReceived => false,
+            _ => !self.can_send() || self.has_send_space(),
+        }
+    }
+
+    pub fn reuse_port(&self) -> bool {
+        self.reuse_port
+    }
+
+    pub fn set_reuse_port(&mut self, enable: bool) {
+        self.reuse_port = enable;
+    }
+
+    pub fn rx_capacity(&self) -> usize {
+        self.rx_capacity
+    }
+
+    pub fn tx_capacity(&self) -> usize {
+        self.tx_capacity
+    }
+
+    // SO_RCVBUF. Data already buffered must still fit; the new window goes
+    // out with the next segment. Before connect it also sets the scale.
+    pub fn resize_rx_buf(&mut self, new_capacity: usize) -> Result<()> {
+        let queued: usize = self.rx_ooo.iter().map(|(_, data)| data.len()).sum();
+        Self::check_buffer_size(new_capacity, self.rx_buf.len() + queued)?;
+        self.rx_capacity = new_capacity;
+        self.rx_buf
+            .reserve(new_capacity.saturating_sub(self.rx_buf.len()));
+        self.rcv_wnd = (self.rx_capacity - self.rx_buf.len()) as u32;
+        Ok(())
+    }
+
+    // SO_SNDBUF. Shrinking below what is still queued is refused.
+    pub fn resize_tx_buf(&mut self, new_capacity: usize) -> Result<()> {
+        Self::check_buffer_size(new_capacity, self.tx_buf.len())?;
+        self.tx_capacity = new_capacity;
+        self.tx_buf
+            .reserve(new_capacity.saturating_sub(self.tx_buf.len()));
+        Ok(())
+    }
+
+    fn check_buffer_size(new_capacity: usize, in_use: usize) -> Result<()> {
+        if !(Self::MIN_BUFFER_SIZE..=Self::MAX_BUFFER_SIZE).contains(&new_capacity)
+            || new_capacity < in_use
+        {
+            return Err(Error::InvalidArgument);
+        }
+        Ok(())
+    }
+
+    pub fn reuse_addr(&self) -> bool {
+        self.reuse_addr
+    }
+
+    pub fn set_reuse_addr(&mut self, enable: bool) {
+        self.reuse_addr = enable;
+    }
+
+    pub fn nonblocking(&self) -> bool {
+        self.nonblocking
+    }
+
+    pub fn set_nonblocking(&mut self, enable: bool) {
+        self.nonblocking = enable;
+    }
+
+    pub fn recv_timeout_ms(&self) -> Option<u64> {
+        self.recv_timeout_ms
+    }
+
+    pub fn set_recv_timeout_ms(&mut self, timeout_ms: Option<u64>) {
+        self.recv_timeout_ms = timeout_ms;
+    }
+
+    pub fn send_timeout_ms(&self) -> Option<u64> {
+        self.send_timeout_ms
+    }
+
+    pub fn set_send_timeout_ms(&mut self, timeout_ms: Option<u64>) {
+        self.send_timeout_ms = timeout_ms;
+    }
+
+    pub fn nodelay(&self) -> bool {
+        self.nagle_disabled
+    }
+
+    pub fn set_nodelay(&mut self, enable: bool) {
+        self.nagle_disabled = enable;
+    }
+
+    pub fn ttl(&self) -> u8 {
+        self.ip_ttl
+    }
+
+    pub fn set_ttl(&mut self, ttl: u8) -> Result<()> {
+        if ttl == 0 {
+            return Err(Error::InvalidArgument);
+        }
+        self.ip_ttl = ttl;
+        Ok(())
+    }
+
+    pub fn tos(&self) -> u8 {
+        self.ip_tos
+    }
+
+    pub fn set_tos(&mut self, tos: u8) {
+        self.ip_tos = tos;
+    }
+
+    // A segment on this connection, sent with the socket's TTL and TOS.
+    pub(super) fn send_request(
+        &self,
+        seq: u32,
+        ack: u32,
+        flags: u8,
+        wnd: u16,
+        options: Vec<wire::TcpOption>,
+        payload: Vec<u8>,
+    ) -> SendRequest {
+        SendRequest {
+            seq,
+            ack,
+            flags,
+            wnd,
+            options,
+            payload,
+            local: self.local,
+            foreign: self.foreign,
+            ttl: self.ip_ttl,
+            tos: self.ip_tos,
+        }
+    }
+
+    pub fn keepalive(&self) -> bool {
+        self.keepalive_enabled
+    }
+
+    pub fn set_keepalive(&mut self, enable: bool) {
+        self.keepalive_enabled = enable;
+        self.keepalive_sent = 0;
+    }
+
+    pub fn set_keepalive_params(
+        &mut self,
+        idle_ms: u64,
+        interval_ms: u64,
+        probes: u8,
+    ) -> Result<()> {
+        if idle_ms == 0 || interval_ms == 0 || probes == 0 {
+            return Err(Error::InvalidArgument);
+        }
+        self.keepalive_idle_ms = idle_ms;
+        self.keepalive_interval_ms = interval_ms;
+        self.keepalive_probes = probes;
+        Ok(())
+    }
+
+    pub fn set_finwait2_timeout(&mut self, timeout_ms: u64) -> Result<()> {
+        if timeout_ms == 0 {
+            return Err(Error::InvalidArgument);
+        }
+        self.finwait2_timeout_ms = timeout_ms;
+        Ok(())
+    }
+
+    // Any segment from the peer proves it is still there.
+    pub(super) fn note_recv(&mut self, now: u64) {
+        self.last_recv_time = now;
+        self.keepalive_sent = 0;
+    }
+
     pub fn listen(&mut self, local: IpEndpoint) -> Result<()> {
         if self.state != State::Closed {
             return Err(Error::SocketAlreadyOpen);
         }
         self.local = local;
-        self.state = State::Listen;
+        self.set_state(State::Listen);
         Ok(())
     }
 
@@ -132,12 +565,17 @@ impl Socket {
 
         self.local = local_ep;
         self.foreign = remote;
-        self.rcv_wnd = self.rx_capacity as u16;
-        self.iss = initial_iss(local_ep.port);
+        self.rcv_wnd = self.rx_capacity as u32;
+        self.rcv_wscale = self.desired_wscale();
+        self.wscale_offer = true;
+        self.ts_enabled = true;
+        self.sack_enabled = true;
+        self.iss = initial_iss(&local_ep, &remote);
         self.snd_una = self.iss;
         self.snd_nxt = self.iss + 1;
-        self.state = State::SynSent;
-        let _ = self.egress(wire::field::FLG_SYN, &[]);
+        self.set_state(State::SynSent);
+        let options = self.syn_options();
+        let _ = self.egress_with_options(wire::field::FLG_SYN, &options, &[]);
         Ok(())
     }
 
@@ -148,14 +586,30 @@ impl Socket {
         let available = self.tx_capacity.saturating_sub(self.tx_buf.len());
         let to_write = cmp::min(data.len(), available);
         if to_write == 0 {
-            return Err(Error::BufferFull);
+            return Err(if self.nonblocking {
+                Error::WouldBlock
+            } else {
+                Error::BufferFull
+            });
         }
         self.tx_buf.extend(data[..to_write].iter().copied());
         self.flush_tx(timer::get_time_ms());
         Ok(to_write)
     }
 
+    pub fn eof_received(&self) -> bool {
+        self.eof_received
+    }
+
     pub fn recv_slice(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if self.rx_buf.is_empty() {
+            if self.eof_received {
+                return Ok(0);
+            }
+            if self.can_recv() {
+                return Err(Error::WouldBlock);
+            }
+        }
         if !self.can_recv() {
             return Err(Error::SocketNotOpen);
         }
@@ -165,7 +619,8 @@ impl Socket {
                 *byte = b;
             }
         }
-        self.rcv_wnd = (self.rx_capacity - self.rx_buf.len()) as u16;
+        self.push_pending = false;
+        self.rcv_wnd = (self.rx_capacity - self.rx_buf.len()) as u32;
         Ok(to_read)
     }
 
@@ -173,22 +628,42 @@ impl Socket {
         match self.state {
             State::Closed => {}
             State::Listen | State::SynSent => {
-                self.state = State::Closed;
-            }
-            State::SynReceived | State::Established => {
-                let _ = self.egress(wire::field::FLG_ACK | wire::field::FLG_FIN, &[]);
-                self.snd_nxt = self.snd_nxt.wrapping_add(1);
-                self.state = State::FinWait1;
+                self.set_state(State::Closed);
             }
-            State::CloseWait => {
-                let _ = self.egress(wire::field::FLG_ACK | wire::field::FLG_FIN, &[]);
-                self.snd_nxt = self.snd_nxt.wrapping_add(1);
-                self.state = State::LastAck;
+            _ => {
+                let _ = self.shutdown_write();
             }
-            _ => {}
         }
     }
 
+    // Half-close: queued data and a FIN go out, but the receive side stays
+    // open until the peer's FIN. Calling it again is a no-op.
+    pub fn shutdown_write(&mut self) -> Result<()> {
+        let next = match self.state {
+            State::SynReceived | State::Established => State::FinWait1,
+            State::CloseWait => State::LastAck,
+            State::Closed | State::Listen | State::SynSent => return Err(Error::SocketNotOpen),
+            _ => return Ok(()),
+        };
+        self.transmit(false);
+        let _ = self.egress(wire::field::FLG_ACK | wire::field::FLG_FIN, &[]);
+        self.snd_nxt = self.snd_nxt.wrapping_add(1);
+        self.set_state(next);
+        Ok(())
+    }
+
+    fn recv_wait_done(&self) -> bool {
+        self.has_data()
+            || !matches!(
+                self.state,
+                State::SynSent
+                    | State::SynReceived
+                    | State::Established
+                    | State::FinWait1
+                    | State::FinWait2
+            )
+    }
+
     fn can_recv(&self) -> bool {
         matches!(
             self.state,
@@ -196,7 +671,7 @@ impl Socket {
         )
     }
 
-    fn can_send(&self) -> bool {
+    pub(super) fn can_send(&self) -> bool {
         matches!(self.state, State::Established | State::CloseWait)
     }
 
@@ -206,45 +681,277 @@ impl Socket {
         }
     }
 
-    fn handle_segment(
-        &mut self,
-        seg_seq: u32,
-        seg_ack: u32,
-        seg_len: u32,
-        seg_wnd: u16,
-        flags: u8,
-        payload: &[u8],
-    ) {
-        let seg = SegmentInfo::new(seg_seq, seg_ack, seg_len, seg_wnd, flags, payload);
+    fn handle_segment(&mut self, seg: SegmentInfo<'_>) {
         let mut processor = SegmentProcessor::new(self, seg);
         processor.run();
+        let log = processor.take_log();
+        trace!(TCP, "[tcp] segment: {}", log);
+    }
+
+    // Smallest shift that lets the whole receive buffer be advertised.
+    pub(super) fn desired_wscale(&self) -> u8 {
+        let mut shift = 0;
+        while shift < Self::MAX_WSCALE && (self.rx_capacity >> shift) > u16::MAX as usize {
+            shift += 1;
+        }
+        shift
+    }
+
+    // Settles the shifts from the peer's SYN: scaling is used only when
+    // both sides sent the option.
+    pub(super) fn negotiate_wscale(&mut self, peer: Option<u8>) {
+        match peer {
+            Some(shift) if self.wscale_offer => {
+                self.snd_wscale = cmp::min(shift, Self::MAX_WSCALE);
+            }
+            _ => {
+                self.wscale_offer = false;
+                self.snd_wscale = 0;
+                self.rcv_wscale = 0;
+            }
+        }
+    }
+
+    pub(super) fn negotiate_timestamps(&mut self, peer: Option<wire::TsOption>) {
+        match peer {
+            Some(ts) if self.ts_enabled => self.ts_ecr = ts.tsval,
+            _ => self.ts_enabled = false,
+        }
+    }
+
+    // Clamps the send MSS to what the peer's SYN announced. Without the
+    // option the default is kept.
+    pub(super) fn negotiate_mss(&mut self, peer: Option<u16>) {
+        if let Some(peer) = peer {
+            self.mss = peer.clamp(Self::MIN_MSS, Self::DEFAULT_MSS as u16);
+            self.cwnd = self.mss as u32;
+        }
+    }
+
+    pub(super) fn negotiate_sack(&mut self, peer_permitted: bool) {
+        self.sack_enabled &= peer_permitted;
+    }
+
+    // Keeps a segment that arrived beyond rcv_nxt until the gap before it
+    // is filled. Data past the window, or past what rx_buf can still take,
+    // is dropped.
+    pub(super) fn queue_ooo(&mut self, seq: u32, payload: &[u8]) {
+        let room = self.rcv_nxt.wrapping_add(self.rcv_wnd).wrapping_sub(seq) as usize;
+        let queued: usize = self.rx_ooo.iter().map(|(_, data)| data.len()).sum();
+        let free = self
+            .rx_capacity
+            .saturating_sub(self.rx_buf.len())
+            .saturating_sub(queued);
+        let data = &payload[..cmp::min(payload.len(), cmp::min(room, free))];
+        if data.is_empty() {
+            return;
+        }
+        match self
+            .rx_ooo
+            .iter()
+            .position(|(s, _)| !SegmentProcessor::seq_lt(*s, seq))
+        {
+            Some(i) if self.rx_ooo[i].0 == seq => {
+                if self.rx_ooo[i].1.len() < data.len() {
+                    self.rx_ooo[i].1 = data.to_vec();
+                }
+            }
+            Some(i) => self.rx_ooo.insert(i, (seq, data.to_vec())),
+            None => self.rx_ooo.push_back((seq, data.to_vec())),
+        }
+        self.update_sack_rx(Some(seq));
+    }
+
+    // Moves queued segments that now continue rcv_nxt into rx_buf and
+    // returns how many bytes were added.
+    pub(super) fn drain_ooo(&mut self) -> usize {
+        let mut moved = 0;
+        while let Some(&(seq, _)) = self.rx_ooo.front() {
+            if SegmentProcessor::seq_lt(self.rcv_nxt, seq) {
+                break;
+            }
+            let Some((_, data)) = self.rx_ooo.pop_front() else {
+                break;
+            };
+            let skip = self.rcv_nxt.wrapping_sub(seq) as usize;
+            if skip >= data.len() {
+                continue;
+            }
+            let space = self.rx_capacity.saturating_sub(self.rx_buf.len());
+            let fresh = &data[skip..skip + cmp::min(space, data.len() - skip)];
+            self.rx_buf.extend(fresh);
+            self.rcv_nxt = self.rcv_nxt.wrapping_add(fresh.len() as u32);
+            moved += fresh.len();
+        }
+        self.update_sack_rx(None);
+        moved
+    }
+
+    // Rebuilds the advertised blocks from rx_ooo. RFC 2018 4: the block
+    // holding the most recent segment goes first. With timestamps on there
+    // is only room for three.
+    fn update_sack_rx(&mut self, latest: Option<u32>) {
+        let mut ranges: Vec<(u32, u32)> = Vec::new();
+        for (seq, data) in self.rx_ooo.iter() {
+            let end = seq.wrapping_add(data.len() as u32);
+            match ranges.last_mut() {
+                Some(last) if !SegmentProcessor::seq_lt(last.1, *seq) => {
+                    if SegmentProcessor::seq_lt(last.1, end) {
+                        last.1 = end;
+                    }
+                }
+                _ => ranges.push((*seq, end)),
+            }
+        }
+        if let Some(latest) = latest {
+            if let Some(i) = ranges
+                .iter()
+                .position(|&(left, right)| SegmentProcessor::seq_between(left, latest, right))
+            {
+                let block = ranges.remove(i);
+                ranges.insert(0, block);
+            }
+        }
+        self.sack_rx.clear();
+        for (left, right) in ranges {
+            self.sack_rx.push(left, right);
+        }
+        if self.ts_enabled {
+            self.sack_rx.truncate(wire::SACK_MAX_BLOCKS - 1);
+        }
+    }
+
+    // Whether the peer's latest SACK blocks cover the whole entry.
+    fn is_sacked(sacked: &wire::SackBlocks, entry: &RetransmitEntry) -> bool {
+        let mut len = entry.payload.len() as u32;
+        if (entry.flags & (wire::field::FLG_SYN | wire::field::FLG_FIN)) != 0 {
+            len += 1;
+        }
+        let end = entry.seq.wrapping_add(len);
+        sacked.as_slice().iter().any(|&(left, right)| {
+            SegmentProcessor::seq_le(left, entry.seq) && SegmentProcessor::seq_le(end, right)
+        })
+    }
+
+    pub(super) fn update_rtt(&mut self, sample: u64) {
+        match self.srtt {
+            None => {
+                self.srtt = Some(sample);
+                self.rttvar = sample / 2;
+            }
+            Some(srtt) => {
+                self.rttvar = (3 * self.rttvar + srtt.abs_diff(sample)) / 4;
+                self.srtt = Some((7 * srtt + sample) / 8);
+            }
+        }
+        let srtt = self.srtt.unwrap_or(sample);
+        self.rto = (srtt + cmp::max(1, 4 * self.rttvar)).clamp(Self::MIN_RTO_MS, Self::MAX_RTO_MS);
+    }
+
+    // Timestamps for a segment sent now, if they are in use.
+    fn outgoing_ts(ts_enabled: bool, ts_ecr: u32) -> Option<wire::TsOption> {
+        ts_enabled.then(|| wire::TsOption {
+            tsval: timer::get_time_ms() as u32,
+            tsecr: ts_ecr,
+        })
+    }
+
+    fn stamped(options: &[wire::TcpOption], ts: Option<wire::TsOption>) -> Vec<wire::TcpOption> {
+        let mut out = options.to_vec();
+        out.extend(ts.map(wire::TcpOption::from));
+        out
+    }
+
+    // Payload per segment once per-segment options are taken out.
+    fn send_mss(&self) -> usize {
+        let mut mss = self.mss as usize;
+        if self.ts_enabled {
+            mss -= wire::PacketMut::total_option_bytes(&[wire::TcpOption::Timestamps {
+                tsval: 0,
+                tsecr: 0,
+            }]);
+        }
+        mss
+    }
+
+    // Window field for an outgoing segment. RFC 7323 2.2: the window in a
+    // SYN is never scaled.
+    pub(super) fn advertised_window(&self, flags: u8) -> u16 {
+        Self::window_field(self.rcv_wnd, self.rcv_wscale, flags)
+    }
+
+    fn window_field(rcv_wnd: u32, rcv_wscale: u8, flags: u8) -> u16 {
+        let wnd = if (flags & wire::field::FLG_SYN) != 0 {
+            rcv_wnd
+        } else {
+            rcv_wnd >> rcv_wscale
+        };
+        cmp::min(wnd, u16::MAX as u32) as u16
+    }
+
+    // Options announced on SYN and SYN-ACK.
+    pub(super) fn syn_options(&self) -> Vec<wire::TcpOption> {
+        // our receive limit, independent of what the peer announced
+        let mut options = alloc::vec![wire::TcpOption::Mss(Self::DEFAULT_MSS as u16)];
+        if self.wscale_offer {
+            options.push(wire::TcpOption::WindowScale(self.rcv_wscale));
+        }
+        if self.sack_enabled {
+            options.push(wire::TcpOption::SackPermitted);
+        }
+        options
     }
 
     pub(super) fn egress(&mut self, flags: u8, payload: &[u8]) -> Result<()> {
+        self.egress_with_options(flags, &[], payload)
+    }
+
+    pub(super) fn egress_with_options(
+        &mut self,
+        flags: u8,
+        options: &[wire::TcpOption],
+        payload: &[u8],
+    ) -> Result<()> {
         let mut seq = self.snd_nxt;
         if (flags & wire::field::FLG_SYN) != 0 {
             seq = self.iss;
         }
         let payload_vec = payload.to_vec();
+        if (flags & wire::field::FLG_ACK) != 0 {
+            self.ack_deadline = None;
+        }
+        let ts = Self::outgoing_ts(self.ts_enabled, self.ts_ecr);
+        if let Some(ts) = ts {
+            self.ts_val = ts.tsval;
+        }
         if (flags & (wire::field::FLG_SYN | wire::field::FLG_FIN)) != 0 || !payload.is_empty() {
             self.retransmit.push_back(RetransmitEntry {
                 first_at: timer::get_time_ms(),
                 last_at: timer::get_time_ms(),
-                rto: Self::DEFAULT_RTO_MS,
+                rto: self.rto,
                 seq,
                 flags,
+                options: options.to_vec(),
                 payload: payload_vec.clone(),
             });
         }
-        self.pending.push_back(SendRequest {
+        let mut sent_options = Self::stamped(options, ts);
+        // SACK blocks only ride on pure ACKs, which are never retransmitted
+        let pure_ack = (flags & (wire::field::FLG_SYN | wire::field::FLG_FIN)) == 0
+            && (flags & wire::field::FLG_ACK) != 0
+            && payload.is_empty();
+        if self.sack_enabled && pure_ack && !self.sack_rx.is_empty() {
+            sent_options.push(wire::TcpOption::Sack(self.sack_rx));
+        }
+        let req = self.send_request(
             seq,
-            ack: self.rcv_nxt,
+            self.rcv_nxt,
             flags,
-            wnd: self.rcv_wnd,
-            payload: payload_vec,
-            local: self.local,
-            foreign: self.foreign,
-        });
+            self.advertised_window(flags),
+            sent_options,
+            payload_vec,
+        );
+        self.pending.push_back(req);
         Ok(())
     }
 
@@ -257,20 +964,93 @@ impl Socket {
         }
     }
 
-    fn flush_tx(&mut self, _now: u64) {
+    pub(super) fn flush_tx(&mut self, now: u64) {
+        self.transmit(!self.nagle_disabled);
+        self.update_persist(now);
+    }
+
+    pub(super) fn poll_delayed_ack(&mut self, now: u64) {
+        match self.ack_deadline {
+            Some(deadline) if now >= deadline => {
+                let _ = self.egress(wire::field::FLG_ACK, &[]);
+            }
+            _ => {}
+        }
+    }
+
+    // Arms the persist timer when data is stuck behind a zero window with
+    // nothing in flight to draw an ACK, and cancels it once the window opens.
+    fn update_persist(&mut self, now: u64) {
+        let stalled = self.snd_wnd == 0
+            && !self.tx_buf.is_empty()
+            && self.retransmit.is_empty()
+            && self.can_send();
+        if !stalled {
+            self.persist_deadline = None;
+        } else if self.persist_deadline.is_none() {
+            self.persist_backoff = Self::DEFAULT_RTO_MS;
+            self.persist_deadline = Some(now.saturating_add(self.persist_backoff));
+        }
+    }
+
+    // Sends the first queued byte past the zero window. snd_nxt stays put:
+    // a peer that drops the byte answers with its current window, and one
+    // that keeps it is handled by commit_persist_probe.
+    pub(super) fn poll_persist(&mut self, now: u64) {
+        match self.persist_deadline {
+            Some(deadline) if now >= deadline => {}
+            _ => return,
+        }
+        let Some(&byte) = self.tx_buf.front() else {
+            self.persist_deadline = None;
+            return;
+        };
+        let flags = wire::field::FLG_ACK;
+        let ts = Self::outgoing_ts(self.ts_enabled, self.ts_ecr);
+        let req = self.send_request(
+            self.snd_nxt,
+            self.rcv_nxt,
+            flags,
+            self.advertised_window(flags),
+            Self::stamped(&[], ts),
+            alloc::vec![byte],
+        );
+        self.pending.push_back(req);
+        self.persist_backoff = cmp::min(self.persist_backoff * 2, Self::MAX_PERSIST_MS);
+        self.persist_deadline = Some(now.saturating_add(self.persist_backoff));
+    }
+
+    // The peer accepted the probe byte: account for it as sent so the ACK
+    // is not taken for one of unsent data.
+    pub(super) fn commit_persist_probe(&mut self, ack: u32) {
+        if self.persist_deadline.is_some()
+            && ack == self.snd_nxt.wrapping_add(1)
+            && self.tx_buf.pop_front().is_some()
+        {
+            self.snd_nxt = ack;
+        }
+    }
+
+    // With `nagle` set, a segment shorter than the MSS only goes out when
+    // nothing is in flight (RFC 896), so small writes coalesce while
+    // waiting for the ACK.
+    fn transmit(&mut self, nagle: bool) {
         if !self.can_send() {
             return;
         }
         let in_flight = self.snd_nxt.wrapping_sub(self.snd_una);
-        let mut window_available = self.snd_wnd as u32;
+        let mut window_available = cmp::min(self.snd_wnd, self.cwnd);
         if window_available > in_flight {
             window_available -= in_flight;
         } else {
             window_available = 0;
         }
         while window_available > 0 && !self.tx_buf.is_empty() {
-            let mss = self.mss as usize;
+            let mss = self.send_mss();
             let to_send = cmp::min(mss, cmp::min(window_available as usize, self.tx_buf.len()));
+            if nagle && self.tx_buf.len() < mss && self.snd_una != self.snd_nxt {
+                break;
+            }
             let mut payload = Vec::with_capacity(to_send);
             for _ in 0..to_send {
                 if let Some(b) = self.tx_buf.pop_front() {
@@ -283,35 +1063,189 @@ impl Socket {
         }
     }
 
+    // Earliest time Tcp::poll has timer work for this socket. None means
+    // only an incoming segment or a syscall can give it more to do, and
+    // both of those reschedule it.
+    pub(super) fn next_deadline(&self) -> Option<u64> {
+        if !self.pending.is_empty() {
+            return Some(0);
+        }
+        let retransmit = self.retransmit.iter().map(|entry| {
+            cmp::min(
+                entry.first_at.saturating_add(Self::RETRANSMIT_DEADLINE_MS),
+                entry.last_at.saturating_add(entry.rto),
+            )
+        });
+        self.timewait_deadline
+            .into_iter()
+            .chain(self.finwait2_deadline)
+            .chain(retransmit)
+            .chain(self.keepalive_deadline())
+            .chain(self.persist_deadline)
+            .chain(self.ack_deadline)
+            .chain(self.recv_wait_deadline)
+            .chain(self.send_wait_deadline)
+            .min()
+    }
+
+    fn wake_recv_waiter(&mut self, now: u64) -> bool {
+        if let Some(deadline) = self.recv_wait_deadline {
+            if self.recv_wait_done() || now >= deadline {
+                self.recv_wait_deadline = None;
+                return true;
+            }
+        }
+        false
+    }
+
+    fn wake_send_waiter(&mut self, now: u64) -> bool {
+        if let Some(deadline) = self.send_wait_deadline {
+            if self.has_send_space() || !self.can_send() || now >= deadline {
+                self.send_wait_deadline = None;
+                return true;
+            }
+        }
+        false
+    }
+
     fn poll_timewait(&mut self, now: u64) {
         if let Some(deadline) = self.timewait_deadline {
             if now >= deadline && self.state == State::TimeWait {
-                self.state = State::Closed;
+                self.set_state(State::Closed);
                 self.timewait_deadline = None;
             }
         }
     }
 
-    fn poll_retransmit(&mut self, now: u64) {
-        for entry in self.retransmit.iter_mut() {
+    pub(super) fn poll_finwait2(&mut self, now: u64) {
+        if self.state != State::FinWait2 {
+            self.finwait2_deadline = None;
+            return;
+        }
+        if let Some(deadline) = self.finwait2_deadline {
+            if now >= deadline {
+                self.set_state(State::Closed);
+                self.finwait2_deadline = None;
+            }
+        }
+    }
+
+    // Next keepalive action: a probe, or giving up once the last probe's
+    // interval has gone unanswered. Idle only counts with nothing in
+    // flight; the retransmit timer covers that case.
+    fn keepalive_deadline(&self) -> Option<u64> {
+        if !self.keepalive_enabled
+            || !matches!(self.state, State::Established | State::CloseWait)
+            || !self.retransmit.is_empty()
+        {
+            return None;
+        }
+        let wait = self.keepalive_idle_ms.saturating_add(
+            self.keepalive_interval_ms
+                .saturating_mul(self.keepalive_sent as u64),
+        );
+        Some(self.last_recv_time.saturating_add(wait))
+    }
+
+    pub(super) fn poll_keepalive(&mut self, now: u64) {
+        match self.keepalive_deadline() {
+            Some(deadline) if now >= deadline => {}
+            _ => return,
+        }
+        if self.keepalive_sent >= self.keepalive_probes {
+            trace!(TCP, "[tcp] keepalive timeout {:?}", self.foreign);
+            self.set_state(State::Closed);
+            return;
+        }
+        // An old sequence number makes the peer answer with an ACK.
+        let ts = Self::outgoing_ts(self.ts_enabled, self.ts_ecr);
+        let req = self.send_request(
+            self.snd_nxt.wrapping_sub(1),
+            self.rcv_nxt,
+            wire::field::FLG_ACK,
+            self.advertised_window(wire::field::FLG_ACK),
+            Self::stamped(&[], ts),
+            Vec::new(),
+        );
+        self.pending.push_back(req);
+        self.keepalive_sent += 1;
+    }
+
+    pub(super) fn poll_retransmit(&mut self, now: u64) {
+        let ts = Self::outgoing_ts(self.ts_enabled, self.ts_ecr);
+        let mut timed_out = false;
+        for i in 0..self.retransmit.len() {
+            let entry = &self.retransmit[i];
             if now.saturating_sub(entry.first_at) >= Self::RETRANSMIT_DEADLINE_MS {
                 self.state = State::Closed;
+                self.state_entered_at = now;
                 return;
             }
+            if Self::is_sacked(&self.sacked, entry) {
+                continue;
+            }
             if now.saturating_sub(entry.last_at) >= entry.rto {
-                self.pending.push_back(SendRequest {
-                    seq: entry.seq,
-                    ack: self.rcv_nxt,
-                    flags: entry.flags,
-                    wnd: self.rcv_wnd,
-                    payload: entry.payload.clone(),
-                    local: self.local,
-                    foreign: self.foreign,
-                });
+                let req = self.send_request(
+                    entry.seq,
+                    self.rcv_nxt,
+                    entry.flags,
+                    Self::window_field(self.rcv_wnd, self.rcv_wscale, entry.flags),
+                    Self::stamped(&entry.options, ts),
+                    entry.payload.clone(),
+                );
+                self.pending.push_back(req);
+                let entry = &mut self.retransmit[i];
                 entry.last_at = now;
                 entry.rto = entry.rto.saturating_mul(2);
+                self.retransmit_count += 1;
+                timed_out = true;
             }
         }
+        if timed_out {
+            self.on_rto_loss();
+        }
+    }
+
+    // RFC 5681 3.1: a timeout halves ssthresh and drops back to slow start.
+    fn on_rto_loss(&mut self) {
+        let mss = self.mss as u32;
+        self.ssthresh = cmp::max(self.cwnd / 2, 2 * mss);
+        self.cwnd = mss;
+    }
+
+    // Slow start below ssthresh, then roughly one MSS per RTT.
+    pub(super) fn grow_cwnd(&mut self) {
+        let mss = self.mss as u32;
+        let step = if self.cwnd < self.ssthresh {
+            mss
+        } else {
+            cmp::max(1, mss * mss / self.cwnd)
+        };
+        self.cwnd = self.cwnd.saturating_add(step);
+    }
+
+    // RFC 5681 3.2: resend the oldest segment right away and continue from
+    // half the flight instead of restarting slow start.
+    pub(super) fn fast_retransmit(&mut self, now: u64) {
+        let mss = self.mss as u32;
+        self.ssthresh = cmp::max(self.cwnd / 2, 2 * mss);
+        self.cwnd = self.ssthresh;
+        let ts = Self::outgoing_ts(self.ts_enabled, self.ts_ecr);
+        if let Some(entry) = self.retransmit.front() {
+            trace!(TCP, "[tcp] fast retransmit seq={}", entry.seq);
+            let req = self.send_request(
+                entry.seq,
+                self.rcv_nxt,
+                entry.flags,
+                Self::window_field(self.rcv_wnd, self.rcv_wscale, entry.flags),
+                Self::stamped(&entry.options, ts),
+                entry.payload.clone(),
+            );
+            self.pending.push_back(req);
+            let entry = self.retransmit.front_mut().unwrap();
+            entry.last_at = now;
+            self.retransmit_count += 1;
+        }
     }
 
     fn matches_established(&self, local: &IpEndpoint, foreign: &IpEndpoint) -> bool {
@@ -334,20 +1268,39 @@ impl Socket {
     }
 }
 
-struct Tcp {
+pub(super) struct Tcp {
     sockets: Mutex<SocketSet<Socket>>,
+    // min-heap of (next_poll_at, index); entries whose time no longer
+    // matches the socket's next_poll_at are stale and skipped. Taken
+    // after `sockets`.
+    poll_queue: Mutex<BinaryHeap<Reverse<(u64, usize)>>>,
     next_ephemeral_port: AtomicU16,
+    accept_robin: AtomicUsize,
+    // answer a SYN for a closed port with ICMP Port Unreachable instead
+    // of a RST
+    reject_with_icmp: AtomicBool,
+    window_open: Condvar,
+    data_ready: Condvar,
 }
 
 impl Tcp {
-    const SOCKET_CAPACITY: usize = 16;
+    const SOCKET_CAPACITY: usize = TCP_SOCKET_SIZE;
     const EPHEMERAL_PORT_MIN: u16 = 49152;
     const EPHEMERAL_PORT_MAX: u16 = 65535;
 
     const fn new() -> Self {
+        Self::with_capacity(Self::SOCKET_CAPACITY)
+    }
+
+    pub(super) const fn with_capacity(capacity: usize) -> Self {
         Self {
-            sockets: Mutex::new(SocketSet::new(Self::SOCKET_CAPACITY), "tcp_sockets"),
+            sockets: Mutex::new(SocketSet::new(capacity), "tcp_sockets"),
+            poll_queue: Mutex::new(BinaryHeap::new(), "tcp_poll_queue"),
             next_ephemeral_port: AtomicU16::new(Self::EPHEMERAL_PORT_MIN),
+            accept_robin: AtomicUsize::new(0),
+            reject_with_icmp: AtomicBool::new(false),
+            window_open: Condvar::new(),
+            data_ready: Condvar::new(),
         }
     }
 
@@ -363,13 +1316,51 @@ impl Tcp {
         sockets.free(SocketHandle::new(index))
     }
 
+    // Starts an orderly close and gives up the socket; the index must not
+    // be used again.
+    pub fn socket_close(&self, index: usize) -> Result<()> {
+        let mut sockets = self.sockets.lock();
+        let socket = sockets.get_mut(SocketHandle::new(index))?;
+        socket.close();
+        socket.orphaned = true;
+        self.schedule(socket, index, 0);
+        Ok(())
+    }
+
     pub fn socket_get_mut<R, F>(&self, index: usize, f: F) -> Result<R>
     where
         F: FnOnce(&mut Socket) -> R,
     {
         let mut sockets = self.sockets.lock();
         let socket = sockets.get_mut(SocketHandle::new(index))?;
-        Ok(f(socket))
+        let result = f(socket);
+        self.schedule(socket, index, 0);
+        Ok(result)
+    }
+
+    fn schedule(&self, socket: &mut Socket, index: usize, at: u64) {
+        if at < socket.next_poll_at {
+            socket.next_poll_at = at;
+            self.poll_queue.lock().push(Reverse((at, index)));
+        }
+    }
+
+    fn take_due(&self, sockets: &mut SocketSet<Socket>, now: u64) -> Vec<usize> {
+        let mut queue = self.poll_queue.lock();
+        let mut due = Vec::new();
+        while let Some(&Reverse((at, index))) = queue.peek() {
+            if at > now {
+                break;
+            }
+            queue.pop();
+            if let Ok(socket) = sockets.get_mut(SocketHandle::new(index)) {
+                if socket.next_poll_at == at {
+                    socket.next_poll_at = Socket::POLL_NEVER;
+                    due.push(index);
+                }
+            }
+        }
+        due
     }
 
     pub fn socket_get<R, F>(&self, index: usize, f: F) -> Result<R>
@@ -381,6 +1372,36 @@ impl Tcp {
         Ok(f(socket))
     }
 
+    pub fn socket_listen(&self, index: usize, local: IpEndpoint) -> Result<()> {
+        let mut sockets = self.sockets.lock();
+        let reuse_port = sockets.get(SocketHandle::new(index))?.reuse_port;
+        self.check_port_available(&sockets, index, &local, reuse_port)?;
+        sockets.get_mut(SocketHandle::new(index))?.listen(local)
+    }
+
+    fn check_port_available(
+        &self,
+        sockets: &SocketSet<Socket>,
+        index: usize,
+        local: &IpEndpoint,
+        reuse_port: bool,
+    ) -> Result<()> {
+        for (handle, socket) in sockets.iter() {
+            if handle.index() == index || socket.state != State::Listen {
+                continue;
+            }
+            if socket.local.port != local.port {
+                continue;
+            }
+            let addr_overlap =
+                socket.local.addr.0 == 0 || local.addr.0 == 0 || socket.local.addr == local.addr;
+            if addr_overlap && !(reuse_port && socket.reuse_port) {
+                return Err(Error::PortInUse);
+            }
+        }
+        Ok(())
+    }
+
     pub fn socket_accept(&self, listen_index: usize) -> Result<usize> {
         let mut sockets = self.sockets.lock();
         let listen_socket = sockets.get_mut(SocketHandle::new(listen_index))?;
@@ -393,7 +1414,90 @@ impl Tcp {
         Ok(child_index)
     }
 
+    pub fn wait_for_send_space(&self, index: usize, timeout_ms: u64) -> Result<()> {
+        let deadline = timer::get_time_ms().saturating_add(timeout_ms);
+        let mut sockets = self.sockets.lock();
+        loop {
+            let socket = sockets.get_mut(SocketHandle::new(index))?;
+            if !socket.can_send() {
+                socket.send_wait_deadline = None;
+                return Err(Error::SocketNotOpen);
+            }
+            if socket.has_send_space() {
+                socket.send_wait_deadline = None;
+                return Ok(());
+            }
+            if timer::get_time_ms() >= deadline {
+                socket.send_wait_deadline = None;
+                return Err(Error::Timeout);
+            }
+            socket.send_wait_deadline = Some(deadline);
+            self.schedule(socket, index, deadline);
+            sockets = self.window_open.wait(sockets);
+        }
+    }
+
+    pub fn wait_for_data(&self, index: usize, timeout_ms: u64) -> Result<()> {
+        let deadline = timer::get_time_ms().saturating_add(timeout_ms);
+        let mut sockets = self.sockets.lock();
+        loop {
+            let socket = sockets.get_mut(SocketHandle::new(index))?;
+            if socket.recv_wait_done() {
+                socket.recv_wait_deadline = None;
+                return Ok(());
+            }
+            if timer::get_time_ms() >= deadline {
+                socket.recv_wait_deadline = None;
+                return Err(Error::Timeout);
+            }
+            socket.recv_wait_deadline = Some(deadline);
+            self.schedule(socket, index, deadline);
+            sockets = self.data_ready.wait(sockets);
+        }
+    }
+
+    fn wake_recv_waiters(&self, sockets: &mut SocketSet<Socket>, now: u64) {
+        let mut woken = false;
+        for (_, socket) in sockets.iter_mut() {
+            woken |= socket.wake_recv_waiter(now);
+        }
+        if woken {
+            self.data_ready.notify_all();
+        }
+    }
+
+    fn wake_send_waiters(&self, sockets: &mut SocketSet<Socket>, now: u64) {
+        let mut woken = false;
+        for (_, socket) in sockets.iter_mut() {
+            woken |= socket.wake_send_waiter(now);
+        }
+        if woken {
+            self.window_open.notify_all();
+        }
+    }
+
     pub fn ingress(&self, src_ip: IpAddr, dst_ip: IpAddr, data: &[u8]) -> Result<()> {
+        let (sends, unmatched_syn) = self.process_segment(src_ip, dst_ip, data)?;
+        for req in sends {
+            self.output_segment(&req)?;
+        }
+
+        if unmatched_syn {
+            // the IP layer turns this into ICMP Port Unreachable
+            return Err(Error::NoMatchingSocket);
+        }
+        Ok(())
+    }
+
+    // Runs a segment through the socket table. Returns the segments to send
+    // in reply and whether it was a SYN for a closed port that should be
+    // answered with ICMP instead.
+    pub(super) fn process_segment(
+        &self,
+        src_ip: IpAddr,
+        dst_ip: IpAddr,
+        data: &[u8],
+    ) -> Result<(Vec<SendRequest>, bool)> {
         trace!(
             TCP,
             "[tcp] ingress: {} bytes from {:?}",
@@ -435,51 +1539,96 @@ impl Tcp {
             flags,
             payload,
         );
+        let seg = seg.with_options(&packet.options().unwrap_or_default());
 
         let local = IpEndpoint::new(dst_ip, packet.dst_port());
         let foreign = IpEndpoint::new(src_ip, packet.src_port());
 
         let mut sends = Vec::new();
+        let mut unmatched_syn = false;
         {
             let mut sockets = self.sockets.lock();
 
-            let (established_idx, listen_idx) = self.find_sockets(&sockets, &local, &foreign);
+            let new_syn = seg.has_syn() && !seg.has_ack();
+            let (established_idx, listen_idx) =
+                self.find_sockets(&sockets, &local, &foreign, new_syn);
 
             if let Some(index) = established_idx {
                 self.handle_on_socket(&mut sockets, index, &seg, &mut sends);
             } else if let Some(index) = listen_idx {
                 self.handle_on_listen(&mut sockets, index, &local, &foreign, &seg, &mut sends)?;
+            } else if seg.has_syn()
+                && !seg.has_ack()
+                && self.reject_with_icmp.load(Ordering::Relaxed)
+            {
+                unmatched_syn = true;
             } else {
                 self.send_rst_response(&local, &foreign, &seg, &mut sends);
             }
+            let now = timer::get_time_ms();
+            self.wake_send_waiters(&mut sockets, now);
+            self.wake_recv_waiters(&mut sockets, now);
         }
+        Ok((sends, unmatched_syn))
+    }
 
-        for req in sends {
-            self.output_segment(&req)?;
-        }
-
-        Ok(())
+    pub fn set_reject_with_icmp(&self, enable: bool) {
+        self.reject_with_icmp.store(enable, Ordering::Relaxed);
     }
 
     pub fn poll(&self) -> Result<()> {
-        let now = timer::get_time_ms();
+        self.poll_at(timer::get_time_ms()).map(|_| ())
+    }
+
+    // Runs timers only for sockets whose next_poll_at has passed, instead
+    // of walking the whole table every tick. Returns how many were serviced.
+    pub(super) fn poll_at(&self, now: u64) -> Result<usize> {
         let mut sends = Vec::new();
+        let serviced;
 
         {
             let mut sockets = self.sockets.lock();
-            for (_, socket) in sockets.iter_mut() {
+            let due = self.take_due(&mut sockets, now);
+            let mut send_woken = false;
+            let mut recv_woken = false;
+            let mut reaped = Vec::new();
+            for &index in &due {
+                let socket = sockets.get_mut(SocketHandle::new(index))?;
                 socket.poll_timewait(now);
+                socket.poll_finwait2(now);
                 socket.poll_retransmit(now);
+                socket.poll_keepalive(now);
+                socket.poll_persist(now);
                 socket.flush_tx(now);
+                socket.poll_delayed_ack(now);
                 socket.drain_pending(&mut sends);
+                if socket.orphaned && socket.state == State::Closed {
+                    reaped.push(index);
+                    continue;
+                }
+                send_woken |= socket.wake_send_waiter(now);
+                recv_woken |= socket.wake_recv_waiter(now);
+                if let Some(at) = socket.next_deadline() {
+                    self.schedule(socket, index, at);
+                }
+            }
+            for index in reaped {
+                sockets.free(SocketHandle::new(index))?;
+            }
+            if send_woken {
+                self.window_open.notify_all();
+            }
+            if recv_woken {
+                self.data_ready.notify_all();
             }
+            serviced = due.len();
         }
 
         for req in sends {
             self.output_segment(&req)?;
         }
 
-        Ok(())
+        Ok(serviced)
     }
 
     fn next_ephemeral_port(&self) -> u16 {
@@ -497,18 +1646,43 @@ impl Tcp {
         sockets: &SocketSet<Socket>,
         local: &IpEndpoint,
         foreign: &IpEndpoint,
+        new_syn: bool,
     ) -> (Option<usize>, Option<usize>) {
         let mut established_idx = None;
-        let mut listen_idx = None;
+        let mut time_wait_idx = None;
+        let mut listeners = Vec::new();
 
         for (handle, socket) in sockets.iter() {
             if socket.matches_established(local, foreign) {
+                // a reused tuple can leave an old TIME_WAIT twin behind
+                if socket.state == State::TimeWait {
+                    time_wait_idx = Some(handle.index());
+                    continue;
+                }
                 established_idx = Some(handle.index());
                 break;
             }
             if socket.matches_listen(local) {
-                listen_idx = Some(handle.index());
+                listeners.push(handle.index());
+            }
+        }
+
+        let listen_idx = match listeners.len() {
+            0 | 1 => listeners.pop(),
+            n => {
+                let pick = self.accept_robin.fetch_add(1, Ordering::Relaxed) % n;
+                Some(listeners[pick])
             }
+        };
+
+        // SO_REUSEADDR: a fresh SYN for a tuple still in TIME_WAIT opens a
+        // new connection on the listener instead of being answered by the twin
+        let reuse = new_syn
+            && listen_idx
+                .and_then(|i| sockets.get(SocketHandle::new(i)).ok())
+                .is_some_and(|socket| socket.reuse_addr);
+        if established_idx.is_none() && !reuse {
+            established_idx = time_wait_idx;
         }
 
         (established_idx, listen_idx)
@@ -522,14 +1696,15 @@ impl Tcp {
         sends: &mut Vec<SendRequest>,
     ) {
         let socket = sockets.get_mut(SocketHandle::new(index)).unwrap();
-        socket.handle_segment(seg.seq, seg.ack, seg.len, seg.wnd, seg.flags, seg.payload);
+        socket.handle_segment(*seg);
         socket.drain_pending(sends);
+        self.schedule(socket, index, 0);
 
         if socket.accept_ready {
             socket.accept_ready = false;
             if let Some(parent_idx) = socket.parent {
                 let parent = sockets.get_mut(SocketHandle::new(parent_idx)).unwrap();
-                parent.backlog.push_back(index);
+                parent.push_accept_queue(index);
             }
         }
     }
@@ -548,35 +1723,68 @@ impl Tcp {
         }
 
         if seg.has_ack() {
-            sends.push(SendRequest {
-                seq: seg.seq,
-                ack: 0,
-                flags: wire::field::FLG_RST,
-                wnd: 0,
-                payload: Vec::new(),
-                local: *local,
-                foreign: *foreign,
-            });
+            sends.push(SendRequest::reset(
+                seg.seq,
+                0,
+                wire::field::FLG_RST,
+                *local,
+                *foreign,
+            ));
             return Ok(());
         }
 
         if seg.has_syn() {
-            let mut child = Socket::new(Socket::RX_BUFFER_SIZE, Socket::TX_BUFFER_SIZE);
+            let listener = sockets.get_mut(SocketHandle::new(listen_index))?;
+            if listener.accept_queue_depth() >= listener.accept_queue_max() {
+                listener.connections_refused += 1;
+                trace!(
+                    TCP,
+                    "[tcp] accept queue full on port {}, refusing {:?}",
+                    local.port,
+                    foreign
+                );
+                if listener.syn_overflow_rst {
+                    self.send_rst_response(local, foreign, seg, sends);
+                }
+                return Ok(());
+            }
+            // accepted connections inherit SO_RCVBUF / SO_SNDBUF and the
+            // IP TTL / TOS
+            let (rx_capacity, tx_capacity) = (listener.rx_capacity, listener.tx_capacity);
+            let (ip_ttl, ip_tos) = (listener.ip_ttl, listener.ip_tos);
+
+            let mut child = Socket::new(rx_capacity, tx_capacity);
             child.parent = Some(listen_index);
+            child.ip_ttl = ip_ttl;
+            child.ip_tos = ip_tos;
             child.local = *local;
             child.foreign = *foreign;
-            child.rcv_wnd = child.rx_capacity as u16;
+            child.rcv_wnd = child.rx_capacity as u32;
+            child.rcv_wscale = child.desired_wscale();
+            child.wscale_offer = true;
+            child.negotiate_mss(seg.mss);
+            child.negotiate_wscale(seg.wscale);
+            child.ts_enabled = true;
+            child.sack_enabled = true;
+            child.negotiate_timestamps(seg.ts);
+            child.negotiate_sack(seg.sack_permitted);
             child.rcv_nxt = seg.seq.wrapping_add(1);
             child.irs = seg.seq;
-            child.iss = initial_iss(local.port);
+            child.iss = initial_iss(local, foreign);
             child.snd_una = child.iss;
             child.snd_nxt = child.iss + 1;
-            child.state = State::SynReceived;
+            child.set_state(State::SynReceived);
 
             let handle = sockets.alloc(child)?;
             let child = sockets.get_mut(handle).unwrap();
-            let _ = child.egress(wire::field::FLG_SYN | wire::field::FLG_ACK, &[]);
+            let options = child.syn_options();
+            let _ = child.egress_with_options(
+                wire::field::FLG_SYN | wire::field::FLG_ACK,
+                &options,
+                &[],
+            );
             child.drain_pending(sends);
+            self.schedule(child, handle.index(), 0);
         }
 
         Ok(())
@@ -594,30 +1802,27 @@ impl Tcp {
         }
 
         if !seg.has_ack() {
-            sends.push(SendRequest {
-                seq: 0,
-                ack: seg.seq.wrapping_add(seg.len),
-                flags: wire::field::FLG_RST | wire::field::FLG_ACK,
-                wnd: 0,
-                payload: Vec::new(),
-                local: *local,
-                foreign: *foreign,
-            });
+            sends.push(SendRequest::reset(
+                0,
+                seg.seq.wrapping_add(seg.len),
+                wire::field::FLG_RST | wire::field::FLG_ACK,
+                *local,
+                *foreign,
+            ));
         } else {
-            sends.push(SendRequest {
-                seq: seg.ack,
-                ack: 0,
-                flags: wire::field::FLG_RST,
-                wnd: 0,
-                payload: Vec::new(),
-                local: *local,
-                foreign: *foreign,
-            });
+            sends.push(SendRequest::reset(
+                seg.ack,
+                0,
+                wire::field::FLG_RST,
+                *local,
+                *foreign,
+            ));
         }
     }
 
     fn output_segment(&self, req: &SendRequest) -> Result<()> {
-        let total_len = wire::HEADER_LEN + req.payload.len();
+        let header_len = wire::HEADER_LEN + wire::PacketMut::total_option_bytes(&req.options);
+        let total_len = header_len + req.payload.len();
         let mut buf = alloc::vec![0u8; total_len];
 
         {
@@ -626,25 +1831,52 @@ impl Tcp {
             packet.set_dst_port(req.foreign.port);
             packet.set_seq_number(req.seq);
             packet.set_ack_number(req.ack);
-            packet.set_header_len(wire::HEADER_LEN);
             packet.set_flags(req.flags);
             packet.set_window_len(req.wnd);
             packet.set_checksum(0);
             packet.set_urg_ptr(0);
+            packet.set_options(&req.options)?;
             if !req.payload.is_empty() {
                 packet.payload_mut().copy_from_slice(&req.payload);
             }
             packet.fill_checksum(req.local.addr, req.foreign.addr);
         }
 
-        ip::egress_route(req.foreign.addr, wire::PROTOCOL_TCP, &buf)?;
+        ip::egress_route_flags(
+            req.foreign.addr,
+            wire::PROTOCOL_TCP,
+            &buf,
+            req.ttl,
+            IpFlags::df().with_tos(req.tos),
+        )?;
         Ok(())
     }
 }
 
 static TCP: Tcp = Tcp::new();
 
+// Called while panicking, so it must not block on (or allocate under) the
+// socket table lock.
+pub fn panic_dump() {
+    let Some(sockets) = TCP.sockets.try_lock() else {
+        crate::println!("  tcp: socket table locked");
+        return;
+    };
+    for (handle, socket) in sockets.iter() {
+        crate::println!(
+            "  tcp[{}] {} {}:{} -> {}:{}",
+            handle.index(),
+            socket.state,
+            socket.local.addr,
+            socket.local.port,
+            socket.foreign.addr,
+            socket.foreign.port
+        );
+    }
+}
+
 pub fn socket_alloc() -> Result<usize> {
+    require_state(NetStackState::IpInitialized)?;
     TCP.socket_alloc()
 }
 
@@ -652,6 +1884,10 @@ pub fn socket_free(index: usize) -> Result<()> {
     TCP.socket_free(index)
 }
 
+pub fn socket_close(index: usize) -> Result<()> {
+    TCP.socket_close(index)
+}
+
 pub fn socket_get_mut<R, F>(index: usize, f: F) -> Result<R>
 where
     F: FnOnce(&mut Socket) -> R,
@@ -666,10 +1902,22 @@ where
     TCP.socket_get(index, f)
 }
 
+pub fn socket_listen(index: usize, local: IpEndpoint) -> Result<()> {
+    TCP.socket_listen(index, local)
+}
+
 pub fn socket_accept(listen_index: usize) -> Result<usize> {
     TCP.socket_accept(listen_index)
 }
 
+pub fn wait_for_send_space(index: usize, timeout_ms: u64) -> Result<()> {
+    TCP.wait_for_send_space(index, timeout_ms)
+}
+
+pub fn wait_for_data(index: usize, timeout_ms: u64) -> Result<()> {
+    TCP.wait_for_data(index, timeout_ms)
+}
+
 pub fn ingress(src_ip: IpAddr, dst_ip: IpAddr, data: &[u8]) -> Result<()> {
     TCP.ingress(src_ip, dst_ip, data)
 }
@@ -678,10 +1926,51 @@ pub fn poll() -> Result<()> {
     TCP.poll()
 }
 
+pub fn set_reject_with_icmp(enable: bool) {
+    TCP.set_reject_with_icmp(enable)
+}
+
 fn next_ephemeral_port() -> u16 {
     TCP.next_ephemeral_port()
 }
 
-fn initial_iss(port: u16) -> u32 {
-    (port as u32).wrapping_mul(1000).wrapping_add(12345)
+pub(super) static ISS_SECRET: Mutex<[u64; 2]> = Mutex::new([0; 2], "iss_secret");
+
+// There is no hardware RNG, so the per-boot key is hashed together from
+// how long the spin loop waits for each timebase tick.
+pub fn init_iss_secret() {
+    const SAMPLES: usize = 256;
+    const MAX_SPINS: u64 = 1 << 16;
+
+    let mut key = [0x6f63_746f_782d_6e65, 0x742d_6973_732d_6b65];
+    for half in 0..2 {
+        let mut h = key[half];
+        let mut prev = timer::get_time_cycles();
+        for _ in 0..SAMPLES {
+            let mut spins = 0;
+            let mut now = prev;
+            while now == prev && spins < MAX_SPINS {
+                spins += 1;
+                core::hint::spin_loop();
+                now = timer::get_time_cycles();
+            }
+            h = siphash13(&key, &(h ^ now ^ spins.rotate_left(32)).to_le_bytes());
+            prev = now;
+        }
+        key[half] = h;
+    }
+    *ISS_SECRET.lock() = key;
+}
+
+// RFC 6528: ISN = M + F(localip, localport, remoteip, remoteport, secretkey),
+// where M ticks every 4 microseconds and F is keyed SipHash.
+pub(super) fn initial_iss(local: &IpEndpoint, foreign: &IpEndpoint) -> u32 {
+    let mut tuple = [0u8; 12];
+    tuple[0..4].copy_from_slice(&local.addr.0.to_be_bytes());
+    tuple[4..6].copy_from_slice(&local.port.to_be_bytes());
+    tuple[6..10].copy_from_slice(&foreign.addr.0.to_be_bytes());
+    tuple[10..12].copy_from_slice(&foreign.port.to_be_bytes());
+    let f = siphash13(&ISS_SECRET.lock(), &tuple) as u32;
+    let m = timer::get_time_cycles() / (crate::param::TIMEBASE_FREQ / 250_000) as u64;
+    (m as u32).wrapping_add(f)
 }
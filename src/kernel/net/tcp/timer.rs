@@ -2,3 +2,8 @@ pub(crate) fn get_time_ms() -> u64 {
     let ticks = crate::trap::TICKS.lock();
     (*ticks as u64) * (crate::param::TICK_MS as u64)
 }
+
+// Raw timebase counter, TIMEBASE_FREQ ticks per second.
+pub(crate) fn get_time_cycles() -> u64 {
+    unsafe { (crate::memlayout::CLINT_MTIME as *const u64).read_volatile() }
+}
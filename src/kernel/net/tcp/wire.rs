@@ -1,6 +1,7 @@
 use crate::error::{Error, Result};
 use crate::net::ip::IpAddr;
 use crate::net::util::{read_u16, write_u16};
+use alloc::vec::Vec;
 
 pub mod field {
     pub type Field = core::ops::Range<usize>;
@@ -22,8 +23,182 @@ pub mod field {
 }
 
 pub const HEADER_LEN: usize = field::URGENT.end;
+pub const MAX_HEADER_LEN: usize = 60;
 pub const PROTOCOL_TCP: u8 = 6;
 
+const OPT_END: u8 = 0;
+const OPT_NOP: u8 = 1;
+const OPT_MSS: u8 = 2;
+const OPT_WINDOW_SCALE: u8 = 3;
+const OPT_SACK_PERMITTED: u8 = 4;
+const OPT_SACK: u8 = 5;
+const OPT_TIMESTAMPS: u8 = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TcpOption {
+    NoOp,
+    Mss(u16),
+    WindowScale(u8),
+    SackPermitted,
+    Sack(SackBlocks),
+    Timestamps { tsval: u32, tsecr: u32 },
+    Unknown { kind: u8 },
+}
+
+pub const SACK_MAX_BLOCKS: usize = 4;
+
+// RFC 2018 3: up to four [left, right) sequence ranges the receiver holds
+// beyond the cumulative ACK.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SackBlocks {
+    blocks: [(u32, u32); SACK_MAX_BLOCKS],
+    len: u8,
+}
+
+impl SackBlocks {
+    pub const fn new() -> Self {
+        Self {
+            blocks: [(0, 0); SACK_MAX_BLOCKS],
+            len: 0,
+        }
+    }
+
+    pub fn push(&mut self, left: u32, right: u32) -> bool {
+        if self.len as usize == SACK_MAX_BLOCKS {
+            return false;
+        }
+        self.blocks[self.len as usize] = (left, right);
+        self.len += 1;
+        true
+    }
+
+    pub fn truncate(&mut self, len: usize) {
+        self.len = self.len.min(len as u8);
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn as_slice(&self) -> &[(u32, u32)] {
+        &self.blocks[..self.len as usize]
+    }
+
+    pub fn find(opts: &[TcpOption]) -> Option<Self> {
+        opts.iter().find_map(|opt| match *opt {
+            TcpOption::Sack(blocks) => Some(blocks),
+            _ => None,
+        })
+    }
+}
+
+// RFC 7323 timestamps, carried on the wire as TcpOption::Timestamps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TsOption {
+    pub tsval: u32,
+    pub tsecr: u32,
+}
+
+impl TsOption {
+    pub const LEN: usize = 10;
+
+    pub fn find(opts: &[TcpOption]) -> Option<Self> {
+        opts.iter().find_map(|opt| match *opt {
+            TcpOption::Timestamps { tsval, tsecr } => Some(Self { tsval, tsecr }),
+            _ => None,
+        })
+    }
+}
+
+impl From<TsOption> for TcpOption {
+    fn from(ts: TsOption) -> Self {
+        TcpOption::Timestamps {
+            tsval: ts.tsval,
+            tsecr: ts.tsecr,
+        }
+    }
+}
+
+impl TcpOption {
+    pub fn buffer_len(&self) -> usize {
+        match self {
+            TcpOption::NoOp => 1,
+            TcpOption::Mss(_) => 4,
+            TcpOption::WindowScale(_) => 3,
+            TcpOption::SackPermitted => 2,
+            TcpOption::Sack(blocks) => 2 + 8 * blocks.as_slice().len(),
+            TcpOption::Timestamps { .. } => 10,
+            TcpOption::Unknown { .. } => 0,
+        }
+    }
+
+    fn emit(&self, buf: &mut [u8]) -> usize {
+        match *self {
+            TcpOption::NoOp => buf[0] = OPT_NOP,
+            TcpOption::Mss(mss) => {
+                buf[..2].copy_from_slice(&[OPT_MSS, 4]);
+                write_u16(&mut buf[2..4], mss);
+            }
+            TcpOption::WindowScale(shift) => {
+                buf[..3].copy_from_slice(&[OPT_WINDOW_SCALE, 3, shift])
+            }
+            TcpOption::SackPermitted => buf[..2].copy_from_slice(&[OPT_SACK_PERMITTED, 2]),
+            TcpOption::Sack(blocks) => {
+                buf[..2].copy_from_slice(&[OPT_SACK, self.buffer_len() as u8]);
+                for (i, &(left, right)) in blocks.as_slice().iter().enumerate() {
+                    write_u32(&mut buf[2 + i * 8..], left);
+                    write_u32(&mut buf[6 + i * 8..], right);
+                }
+            }
+            TcpOption::Timestamps { tsval, tsecr } => {
+                buf[..2].copy_from_slice(&[OPT_TIMESTAMPS, 10]);
+                write_u32(&mut buf[2..6], tsval);
+                write_u32(&mut buf[6..10], tsecr);
+            }
+            TcpOption::Unknown { .. } => {}
+        }
+        self.buffer_len()
+    }
+
+    fn parse(buf: &[u8]) -> Result<(Option<TcpOption>, usize)> {
+        match buf[0] {
+            OPT_END => return Ok((None, buf.len())),
+            OPT_NOP => return Ok((Some(TcpOption::NoOp), 1)),
+            _ => {}
+        }
+        let len = *buf.get(1).ok_or(Error::InvalidLength)? as usize;
+        if len < 2 || len > buf.len() {
+            return Err(Error::InvalidLength);
+        }
+        let data = &buf[2..len];
+        let option = match (buf[0], data.len()) {
+            (OPT_MSS, 2) => TcpOption::Mss(read_u16(data)),
+            (OPT_WINDOW_SCALE, 1) => TcpOption::WindowScale(data[0]),
+            (OPT_SACK_PERMITTED, 0) => TcpOption::SackPermitted,
+            (OPT_SACK, n) if n % 8 == 0 && (1..=SACK_MAX_BLOCKS).contains(&(n / 8)) => {
+                let mut blocks = SackBlocks::new();
+                for i in (0..n).step_by(8) {
+                    blocks.push(read_u32(&data[i..i + 4]), read_u32(&data[i + 4..i + 8]));
+                }
+                TcpOption::Sack(blocks)
+            }
+            (OPT_TIMESTAMPS, 8) => TcpOption::Timestamps {
+                tsval: read_u32(&data[..4]),
+                tsecr: read_u32(&data[4..]),
+            },
+            (OPT_MSS | OPT_WINDOW_SCALE | OPT_SACK_PERMITTED | OPT_SACK | OPT_TIMESTAMPS, _) => {
+                return Err(Error::InvalidLength)
+            }
+            (kind, _) => TcpOption::Unknown { kind },
+        };
+        Ok((Some(option), len))
+    }
+}
+
 pub struct Packet<'a> {
     buffer: &'a [u8],
 }
@@ -73,6 +248,30 @@ impl<'a> Packet<'a> {
         &self.buffer[header_len..]
     }
 
+    pub fn options(&self) -> Result<Vec<TcpOption>> {
+        let mut opts = &self.buffer[HEADER_LEN..self.header_len()];
+        let mut out = Vec::new();
+        while !opts.is_empty() {
+            let (option, len) = TcpOption::parse(opts)?;
+            if let Some(option) = option {
+                out.push(option);
+            }
+            opts = &opts[len..];
+        }
+        Ok(out)
+    }
+
+    pub fn window_scale(&self) -> Option<u8> {
+        self.options().ok()?.into_iter().find_map(|opt| match opt {
+            TcpOption::WindowScale(shift) => Some(shift),
+            _ => None,
+        })
+    }
+
+    pub fn timestamps(&self) -> Option<TsOption> {
+        TsOption::find(&self.options().ok()?)
+    }
+
     pub fn verify_checksum(&self, src: IpAddr, dst: IpAddr) -> bool {
         checksum_sum(src, dst, self.buffer) == 0xffff
     }
@@ -102,6 +301,10 @@ impl<'a> PacketMut<'a> {
         write_u32(&mut self.buffer[field::ACK_NUM], value);
     }
 
+    pub fn header_len(&self) -> usize {
+        ((self.buffer[field::FLAGS.start] >> 4) as usize) * 4
+    }
+
     pub fn set_header_len(&mut self, header_len: usize) {
         self.buffer[field::FLAGS.start] = ((header_len / 4) as u8) << 4;
     }
@@ -122,8 +325,35 @@ impl<'a> PacketMut<'a> {
         write_u16(&mut self.buffer[field::URGENT], value);
     }
 
+    pub fn options_mut(&mut self) -> &mut [u8] {
+        let header_len = self.header_len();
+        &mut self.buffer[HEADER_LEN..header_len]
+    }
+
+    pub fn total_option_bytes(opts: &[TcpOption]) -> usize {
+        let len: usize = opts.iter().map(|o| o.buffer_len()).sum();
+        (len + 3) & !3
+    }
+
+    pub fn set_options(&mut self, opts: &[TcpOption]) -> Result<()> {
+        let header_len = HEADER_LEN + Self::total_option_bytes(opts);
+        if header_len > MAX_HEADER_LEN || header_len > self.buffer.len() {
+            return Err(Error::InvalidHeaderLen);
+        }
+        self.set_header_len(header_len);
+
+        let region = self.options_mut();
+        let mut offset = 0;
+        for opt in opts {
+            offset += opt.emit(&mut region[offset..]);
+        }
+        region[offset..].fill(OPT_END);
+        Ok(())
+    }
+
     pub fn payload_mut(&mut self) -> &mut [u8] {
-        &mut self.buffer[HEADER_LEN..]
+        let header_len = self.header_len();
+        &mut self.buffer[header_len..]
     }
 
     pub fn fill_checksum(&mut self, src: IpAddr, dst: IpAddr) {
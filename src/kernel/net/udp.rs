@@ -1,15 +1,24 @@
 use super::{
-    ip::{egress_route, IpAddr, IpEndpoint, IpHeader},
+    device::{net_device_by_name, NetDevice},
+    igmp,
+    ip::{egress_route_flags, IpAddr, IpEndpoint, IpFlags, IpHeader, DEFAULT_TTL},
     util::checksum,
 };
 use crate::{
     error::{Error, Result},
-    net::socket::{SocketHandle, SocketSet},
+    net::{
+        flush_tx, require_state,
+        socket::{SocketHandle, SocketSet},
+        NetStackState,
+    },
     spinlock::Mutex,
     trace,
 };
 extern crate alloc;
-use alloc::{collections::VecDeque, vec::Vec};
+use alloc::{
+    collections::{BTreeSet, VecDeque},
+    vec::Vec,
+};
 
 pub const UDP_PROTOCOL: u8 = IpHeader::UDP;
 
@@ -18,6 +27,13 @@ const UDP_SOURCE_PORT_MAX: u16 = 65535;
 
 const UDP_SOCKET_SIZE: usize = 16;
 
+const UDP_MAX_GROUPS: usize = 4;
+
+const UDP_DEFAULT_RECV_QUEUE_DEPTH: usize = 16;
+
+// Ethernet MTU minus the IPv4 header, i.e. header + 1472 bytes of payload.
+const UDP_DEFAULT_MAX_DATAGRAM_SIZE: u16 = 1500 - 20;
+
 mod wire {
     use crate::error::{Error, Result};
     use crate::net::util::{read_u16, write_u16};
@@ -99,21 +115,96 @@ struct UdpPacket {
     data: Vec<u8>,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UdpMulticastGroup {
+    pub group: IpAddr,
+    pub iface: &'static str,
+}
+
+// What to discard when a datagram arrives at a full receive queue.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UdpOverflowPolicy {
+    // keep what is queued and drop the arrival
+    TailDrop,
+    // drop the oldest queued datagram to make room
+    HeadDrop,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct UdpSocketStats {
+    pub queued: usize,
+    pub queue_depth: usize,
+    pub dropped: u64,
+}
+
 struct UdpSocket {
     local: IpEndpoint,
+    // set by connect: the only peer datagrams are accepted from, and the
+    // default destination for send
+    remote: Option<IpEndpoint>,
     recv_queue: VecDeque<UdpPacket>,
+    recv_queue_capacity: usize,
+    overflow_policy: UdpOverflowPolicy,
+    dropped: u64,
+    max_datagram_size: u16,
+    auto_fragment: bool,
+    pmtu_discover: bool,
+    ttl: u8,
+    tos: u8,
+    groups: [Option<UdpMulticastGroup>; UDP_MAX_GROUPS],
 }
 impl UdpSocket {
     const fn new() -> Self {
         Self {
             local: IpEndpoint::unspecified(),
+            remote: None,
             recv_queue: VecDeque::new(),
+            recv_queue_capacity: UDP_DEFAULT_RECV_QUEUE_DEPTH,
+            overflow_policy: UdpOverflowPolicy::TailDrop,
+            dropped: 0,
+            max_datagram_size: UDP_DEFAULT_MAX_DATAGRAM_SIZE,
+            auto_fragment: false,
+            pmtu_discover: false,
+            ttl: DEFAULT_TTL,
+            tos: 0,
+            groups: [None; UDP_MAX_GROUPS],
+        }
+    }
+
+    fn enqueue(&mut self, packet: UdpPacket) {
+        if self.recv_queue.len() >= self.recv_queue_capacity {
+            self.dropped += 1;
+            match self.overflow_policy {
+                UdpOverflowPolicy::TailDrop => return,
+                UdpOverflowPolicy::HeadDrop => {
+                    self.recv_queue.pop_front();
+                }
+            }
         }
+        self.recv_queue.push_back(packet);
+    }
+
+    fn accepts_from(&self, src: IpAddr, src_port: u16) -> bool {
+        self.remote
+            .is_none_or(|remote| remote.addr == src && remote.port == src_port)
+    }
+
+    fn joined(&self, group: IpAddr, iface: &str) -> bool {
+        self.groups
+            .iter()
+            .flatten()
+            .any(|g| g.group == group && g.iface == iface)
+    }
+
+    fn max_payload_len(&self) -> usize {
+        self.max_datagram_size as usize - wire::HEADER_LEN
     }
 }
 pub struct Udp {
     sockets: Mutex<SocketSet<UdpSocket>>,
     next_ephemeral_port: Mutex<u16>,
+    // every port some socket is bound to; taken after `sockets`
+    used_ports: Mutex<BTreeSet<u16>>,
 }
 
 impl Udp {
@@ -123,12 +214,30 @@ impl Udp {
         Self {
             sockets: Mutex::new(SocketSet::new(Self::SOCKET_CAPACITY), "udp_sockets"),
             next_ephemeral_port: Mutex::new(UDP_SOURCE_PORT_MIN, "udp_port"),
+            used_ports: Mutex::new(BTreeSet::new(), "udp_used_ports"),
         }
     }
 }
 
 static UDP: Udp = Udp::new();
 
+// Called while panicking; see tcp::panic_dump.
+pub fn panic_dump() {
+    let Some(sockets) = UDP.sockets.try_lock() else {
+        crate::println!("  udp: socket table locked");
+        return;
+    };
+    for (handle, socket) in sockets.iter() {
+        crate::println!(
+            "  udp[{}] {}:{} queued {}",
+            handle.index(),
+            socket.local.addr,
+            socket.local.port,
+            socket.recv_queue.len()
+        );
+    }
+}
+
 impl Udp {
     fn socket_alloc(&self) -> Result<usize> {
         let mut sockets = self.sockets.lock();
@@ -143,29 +252,118 @@ impl Udp {
             return Err(Error::InvalidSocketIndex);
         }
         match sockets.get(handle) {
-            Ok(_) => sockets.free(handle),
+            Ok(socket) => {
+                let port = socket.local.port;
+                let groups = socket.groups;
+                sockets.free(handle)?;
+                self.release_port(&sockets, port);
+                let abandoned: Vec<UdpMulticastGroup> = groups
+                    .into_iter()
+                    .flatten()
+                    .filter(|g| !Self::group_member(&sockets, g))
+                    .collect();
+                drop(sockets);
+                for g in abandoned {
+                    let _ = igmp::send_leave(g.iface, g.group);
+                }
+                Ok(())
+            }
             Err(Error::InvalidSocketState) => Err(Error::InvalidSocketIndex),
             Err(err) => Err(err),
         }
     }
 
+    // Drops `port` from the used set once no remaining socket holds it;
+    // several sockets can share a port on distinct addresses.
+    fn release_port(&self, sockets: &SocketSet<UdpSocket>, port: u16) {
+        if port == 0 || sockets.iter().any(|(_, other)| other.local.port == port) {
+            return;
+        }
+        self.used_ports.lock().remove(&port);
+    }
+
+    fn group_member(sockets: &SocketSet<UdpSocket>, membership: &UdpMulticastGroup) -> bool {
+        sockets
+            .iter()
+            .any(|(_, socket)| socket.groups.contains(&Some(*membership)))
+    }
+
+    // The host reports a group once, when its first socket joins, and
+    // leaves once the last one is gone.
+    fn socket_join_group(&self, index: usize, group: IpAddr, iface: &'static str) -> Result<()> {
+        if !group.is_multicast() {
+            return Err(Error::InvalidAddress);
+        }
+        if net_device_by_name(iface).is_none() {
+            return Err(Error::DeviceNotFound);
+        }
+        let membership = UdpMulticastGroup { group, iface };
+        let mut sockets = self.sockets.lock();
+        let first = !Self::group_member(&sockets, &membership);
+        let socket = sockets.get_mut(SocketHandle::new(index))?;
+        if socket.groups.contains(&Some(membership)) {
+            return Err(Error::AlreadyExists);
+        }
+        let slot = socket
+            .groups
+            .iter_mut()
+            .find(|g| g.is_none())
+            .ok_or(Error::NoBufferSpace)?;
+        *slot = Some(membership);
+        drop(sockets);
+
+        if first {
+            igmp::send_report(iface, group)?;
+        }
+        Ok(())
+    }
+
+    fn socket_leave_group(&self, index: usize, group: IpAddr, iface: &'static str) -> Result<()> {
+        let membership = UdpMulticastGroup { group, iface };
+        let mut sockets = self.sockets.lock();
+        let socket = sockets.get_mut(SocketHandle::new(index))?;
+        let slot = socket
+            .groups
+            .iter_mut()
+            .find(|g| **g == Some(membership))
+            .ok_or(Error::NotFound)?;
+        *slot = None;
+        let last = !Self::group_member(&sockets, &membership);
+        drop(sockets);
+
+        if last {
+            igmp::send_leave(iface, group)?;
+        }
+        Ok(())
+    }
+
+    fn port_in_use(&self, port: u16) -> bool {
+        self.used_ports.lock().contains(&port)
+    }
+
     fn socket_bind(&self, index: usize, mut local: IpEndpoint) -> Result<()> {
+        udp_validate_local_addr(&local)?;
         let mut sockets = self.sockets.lock();
         let handle = SocketHandle::new(index);
-        let _ = sockets.get(handle)?;
+        let previous_port = sockets.get(handle)?.local.port;
 
         if local.port != 0 {
-            for (other_handle, other) in sockets.iter() {
-                if other_handle.index() != index
-                    && other.local.port == local.port
-                    && (other.local.addr.0 == 0
-                        || local.addr.0 == 0
-                        || other.local.addr.0 == local.addr.0)
-                {
-                    return Err(Error::PortInUse);
+            // the set answers the common case; only a shared port needs the
+            // per-address comparison
+            if self.port_in_use(local.port) {
+                for (other_handle, other) in sockets.iter() {
+                    if other_handle.index() != index
+                        && other.local.port == local.port
+                        && (other.local.addr.0 == 0
+                            || local.addr.0 == 0
+                            || other.local.addr.0 == local.addr.0)
+                    {
+                        return Err(Error::PortInUse);
+                    }
                 }
             }
         } else {
+            let used = self.used_ports.lock();
             let mut next_port = self.next_ephemeral_port.lock();
             for _ in 0..(UDP_SOURCE_PORT_MAX - UDP_SOURCE_PORT_MIN + 1) {
                 let port = *next_port;
@@ -174,15 +372,7 @@ impl Udp {
                     *next_port = UDP_SOURCE_PORT_MIN;
                 }
 
-                let mut available = true;
-                for (other_handle, other) in sockets.iter() {
-                    if other_handle.index() != index && other.local.port == port {
-                        available = false;
-                        break;
-                    }
-                }
-
-                if available {
+                if !used.contains(&port) {
                     local.port = port;
                     break;
                 }
@@ -195,10 +385,14 @@ impl Udp {
 
         let socket = sockets.get_mut(handle)?;
         socket.local = local;
+        self.used_ports.lock().insert(local.port);
+        if previous_port != local.port {
+            self.release_port(&sockets, previous_port);
+        }
         Ok(())
     }
 
-    fn ingress(&self, src: IpAddr, dst: IpAddr, data: &[u8]) -> Result<()> {
+    fn ingress(&self, iface: &str, src: IpAddr, dst: IpAddr, data: &[u8]) -> Result<()> {
         let header = wire::Packet::new_checked(data)?;
         let src_port = header.src_port();
         let dst_port = header.dst_port();
@@ -225,6 +419,30 @@ impl Udp {
         }
 
         let mut sockets = self.sockets.lock();
+        // Group datagrams go to every socket on the port that joined the
+        // group on the arrival interface and is bound to INADDR_ANY.
+        if dst.is_multicast() {
+            let mut delivered = false;
+            for (_, socket) in sockets.iter_mut() {
+                if socket.local.port != dst_port
+                    || socket.local.addr.0 != 0
+                    || !socket.joined(dst, iface)
+                    || !socket.accepts_from(src, src_port)
+                {
+                    continue;
+                }
+                socket.enqueue(UdpPacket {
+                    foreign: IpEndpoint::new(src, src_port),
+                    data: data[wire::HEADER_LEN..length].to_vec(),
+                });
+                delivered = true;
+            }
+            if !delivered {
+                return Err(Error::NoMatchingSocket);
+            }
+            trace!(UDP, "[udp] multicast packet queued for port {}", dst_port);
+            return Ok(());
+        }
         for (_, socket) in sockets.iter_mut() {
             if socket.local.port != dst_port {
                 continue;
@@ -232,13 +450,16 @@ impl Udp {
             if socket.local.addr.0 != 0 && socket.local.addr.0 != dst.0 {
                 continue;
             }
+            if !socket.accepts_from(src, src_port) {
+                continue;
+            }
 
             let payload = &data[wire::HEADER_LEN..length];
             let packet = UdpPacket {
                 foreign: IpEndpoint::new(src, src_port),
                 data: payload.to_vec(),
             };
-            socket.recv_queue.push_back(packet);
+            socket.enqueue(packet);
             trace!(UDP, "[udp] packet queued for port {}", dst_port);
             return Ok(());
         }
@@ -250,9 +471,141 @@ impl Udp {
         let sockets = self.sockets.lock();
         let socket = sockets.get(SocketHandle::new(index))?;
         let src = socket.local;
+        if !socket.auto_fragment && data.len() > socket.max_payload_len() {
+            trace!(
+                UDP,
+                "[udp] datagram too large: {} bytes (max {})",
+                data.len(),
+                socket.max_payload_len()
+            );
+            return Err(Error::PacketTooLarge);
+        }
+        let flags = if socket.pmtu_discover {
+            IpFlags::df()
+        } else {
+            IpFlags::none()
+        };
+        let flags = flags.with_tos(socket.tos);
+        let ttl = socket.ttl;
         drop(sockets);
 
-        egress(src, dst, data)
+        let result = egress_flags(src, dst, data, ttl, flags);
+        flush_tx();
+        result
+    }
+
+    // An unbound socket gets an ephemeral port, so replies can find it.
+    fn socket_connect(&self, index: usize, remote: IpEndpoint) -> Result<()> {
+        if remote.addr.0 == 0 || remote.port == 0 {
+            return Err(Error::InvalidAddress);
+        }
+        let local = self.sockets.lock().get(SocketHandle::new(index))?.local;
+        if local.port == 0 {
+            self.socket_bind(index, IpEndpoint::new(local.addr, 0))?;
+        }
+        let mut sockets = self.sockets.lock();
+        sockets.get_mut(SocketHandle::new(index))?.remote = Some(remote);
+        Ok(())
+    }
+
+    fn socket_disconnect(&self, index: usize) -> Result<()> {
+        let mut sockets = self.sockets.lock();
+        sockets.get_mut(SocketHandle::new(index))?.remote = None;
+        Ok(())
+    }
+
+    fn socket_remote(&self, index: usize) -> Result<IpEndpoint> {
+        let sockets = self.sockets.lock();
+        sockets
+            .get(SocketHandle::new(index))?
+            .remote
+            .ok_or(Error::NotConnected)
+    }
+
+    fn socket_send(&self, index: usize, data: &[u8]) -> Result<()> {
+        let remote = self.socket_remote(index)?;
+        self.socket_sendto(index, remote, data)
+    }
+
+    fn socket_recv(&self, index: usize, buf: &mut [u8]) -> Result<usize> {
+        self.socket_remote(index)?;
+        let (len, _) = self.socket_recvfrom(index, buf)?;
+        Ok(len)
+    }
+
+    fn socket_set_max_datagram_size(&self, index: usize, size: u16) -> Result<()> {
+        if (size as usize) <= wire::HEADER_LEN {
+            return Err(Error::InvalidArgument);
+        }
+        let mut sockets = self.sockets.lock();
+        let socket = sockets.get_mut(SocketHandle::new(index))?;
+        socket.max_datagram_size = size;
+        Ok(())
+    }
+
+    // Shrinking the queue applies the policy to what is already queued.
+    fn socket_set_recv_queue_depth(
+        &self,
+        index: usize,
+        depth: usize,
+        policy: UdpOverflowPolicy,
+    ) -> Result<()> {
+        if depth == 0 {
+            return Err(Error::InvalidArgument);
+        }
+        let mut sockets = self.sockets.lock();
+        let socket = sockets.get_mut(SocketHandle::new(index))?;
+        socket.recv_queue_capacity = depth;
+        socket.overflow_policy = policy;
+        while socket.recv_queue.len() > depth {
+            match policy {
+                UdpOverflowPolicy::TailDrop => socket.recv_queue.pop_back(),
+                UdpOverflowPolicy::HeadDrop => socket.recv_queue.pop_front(),
+            };
+            socket.dropped += 1;
+        }
+        Ok(())
+    }
+
+    fn socket_stats(&self, index: usize) -> Result<UdpSocketStats> {
+        let sockets = self.sockets.lock();
+        let socket = sockets.get(SocketHandle::new(index))?;
+        Ok(UdpSocketStats {
+            queued: socket.recv_queue.len(),
+            queue_depth: socket.recv_queue_capacity,
+            dropped: socket.dropped,
+        })
+    }
+
+    fn socket_set_auto_fragment(&self, index: usize, enable: bool) -> Result<()> {
+        let mut sockets = self.sockets.lock();
+        let socket = sockets.get_mut(SocketHandle::new(index))?;
+        socket.auto_fragment = enable;
+        Ok(())
+    }
+
+    fn socket_set_pmtu_discover(&self, index: usize, enable: bool) -> Result<()> {
+        let mut sockets = self.sockets.lock();
+        let socket = sockets.get_mut(SocketHandle::new(index))?;
+        socket.pmtu_discover = enable;
+        Ok(())
+    }
+
+    fn socket_set_ttl(&self, index: usize, ttl: u8) -> Result<()> {
+        if ttl == 0 {
+            return Err(Error::InvalidArgument);
+        }
+        let mut sockets = self.sockets.lock();
+        let socket = sockets.get_mut(SocketHandle::new(index))?;
+        socket.ttl = ttl;
+        Ok(())
+    }
+
+    fn socket_set_tos(&self, index: usize, tos: u8) -> Result<()> {
+        let mut sockets = self.sockets.lock();
+        let socket = sockets.get_mut(SocketHandle::new(index))?;
+        socket.tos = tos;
+        Ok(())
     }
 
     fn socket_recvfrom(&self, index: usize, buf: &mut [u8]) -> Result<(usize, IpEndpoint)> {
@@ -267,9 +620,24 @@ impl Udp {
         buf[..len].copy_from_slice(&packet.data[..len]);
         Ok((len, packet.foreign))
     }
+
+    fn socket_peekfrom(&self, index: usize) -> Result<(usize, IpEndpoint)> {
+        let sockets = self.sockets.lock();
+        let socket = sockets.get(SocketHandle::new(index))?;
+
+        let packet = socket.recv_queue.front().ok_or(Error::WouldBlock)?;
+        Ok((packet.data.len(), packet.foreign))
+    }
+
+    fn socket_recv_ready(&self, index: usize) -> Result<bool> {
+        let sockets = self.sockets.lock();
+        let socket = sockets.get(SocketHandle::new(index))?;
+        Ok(!socket.recv_queue.is_empty())
+    }
 }
 
 pub fn socket_alloc() -> Result<usize> {
+    require_state(NetStackState::IpInitialized)?;
     UDP.socket_alloc()
 }
 
@@ -281,6 +649,30 @@ pub fn socket_bind(index: usize, local: IpEndpoint) -> Result<()> {
     UDP.socket_bind(index, local)
 }
 
+pub fn udp_join_group(index: usize, group: IpAddr, iface: &'static str) -> Result<()> {
+    UDP.socket_join_group(index, group, iface)
+}
+
+pub fn udp_leave_group(index: usize, group: IpAddr, iface: &'static str) -> Result<()> {
+    UDP.socket_leave_group(index, group, iface)
+}
+
+pub fn udp_port_in_use(port: u16) -> bool {
+    UDP.port_in_use(port)
+}
+
+// Ports need no range check here: IpEndpoint::port is a u16, so values above
+// 65535 cannot reach the bind path.
+fn udp_validate_local_addr(ep: &IpEndpoint) -> Result<()> {
+    if ep.addr.0 == 0xFFFF_FFFF {
+        return Err(Error::InvalidAddress);
+    }
+    if ep.addr.is_multicast() {
+        return Err(Error::InvalidAddress);
+    }
+    Ok(())
+}
+
 fn udp_checksum(src: IpAddr, dst: IpAddr, data: &[u8]) -> u16 {
     let mut buf = Vec::with_capacity(12 + data.len());
     buf.extend_from_slice(&src.0.to_be_bytes());
@@ -293,11 +685,24 @@ fn udp_checksum(src: IpAddr, dst: IpAddr, data: &[u8]) -> u16 {
     checksum(&buf)
 }
 
-pub fn ingress(src: IpAddr, dst: IpAddr, data: &[u8]) -> Result<()> {
-    UDP.ingress(src, dst, data)
+pub fn ingress(dev: &NetDevice, src: IpAddr, dst: IpAddr, data: &[u8]) -> Result<()> {
+    UDP.ingress(dev.name(), src, dst, data)
 }
 
 pub fn egress(src: IpEndpoint, dst: IpEndpoint, data: &[u8]) -> Result<()> {
+    egress_flags(src, dst, data, DEFAULT_TTL, IpFlags::none())
+}
+
+fn egress_flags(
+    src: IpEndpoint,
+    dst: IpEndpoint,
+    data: &[u8],
+    ttl: u8,
+    flags: IpFlags,
+) -> Result<()> {
+    if dst.port == 0 {
+        return Err(Error::InvalidAddress);
+    }
     let total_len = wire::HEADER_LEN + data.len();
     if total_len > 65535 {
         return Err(Error::PacketTooLarge);
@@ -334,7 +739,7 @@ pub fn egress(src: IpEndpoint, dst: IpEndpoint, data: &[u8]) -> Result<()> {
         total_len
     );
 
-    egress_route(dst.addr, UDP_PROTOCOL, &packet)
+    egress_route_flags(dst.addr, UDP_PROTOCOL, &packet, ttl, flags)
 }
 
 pub fn socket_sendto(index: usize, dst: IpEndpoint, data: &[u8]) -> Result<()> {
@@ -345,11 +750,72 @@ pub fn socket_recvfrom(index: usize, buf: &mut [u8]) -> Result<(usize, IpEndpoin
     UDP.socket_recvfrom(index, buf)
 }
 
+pub fn socket_connect(index: usize, remote: IpEndpoint) -> Result<()> {
+    UDP.socket_connect(index, remote)
+}
+
+pub fn socket_disconnect(index: usize) -> Result<()> {
+    UDP.socket_disconnect(index)
+}
+
+pub fn socket_send(index: usize, data: &[u8]) -> Result<()> {
+    UDP.socket_send(index, data)
+}
+
+pub fn socket_recv(index: usize, buf: &mut [u8]) -> Result<usize> {
+    UDP.socket_recv(index, buf)
+}
+
+pub fn socket_set_max_datagram_size(index: usize, size: u16) -> Result<()> {
+    UDP.socket_set_max_datagram_size(index, size)
+}
+
+pub fn socket_set_recv_queue_depth(
+    index: usize,
+    depth: usize,
+    policy: UdpOverflowPolicy,
+) -> Result<()> {
+    UDP.socket_set_recv_queue_depth(index, depth, policy)
+}
+
+pub fn socket_stats(index: usize) -> Result<UdpSocketStats> {
+    UDP.socket_stats(index)
+}
+
+pub fn socket_set_auto_fragment(index: usize, enable: bool) -> Result<()> {
+    UDP.socket_set_auto_fragment(index, enable)
+}
+
+pub fn socket_set_pmtu_discover(index: usize, enable: bool) -> Result<()> {
+    UDP.socket_set_pmtu_discover(index, enable)
+}
+
+pub fn socket_set_ttl(index: usize, ttl: u8) -> Result<()> {
+    UDP.socket_set_ttl(index, ttl)
+}
+
+pub fn socket_set_tos(index: usize, tos: u8) -> Result<()> {
+    UDP.socket_set_tos(index, tos)
+}
+
+pub fn socket_peekfrom(index: usize) -> Result<(usize, IpEndpoint)> {
+    UDP.socket_peekfrom(index)
+}
+
+pub fn socket_recv_ready(index: usize) -> Result<bool> {
+    UDP.socket_recv_ready(index)
+}
+
 #[cfg(test)]
 mod tests {
-    use super::{wire, IpEndpoint, Udp};
+    use super::{egress, wire, IpEndpoint, Udp, UdpOverflowPolicy, UdpPacket, UdpSocketStats};
     use crate::error::Error;
+    use crate::net::device::tests::capture_transmit;
+    use crate::net::device::tests::{captured_frames, last_captured_frame, register_test_device};
+    use crate::net::ethernet::{self, MacAddr};
+    use crate::net::ip::{IpAddr, IpHeader};
     use crate::net::socket::SocketHandle;
+    use alloc::vec::Vec;
 
     #[test_case]
     fn packet_too_short() {
@@ -386,6 +852,96 @@ mod tests {
         assert_eq!(err, Error::WouldBlock);
     }
 
+    #[test_case]
+    fn sendto_enforces_max_datagram_size() {
+        let udp = Udp::new();
+        let idx = udp.socket_alloc().unwrap();
+        let dst = IpEndpoint::new(IpAddr::new(203, 0, 113, 9), 69);
+
+        let data = [0u8; 1473];
+        let err = udp.socket_sendto(idx, dst, &data).unwrap_err();
+        assert_eq!(err, Error::PacketTooLarge);
+
+        udp.socket_set_max_datagram_size(idx, (wire::HEADER_LEN + 512) as u16)
+            .unwrap();
+        let err = udp.socket_sendto(idx, dst, &data[..513]).unwrap_err();
+        assert_eq!(err, Error::PacketTooLarge);
+
+        udp.socket_set_auto_fragment(idx, true).unwrap();
+        let result = udp.socket_sendto(idx, dst, &data[..513]);
+        assert_ne!(result, Err(Error::PacketTooLarge));
+
+        let err = udp
+            .socket_set_max_datagram_size(idx, wire::HEADER_LEN as u16)
+            .unwrap_err();
+        assert_eq!(err, Error::InvalidArgument);
+    }
+
+    #[test_case]
+    fn peek_does_not_consume() {
+        let udp = Udp::new();
+        let idx = udp.socket_alloc().unwrap();
+        assert_eq!(udp.socket_recv_ready(idx), Ok(false));
+        assert_eq!(udp.socket_peekfrom(idx), Err(Error::WouldBlock));
+
+        let first = IpEndpoint::new(IpAddr::new(10, 0, 2, 2), 4000);
+        let second = IpEndpoint::new(IpAddr::new(10, 0, 2, 3), 4001);
+        {
+            let mut sockets = udp.sockets.lock();
+            let socket = sockets.get_mut(SocketHandle::new(idx)).unwrap();
+            socket.recv_queue.push_back(UdpPacket {
+                foreign: first,
+                data: alloc::vec![1, 2, 3],
+            });
+            socket.recv_queue.push_back(UdpPacket {
+                foreign: second,
+                data: alloc::vec![4, 5],
+            });
+        }
+
+        assert_eq!(udp.socket_recv_ready(idx), Ok(true));
+        assert_eq!(udp.socket_peekfrom(idx), Ok((3, first)));
+        assert_eq!(udp.socket_peekfrom(idx), Ok((3, first)));
+        {
+            let sockets = udp.sockets.lock();
+            let socket = sockets.get(SocketHandle::new(idx)).unwrap();
+            assert_eq!(socket.recv_queue.len(), 2);
+        }
+
+        let mut buf = [0u8; 8];
+        assert_eq!(udp.socket_recvfrom(idx, &mut buf), Ok((3, first)));
+        assert_eq!(&buf[..3], &[1, 2, 3]);
+        assert_eq!(udp.socket_peekfrom(idx), Ok((2, second)));
+        assert_eq!(udp.socket_recv_ready(idx), Ok(true));
+    }
+
+    #[test_case]
+    fn used_ports_track_bind_and_free() {
+        let udp = Udp::new();
+        let mut bound = Vec::new();
+        for port in 2000..2010 {
+            let index = udp.socket_alloc().unwrap();
+            assert!(!udp.port_in_use(port));
+            udp.socket_bind(index, IpEndpoint::any(port)).unwrap();
+            assert!(udp.port_in_use(port));
+            bound.push(index);
+        }
+        assert_eq!(udp.used_ports.lock().len(), 10);
+
+        let extra = udp.socket_alloc().unwrap();
+        let err = udp.socket_bind(extra, IpEndpoint::any(2005)).unwrap_err();
+        assert_eq!(err, Error::PortInUse);
+
+        // rebinding moves the socket off its old port
+        udp.socket_bind(bound[0], IpEndpoint::any(2100)).unwrap();
+        assert!(!udp.port_in_use(2000));
+        assert!(udp.port_in_use(2100));
+
+        udp.socket_free(bound[5]).unwrap();
+        assert!(!udp.port_in_use(2005));
+        udp.socket_bind(extra, IpEndpoint::any(2005)).unwrap();
+    }
+
     #[test_case]
     fn bind_ephemeral_ports_unique() {
         let udp = Udp::new();
@@ -402,4 +958,199 @@ mod tests {
         assert_ne!(b_port, 0);
         assert_ne!(a_port, b_port);
     }
+
+    #[test_case]
+    fn bind_rejects_broadcast_and_multicast() {
+        let udp = Udp::new();
+        let idx = udp.socket_alloc().unwrap();
+        let broadcast = IpEndpoint::new(IpAddr(0xFFFF_FFFF), 5000);
+        assert_eq!(udp.socket_bind(idx, broadcast), Err(Error::InvalidAddress));
+        let multicast = IpEndpoint::new(IpAddr::new(224, 0, 0, 251), 5353);
+        assert_eq!(udp.socket_bind(idx, multicast), Err(Error::InvalidAddress));
+        udp.socket_bind(idx, IpEndpoint::new(IpAddr::new(10, 0, 2, 15), 5000))
+            .unwrap();
+    }
+
+    #[test_case]
+    fn egress_rejects_zero_destination_port() {
+        let dst = IpEndpoint::new(IpAddr::new(10, 0, 2, 2), 0);
+        let err = egress(IpEndpoint::any(5000), dst, &[1, 2, 3]).unwrap_err();
+        assert_eq!(err, Error::InvalidAddress);
+    }
+
+    fn datagram(dst_port: u16, payload: &[u8]) -> Vec<u8> {
+        let mut data = alloc::vec![0u8; wire::HEADER_LEN + payload.len()];
+        let mut header = wire::PacketMut::new_unchecked(&mut data);
+        header.set_src_port(4000);
+        header.set_dst_port(dst_port);
+        header.set_length((wire::HEADER_LEN + payload.len()) as u16);
+        header.payload_mut().copy_from_slice(payload);
+        data
+    }
+
+    // (destination MAC, IGMP type, group) of the next frame sent
+    fn next_igmp() -> Option<([u8; 6], u8, [u8; 4])> {
+        let frame = last_captured_frame("udptest0")?;
+        let ip = &frame[ethernet::EthHeader::LEN..];
+        assert_eq!(ip[9], IpHeader::IGMP);
+        assert_eq!(ip[8], 1);
+        let igmp = &ip[20..];
+        let mut mac = [0u8; 6];
+        mac.copy_from_slice(&frame[..6]);
+        Some((mac, igmp[0], [igmp[4], igmp[5], igmp[6], igmp[7]]))
+    }
+
+    #[test_case]
+    fn multicast_group_membership() {
+        register_test_device(
+            "udptest0",
+            MacAddr([0x02, 0, 0, 0, 0, 0x31]),
+            IpAddr::new(192, 0, 2, 10),
+            capture_transmit,
+        );
+        captured_frames("udptest0");
+
+        let udp = Udp::new();
+        let group = IpAddr::new(239, 1, 2, 3);
+        let a = udp.socket_alloc().unwrap();
+        let b = udp.socket_alloc().unwrap();
+        let other = udp.socket_alloc().unwrap();
+        udp.socket_bind(a, IpEndpoint::any(5000)).unwrap();
+        udp.socket_bind(b, IpEndpoint::new(IpAddr::LOOPBACK, 5002))
+            .unwrap();
+        udp.socket_bind(other, IpEndpoint::any(5001)).unwrap();
+
+        let unicast = IpAddr::new(192, 0, 2, 1);
+        assert_eq!(
+            udp.socket_join_group(a, unicast, "udptest0"),
+            Err(Error::InvalidAddress)
+        );
+        assert_eq!(
+            udp.socket_join_group(a, group, "nosuchdev"),
+            Err(Error::DeviceNotFound)
+        );
+
+        udp.socket_join_group(a, group, "udptest0").unwrap();
+        let report = ([0x01, 0x00, 0x5e, 0x01, 0x02, 0x03], 0x16, [239, 1, 2, 3]);
+        assert_eq!(next_igmp(), Some(report));
+        assert_eq!(
+            udp.socket_join_group(a, group, "udptest0"),
+            Err(Error::AlreadyExists)
+        );
+        udp.socket_join_group(b, group, "udptest0").unwrap();
+        udp.socket_join_group(other, group, "udptest0").unwrap();
+        assert_eq!(next_igmp(), None);
+
+        let src = IpAddr::new(192, 0, 2, 7);
+        udp.ingress("udptest0", src, group, &datagram(5000, &[1, 2]))
+            .unwrap();
+        let mut buf = [0u8; 4];
+        let from = IpEndpoint::new(src, 4000);
+        assert_eq!(udp.socket_recvfrom(a, &mut buf), Ok((2, from)));
+        assert_eq!(udp.socket_recv_ready(other), Ok(false));
+        // bound to a unicast address
+        assert_eq!(
+            udp.ingress("udptest0", src, group, &datagram(5002, &[1])),
+            Err(Error::NoMatchingSocket)
+        );
+        // joined on another interface
+        assert_eq!(
+            udp.ingress("udptest1", src, group, &datagram(5000, &[1])),
+            Err(Error::NoMatchingSocket)
+        );
+        let stranger = IpAddr::new(239, 9, 9, 9);
+        assert_eq!(
+            udp.ingress("udptest0", src, stranger, &datagram(5000, &[1])),
+            Err(Error::NoMatchingSocket)
+        );
+
+        udp.socket_leave_group(a, group, "udptest0").unwrap();
+        assert_eq!(
+            udp.socket_leave_group(a, group, "udptest0"),
+            Err(Error::NotFound)
+        );
+        udp.socket_free(b).unwrap();
+        assert_eq!(next_igmp(), None);
+        udp.ingress("udptest0", src, group, &datagram(5000, &[3]))
+            .unwrap_err();
+
+        udp.socket_leave_group(other, group, "udptest0").unwrap();
+        let leave = ([0x01, 0x00, 0x5e, 0x00, 0x00, 0x02], 0x17, [239, 1, 2, 3]);
+        assert_eq!(next_igmp(), Some(leave));
+    }
+
+    #[test_case]
+    fn connected_socket_filters_peers() {
+        let udp = Udp::new();
+        let idx = udp.socket_alloc().unwrap();
+        let mut buf = [0u8; 4];
+        assert_eq!(udp.socket_send(idx, &[1]), Err(Error::NotConnected));
+        assert_eq!(udp.socket_recv(idx, &mut buf), Err(Error::NotConnected));
+        assert_eq!(
+            udp.socket_connect(idx, IpEndpoint::new(IpAddr::new(10, 0, 2, 2), 0)),
+            Err(Error::InvalidAddress)
+        );
+
+        let peer = IpEndpoint::new(IpAddr::new(10, 0, 2, 2), 4000);
+        udp.socket_connect(idx, peer).unwrap();
+        let port = {
+            let sockets = udp.sockets.lock();
+            sockets.get(SocketHandle::new(idx)).unwrap().local.port
+        };
+        assert_ne!(port, 0);
+
+        let local = IpAddr::new(10, 0, 2, 15);
+        udp.ingress("lo", peer.addr, local, &datagram(port, &[7, 8]))
+            .unwrap();
+        assert_eq!(
+            udp.ingress("lo", IpAddr::new(10, 0, 2, 3), local, &datagram(port, &[9])),
+            Err(Error::NoMatchingSocket)
+        );
+        assert_eq!(udp.socket_recv(idx, &mut buf), Ok(2));
+        assert_eq!(&buf[..2], &[7, 8]);
+        assert_eq!(udp.socket_recv(idx, &mut buf), Err(Error::WouldBlock));
+
+        udp.socket_disconnect(idx).unwrap();
+        udp.ingress("lo", IpAddr::new(10, 0, 2, 3), local, &datagram(port, &[9]))
+            .unwrap();
+        assert_eq!(udp.socket_send(idx, &[1]), Err(Error::NotConnected));
+    }
+
+    #[test_case]
+    fn recv_queue_overflow_policies() {
+        let udp = Udp::new();
+        let idx = udp.socket_alloc().unwrap();
+        udp.socket_bind(idx, IpEndpoint::any(6000)).unwrap();
+        let src = IpAddr::new(10, 0, 2, 2);
+        let dst = IpAddr::new(10, 0, 2, 15);
+        let mut buf = [0u8; 4];
+
+        for i in 0..20u8 {
+            udp.ingress("lo", src, dst, &datagram(6000, &[i])).unwrap();
+        }
+        let stats = UdpSocketStats {
+            queued: 16,
+            queue_depth: 16,
+            dropped: 4,
+        };
+        assert_eq!(udp.socket_stats(idx), Ok(stats));
+        udp.socket_recvfrom(idx, &mut buf).unwrap();
+        assert_eq!(buf[0], 0);
+
+        // shrinking under head drop discards the oldest
+        udp.socket_set_recv_queue_depth(idx, 2, UdpOverflowPolicy::HeadDrop)
+            .unwrap();
+        assert_eq!(udp.socket_stats(idx).unwrap().dropped, 17);
+        udp.ingress("lo", src, dst, &datagram(6000, &[99])).unwrap();
+        udp.socket_recvfrom(idx, &mut buf).unwrap();
+        assert_eq!(buf[0], 15);
+        udp.socket_recvfrom(idx, &mut buf).unwrap();
+        assert_eq!(buf[0], 99);
+        assert_eq!(udp.socket_stats(idx).unwrap().dropped, 18);
+
+        assert_eq!(
+            udp.socket_set_recv_queue_depth(idx, 0, UdpOverflowPolicy::TailDrop),
+            Err(Error::InvalidArgument)
+        );
+    }
 }
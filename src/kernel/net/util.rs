@@ -71,6 +71,58 @@ pub fn verify_checksum(data: &[u8]) -> bool {
     checksum(data) == 0
 }
 
+/// SipHash-1-3 (one compression round, three finalization rounds) with a
+/// 128-bit key. Keyed hash for values an attacker must not predict.
+pub fn siphash13(key: &[u64; 2], data: &[u8]) -> u64 {
+    siphash(key, data, 1, 3)
+}
+
+fn sip_round(v: &mut [u64; 4]) {
+    v[0] = v[0].wrapping_add(v[1]);
+    v[1] = v[1].rotate_left(13) ^ v[0];
+    v[0] = v[0].rotate_left(32);
+    v[2] = v[2].wrapping_add(v[3]);
+    v[3] = v[3].rotate_left(16) ^ v[2];
+    v[0] = v[0].wrapping_add(v[3]);
+    v[3] = v[3].rotate_left(21) ^ v[0];
+    v[2] = v[2].wrapping_add(v[1]);
+    v[1] = v[1].rotate_left(17) ^ v[2];
+    v[2] = v[2].rotate_left(32);
+}
+
+fn siphash(key: &[u64; 2], data: &[u8], c_rounds: usize, d_rounds: usize) -> u64 {
+    let mut v = [
+        key[0] ^ 0x736f_6d65_7073_6575,
+        key[1] ^ 0x646f_7261_6e64_6f6d,
+        key[0] ^ 0x6c79_6765_6e65_7261,
+        key[1] ^ 0x7465_6462_7974_6573,
+    ];
+    let compress = |v: &mut [u64; 4], m: u64| {
+        v[3] ^= m;
+        for _ in 0..c_rounds {
+            sip_round(v);
+        }
+        v[0] ^= m;
+    };
+
+    let tail = data.len() - data.len() % 8;
+    let mut word = [0u8; 8];
+    for i in (0..tail).step_by(8) {
+        word.copy_from_slice(&data[i..i + 8]);
+        compress(&mut v, u64::from_le_bytes(word));
+    }
+    let mut last = [0u8; 8];
+    last[..data.len() - tail].copy_from_slice(&data[tail..]);
+    last[7] = data.len() as u8;
+    compress(&mut v, u64::from_le_bytes(last));
+
+    v[2] ^= 0xff;
+    for _ in 0..d_rounds {
+        sip_round(&mut v);
+    }
+    v[0] ^ v[1] ^ v[2] ^ v[3]
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -97,4 +149,17 @@ mod tests {
         ];
         assert!(verify_checksum(&packet));
     }
+
+    #[test_case]
+    fn siphash_reference_vector() {
+        // SipHash-2-4 vector from the reference paper checks the shared core
+        let key = [0x0706_0504_0302_0100, 0x0f0e_0d0c_0b0a_0908];
+        let data: [u8; 15] = core::array::from_fn(|i| i as u8);
+        assert_eq!(siphash(&key, &data, 2, 4), 0xa129_ca61_49be_45e5);
+
+        let a = siphash13(&key, b"10.0.2.15:80");
+        assert_eq!(a, siphash13(&key, b"10.0.2.15:80"));
+        assert_ne!(a, siphash13(&key, b"10.0.2.15:81"));
+        assert_ne!(a, siphash13(&[key[0], !key[1]], b"10.0.2.15:80"));
+    }
 }
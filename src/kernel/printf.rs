@@ -91,6 +91,8 @@ macro_rules! eprint {
 pub fn panic_inner(info: &panic::PanicInfo<'_>) -> ! {
     PR.locking.store(false, Ordering::Relaxed);
     crate::println!("core {}: {}", unsafe { crate::proc::Cpus::cpu_id() }, info);
+    #[cfg(all(target_os = "none", feature = "kernel"))]
+    crate::net::net_panic_dump();
     PR.panicked.store(true, Ordering::Relaxed);
     loop {}
 }
@@ -57,6 +57,30 @@ impl<T> Mutex<T> {
         }
     }
 
+    // Single acquire attempt. Returns None instead of spinning (or
+    // panicking) when the lock is held, including by this cpu.
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        let _intr_lock = Cpus::lock_mycpu(self.name);
+
+        unsafe {
+            if self.holding() {
+                return None;
+            }
+            self.locked
+                .compare_exchange(
+                    ptr::null_mut(),
+                    CPUS.mycpu(),
+                    Ordering::Acquire,
+                    Ordering::Relaxed,
+                )
+                .ok()
+                .map(|_| MutexGuard {
+                    mutex: self,
+                    _intr_lock,
+                })
+        }
+    }
+
     // Check whether this cpu is holding the lock.
     // Interrupts must be off.
     unsafe fn holding(&self) -> bool {
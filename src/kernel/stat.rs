@@ -23,3 +23,66 @@ impl Stat {
         self.ftype
     }
 }
+
+#[derive(Default, Debug, Clone, Copy)]
+#[repr(C)]
+pub struct TcpListenStats {
+    pub current_depth: usize,     // Connections waiting in the accept queue
+    pub max_depth: usize,         // Accept queue limit
+    pub peak_depth: usize,        // Highest accept queue depth observed
+    pub connections_refused: u64, // SYNs reset because the queue was full
+}
+
+#[derive(Default, Debug, Clone, Copy)]
+#[repr(C)]
+pub struct TcpBufInfo {
+    pub rx_capacity: usize,  // Receive buffer size
+    pub rx_used: usize,      // Bytes waiting to be read
+    pub tx_capacity: usize,  // Send buffer size
+    pub tx_used: usize,      // Bytes queued but not yet sent
+    pub tx_in_flight: usize, // Bytes sent but not yet acknowledged
+}
+
+// TcpSocketStats::state of a connection in tcp::State::Established
+pub const TCP_STATE_ESTABLISHED: u8 = 4;
+
+#[derive(Default, Debug, Clone, Copy)]
+#[repr(C)]
+pub struct TcpSocketStats {
+    pub local_addr: u32,       // Local IPv4 address, host order
+    pub foreign_addr: u32,     // Peer IPv4 address, host order
+    pub local_port: u16,       // Local port
+    pub foreign_port: u16,     // Peer port
+    pub state: u8,             // tcp::State as u8
+    pub _pad: [u8; 3],         // Keeps the copyout free of uninitialized bytes
+    pub rx_buf_len: usize,     // Bytes waiting to be read
+    pub tx_buf_len: usize,     // Bytes queued but not yet sent
+    pub snd_nxt: u32,          // Next sequence number to send
+    pub snd_una: u32,          // Oldest unacknowledged sequence number
+    pub rcv_nxt: u32,          // Next sequence number expected
+    pub rcv_wnd: u32,          // Receive window in bytes
+    pub retransmit_count: u64, // Segments resent by timeout or fast retransmit
+    pub rto_ms: u64,           // Current retransmission timeout
+}
+
+#[derive(Default, Debug, Clone, Copy)]
+#[repr(C)]
+pub struct IcmpEchoStats {
+    pub sent: u64,       // Echo requests sent
+    pub received: u64,   // Echo replies matched to a request
+    pub min_rtt_us: u64, // Fastest round trip
+    pub max_rtt_us: u64, // Slowest round trip
+    pub sum_rtt_us: u64, // Total of all round trips, for the average
+}
+
+#[derive(Default, Debug, Clone, Copy)]
+#[repr(C)]
+pub struct NetDeviceStats {
+    pub name: [u8; 16],  // Device name, NUL padded
+    pub rx_packets: u64, // Frames handed to the stack
+    pub rx_bytes: u64,   // Bytes in those frames
+    pub rx_errors: u64,  // Received frames the stack rejected
+    pub tx_packets: u64, // Frames the driver accepted
+    pub tx_bytes: u64,   // Bytes in those frames
+    pub tx_errors: u64,  // Frames the driver refused
+}
@@ -65,6 +65,42 @@ pub enum SysCalls {
     TcpRecv = 34,
     TcpClose = 35,
     TcpAccept = 36,
+    DnsQuery = 37,
+    TcpWaitSendSpace = 38,
+    IcmpSendToTtl = 39,
+    TcpListenStats = 40,
+    TcpSetReusePort = 41,
+    TcpBufInfo = 42,
+    DnsQueryServer = 43,
+    DnsSearchDomains = 44,
+    TcpShutdown = 45,
+    TcpSockStats = 46,
+    TcpListenBacklog = 47,
+    UdpSocket = 48,
+    UdpConnect = 49,
+    UdpSend = 50,
+    UdpRecv = 51,
+    UdpDisconnect = 52,
+    UdpClose = 53,
+    IcmpEchoStats = 54,
+    ArpSet = 55,
+    ArpDel = 56,
+    NetDevStats = 57,
+    Lseek = 58,
+    TcpSelect = 59,
+    TcpSetNonblocking = 60,
+    TcpSetRecvTimeout = 61,
+    TcpSetSendTimeout = 62,
+    TcpSetReuseAddr = 63,
+    TcpSetBuf = 64,
+    RawSocket = 65,
+    RawSend = 66,
+    RawRecv = 67,
+    RawClose = 68,
+    DnsSearch = 69,
+    RawEchoStats = 70,
+    UdpPeekFrom = 71,
+    UdpRecvReady = 72,
     Invalid = 0,
 }
 
@@ -125,7 +161,7 @@ impl SysCalls {
         ),
         (
             Fn::I(Self::icmprecvfrom),
-            "(sock: usize, buf: &mut [u8], src_addr: &mut u32)",
+            "(sock: usize, buf: &mut [u8], src_addr: &mut u32, ttl: &mut u8)",
         ),
         (Fn::U(Self::icmpclose), "(sock: usize)"),
         (Fn::I(Self::clocktime), "()"),
@@ -143,6 +179,105 @@ impl SysCalls {
         (Fn::I(Self::tcprecv), "(sock: usize, buf: &mut [u8])"),
         (Fn::U(Self::tcpclose), "(sock: usize)"),
         (Fn::I(Self::tcpaccept), "(sock: usize)"),
+        (
+            Fn::I(Self::dnsquery),
+            "(domain: &[u8], qtype: u16, buf: &mut [u8])",
+        ),
+        (
+            Fn::U(Self::tcpwaitsendspace),
+            "(sock: usize, timeout_ms: usize)",
+        ),
+        (
+            Fn::I(Self::icmpsendtottl),
+            "(sock: usize, dst: &[u8], data: &[u8], ttl: u8)",
+        ),
+        (
+            Fn::U(Self::tcplistenstats),
+            "(sock: usize, st: &mut TcpListenStats)",
+        ),
+        (Fn::U(Self::tcpsetreuseport), "(sock: usize, enable: usize)"),
+        (
+            Fn::U(Self::tcpbufinfo),
+            "(sock: usize, info: &mut TcpBufInfo)",
+        ),
+        (
+            Fn::I(Self::dnsqueryserver),
+            "(domain: &[u8], qtype: u16, server: &[u8], port: u16, buf: &mut [u8])",
+        ),
+        (Fn::I(Self::dnssearchdomains), "(buf: &mut [u8])"),
+        (Fn::U(Self::tcpshutdown), "(sock: usize)"),
+        (
+            Fn::U(Self::tcpsockstats),
+            "(sock: usize, st: &mut TcpSocketStats)",
+        ),
+        (
+            Fn::U(Self::tcplistenbacklog),
+            "(sock: usize, port: u16, backlog: usize)",
+        ),
+        (Fn::I(Self::udpsocket), "(port: u16)"),
+        (
+            Fn::U(Self::udpconnect),
+            "(sock: usize, addr: &[u8], port: u16)",
+        ),
+        (Fn::I(Self::udpsend), "(sock: usize, data: &[u8])"),
+        (Fn::I(Self::udprecv), "(sock: usize, buf: &mut [u8])"),
+        (Fn::U(Self::udpdisconnect), "(sock: usize)"),
+        (Fn::U(Self::udpclose), "(sock: usize)"),
+        (
+            Fn::U(Self::icmpechostats),
+            "(sock: usize, st: &mut IcmpEchoStats)",
+        ),
+        (Fn::U(Self::arpset), "(addr: &[u8], mac: &[u8])"),
+        (Fn::U(Self::arpdel), "(addr: &[u8])"),
+        (
+            Fn::U(Self::netdevstats),
+            "(index: usize, st: &mut NetDeviceStats)",
+        ),
+        (
+            Fn::I(Self::lseek),
+            "(fd: usize, offset: isize, whence: usize)",
+        ),
+        (
+            Fn::I(Self::tcpselect),
+            "(read: &[usize], write: &[usize], ready: &mut [u8], timeout_ms: usize)",
+        ),
+        (
+            Fn::U(Self::tcpsetnonblocking),
+            "(sock: usize, nonblocking: usize)",
+        ),
+        (
+            Fn::U(Self::tcpsetrecvtimeout),
+            "(sock: usize, timeout_ms: usize)",
+        ),
+        (
+            Fn::U(Self::tcpsetsendtimeout),
+            "(sock: usize, timeout_ms: usize)",
+        ),
+        (Fn::U(Self::tcpsetreuseaddr), "(sock: usize, enable: usize)"),
+        (
+            Fn::U(Self::tcpsetbuf),
+            "(sock: usize, direction: usize, size: usize)",
+        ),
+        (Fn::I(Self::rawsocket), "(protocol: u8)"),
+        (
+            Fn::I(Self::rawsend),
+            "(sock: usize, dst: &[u8], data: &[u8], ttl: u8)",
+        ),
+        (Fn::I(Self::rawrecv), "(sock: usize, buf: &mut [u8])"),
+        (Fn::U(Self::rawclose), "(sock: usize)"),
+        (
+            Fn::I(Self::dnssearch),
+            "(domain: &[u8], qtypes: &[usize], server: &[u8], port: u16, name: &mut [u8], buf: &mut [u8])",
+        ),
+        (
+            Fn::U(Self::rawechostats),
+            "(sock: usize, st: &mut IcmpEchoStats)",
+        ),
+        (
+            Fn::I(Self::udppeekfrom),
+            "(sock: usize, src_addr: &mut u32, src_port: &mut u16)",
+        ),
+        (Fn::I(Self::udprecvready), "(sock: usize)"),
     ];
     pub fn invalid() -> ! {
         unimplemented!()
@@ -347,6 +482,22 @@ fn fdalloc(file: File) -> Result<usize> {
     Err(FileDescriptorTooLarge)
 }
 
+// How long a blocked socket call may sleep before rechecking, capped by
+// the caller's deadline; Timeout once the deadline has passed.
+#[cfg(all(target_os = "none", feature = "kernel"))]
+fn socket_wait_ms(deadline: Option<u64>, max_ms: u64) -> Result<u64> {
+    match deadline {
+        Some(deadline) => {
+            let now = crate::net::tcp::timer::get_time_ms();
+            if now >= deadline {
+                return Err(Timeout);
+            }
+            Ok(core::cmp::min(max_ms, deadline - now))
+        }
+        None => Ok(max_ms),
+    }
+}
+
 // Process related system calls
 impl SysCalls {
     pub fn exit() -> ! {
@@ -747,7 +898,7 @@ impl SysCalls {
             let sbinfo_payload = SBInfo::from_arg(2, &mut sbinfo_payload)?;
             let mut payload = alloc::vec![0u8; sbinfo_payload.len];
             crate::proc::either_copyin(&mut payload[..], sbinfo_payload.ptr.into())?;
-            crate::net::icmp::socket_sendto(sock, dst, &payload)
+            crate::net::icmp::socket_sendto(sock, dst, &payload, crate::net::ip::DEFAULT_TTL)
         }
     }
 
@@ -760,11 +911,13 @@ impl SysCalls {
             let mut sbinfo: SBInfo = Default::default();
             let sbinfo = SBInfo::from_arg(1, &mut sbinfo)?;
             let addr_ptr: UVAddr = argraw(2).into();
+            let ttl_ptr: UVAddr = argraw(3).into();
 
             let mut buf = alloc::vec![0u8; sbinfo.len];
-            let (len, src) = crate::net::icmp::socket_recvfrom(sock, &mut buf)?;
+            let (len, src, ttl) = crate::net::icmp::socket_recvfrom(sock, &mut buf)?;
             crate::proc::either_copyout(sbinfo.ptr.into(), &buf[..len])?;
             crate::proc::either_copyout(addr_ptr.into(), &src.0.to_ne_bytes())?;
+            crate::proc::either_copyout(ttl_ptr.into(), &[ttl])?;
             Ok(len)
         }
     }
@@ -869,7 +1022,7 @@ impl SysCalls {
 
             let endpoint = IpEndpoint::new(IpAddr(0), port);
 
-            crate::net::tcp::socket_get_mut(sock, |socket| socket.listen(endpoint))?
+            crate::net::tcp::socket_listen(sock, endpoint)
         }
     }
 
@@ -907,14 +1060,20 @@ impl SysCalls {
                     return crate::net::tcp::socket_accept(sock);
                 }
 
-                let is_listening = crate::net::tcp::socket_get(sock, |s| {
-                    s.is_listening() || s.has_pending_connection()
+                let (is_listening, nonblocking) = crate::net::tcp::socket_get(sock, |s| {
+                    (
+                        s.is_listening() || s.has_pending_connection(),
+                        s.nonblocking(),
+                    )
                 })?;
 
                 if !is_listening {
                     crate::println!("[tcpaccept] socket not listening, state={}", state);
                     return Err(SocketNotOpen);
                 }
+                if nonblocking {
+                    return Err(WouldBlock);
+                }
 
                 if p.inner.lock().killed {
                     return Err(Interrupted);
@@ -936,11 +1095,28 @@ impl SysCalls {
             let mut buf = alloc::vec![0u8; sbinfo.len];
             crate::proc::either_copyin(&mut buf[..], sbinfo.ptr.into())?;
 
-            let result = crate::net::tcp::socket_get_mut(sock, |socket| socket.send_slice(&buf))??;
-
-            crate::net::poll();
+            const SEND_WAIT_MS: u64 = 1000;
+            let deadline = crate::net::tcp::socket_get(sock, |s| s.send_timeout_ms())?
+                .map(|ms| crate::net::tcp::timer::get_time_ms().saturating_add(ms));
+            let p = Cpus::myproc().unwrap();
+            loop {
+                match crate::net::tcp::socket_get_mut(sock, |socket| socket.send_slice(&buf))? {
+                    Err(BufferFull) => {}
+                    result => {
+                        crate::net::poll();
+                        return result;
+                    }
+                }
 
-            Ok(result)
+                if p.inner.lock().killed {
+                    return Err(Interrupted);
+                }
+                let wait_ms = socket_wait_ms(deadline, SEND_WAIT_MS)?;
+                match crate::net::tcp::wait_for_send_space(sock, wait_ms) {
+                    Ok(()) | Err(Timeout) => {}
+                    Err(e) => return Err(e),
+                }
+            }
         }
     }
 
@@ -955,11 +1131,15 @@ impl SysCalls {
             let mut sbinfo: SBInfo = Default::default();
             let sbinfo = SBInfo::from_arg(1, &mut sbinfo)?;
 
+            const RECV_WAIT_MS: u64 = 1000;
+            let deadline = crate::net::tcp::socket_get(sock, |s| s.recv_timeout_ms())?
+                .map(|ms| crate::net::tcp::timer::get_time_ms().saturating_add(ms));
             let p = Cpus::myproc().unwrap();
             loop {
                 crate::net::poll();
-                let (may_recv, state) =
-                    crate::net::tcp::socket_get(sock, |s| (s.may_recv(), s.state()))?;
+                let (may_recv, eof, state, nonblocking) = crate::net::tcp::socket_get(sock, |s| {
+                    (s.may_recv(), s.eof_received(), s.state(), s.nonblocking())
+                })?;
 
                 if may_recv {
                     let mut buf = alloc::vec![0u8; sbinfo.len];
@@ -970,42 +1150,325 @@ impl SysCalls {
                     return Ok(n);
                 }
 
-                match state {
-                    State::Closed | State::TimeWait => return Ok(0),
-                    State::CloseWait if !may_recv => return Ok(0),
-                    _ => {}
+                if eof || matches!(state, State::Closed | State::TimeWait) {
+                    return Ok(0);
+                }
+                if nonblocking {
+                    return Err(WouldBlock);
                 }
 
                 if p.inner.lock().killed {
                     return Err(Interrupted);
                 }
-                let ticks = TICKS.lock();
-                let _ = sleep(&(*ticks) as *const _ as usize, ticks);
+                let wait_ms = socket_wait_ms(deadline, RECV_WAIT_MS)?;
+                match crate::net::tcp::wait_for_data(sock, wait_ms) {
+                    Ok(()) | Err(Timeout) => {}
+                    Err(e) => return Err(e),
+                }
             }
         }
     }
 
     pub fn tcpclose() -> Result<()> {
+        #[cfg(not(all(target_os = "none", feature = "kernel")))]
+        return Ok(());
+        #[cfg(all(target_os = "none", feature = "kernel"))]
+        {
+            // the stack finishes the close and frees the slot
+            let sock = argraw(0);
+            crate::net::tcp::socket_close(sock)
+        }
+    }
+
+    pub fn dnsquery() -> Result<usize> {
+        #[cfg(not(all(target_os = "none", feature = "kernel")))]
+        return Ok(0);
+        #[cfg(all(target_os = "none", feature = "kernel"))]
+        {
+            let mut sbinfo: SBInfo = Default::default();
+            let sbinfo = SBInfo::from_arg(0, &mut sbinfo)?;
+            let qtype = argraw(1) as u16;
+            let mut outinfo: SBInfo = Default::default();
+            let outinfo = SBInfo::from_arg(2, &mut outinfo)?;
+
+            let mut buf = alloc::vec![0u8; sbinfo.len];
+            crate::proc::either_copyin(&mut buf[..], sbinfo.ptr.into())?;
+            let domain = core::str::from_utf8(&buf).or(Err(Utf8Error))?;
+
+            let records = crate::net::dns::query(domain, qtype)?;
+            let out = crate::net::dns::encode_records(&records, outinfo.len);
+            crate::proc::either_copyout(outinfo.ptr.into(), &out[..])?;
+
+            Ok(out.len())
+        }
+    }
+
+    pub fn tcpwaitsendspace() -> Result<()> {
         #[cfg(not(all(target_os = "none", feature = "kernel")))]
         return Ok(());
         #[cfg(all(target_os = "none", feature = "kernel"))]
         {
             let sock = argraw(0);
+            let timeout_ms = argraw(1) as u64;
+            crate::net::tcp::wait_for_send_space(sock, timeout_ms)
+        }
+    }
+
+    pub fn icmpsendtottl() -> Result<usize> {
+        #[cfg(not(all(target_os = "none", feature = "kernel")))]
+        return Ok(0);
+        #[cfg(all(target_os = "none", feature = "kernel"))]
+        {
+            use crate::net::ip::parse_ip_str;
+            let sock = argraw(0);
+
+            let mut sbinfo: SBInfo = Default::default();
+            let sbinfo = SBInfo::from_arg(1, &mut sbinfo)?;
+            let mut buf = alloc::vec![0u8; sbinfo.len];
+            crate::proc::either_copyin(&mut buf[..], sbinfo.ptr.into())?;
+            let s = core::str::from_utf8(&buf).or(Err(Utf8Error))?;
+            let s = s.trim_end_matches(char::from(0));
+            let dst = parse_ip_str(s)?;
+
+            let mut sbinfo_payload: SBInfo = Default::default();
+            let sbinfo_payload = SBInfo::from_arg(2, &mut sbinfo_payload)?;
+            let mut payload = alloc::vec![0u8; sbinfo_payload.len];
+            crate::proc::either_copyin(&mut payload[..], sbinfo_payload.ptr.into())?;
+            let ttl = argraw(3) as u8;
+            if ttl == 0 {
+                return Err(InvalidArgument);
+            }
+            crate::net::icmp::socket_sendto(sock, dst, &payload, ttl)
+        }
+    }
+
+    pub fn tcplistenstats() -> Result<()> {
+        #[cfg(not(all(target_os = "none", feature = "kernel")))]
+        return Ok(());
+        #[cfg(all(target_os = "none", feature = "kernel"))]
+        {
+            let sock = argraw(0);
+            let st: UVAddr = argraw(1).into();
+
+            let stats = crate::net::tcp::socket_get(sock, |socket| {
+                if socket.is_listening() {
+                    Ok(socket.listen_stats())
+                } else {
+                    Err(InvalidArgument)
+                }
+            })??;
+            either_copyout(st.into(), &stats)
+        }
+    }
+
+    pub fn tcpsetreuseport() -> Result<()> {
+        #[cfg(not(all(target_os = "none", feature = "kernel")))]
+        return Ok(());
+        #[cfg(all(target_os = "none", feature = "kernel"))]
+        {
+            let sock = argraw(0);
+            let enable = argraw(1) != 0;
 
             crate::net::tcp::socket_get_mut(sock, |socket| {
-                socket.close();
-            })?;
+                if socket.state() != crate::net::tcp::State::Closed {
+                    return Err(SocketAlreadyOpen);
+                }
+                socket.set_reuse_port(enable);
+                Ok(())
+            })?
+        }
+    }
+
+    pub fn tcpbufinfo() -> Result<()> {
+        #[cfg(not(all(target_os = "none", feature = "kernel")))]
+        return Ok(());
+        #[cfg(all(target_os = "none", feature = "kernel"))]
+        {
+            let sock = argraw(0);
+            let info: UVAddr = argraw(1).into();
+
+            let buf_info = crate::net::tcp::socket_get(sock, |socket| socket.buf_info())?;
+            either_copyout(info.into(), &buf_info)
+        }
+    }
+
+    pub fn dnsqueryserver() -> Result<usize> {
+        #[cfg(not(all(target_os = "none", feature = "kernel")))]
+        return Ok(0);
+        #[cfg(all(target_os = "none", feature = "kernel"))]
+        {
+            use crate::net::ip::parse_ip_str;
+
+            let mut sbinfo: SBInfo = Default::default();
+            let sbinfo = SBInfo::from_arg(0, &mut sbinfo)?;
+            let qtype = argraw(1) as u16;
+            let mut serverinfo: SBInfo = Default::default();
+            let serverinfo = SBInfo::from_arg(2, &mut serverinfo)?;
+            let port = argraw(3) as u16;
+            let mut outinfo: SBInfo = Default::default();
+            let outinfo = SBInfo::from_arg(4, &mut outinfo)?;
+
+            let mut buf = alloc::vec![0u8; sbinfo.len];
+            crate::proc::either_copyin(&mut buf[..], sbinfo.ptr.into())?;
+            let domain = core::str::from_utf8(&buf).or(Err(Utf8Error))?;
+
+            let mut server = crate::net::dns::default_server();
+            if serverinfo.len > 0 {
+                let mut addr = alloc::vec![0u8; serverinfo.len];
+                crate::proc::either_copyin(&mut addr[..], serverinfo.ptr.into())?;
+                let addr = core::str::from_utf8(&addr).or(Err(Utf8Error))?;
+                server.addr = parse_ip_str(addr)?;
+            }
+            if port != 0 {
+                server.port = port;
+            }
+
+            let records = crate::net::dns::query_with_server(domain, qtype, server)?;
+            let out = crate::net::dns::encode_records(&records, outinfo.len);
+            crate::proc::either_copyout(outinfo.ptr.into(), &out[..])?;
+
+            Ok(out.len())
+        }
+    }
+
+    pub fn dnssearchdomains() -> Result<usize> {
+        #[cfg(not(all(target_os = "none", feature = "kernel")))]
+        return Ok(0);
+        #[cfg(all(target_os = "none", feature = "kernel"))]
+        {
+            let mut outinfo: SBInfo = Default::default();
+            let outinfo = SBInfo::from_arg(0, &mut outinfo)?;
+
+            let mut out = alloc::vec::Vec::new();
+            for domain in crate::net::dns::search_domains() {
+                let needed = domain.len() + usize::from(!out.is_empty());
+                if out.len() + needed > outinfo.len {
+                    break;
+                }
+                if !out.is_empty() {
+                    out.push(b' ');
+                }
+                out.extend_from_slice(domain.as_bytes());
+            }
+            crate::proc::either_copyout(outinfo.ptr.into(), &out[..])?;
+
+            Ok(out.len())
+        }
+    }
+
+    pub fn tcpshutdown() -> Result<()> {
+        #[cfg(not(all(target_os = "none", feature = "kernel")))]
+        return Ok(());
+        #[cfg(all(target_os = "none", feature = "kernel"))]
+        {
+            let sock = argraw(0);
+            crate::net::tcp::socket_get_mut(sock, |socket| socket.shutdown_write())?
+        }
+    }
+
+    pub fn tcpsockstats() -> Result<()> {
+        #[cfg(not(all(target_os = "none", feature = "kernel")))]
+        return Ok(());
+        #[cfg(all(target_os = "none", feature = "kernel"))]
+        {
+            let sock = argraw(0);
+            let st: UVAddr = argraw(1).into();
+
+            let stats = crate::net::tcp::socket_get(sock, |socket| socket.stats())?;
+            either_copyout(st.into(), &stats)
+        }
+    }
+
+    pub fn tcplistenbacklog() -> Result<()> {
+        #[cfg(not(all(target_os = "none", feature = "kernel")))]
+        return Ok(());
+        #[cfg(all(target_os = "none", feature = "kernel"))]
+        {
+            use crate::net::ip::{IpAddr, IpEndpoint};
+
+            let sock = argraw(0);
+            let port = argraw(1) as u16;
+            let backlog = argraw(2);
+
+            let endpoint = IpEndpoint::new(IpAddr(0), port);
+
+            crate::net::tcp::socket_get_mut(sock, |socket| socket.set_backlog(backlog))?;
+            crate::net::tcp::socket_listen(sock, endpoint)
+        }
+    }
+
+    pub fn udpsocket() -> Result<usize> {
+        #[cfg(not(all(target_os = "none", feature = "kernel")))]
+        return Ok(0);
+        #[cfg(all(target_os = "none", feature = "kernel"))]
+        {
+            use crate::net::ip::IpEndpoint;
+            let port = argraw(0) as u16;
+            let sock = crate::net::udp::socket_alloc()?;
+            if let Err(err) = crate::net::udp::socket_bind(sock, IpEndpoint::any(port)) {
+                let _ = crate::net::udp::socket_free(sock);
+                return Err(err);
+            }
+            Ok(sock)
+        }
+    }
+
+    pub fn udpconnect() -> Result<()> {
+        #[cfg(not(all(target_os = "none", feature = "kernel")))]
+        return Ok(());
+        #[cfg(all(target_os = "none", feature = "kernel"))]
+        {
+            use crate::net::ip::{parse_ip_str, IpEndpoint};
+            let sock = argraw(0);
+            let mut sbinfo: SBInfo = Default::default();
+            let sbinfo = SBInfo::from_arg(1, &mut sbinfo)?;
+            let mut buf = alloc::vec![0u8; sbinfo.len];
+            crate::proc::either_copyin(&mut buf[..], sbinfo.ptr.into())?;
+            let s = core::str::from_utf8(&buf).or(Err(Utf8Error))?;
+            let addr = parse_ip_str(s.trim_end_matches(char::from(0)))?;
+            let port = argraw(2) as u16;
+            crate::net::udp::socket_connect(sock, IpEndpoint::new(addr, port))
+        }
+    }
+
+    pub fn udpsend() -> Result<usize> {
+        #[cfg(not(all(target_os = "none", feature = "kernel")))]
+        return Ok(0);
+        #[cfg(all(target_os = "none", feature = "kernel"))]
+        {
+            let sock = argraw(0);
+            let mut sbinfo: SBInfo = Default::default();
+            let sbinfo = SBInfo::from_arg(1, &mut sbinfo)?;
+            let mut data = alloc::vec![0u8; sbinfo.len];
+            crate::proc::either_copyin(&mut data[..], sbinfo.ptr.into())?;
+            crate::net::udp::socket_send(sock, &data)?;
+            Ok(data.len())
+        }
+    }
+
+    pub fn udprecv() -> Result<usize> {
+        #[cfg(not(all(target_os = "none", feature = "kernel")))]
+        return Ok(0);
+        #[cfg(all(target_os = "none", feature = "kernel"))]
+        {
+            let sock = argraw(0);
+            let mut sbinfo: SBInfo = Default::default();
+            let sbinfo = SBInfo::from_arg(1, &mut sbinfo)?;
+            let mut buf = alloc::vec![0u8; sbinfo.len];
 
             let p = Cpus::myproc().unwrap();
             loop {
-                let state = crate::net::tcp::socket_get(sock, |s| s.state())?;
-                if state == crate::net::tcp::State::Closed {
-                    crate::net::tcp::socket_free(sock)?;
-                    return Ok(());
+                crate::net::poll();
+                match crate::net::udp::socket_recv(sock, &mut buf) {
+                    Ok(n) => {
+                        crate::proc::either_copyout(sbinfo.ptr.into(), &buf[..n])?;
+                        return Ok(n);
+                    }
+                    Err(WouldBlock) => {}
+                    Err(e) => return Err(e),
                 }
 
                 if p.inner.lock().killed {
-                    let _ = crate::net::tcp::socket_free(sock);
                     return Err(Interrupted);
                 }
                 let ticks = TICKS.lock();
@@ -1013,6 +1476,404 @@ impl SysCalls {
             }
         }
     }
+
+    pub fn udpdisconnect() -> Result<()> {
+        #[cfg(not(all(target_os = "none", feature = "kernel")))]
+        return Ok(());
+        #[cfg(all(target_os = "none", feature = "kernel"))]
+        {
+            let sock = argraw(0);
+            crate::net::udp::socket_disconnect(sock)
+        }
+    }
+
+    pub fn udpclose() -> Result<()> {
+        #[cfg(not(all(target_os = "none", feature = "kernel")))]
+        return Ok(());
+        #[cfg(all(target_os = "none", feature = "kernel"))]
+        {
+            let sock = argraw(0);
+            crate::net::udp::socket_free(sock)
+        }
+    }
+
+    pub fn icmpechostats() -> Result<()> {
+        #[cfg(not(all(target_os = "none", feature = "kernel")))]
+        return Ok(());
+        #[cfg(all(target_os = "none", feature = "kernel"))]
+        {
+            let sock = argraw(0);
+            let st: UVAddr = argraw(1).into();
+
+            let stats = crate::net::icmp::echo_stats(sock)?;
+            either_copyout(st.into(), &stats)
+        }
+    }
+
+    pub fn arpset() -> Result<()> {
+        #[cfg(not(all(target_os = "none", feature = "kernel")))]
+        return Ok(());
+        #[cfg(all(target_os = "none", feature = "kernel"))]
+        {
+            use crate::net::{ethernet::MacAddr, ip::parse_ip_str};
+            let mut sbinfo: SBInfo = Default::default();
+            let sbinfo = SBInfo::from_arg(0, &mut sbinfo)?;
+            let mut buf = alloc::vec![0u8; sbinfo.len];
+            crate::proc::either_copyin(&mut buf[..], sbinfo.ptr.into())?;
+            let s = core::str::from_utf8(&buf).or(Err(Utf8Error))?;
+            let addr = parse_ip_str(s.trim_end_matches(char::from(0)))?;
+
+            let mut sbinfo_mac: SBInfo = Default::default();
+            let sbinfo_mac = SBInfo::from_arg(1, &mut sbinfo_mac)?;
+            if sbinfo_mac.len != 6 {
+                return Err(InvalidArgument);
+            }
+            let mut mac = [0u8; 6];
+            crate::proc::either_copyin(&mut mac[..], sbinfo_mac.ptr.into())?;
+            crate::net::arp::add_static(addr, MacAddr(mac));
+            Ok(())
+        }
+    }
+
+    pub fn arpdel() -> Result<()> {
+        #[cfg(not(all(target_os = "none", feature = "kernel")))]
+        return Ok(());
+        #[cfg(all(target_os = "none", feature = "kernel"))]
+        {
+            use crate::net::ip::parse_ip_str;
+            let mut sbinfo: SBInfo = Default::default();
+            let sbinfo = SBInfo::from_arg(0, &mut sbinfo)?;
+            let mut buf = alloc::vec![0u8; sbinfo.len];
+            crate::proc::either_copyin(&mut buf[..], sbinfo.ptr.into())?;
+            let s = core::str::from_utf8(&buf).or(Err(Utf8Error))?;
+            let addr = parse_ip_str(s.trim_end_matches(char::from(0)))?;
+            crate::net::arp::remove_static(addr)
+        }
+    }
+
+    pub fn netdevstats() -> Result<()> {
+        #[cfg(not(all(target_os = "none", feature = "kernel")))]
+        return Ok(());
+        #[cfg(all(target_os = "none", feature = "kernel"))]
+        {
+            let index = argraw(0);
+            let st: UVAddr = argraw(1).into();
+
+            let dev = crate::net::device::net_device_by_index(index).ok_or(DeviceNotFound)?;
+            either_copyout(st.into(), &dev.stats())
+        }
+    }
+
+    pub fn lseek() -> Result<usize> {
+        #[cfg(not(all(target_os = "none", feature = "kernel")))]
+        return Ok(0);
+        #[cfg(all(target_os = "none", feature = "kernel"))]
+        {
+            let mut _fd = 0;
+
+            let (f, _) = File::from_arg(0, &mut _fd)?;
+            let offset = argraw(1) as isize;
+
+            f.seek(offset, argraw(2))
+        }
+    }
+
+    pub fn tcpselect() -> Result<usize> {
+        #[cfg(not(all(target_os = "none", feature = "kernel")))]
+        return Ok(0);
+        #[cfg(all(target_os = "none", feature = "kernel"))]
+        {
+            use crate::param::TICK_MS;
+
+            let mut rinfo: SBInfo = Default::default();
+            let rinfo = SBInfo::from_arg(0, &mut rinfo)?;
+            let mut winfo: SBInfo = Default::default();
+            let winfo = SBInfo::from_arg(1, &mut winfo)?;
+            // a set never needs more entries than there are sockets
+            let max = crate::net::tcp::TCP_SOCKET_SIZE;
+            if rinfo.len > max || winfo.len > max {
+                return Err(InvalidArgument);
+            }
+            let mut read = alloc::vec![0usize; rinfo.len];
+            either_copyin(&mut read[..], rinfo.ptr.into())?;
+            let mut write = alloc::vec![0usize; winfo.len];
+            either_copyin(&mut write[..], winfo.ptr.into())?;
+            let mut rdinfo: SBInfo = Default::default();
+            let rdinfo = SBInfo::from_arg(2, &mut rdinfo)?;
+            if rdinfo.len < read.len() + write.len() {
+                return Err(NoBufferSpace);
+            }
+            let timeout_ms = argraw(3);
+            let timeout_ticks = timeout_ms.div_ceil(TICK_MS);
+            let start = *TICKS.lock();
+
+            let p = Cpus::myproc().unwrap();
+            let mut ready = alloc::vec![0u8; read.len() + write.len()];
+            loop {
+                crate::net::poll();
+
+                let mut n = 0;
+                for (i, &sock) in read.iter().enumerate() {
+                    ready[i] = crate::net::tcp::socket_get(sock, |s| s.is_readable())? as u8;
+                    n += ready[i] as usize;
+                }
+                for (i, &sock) in write.iter().enumerate() {
+                    let flag = crate::net::tcp::socket_get(sock, |s| s.is_writable())? as u8;
+                    ready[read.len() + i] = flag;
+                    n += flag as usize;
+                }
+                let ticks = TICKS.lock();
+                if n > 0 || ticks.wrapping_sub(start) >= timeout_ticks {
+                    drop(ticks);
+                    either_copyout(rdinfo.ptr.into(), &ready[..])?;
+                    return Ok(n);
+                }
+                if p.inner.lock().killed {
+                    return Err(Interrupted);
+                }
+                let _ = sleep(&(*ticks) as *const _ as usize, ticks);
+            }
+        }
+    }
+
+    pub fn tcpsetnonblocking() -> Result<()> {
+        #[cfg(not(all(target_os = "none", feature = "kernel")))]
+        return Ok(());
+        #[cfg(all(target_os = "none", feature = "kernel"))]
+        {
+            let sock = argraw(0);
+            let nonblocking = argraw(1) != 0;
+
+            crate::net::tcp::socket_get_mut(sock, |socket| socket.set_nonblocking(nonblocking))
+        }
+    }
+
+    pub fn tcpsetrecvtimeout() -> Result<()> {
+        #[cfg(not(all(target_os = "none", feature = "kernel")))]
+        return Ok(());
+        #[cfg(all(target_os = "none", feature = "kernel"))]
+        {
+            let sock = argraw(0);
+            let timeout_ms = match argraw(1) {
+                0 => None,
+                ms => Some(ms as u64),
+            };
+
+            crate::net::tcp::socket_get_mut(sock, |socket| socket.set_recv_timeout_ms(timeout_ms))
+        }
+    }
+
+    pub fn tcpsetsendtimeout() -> Result<()> {
+        #[cfg(not(all(target_os = "none", feature = "kernel")))]
+        return Ok(());
+        #[cfg(all(target_os = "none", feature = "kernel"))]
+        {
+            let sock = argraw(0);
+            let timeout_ms = match argraw(1) {
+                0 => None,
+                ms => Some(ms as u64),
+            };
+
+            crate::net::tcp::socket_get_mut(sock, |socket| socket.set_send_timeout_ms(timeout_ms))
+        }
+    }
+
+    pub fn tcpsetreuseaddr() -> Result<()> {
+        #[cfg(not(all(target_os = "none", feature = "kernel")))]
+        return Ok(());
+        #[cfg(all(target_os = "none", feature = "kernel"))]
+        {
+            let sock = argraw(0);
+            let enable = argraw(1) != 0;
+
+            crate::net::tcp::socket_get_mut(sock, |socket| {
+                if socket.state() != crate::net::tcp::State::Closed {
+                    return Err(SocketAlreadyOpen);
+                }
+                socket.set_reuse_addr(enable);
+                Ok(())
+            })?
+        }
+    }
+
+    pub fn tcpsetbuf() -> Result<()> {
+        #[cfg(not(all(target_os = "none", feature = "kernel")))]
+        return Ok(());
+        #[cfg(all(target_os = "none", feature = "kernel"))]
+        {
+            let sock = argraw(0);
+            let direction = argraw(1);
+            let size = argraw(2);
+
+            crate::net::tcp::socket_get_mut(sock, |socket| match direction {
+                0 => socket.resize_rx_buf(size),
+                1 => socket.resize_tx_buf(size),
+                _ => Err(InvalidArgument),
+            })?
+        }
+    }
+
+    pub fn rawsocket() -> Result<usize> {
+        #[cfg(not(all(target_os = "none", feature = "kernel")))]
+        return Ok(0);
+        #[cfg(all(target_os = "none", feature = "kernel"))]
+        {
+            let protocol = argraw(0) as u8;
+            crate::net::raw::socket_alloc(protocol)
+        }
+    }
+
+    pub fn rawsend() -> Result<usize> {
+        #[cfg(not(all(target_os = "none", feature = "kernel")))]
+        return Ok(0);
+        #[cfg(all(target_os = "none", feature = "kernel"))]
+        {
+            use crate::net::ip::parse_ip_str;
+            let sock = argraw(0);
+
+            let mut sbinfo: SBInfo = Default::default();
+            let sbinfo = SBInfo::from_arg(1, &mut sbinfo)?;
+            let mut buf = alloc::vec![0u8; sbinfo.len];
+            crate::proc::either_copyin(&mut buf[..], sbinfo.ptr.into())?;
+            let s = core::str::from_utf8(&buf).or(Err(Utf8Error))?;
+            let dst = parse_ip_str(s.trim_end_matches(char::from(0)))?;
+
+            let mut sbinfo_payload: SBInfo = Default::default();
+            let sbinfo_payload = SBInfo::from_arg(2, &mut sbinfo_payload)?;
+            let mut payload = alloc::vec![0u8; sbinfo_payload.len];
+            crate::proc::either_copyin(&mut payload[..], sbinfo_payload.ptr.into())?;
+            let ttl = argraw(3) as u8;
+            if ttl == 0 {
+                return Err(InvalidArgument);
+            }
+            crate::net::raw::socket_send(sock, dst, &payload, ttl)
+        }
+    }
+
+    pub fn rawrecv() -> Result<usize> {
+        #[cfg(not(all(target_os = "none", feature = "kernel")))]
+        return Ok(0);
+        #[cfg(all(target_os = "none", feature = "kernel"))]
+        {
+            let sock = argraw(0);
+            let mut sbinfo: SBInfo = Default::default();
+            let sbinfo = SBInfo::from_arg(1, &mut sbinfo)?;
+
+            let mut buf = alloc::vec![0u8; sbinfo.len];
+            let len = crate::net::raw::socket_recv(sock, &mut buf)?;
+            crate::proc::either_copyout(sbinfo.ptr.into(), &buf[..len])?;
+            Ok(len)
+        }
+    }
+
+    pub fn rawclose() -> Result<()> {
+        #[cfg(not(all(target_os = "none", feature = "kernel")))]
+        return Ok(());
+        #[cfg(all(target_os = "none", feature = "kernel"))]
+        {
+            let sock = argraw(0);
+            crate::net::raw::socket_free(sock)
+        }
+    }
+
+    pub fn dnssearch() -> Result<usize> {
+        #[cfg(not(all(target_os = "none", feature = "kernel")))]
+        return Ok(0);
+        #[cfg(all(target_os = "none", feature = "kernel"))]
+        {
+            use crate::net::ip::parse_ip_str;
+
+            let mut sbinfo: SBInfo = Default::default();
+            let sbinfo = SBInfo::from_arg(0, &mut sbinfo)?;
+            let mut typeinfo: SBInfo = Default::default();
+            let typeinfo = SBInfo::from_arg(1, &mut typeinfo)?;
+            let mut serverinfo: SBInfo = Default::default();
+            let serverinfo = SBInfo::from_arg(2, &mut serverinfo)?;
+            let port = argraw(3) as u16;
+            let mut nameinfo: SBInfo = Default::default();
+            let nameinfo = SBInfo::from_arg(4, &mut nameinfo)?;
+            let mut outinfo: SBInfo = Default::default();
+            let outinfo = SBInfo::from_arg(5, &mut outinfo)?;
+
+            let mut buf = alloc::vec![0u8; sbinfo.len];
+            crate::proc::either_copyin(&mut buf[..], sbinfo.ptr.into())?;
+            let domain = core::str::from_utf8(&buf).or(Err(Utf8Error))?;
+            const MAX_QTYPES: usize = 8;
+            if typeinfo.len == 0 || typeinfo.len > MAX_QTYPES {
+                return Err(InvalidArgument);
+            }
+            let mut qtypes = alloc::vec![0usize; typeinfo.len];
+            crate::proc::either_copyin(&mut qtypes[..], typeinfo.ptr.into())?;
+            let qtypes: alloc::vec::Vec<u16> = qtypes.into_iter().map(|t| t as u16).collect();
+
+            let mut server = crate::net::dns::default_server();
+            if serverinfo.len > 0 {
+                let mut addr = alloc::vec![0u8; serverinfo.len];
+                crate::proc::either_copyin(&mut addr[..], serverinfo.ptr.into())?;
+                let addr = core::str::from_utf8(&addr).or(Err(Utf8Error))?;
+                server.addr = parse_ip_str(addr)?;
+            }
+            if port != 0 {
+                server.port = port;
+            }
+
+            // the name that answered comes back NUL padded
+            let (name, records) =
+                crate::net::dns::query_with_search_server(domain, &qtypes, server)?;
+            if name.len() > nameinfo.len {
+                return Err(NoBufferSpace);
+            }
+            let mut name_out = alloc::vec![0u8; nameinfo.len];
+            name_out[..name.len()].copy_from_slice(name.as_bytes());
+            crate::proc::either_copyout(nameinfo.ptr.into(), &name_out[..])?;
+            let out = crate::net::dns::encode_records(&records, outinfo.len);
+            crate::proc::either_copyout(outinfo.ptr.into(), &out[..])?;
+
+            Ok(out.len())
+        }
+    }
+
+    pub fn rawechostats() -> Result<()> {
+        #[cfg(not(all(target_os = "none", feature = "kernel")))]
+        return Ok(());
+        #[cfg(all(target_os = "none", feature = "kernel"))]
+        {
+            let sock = argraw(0);
+            let st: UVAddr = argraw(1).into();
+
+            let stats = crate::net::raw::echo_stats(sock)?;
+            either_copyout(st.into(), &stats)
+        }
+    }
+
+    // Length and source of the next queued datagram, left in the queue.
+    pub fn udppeekfrom() -> Result<usize> {
+        #[cfg(not(all(target_os = "none", feature = "kernel")))]
+        return Ok(0);
+        #[cfg(all(target_os = "none", feature = "kernel"))]
+        {
+            let sock = argraw(0);
+            let addr_ptr: UVAddr = argraw(1).into();
+            let port_ptr: UVAddr = argraw(2).into();
+
+            crate::net::poll();
+            let (len, src) = crate::net::udp::socket_peekfrom(sock)?;
+            crate::proc::either_copyout(addr_ptr.into(), &src.addr.0.to_ne_bytes())?;
+            crate::proc::either_copyout(port_ptr.into(), &src.port.to_ne_bytes())?;
+            Ok(len)
+        }
+    }
+
+    pub fn udprecvready() -> Result<usize> {
+        #[cfg(not(all(target_os = "none", feature = "kernel")))]
+        return Ok(0);
+        #[cfg(all(target_os = "none", feature = "kernel"))]
+        {
+            let sock = argraw(0);
+            crate::net::poll();
+            crate::net::udp::socket_recv_ready(sock).map(usize::from)
+        }
+    }
 }
 
 impl SysCalls {
@@ -1054,6 +1915,42 @@ impl SysCalls {
             34 => Self::TcpRecv,
             35 => Self::TcpClose,
             36 => Self::TcpAccept,
+            37 => Self::DnsQuery,
+            38 => Self::TcpWaitSendSpace,
+            39 => Self::IcmpSendToTtl,
+            40 => Self::TcpListenStats,
+            41 => Self::TcpSetReusePort,
+            42 => Self::TcpBufInfo,
+            43 => Self::DnsQueryServer,
+            44 => Self::DnsSearchDomains,
+            45 => Self::TcpShutdown,
+            46 => Self::TcpSockStats,
+            47 => Self::TcpListenBacklog,
+            48 => Self::UdpSocket,
+            49 => Self::UdpConnect,
+            50 => Self::UdpSend,
+            51 => Self::UdpRecv,
+            52 => Self::UdpDisconnect,
+            53 => Self::UdpClose,
+            54 => Self::IcmpEchoStats,
+            55 => Self::ArpSet,
+            56 => Self::ArpDel,
+            57 => Self::NetDevStats,
+            58 => Self::Lseek,
+            59 => Self::TcpSelect,
+            60 => Self::TcpSetNonblocking,
+            61 => Self::TcpSetRecvTimeout,
+            62 => Self::TcpSetSendTimeout,
+            63 => Self::TcpSetReuseAddr,
+            64 => Self::TcpSetBuf,
+            65 => Self::RawSocket,
+            66 => Self::RawSend,
+            67 => Self::RawRecv,
+            68 => Self::RawClose,
+            69 => Self::DnsSearch,
+            70 => Self::RawEchoStats,
+            71 => Self::UdpPeekFrom,
+            72 => Self::UdpRecvReady,
             _ => Self::Invalid,
         }
     }
@@ -30,6 +30,8 @@ pub(crate) fn test_runner(tests: &[&dyn Testable]) -> ! {
         crate::uart::init();
         crate::kalloc::init();
     };
+    #[cfg(all(target_os = "none", feature = "kernel"))]
+    crate::net::init_for_tests();
 
     println!("running {} tests", tests.len());
     println!("");
@@ -57,6 +59,8 @@ pub(crate) fn panic(info: &core::panic::PanicInfo<'_>) -> ! {
     let name = unsafe { CURRENT_TEST.unwrap_or("<unknown>") };
     println!("test {} ... {}FAILED{}", name, COLOR_FAIL, COLOR_RESET);
     println!("{}", info);
+    #[cfg(all(target_os = "none", feature = "kernel"))]
+    crate::net::net_panic_dump();
 
     let passed = PASSED.load(Ordering::Relaxed);
     let failed = FAILED.load(Ordering::Relaxed);
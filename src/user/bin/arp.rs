@@ -0,0 +1,69 @@
+#![no_std]
+
+use ulib::{arp_del, arp_set, env, print, println};
+
+enum Command {
+    Set(&'static str, [u8; 6]),
+    Delete(&'static str),
+}
+
+fn main() {
+    let Some(cmd) = parse_args() else {
+        print_usage();
+        return;
+    };
+
+    match cmd {
+        Command::Set(addr, mac) => {
+            if let Err(e) = arp_set(addr, &mac) {
+                println!("arp: {}: {:?}", addr, e);
+            }
+        }
+        Command::Delete(addr) => {
+            if let Err(e) = arp_del(addr) {
+                println!("arp: {}: {:?}", addr, e);
+            }
+        }
+    }
+}
+
+fn print_usage() {
+    println!("usage: arp -s <ip address> <mac address>");
+    println!("       arp -d <ip address>");
+}
+
+fn parse_args() -> Option<Command> {
+    let mut args = env::args();
+    let _prog = args.next();
+
+    let cmd = match args.next()? {
+        "-s" => {
+            let addr = args.next()?;
+            let mac = parse_mac(args.next()?)?;
+            Command::Set(addr, mac)
+        }
+        "-d" => Command::Delete(args.next()?),
+        _ => return None,
+    };
+    if args.next().is_some() {
+        return None;
+    }
+    Some(cmd)
+}
+
+// aa:bb:cc:dd:ee:ff
+fn parse_mac(s: &str) -> Option<[u8; 6]> {
+    let mut mac = [0u8; 6];
+    let mut parts = s.split(':');
+    for byte in mac.iter_mut() {
+        let part = parts.next()?;
+        if part.is_empty() || part.len() > 2 {
+            return None;
+        }
+        *byte = u8::from_str_radix(part, 16).ok()?;
+    }
+    if parts.next().is_some() {
+        return None;
+    }
+    Some(mac)
+}
@@ -1,28 +1,66 @@
 #![no_std]
 extern crate alloc;
 
-use alloc::string::String;
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::vec;
 use alloc::vec::Vec;
 use args::{Args, Error as ArgsError};
-use ulib::http::{HttpRequest, HttpResponse, HttpStatus};
-use ulib::sys::{self, Error};
-use ulib::{accept, close, fs, io, listen, print, println, recv, send, socket};
+use core::sync::atomic::{AtomicU64, Ordering};
+use ulib::fs::SeekFrom;
+use ulib::http::{
+    accept_quality, mime_type_from_path, parse_accept_header, register_mime_type, HttpMethod,
+    HttpRequest, HttpResponse, HttpStatus, HttpVersion,
+};
+use ulib::mutex::Mutex;
+use ulib::path::Path;
+use ulib::sys::{self, stat, Error};
+use ulib::{
+    accept, close, fs, io, listen, print, println, recv, send, socket, socket_set_reuseaddr,
+    socket_stats, tcp_buf_info,
+};
 
 const DEFAULT_PORT: u16 = 8080;
 const REQUEST_BUFFER_SIZE: usize = 8192;
 const SEND_RETRY_TICKS: usize = 1;
+// Files larger than this are streamed with chunked encoding
+const CHUNKED_THRESHOLD: usize = 64 * 1024;
+const STREAM_CHUNK_SIZE: usize = 2048;
+// 5 s at 10 ticks per second
+const KEEP_ALIVE_IDLE_TICKS: usize = 50;
+const KEEP_ALIVE_POLL_TICKS: usize = 1;
+const KV_API_PREFIX: &str = "/api/kv/";
+const AUTH_REALM: &str = "octox-httpd";
+const CORS_METHODS: &[HttpMethod] = &[
+    HttpMethod::Get,
+    HttpMethod::Head,
+    HttpMethod::Post,
+    HttpMethod::Put,
+    HttpMethod::Delete,
+    HttpMethod::Options,
+];
+const CORS_HEADERS: &[&str] = &["Content-Type", "Authorization"];
+
+static REQUEST_ID_COUNTER: AtomicU64 = AtomicU64::new(1);
+static KV_STORE: KeyValueStore = KeyValueStore::new();
 
 mod args {
     use alloc::string::String;
+    use alloc::vec::Vec;
     use ulib::env;
 
     pub struct Args {
         pub port: u16,
         pub doc_root: String,
+        pub mime_types: Vec<(&'static str, &'static str)>,
+        pub auth: Option<(&'static str, &'static str)>,
+        pub dir_listing: bool,
     }
 
     pub enum Error {
         MissingDocRoot,
+        InvalidMime(String),
+        InvalidAuth(String),
     }
 
     impl Args {
@@ -32,9 +70,30 @@ mod args {
 
             let mut port = super::DEFAULT_PORT;
             let mut doc_root: Option<String> = None;
+            let mut mime_types = Vec::new();
+            let mut auth = None;
+            let mut dir_listing = true;
 
             while let Some(arg) = args.next() {
-                if let Ok(p) = arg.parse::<u16>() {
+                if arg == "--mime" {
+                    let spec = args.next().unwrap_or("");
+                    match spec.split_once(':') {
+                        Some((ext, mime)) if !ext.is_empty() && !mime.is_empty() => {
+                            mime_types.push((ext, mime));
+                        }
+                        _ => return Err(Error::InvalidMime(String::from(spec))),
+                    }
+                } else if arg == "--no-dirlisting" {
+                    dir_listing = false;
+                } else if arg == "--auth" {
+                    let spec = args.next().unwrap_or("");
+                    match spec.split_once(':') {
+                        Some((user, password)) if !user.is_empty() => {
+                            auth = Some((user, password));
+                        }
+                        _ => return Err(Error::InvalidAuth(String::from(spec))),
+                    }
+                } else if let Ok(p) = arg.parse::<u16>() {
                     port = p;
                 } else {
                     doc_root = Some(String::from(arg));
@@ -43,7 +102,28 @@ mod args {
 
             let doc_root = doc_root.ok_or(Error::MissingDocRoot)?;
 
-            Ok(Args { port, doc_root })
+            Ok(Args {
+                port,
+                doc_root,
+                mime_types,
+                auth,
+                dir_listing,
+            })
+        }
+    }
+}
+
+struct SocketWriter(usize);
+
+impl io::Write for SocketWriter {
+    fn write(&mut self, buf: &[u8]) -> sys::Result<usize> {
+        loop {
+            match send(self.0, buf) {
+                Ok(0) | Err(Error::BufferFull) | Err(Error::WouldBlock) => {
+                    let _ = sys::sleep(SEND_RETRY_TICKS);
+                }
+                result => return result,
+            }
         }
     }
 }
@@ -53,14 +133,83 @@ enum FileError {
     ReadError,
 }
 
+// Requests whose path starts with a registered prefix are handed to the
+// handler with the rest of the path instead of being served from disk.
+trait ApiHandler {
+    fn handle(&self, method: HttpMethod, path_suffix: &str, body: &[u8]) -> HttpResponse;
+}
+
+struct KeyValueStore {
+    entries: Mutex<BTreeMap<String, Vec<u8>>>,
+}
+
+impl KeyValueStore {
+    const fn new() -> Self {
+        Self {
+            entries: Mutex::new(BTreeMap::new()),
+        }
+    }
+}
+
+impl ApiHandler for KeyValueStore {
+    fn handle(&self, method: HttpMethod, key: &str, body: &[u8]) -> HttpResponse {
+        if key.is_empty() || key.contains('/') {
+            return HttpResponse::error(HttpStatus::NotFound);
+        }
+
+        match method {
+            HttpMethod::Get => match self.entries.lock().get(key) {
+                Some(value) => HttpResponse::with_body(
+                    HttpStatus::Ok,
+                    "application/octet-stream",
+                    value.clone(),
+                ),
+                None => HttpResponse::error(HttpStatus::NotFound),
+            },
+            HttpMethod::Post | HttpMethod::Put => {
+                self.entries.lock().insert(key.to_string(), body.to_vec());
+                HttpResponse::with_body(HttpStatus::Ok, "text/plain", b"stored\n".to_vec())
+            }
+            HttpMethod::Delete => match self.entries.lock().remove(key) {
+                Some(_) => {
+                    HttpResponse::with_body(HttpStatus::Ok, "text/plain", b"deleted\n".to_vec())
+                }
+                None => HttpResponse::error(HttpStatus::NotFound),
+            },
+            _ => HttpResponse::error(HttpStatus::MethodNotAllowed),
+        }
+    }
+}
+
 struct Server {
     port: u16,
     doc_root: String,
+    api_handlers: Vec<(&'static str, &'static dyn ApiHandler)>,
+    auth: Option<(&'static str, &'static str)>,
+    dir_listing: bool,
 }
 
 impl Server {
     fn new(port: u16, doc_root: String) -> Self {
-        Self { port, doc_root }
+        Self {
+            port,
+            doc_root,
+            api_handlers: Vec::new(),
+            auth: None,
+            dir_listing: true,
+        }
+    }
+
+    fn set_dir_listing(&mut self, enable: bool) {
+        self.dir_listing = enable;
+    }
+
+    fn require_basic_auth(&mut self, user: &'static str, password: &'static str) {
+        self.auth = Some((user, password));
+    }
+
+    fn register_api_prefix(&mut self, prefix: &'static str, handler: &'static dyn ApiHandler) {
+        self.api_handlers.push((prefix, handler));
     }
 
     fn run(&self) -> Result<(), String> {
@@ -68,12 +217,12 @@ impl Server {
 
         println!("[httpd] server started successfully");
 
+        // reused across connections so steady-state responses don't hit the heap
+        let mut tx_buf = Vec::new();
         loop {
             match accept(sock) {
                 Ok(conn_sock) => {
-                    if let Err(e) = self.handle_connection(conn_sock) {
-                        println!("[httpd] connection error: {}", e);
-                    }
+                    self.serve_connection(conn_sock, &mut tx_buf);
                     let _ = close(conn_sock);
                 }
                 Err(e) => {
@@ -85,59 +234,224 @@ impl Server {
 
     fn open_listener(&self) -> Result<usize, String> {
         let sock = socket().map_err(|e| alloc::format!("failed to create socket: {:?}", e))?;
+        // a client reconnecting from a port still in TIME_WAIT gets a new connection
+        socket_set_reuseaddr(sock, true)
+            .map_err(|e| alloc::format!("failed to set SO_REUSEADDR: {:?}", e))?;
         listen(sock, self.port).map_err(|e| alloc::format!("listen failed: {:?}", e))?;
         Ok(sock)
     }
 
-    fn handle_connection(&self, sock: usize) -> Result<(), String> {
-        let request_data = Self::read_request_headers(sock)?;
-        let request = match Self::parse_request(&request_data) {
+    // Keeps answering requests on one connection while the client wants it
+    // kept alive and sends the next request before the idle timeout.
+    // Bytes read past the end of one request belong to the next, so they
+    // are carried over instead of dropped.
+    fn serve_connection(&self, sock: usize, tx_buf: &mut Vec<u8>) {
+        let mut pending = Vec::new();
+        loop {
+            match self.handle_connection(sock, tx_buf, &mut pending) {
+                Ok(true) if !pending.is_empty() || Self::wait_for_next_request(sock) => {}
+                Ok(_) => return,
+                Err(e) => {
+                    println!("[httpd] connection error: {}", e);
+                    return;
+                }
+            }
+        }
+    }
+
+    // recv blocks until data or FIN arrives, so an idle client is watched
+    // through the receive queue instead.
+    fn wait_for_next_request(sock: usize) -> bool {
+        for _ in 0..KEEP_ALIVE_IDLE_TICKS / KEEP_ALIVE_POLL_TICKS {
+            match tcp_buf_info(sock) {
+                Ok(info) if info.rx_used > 0 => return true,
+                Ok(_) => {}
+                Err(_) => return false,
+            }
+            match socket_stats(sock) {
+                Ok(st) if st.state == stat::TCP_STATE_ESTABLISHED => {}
+                _ => return false,
+            }
+            let _ = sys::sleep(KEEP_ALIVE_POLL_TICKS);
+        }
+        false
+    }
+
+    // Returns whether the connection should stay open for another request.
+    fn handle_connection(
+        &self,
+        sock: usize,
+        tx_buf: &mut Vec<u8>,
+        pending: &mut Vec<u8>,
+    ) -> Result<bool, String> {
+        let mut request_data = Self::read_request_headers(sock, pending)?;
+        let request = match Self::read_request(sock, &mut request_data, pending) {
             Ok(req) => req,
             Err(status) => {
-                Self::send_status(sock, status)?;
-                return Ok(());
+                Self::send_status(sock, tx_buf, &Self::next_request_id(), status, true)?;
+                return Ok(false);
             }
         };
+        let keep_alive = request.keep_alive();
+        // HEAD is answered like GET, minus the body bytes
+        let send_body = request.method() != HttpMethod::Head;
+        let request_id = request
+            .request_id()
+            .map(|id| id.to_string())
+            .unwrap_or_else(Self::next_request_id);
+
+        println!(
+            "[httpd] [{}] {} {}",
+            request_id,
+            request.method().as_str(),
+            request.uri()
+        );
+
+        // Preflights never carry credentials, so they are answered before
+        // the auth check.
+        if request.method() == HttpMethod::Options {
+            let mut response = HttpResponse::new(HttpStatus::Ok);
+            response.add_header("Content-Length".to_string(), "0".to_string());
+            response.add_cors_headers(None, CORS_METHODS, CORS_HEADERS);
+            response.echo_request_id(&request_id);
+            response.set_keep_alive(keep_alive);
+            Self::send_response(sock, tx_buf, &request_id, &response, send_body)?;
+            return Ok(keep_alive);
+        }
+
+        if self.auth.is_some() && request.basic_auth() != self.auth {
+            let mut response = HttpResponse::www_authenticate(AUTH_REALM);
+            response.echo_request_id(&request_id);
+            response.set_keep_alive(keep_alive);
+            Self::send_response(sock, tx_buf, &request_id, &response, send_body)?;
+            return Ok(keep_alive);
+        }
 
-        println!("[httpd] {} {}", request.method().as_str(), request.uri());
+        if let Some((prefix, handler)) = self
+            .api_handlers
+            .iter()
+            .find(|(prefix, _)| request.uri().starts_with(prefix))
+        {
+            // HEAD is answered as GET; send_response drops the body
+            let method = match request.method() {
+                HttpMethod::Head => HttpMethod::Get,
+                method => method,
+            };
+            let mut response =
+                handler.handle(method, &request.uri()[prefix.len()..], request.body());
+            response.add_cors_headers(None, CORS_METHODS, CORS_HEADERS);
+            response.echo_request_id(&request_id);
+            response.set_keep_alive(keep_alive);
+            Self::send_response(sock, tx_buf, &request_id, &response, send_body)?;
+            return Ok(keep_alive);
+        }
+
+        if !matches!(request.method(), HttpMethod::Get | HttpMethod::Head) {
+            let status = HttpStatus::MethodNotAllowed;
+            Self::send_status(sock, tx_buf, &request_id, status, send_body)?;
+            return Ok(false);
+        }
 
         let path = match Self::validate_request_path(&request) {
             Ok(p) => p,
             Err(status) => {
-                Self::send_status(sock, status)?;
-                return Ok(());
+                Self::send_status(sock, tx_buf, &request_id, status, send_body)?;
+                return Ok(false);
+            }
+        };
+
+        let (path, negotiated) = match self.negotiate(&path, request.header("Accept")) {
+            Ok(chosen) => chosen,
+            Err(status) => {
+                Self::send_status(sock, tx_buf, &request_id, status, send_body)?;
+                return Ok(false);
             }
         };
 
         let full_path = self.build_full_path(&path);
-        let response = match Self::read_file(&full_path) {
-            Ok(content) => HttpResponse::from_file_content(&path, content),
-            Err(err) => HttpResponse::error(Self::file_error_status(err)),
+        let is_dir = fs::metadata(full_path.as_str()).is_ok_and(|m| m.is_dir());
+        let range = request.header("Range").and_then(parse_range);
+        let mut response = match (Self::open_file(&full_path), range) {
+            (Ok(_), _) if is_dir && self.dir_listing => {
+                match Self::list_directory(&full_path, &path) {
+                    Ok(html) => {
+                        HttpResponse::with_body(HttpStatus::Ok, "text/html", html.into_bytes())
+                    }
+                    Err(err) => HttpResponse::error(Self::file_error_status(err)),
+                }
+            }
+            (Ok(_), _) if is_dir => HttpResponse::error(HttpStatus::NotFound),
+            // If-None-Match is evaluated before Range (RFC 7232 6)
+            (Ok((_, _, ref etag)), _) if request.etag_matches(etag) => {
+                HttpResponse::not_modified(etag)
+            }
+            (Ok((file, size, etag)), Some(range)) => {
+                Self::range_response(&path, file, size, &etag, range)
+            }
+            // HTTP/1.0 clients cannot decode chunked bodies
+            (Ok((file, size, etag)), None)
+                if send_body
+                    && size > CHUNKED_THRESHOLD
+                    && request.version() == HttpVersion::Http11 =>
+            {
+                return Self::send_file_chunked(sock, &request_id, &path, file, &etag, keep_alive);
+            }
+            (Ok((file, size, etag)), None) => match Self::read_file(file, size) {
+                Ok(content) => {
+                    let mut response = HttpResponse::from_file_content(&path, content);
+                    response.set_etag(&etag);
+                    Self::set_disposition(&mut response, &path);
+                    response
+                }
+                Err(err) => HttpResponse::error(Self::file_error_status(err)),
+            },
+            (Err(err), _) => HttpResponse::error(Self::file_error_status(err)),
         };
+        if negotiated {
+            response.add_header("Vary".to_string(), "Accept".to_string());
+        }
+        response.echo_request_id(&request_id);
+        response.set_keep_alive(keep_alive);
 
-        Self::send_response(sock, &response)
+        Self::send_response(sock, tx_buf, &request_id, &response, send_body)?;
+        Ok(keep_alive)
     }
 
-    fn read_request_headers(sock: usize) -> Result<Vec<u8>, String> {
-        let mut buffer = Vec::with_capacity(REQUEST_BUFFER_SIZE);
+    fn next_request_id() -> String {
+        let id = REQUEST_ID_COUNTER.fetch_add(1, Ordering::Relaxed);
+        alloc::format!("{:016x}", id)
+    }
+
+    fn set_disposition(response: &mut HttpResponse, path: &str) {
+        match mime_type_from_path(path) {
+            "application/octet-stream" => {
+                let name = Path::new(path).file_name().unwrap_or(path);
+                response.set_attachment(name);
+            }
+            "text/html" => {
+                response.set_inline();
+            }
+            mime if mime.starts_with("image/") => {
+                response.set_inline();
+            }
+            _ => {}
+        }
+    }
+
+    fn read_request_headers(sock: usize, pending: &mut Vec<u8>) -> Result<Vec<u8>, String> {
+        let mut buffer = core::mem::take(pending);
+        buffer.reserve(REQUEST_BUFFER_SIZE.saturating_sub(buffer.len()));
         let mut tmp = [0u8; 256];
 
-        loop {
+        while !Self::has_header_end(&buffer) {
+            if buffer.len() >= REQUEST_BUFFER_SIZE {
+                return Err(String::from("request too large"));
+            }
             match recv(sock, &mut tmp) {
                 Ok(0) => {
                     return Err(String::from("connection closed before complete request"));
                 }
-                Ok(n) => {
-                    buffer.extend_from_slice(&tmp[..n]);
-
-                    if Self::has_header_end(&buffer) {
-                        break;
-                    }
-
-                    if buffer.len() >= REQUEST_BUFFER_SIZE {
-                        return Err(String::from("request too large"));
-                    }
-                }
+                Ok(n) => buffer.extend_from_slice(&tmp[..n]),
                 Err(_) => {
                     return Err(String::from("recv failed"));
                 }
@@ -148,17 +462,46 @@ impl Server {
     }
 
     fn has_header_end(data: &[u8]) -> bool {
-        if data.len() < 4 {
-            return false;
+        Self::header_end(data).is_some()
+    }
+
+    fn header_end(data: &[u8]) -> Option<usize> {
+        data.windows(4)
+            .position(|window| window == b"\r\n\r\n")
+            .map(|pos| pos + 4)
+    }
+
+    // The header read may already have pulled in part of the body; keep
+    // reading until Content-Length bytes follow the blank line, then parse
+    // again so the request carries the whole body. Anything after that is
+    // the start of the next request and goes back into `pending`.
+    fn read_request(
+        sock: usize,
+        data: &mut Vec<u8>,
+        pending: &mut Vec<u8>,
+    ) -> Result<HttpRequest, HttpStatus> {
+        let request = Self::parse_request(data)?;
+        let len = request.content_length().unwrap_or(0);
+        let start = Self::header_end(data).ok_or(HttpStatus::BadRequest)?;
+        if data.len() >= start + len {
+            *pending = data.split_off(start + len);
+            return Ok(request);
+        }
+
+        if start + len > REQUEST_BUFFER_SIZE {
+            return Err(HttpStatus::PayloadTooLarge);
         }
 
-        for window in data.windows(4) {
-            if window == b"\r\n\r\n" {
-                return true;
+        let mut tmp = [0u8; 256];
+        while data.len() < start + len {
+            match recv(sock, &mut tmp) {
+                Ok(0) | Err(_) => return Err(HttpStatus::BadRequest),
+                Ok(n) => data.extend_from_slice(&tmp[..n]),
             }
         }
 
-        false
+        *pending = data.split_off(start + len);
+        Self::parse_request(data)
     }
 
     fn parse_request(data: &[u8]) -> Result<HttpRequest, HttpStatus> {
@@ -169,17 +512,39 @@ impl Server {
         HttpResponse::validate_path(request.uri())
     }
 
-    fn send_status(sock: usize, status: HttpStatus) -> Result<(), String> {
-        let response = HttpResponse::error(status);
-        Self::send_response(sock, &response)
+    fn send_status(
+        sock: usize,
+        buf: &mut Vec<u8>,
+        request_id: &str,
+        status: HttpStatus,
+        send_body: bool,
+    ) -> Result<(), String> {
+        let mut response = HttpResponse::error(status);
+        response.echo_request_id(request_id);
+        Self::send_response(sock, buf, request_id, &response, send_body)
     }
 
-    fn send_response(sock: usize, response: &HttpResponse) -> Result<(), String> {
-        let bytes = response.to_bytes();
+    // Without the body, Content-Length still gives the size a GET would
+    // have returned.
+    fn send_response(
+        sock: usize,
+        buf: &mut Vec<u8>,
+        request_id: &str,
+        response: &HttpResponse,
+        send_body: bool,
+    ) -> Result<(), String> {
+        buf.clear();
+        buf.reserve(response.estimated_size());
+        if send_body {
+            response.to_bytes_into(buf);
+        } else {
+            response.head_bytes_into(buf);
+        }
+        let bytes = &buf[..];
         let total = bytes.len();
         let mut sent = 0;
 
-        println!("[httpd] sending {} bytes", total);
+        println!("[httpd] [{}] sending {} bytes", request_id, total);
 
         while sent < bytes.len() {
             match send(sock, &bytes[sent..]) {
@@ -188,22 +553,72 @@ impl Server {
                 }
                 Ok(n) => {
                     sent += n;
-                    println!("[httpd] sent {} bytes (total: {}/{})", n, sent, total);
+                    println!(
+                        "[httpd] [{}] sent {} bytes (total: {}/{})",
+                        request_id, n, sent, total
+                    );
                 }
                 Err(Error::BufferFull) | Err(Error::WouldBlock) => {
                     let _ = sys::sleep(SEND_RETRY_TICKS);
                 }
                 Err(_) => {
-                    println!("[httpd] send failed at {}/{}", sent, total);
+                    println!("[httpd] [{}] send failed at {}/{}", request_id, sent, total);
                     return Err(String::from("send failed"));
                 }
             }
         }
 
-        println!("[httpd] send complete");
+        println!("[httpd] [{}] send complete", request_id);
         Ok(())
     }
 
+    // Files sharing the requested name's stem (index.html, index.json, ...)
+    // are alternative representations; the one whose type the client
+    // weighs highest is served, preferring the name asked for on a tie.
+    // The flag reports whether there was a choice to make.
+    fn negotiate(&self, path: &str, accept: Option<&str>) -> Result<(String, bool), HttpStatus> {
+        let unchanged = Ok((path.to_string(), false));
+        let Some(accept) = accept else {
+            return unchanged;
+        };
+        let (dir, name) = match path.rfind('/') {
+            Some(i) => (&path[..i + 1], &path[i + 1..]),
+            None => ("", path),
+        };
+        if name.is_empty() {
+            return unchanged;
+        }
+        let stem = Path::new(name).file_stem().unwrap_or(name);
+        let Ok(entries) = fs::read_dir(self.build_full_path(dir).as_str()) else {
+            return unchanged;
+        };
+        let mut candidates: Vec<String> = entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.file_name())
+            .filter(|file| file == name || Path::new(file).file_stem() == Some(stem))
+            .collect();
+        if candidates.len() < 2 {
+            return unchanged;
+        }
+        candidates.sort();
+
+        let accepted = parse_accept_header(accept);
+        let mut best: Option<(f32, bool, &str)> = None;
+        for file in &candidates {
+            let q = accept_quality(&accepted, mime_type_from_path(file));
+            let exact = file == name;
+            if best.is_none_or(|(best_q, best_exact, _)| {
+                q > best_q || (q == best_q && exact && !best_exact)
+            }) {
+                best = Some((q, exact, file));
+            }
+        }
+        match best {
+            Some((q, _, file)) if q > 0.0 => Ok((alloc::format!("{}{}", dir, file), true)),
+            _ => Err(HttpStatus::NotAcceptable),
+        }
+    }
+
     fn build_full_path(&self, path: &str) -> String {
         if self.doc_root.ends_with('/') {
             alloc::format!("{}{}", self.doc_root, path)
@@ -212,12 +627,16 @@ impl Server {
         }
     }
 
-    fn read_file(path: &str) -> Result<Vec<u8>, FileError> {
-        let mut file = fs::File::open(path).map_err(|_| FileError::NotFound)?;
-
+    // The ETag is built from the inode number and size, so every way of
+    // sending the file can emit it without reading the content first.
+    fn open_file(path: &str) -> Result<(fs::File, usize, String), FileError> {
+        let file = fs::File::open(path).map_err(|_| FileError::NotFound)?;
         let metadata = file.metadata().map_err(|_| FileError::ReadError)?;
-        let file_size = metadata.len();
+        let etag = alloc::format!("\"{:x}-{:x}\"", metadata.inum(), metadata.len());
+        Ok((file, metadata.len(), etag))
+    }
 
+    fn read_file(mut file: fs::File, file_size: usize) -> Result<Vec<u8>, FileError> {
         let mut content = Vec::with_capacity(file_size);
         let mut buffer = [0u8; 512];
 
@@ -232,6 +651,131 @@ impl Server {
         Ok(content)
     }
 
+    fn range_response(
+        path: &str,
+        file: fs::File,
+        size: usize,
+        etag: &str,
+        range: ByteRange,
+    ) -> HttpResponse {
+        let Some((start, end)) = range.resolve(size) else {
+            let mut response = HttpResponse::error(HttpStatus::RangeNotSatisfiable);
+            response.add_header(
+                "Content-Range".to_string(),
+                alloc::format!("bytes */{}", size),
+            );
+            return response;
+        };
+
+        match Self::read_range(file, start, end - start + 1) {
+            Ok(content) => {
+                let mut response = HttpResponse::with_body(
+                    HttpStatus::PartialContent,
+                    mime_type_from_path(path),
+                    content,
+                );
+                response.add_header(
+                    "Content-Range".to_string(),
+                    alloc::format!("bytes {}-{}/{}", start, end, size),
+                );
+                response.set_etag(etag);
+                response
+            }
+            Err(err) => HttpResponse::error(Self::file_error_status(err)),
+        }
+    }
+
+    fn read_range(mut file: fs::File, start: usize, len: usize) -> Result<Vec<u8>, FileError> {
+        file.seek(SeekFrom::Start(start))
+            .map_err(|_| FileError::ReadError)?;
+
+        let mut content = vec![0u8; len];
+        let mut filled = 0;
+        while filled < len {
+            match io::Read::read(&mut file, &mut content[filled..]) {
+                Ok(0) => break,
+                Ok(n) => filled += n,
+                Err(_) => return Err(FileError::ReadError),
+            }
+        }
+        content.truncate(filled);
+
+        Ok(content)
+    }
+
+    // Links are absolute so they resolve the same whether or not the
+    // request path ended in '/'.
+    fn list_directory(full_path: &str, path: &str) -> Result<String, FileError> {
+        let base = path.trim_end_matches('/');
+        let title = alloc::format!("/{}", base);
+        let mut names = Vec::new();
+        for entry in fs::read_dir(full_path).map_err(|_| FileError::ReadError)? {
+            let entry = entry.map_err(|_| FileError::ReadError)?;
+            let is_dir = entry.metadata().is_ok_and(|m| m.is_dir());
+            names.push((entry.file_name(), is_dir));
+        }
+        names.sort();
+
+        let mut html = alloc::format!(
+            "<!DOCTYPE html>\n<html>\n<head><title>Index of {0}</title></head>\n\
+             <body>\n<h1>Index of {0}</h1>\n<ul>\n",
+            escape_html(&title)
+        );
+        for (name, is_dir) in names {
+            let suffix = if is_dir { "/" } else { "" };
+            let href = if base.is_empty() {
+                alloc::format!("/{}{}", name, suffix)
+            } else {
+                alloc::format!("/{}/{}{}", base, name, suffix)
+            };
+            html.push_str(&alloc::format!(
+                "<li><a href=\"{}\">{}{}</a></li>\n",
+                escape_html(&href),
+                escape_html(&name),
+                suffix
+            ));
+        }
+        html.push_str("</ul>\n</body>\n</html>\n");
+        Ok(html)
+    }
+
+    // The file is sent as it is read, so the status line goes out before
+    // the whole body is known to be readable; a read error just drops the
+    // connection.
+    fn send_file_chunked(
+        sock: usize,
+        request_id: &str,
+        path: &str,
+        mut file: fs::File,
+        etag: &str,
+        keep_alive: bool,
+    ) -> Result<bool, String> {
+        let mut response = HttpResponse::streamed(HttpStatus::Ok, mime_type_from_path(path));
+        response.set_etag(etag);
+        Self::set_disposition(&mut response, path);
+        response.echo_request_id(request_id);
+        response.set_keep_alive(keep_alive);
+
+        println!("[httpd] [{}] streaming {} chunked", request_id, path);
+
+        let send_failed = |_| String::from("send failed");
+        let mut writer = response
+            .write_chunked(SocketWriter(sock))
+            .map_err(send_failed)?;
+        let mut buffer = vec![0u8; STREAM_CHUNK_SIZE];
+        loop {
+            match io::Read::read(&mut file, &mut buffer) {
+                Ok(0) => break,
+                Ok(n) => writer.write_chunk(&buffer[..n]).map_err(send_failed)?,
+                Err(_) => return Err(String::from("read failed")),
+            }
+        }
+        writer.finish().map_err(send_failed)?;
+
+        println!("[httpd] [{}] send complete", request_id);
+        Ok(keep_alive)
+    }
+
     fn file_error_status(err: FileError) -> HttpStatus {
         match err {
             FileError::NotFound => HttpStatus::NotFound,
@@ -240,8 +784,71 @@ impl Server {
     }
 }
 
+#[derive(Debug, Clone, Copy)]
+enum ByteRange {
+    // bytes=<start>- or bytes=<start>-<end>
+    From(usize, Option<usize>),
+    // bytes=-<len>: the last len bytes
+    Suffix(usize),
+}
+
+impl ByteRange {
+    // Inclusive first and last byte, or None when nothing of the file is
+    // covered.
+    fn resolve(self, size: usize) -> Option<(usize, usize)> {
+        let last = size.checked_sub(1)?;
+        match self {
+            ByteRange::From(start, end) => {
+                let end = end.unwrap_or(last).min(last);
+                (start <= end).then_some((start, end))
+            }
+            ByteRange::Suffix(0) => None,
+            ByteRange::Suffix(len) => Some((size - len.min(size), last)),
+        }
+    }
+}
+
+// Only a single byte range is served. Any other Range value is ignored and
+// the whole file sent, which RFC 9110 permits.
+fn parse_range(value: &str) -> Option<ByteRange> {
+    let spec = value.trim().strip_prefix("bytes=")?;
+    if spec.contains(',') {
+        return None;
+    }
+    let (start, end) = spec.split_once('-')?;
+    let (start, end) = (start.trim(), end.trim());
+    match (start.is_empty(), end.is_empty()) {
+        (false, true) => Some(ByteRange::From(start.parse().ok()?, None)),
+        (false, false) => Some(ByteRange::From(
+            start.parse().ok()?,
+            Some(end.parse().ok()?),
+        )),
+        (true, false) => Some(ByteRange::Suffix(end.parse().ok()?)),
+        (true, true) => None,
+    }
+}
+
+fn escape_html(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
 fn print_usage() {
-    println!("[httpd] usage: httpd [port] <document_root>");
+    println!(
+        "[httpd] usage: httpd [--mime ext:type]... [--auth user:password] [--no-dirlisting] [port] <document_root>"
+    );
+    println!("[httpd]   --mime: serve files ending in .ext as the given content type");
+    println!("[httpd]   --auth: require HTTP Basic credentials on every request");
+    println!("[httpd]   --no-dirlisting: answer 404 instead of listing directories");
     println!("[httpd]   port: listen port (default: 8080)");
     println!("[httpd]   document_root: path to serve files from");
 }
@@ -254,13 +861,35 @@ fn main() {
             print_usage();
             return;
         }
+        Err(ArgsError::InvalidMime(spec)) => {
+            println!("[httpd] error: invalid --mime value '{}'", spec);
+            print_usage();
+            return;
+        }
+        Err(ArgsError::InvalidAuth(spec)) => {
+            println!("[httpd] error: invalid --auth value '{}'", spec);
+            print_usage();
+            return;
+        }
     };
 
+    for (ext, mime) in args.mime_types {
+        println!("[httpd] mime: .{} -> {}", ext, mime);
+        register_mime_type(ext, mime);
+    }
+
     println!("[httpd] octox-httpd/0.1");
     println!("[httpd] document root: {}", args.doc_root);
     println!("[httpd] listening on port {}", args.port);
 
-    let server = Server::new(args.port, args.doc_root);
+    let mut server = Server::new(args.port, args.doc_root);
+    server.register_api_prefix(KV_API_PREFIX, &KV_STORE);
+    println!("[httpd] api: {} (in-memory key-value store)", KV_API_PREFIX);
+    server.set_dir_listing(args.dir_listing);
+    if let Some((user, password)) = args.auth {
+        server.require_basic_auth(user, password);
+        println!("[httpd] basic auth: user '{}'", user);
+    }
     if let Err(e) = server.run() {
         println!("[httpd] server error: {}", e);
     }
@@ -2,16 +2,20 @@
 extern crate alloc;
 
 use alloc::string::String;
-use args::{Error, Mode};
+use args::{Error, Mode, Options};
+use core::fmt::Write as _;
 use ulib::io::{Read, Write};
 use ulib::stdio::{stdin, stdout};
-use ulib::{accept, close, connect, env, listen, print, println, recv, send, socket, sys};
+use ulib::{
+    accept, close, connect, env, listen, print, println, recv, send, shutdown_write, socket, sys,
+};
 
 const COLOR_RESET: &str = "\x1b[0m";
 const COLOR_RED: &str = "\x1b[31m";
 const COLOR_GREEN: &str = "\x1b[32m";
 const COLOR_CYAN: &str = "\x1b[36m";
 const IO_BUF_SIZE: usize = 1024;
+const HEXDUMP_WIDTH: usize = 16;
 
 mod args {
     use alloc::string::String;
@@ -23,28 +27,45 @@ mod args {
         Connect { addr: String, port: u16 },
     }
 
+    pub struct Options {
+        pub hexdump: bool,
+        pub count: Option<usize>,
+    }
+
+    pub struct Config {
+        pub mode: Mode,
+        pub options: Options,
+    }
+
     pub enum Error {
         Usage,
         UnknownArg(&'static str),
         InvalidPort(&'static str),
+        InvalidCount(&'static str),
     }
 
-    pub fn parse() -> Result<Mode, Error> {
+    pub fn parse() -> Result<Config, Error> {
         let mut args = env::args();
         let _prog = args.next();
 
         let mut listen_mode = false;
+        let mut options = Options {
+            hexdump: false,
+            count: None,
+        };
         let mut positional: Vec<&'static str> = Vec::new();
 
-        for arg in args {
-            if arg == "-l" {
-                listen_mode = true;
-                continue;
-            }
-            if arg.starts_with('-') {
-                return Err(Error::UnknownArg(arg));
+        while let Some(arg) = args.next() {
+            match arg {
+                "-l" => listen_mode = true,
+                "-x" => options.hexdump = true,
+                "--count" => {
+                    let value = args.next().ok_or(Error::Usage)?;
+                    options.count = Some(parse_count(value)?);
+                }
+                _ if arg.starts_with('-') => return Err(Error::UnknownArg(arg)),
+                _ => positional.push(arg),
             }
-            positional.push(arg);
         }
 
         if listen_mode {
@@ -52,7 +73,10 @@ mod args {
                 return Err(Error::Usage);
             }
             let port = parse_port(positional[0])?;
-            return Ok(Mode::Listen { port });
+            return Ok(Config {
+                mode: Mode::Listen { port },
+                options,
+            });
         }
 
         if positional.len() != 2 {
@@ -62,22 +86,64 @@ mod args {
         let addr = String::from(positional[0]);
         let port = parse_port(positional[1])?;
 
-        Ok(Mode::Connect { addr, port })
+        Ok(Config {
+            mode: Mode::Connect { addr, port },
+            options,
+        })
     }
 
     fn parse_port(arg: &'static str) -> Result<u16, Error> {
         arg.parse::<u16>().map_err(|_| Error::InvalidPort(arg))
     }
+
+    fn parse_count(arg: &'static str) -> Result<usize, Error> {
+        match arg.parse::<usize>() {
+            Ok(n) if n > 0 => Ok(n),
+            _ => Err(Error::InvalidCount(arg)),
+        }
+    }
+}
+
+/// Formats `data` as `offset  hex words  ascii` lines, 16 bytes per line.
+fn hexdump(data: &[u8], offset: usize) -> String {
+    let mut out = String::new();
+    for (line, chunk) in data.chunks(HEXDUMP_WIDTH).enumerate() {
+        let _ = write!(out, "{:04x}  ", offset + line * HEXDUMP_WIDTH);
+        for i in 0..HEXDUMP_WIDTH {
+            match chunk.get(i) {
+                Some(b) => {
+                    let _ = write!(out, "{:02x}", b);
+                }
+                None => out.push_str("  "),
+            }
+            if i == HEXDUMP_WIDTH / 2 - 1 {
+                out.push_str("  ");
+            } else if i % 2 == 1 && i != HEXDUMP_WIDTH - 1 {
+                out.push(' ');
+            }
+        }
+        out.push_str("  ");
+        for &b in chunk {
+            out.push(if b.is_ascii_graphic() || b == b' ' {
+                b as char
+            } else {
+                '.'
+            });
+        }
+        out.push('\n');
+    }
+    out
 }
 
 struct Connection {
     sock: usize,
+    options: Options,
 }
 
 impl Connection {
     const CHILD_PROCESS: usize = 0;
 
-    fn listen(port: u16) -> Result<Self, String> {
+    fn listen(port: u16, options: Options) -> Result<Self, String> {
         let sock = socket().map_err(|e| alloc::format!("failed to create socket: {:?}", e))?;
 
         println!("[nc] listening on port {}", port);
@@ -89,10 +155,13 @@ impl Connection {
 
         let _ = close(sock);
 
-        Ok(Self { sock: conn_sock })
+        Ok(Self {
+            sock: conn_sock,
+            options,
+        })
     }
 
-    fn connect(addr: String, port: u16) -> Result<Self, String> {
+    fn connect(addr: String, port: u16, options: Options) -> Result<Self, String> {
         let sock = socket().map_err(|e| alloc::format!("failed to create socket: {:?}", e))?;
 
         println!("[nc] connecting to {}:{}", addr, port);
@@ -102,7 +171,7 @@ impl Connection {
             .map_err(|e| alloc::format!("connect failed: {:?}", e))?;
         println!("{}[nc] connected{}", COLOR_GREEN, COLOR_RESET);
 
-        Ok(Self { sock })
+        Ok(Self { sock, options })
     }
 
     fn start(self) {
@@ -116,16 +185,22 @@ impl Connection {
         };
 
         if pid == Self::CHILD_PROCESS {
-            self.receive_loop();
+            self.send_loop();
         } else {
-            self.send_loop(pid);
+            self.receive_loop(pid);
         }
     }
 
-    fn receive_loop(&self) {
+    fn receive_loop(&self, child_pid: usize) {
         let mut buf = [0u8; IO_BUF_SIZE];
+        let mut received = 0;
         loop {
-            match recv(self.sock, &mut buf) {
+            let limit = match self.options.count {
+                Some(count) if received >= count => break,
+                Some(count) => (count - received).min(IO_BUF_SIZE),
+                None => IO_BUF_SIZE,
+            };
+            match recv(self.sock, &mut buf[..limit]) {
                 Ok(0) => {
                     println!("{}[nc] connection closed{}", COLOR_GREEN, COLOR_RESET);
                     break;
@@ -133,18 +208,27 @@ impl Connection {
                 Ok(n) => {
                     let mut out = stdout();
                     let _ = out.write(COLOR_CYAN.as_bytes());
-                    let _ = out.write(&buf[..n]);
+                    if self.options.hexdump {
+                        let _ = out.write(hexdump(&buf[..n], received).as_bytes());
+                    } else {
+                        let _ = out.write(&buf[..n]);
+                    }
                     let _ = out.write(COLOR_RESET.as_bytes());
+                    received += n;
                 }
                 Err(_) => {
                     break;
                 }
             }
         }
-        sys::exit(0);
+
+        let _ = close(self.sock);
+        let _ = sys::kill(child_pid);
+        let mut status = 0;
+        let _ = sys::wait(&mut status);
     }
 
-    fn send_loop(&self, child_pid: usize) {
+    fn send_loop(&self) {
         let mut buf = [0u8; IO_BUF_SIZE];
         let mut input = stdin();
 
@@ -160,21 +244,21 @@ impl Connection {
             }
         }
 
-        let _ = close(self.sock);
-        let _ = sys::kill(child_pid);
-        let mut status = 0;
-        let _ = sys::wait(&mut status);
+        // Only our side is done: the parent keeps reading until the peer
+        // closes too.
+        let _ = shutdown_write(self.sock);
+        sys::exit(0);
     }
 }
 
 fn print_usage() {
-    println!("usage: nc -l <port>");
-    println!("       nc <host> <port>");
+    println!("usage: nc [-x] [--count N] -l <port>");
+    println!("       nc [-x] [--count N] <host> <port>");
 }
 
 fn main() {
-    let mode = match args::parse() {
-        Ok(mode) => mode,
+    let config = match args::parse() {
+        Ok(config) => config,
         Err(Error::Usage) => {
             println!("{}error: invalid arguments{}", COLOR_RED, COLOR_RESET);
             print_usage();
@@ -190,11 +274,16 @@ fn main() {
             print_usage();
             return;
         }
+        Err(Error::InvalidCount(arg)) => {
+            println!("{}error: invalid count: {}{}", COLOR_RED, arg, COLOR_RESET);
+            print_usage();
+            return;
+        }
     };
 
-    let conn = match mode {
-        Mode::Listen { port } => Connection::listen(port),
-        Mode::Connect { addr, port } => Connection::connect(addr, port),
+    let conn = match config.mode {
+        Mode::Listen { port } => Connection::listen(port, config.options),
+        Mode::Connect { addr, port } => Connection::connect(addr, port, config.options),
     };
 
     match conn {
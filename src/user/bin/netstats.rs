@@ -0,0 +1,33 @@
+#![no_std]
+
+use ulib::sys::Error;
+use ulib::{net_device_stats_by_index, print, println};
+
+fn main() {
+    println!(
+        "{:<8} {:>10} {:>12} {:>8} {:>10} {:>12} {:>8}",
+        "iface", "rx_pkts", "rx_bytes", "rx_errs", "tx_pkts", "tx_bytes", "tx_errs"
+    );
+    for index in 0.. {
+        let stats = match net_device_stats_by_index(index) {
+            Ok(stats) => stats,
+            Err(Error::DeviceNotFound) => break,
+            Err(e) => {
+                println!("netstats: {:?}", e);
+                return;
+            }
+        };
+        let len = stats.name.iter().position(|&b| b == 0).unwrap_or(16);
+        let name = core::str::from_utf8(&stats.name[..len]).unwrap_or("?");
+        println!(
+            "{:<8} {:>10} {:>12} {:>8} {:>10} {:>12} {:>8}",
+            name,
+            stats.rx_packets,
+            stats.rx_bytes,
+            stats.rx_errors,
+            stats.tx_packets,
+            stats.tx_bytes,
+            stats.tx_errors
+        );
+    }
+}
@@ -1,43 +1,289 @@
 #![no_std]
 extern crate alloc;
 
-use ulib::{dns_resolve, env, print, println};
+use alloc::string::String;
+use alloc::vec::Vec;
+use args::{Error, Query, Server};
+use ulib::dns::{self, Record, RecordType};
+use ulib::{dns_query_with_server, dns_search_with_server, print, println, sys};
+
+mod args {
+    use ulib::dns::{parse_ipv4, RecordType};
+    use ulib::env;
+
+    pub enum Query {
+        Lookup {
+            domain: &'static str,
+            rtype: RecordType,
+        },
+        Address {
+            domain: &'static str,
+            ipv4: bool,
+            ipv6: bool,
+        },
+        Reverse {
+            addr: u32,
+        },
+    }
+
+    // An empty address or a zero port leaves the kernel default in place.
+    #[derive(Default)]
+    pub struct Server {
+        pub addr: &'static str,
+        pub port: u16,
+    }
+
+    pub enum Error {
+        Usage,
+        UnknownType(&'static str),
+        InvalidAddress(&'static str),
+        InvalidPort(&'static str),
+    }
+
+    pub fn parse() -> Result<(Query, Server), Error> {
+        let mut args = env::args();
+        let _prog = args.next();
+
+        let mut rtype = None;
+        let mut ipv4 = true;
+        let mut ipv6 = true;
+        let mut domain = None;
+        let mut reverse = None;
+        let mut server = Server::default();
+
+        while let Some(arg) = args.next() {
+            match arg {
+                "-ns" => {
+                    let ip = args.next().ok_or(Error::Usage)?;
+                    parse_ipv4(ip).ok_or(Error::InvalidAddress(ip))?;
+                    server.addr = ip;
+                }
+                "-p" => {
+                    let port = args.next().ok_or(Error::Usage)?;
+                    server.port = match port.parse::<u16>() {
+                        Ok(p) if p != 0 => p,
+                        _ => return Err(Error::InvalidPort(port)),
+                    };
+                }
+                "-t" => {
+                    let name = args.next().ok_or(Error::Usage)?;
+                    rtype = Some(RecordType::from_name(name).ok_or(Error::UnknownType(name))?);
+                }
+                "-4" => ipv6 = false,
+                "-6" => ipv4 = false,
+                "-x" => {
+                    let ip = args.next().ok_or(Error::Usage)?;
+                    let addr = parse_ipv4(ip).ok_or(Error::InvalidAddress(ip))?;
+                    reverse = Some(addr);
+                }
+                _ if arg.starts_with('-') => return Err(Error::Usage),
+                _ => domain = Some(arg),
+            }
+        }
+
+        if let Some(addr) = reverse {
+            return Ok((Query::Reverse { addr }, server));
+        }
+        let domain = domain.ok_or(Error::Usage)?;
+        let query = match rtype {
+            Some(rtype) => Query::Lookup { domain, rtype },
+            None if ipv4 || ipv6 => Query::Address { domain, ipv4, ipv6 },
+            None => return Err(Error::Usage),
+        };
+        Ok((query, server))
+    }
+}
 
 fn main() {
-    let Some(domain) = parse_domain() else {
-        print_usage();
-        return;
+    let (query, server) = match args::parse() {
+        Ok(parsed) => parsed,
+        Err(Error::UnknownType(name)) => {
+            println!("nslookup: unknown record type: {}", name);
+            print_usage();
+            return;
+        }
+        Err(Error::InvalidAddress(ip)) => {
+            println!("nslookup: invalid address: {}", ip);
+            print_usage();
+            return;
+        }
+        Err(Error::InvalidPort(port)) => {
+            println!("nslookup: invalid port: {}", port);
+            print_usage();
+            return;
+        }
+        Err(Error::Usage) => {
+            print_usage();
+            return;
+        }
     };
 
+    if !server.addr.is_empty() || server.port != 0 {
+        let addr = if server.addr.is_empty() {
+            "default"
+        } else {
+            server.addr
+        };
+        match server.port {
+            0 => println!("Server:  {}", addr),
+            port => println!("Server:  {}#{}", addr, port),
+        }
+    }
+
+    match query {
+        Query::Lookup { domain, rtype } => lookup(&server, domain, rtype),
+        Query::Address { domain, ipv4, ipv6 } => addresses(&server, domain, ipv4, ipv6),
+        Query::Reverse { addr } => reverse(&server, addr),
+    }
+}
+
+fn query(server: &Server, domain: &str, rtype: RecordType) -> sys::Result<Vec<Record>> {
+    dns_query_with_server(domain, rtype, server.addr, server.port)
+}
+
+// The kernel expands `domain` with its search list and reports the name
+// that answered.
+fn search(
+    server: &Server,
+    domain: &str,
+    rtypes: &[RecordType],
+) -> sys::Result<(String, Vec<Record>)> {
+    dns_search_with_server(domain, rtypes, server.addr, server.port)
+}
+
+fn lookup(server: &Server, domain: &str, rtype: RecordType) {
     println!("Resolving: {}", domain);
 
-    let addr = match dns_resolve(domain) {
-        Ok(a) => a,
+    let result = search(server, domain, &[rtype]).map(|(name, records)| {
+        if rtype != RecordType::A {
+            return (name, records);
+        }
+        let records = records
+            .into_iter()
+            .filter(|r| matches!(r, Record::A(_)))
+            .collect();
+        (name, records)
+    });
+    let (name, records) = match result {
+        Ok(found) => found,
+        Err(sys::Error::NotFound) if rtype == RecordType::A => {
+            println!("DNS resolution failed: no A records");
+            return;
+        }
         Err(e) => {
             println!("DNS resolution failed: {:?}", e);
             return;
         }
     };
 
-    let (a, b, c, d) = split_ipv4(addr);
+    println!("");
+    println!("Name:    {}", name);
+    for record in records {
+        print_record(&record);
+    }
+}
+
+// A name with records of only one family answers the other query with no
+// records, which is not an error when both families were requested.
+fn addresses(server: &Server, domain: &str, ipv4: bool, ipv6: bool) {
+    println!("Resolving: {}", domain);
+
+    let families = [(ipv4, RecordType::A), (ipv6, RecordType::Aaaa)];
+    let rtypes: Vec<RecordType> = families
+        .into_iter()
+        .filter(|(enabled, _)| *enabled)
+        .map(|(_, rtype)| rtype)
+        .collect();
+    let result = search(server, domain, &rtypes).map(|(name, records)| {
+        let records = records
+            .into_iter()
+            .filter(|r| matches!(r, Record::A(_) | Record::Aaaa(_)))
+            .collect::<Vec<_>>();
+        (name, records)
+    });
+    let (name, records) = match result {
+        Ok(found) => found,
+        Err(sys::Error::NotFound) => {
+            println!("DNS resolution failed: no address records");
+            return;
+        }
+        Err(e) => {
+            println!("DNS resolution failed: {:?}", e);
+            return;
+        }
+    };
 
     println!("");
-    println!("Name:    {}", domain);
-    println!("Address: {}.{}.{}.{}", a, b, c, d);
+    println!("Name:    {}", name);
+    for record in records {
+        print_record(&record);
+    }
+}
+
+fn reverse(server: &Server, addr: u32) {
+    let (a, b, c, d) = split_ipv4(addr);
+    println!("Resolving: {}.{}.{}.{}", a, b, c, d);
+
+    let reverse_name = dns::reverse_name(addr);
+    let name = query(server, &reverse_name, RecordType::Ptr).and_then(|records| {
+        records
+            .into_iter()
+            .find_map(|r| match r {
+                Record::Ptr(name) => Some(name),
+                _ => None,
+            })
+            .ok_or(sys::Error::NotFound)
+    });
+    match name {
+        Ok(name) => {
+            println!("");
+            println!("{}", reverse_name);
+            println!("name = {}", name);
+        }
+        Err(e) => println!("DNS resolution failed: {:?}", e),
+    }
 }
 
-fn parse_domain() -> Option<&'static str> {
-    let mut args = env::args();
-    let _prog = args.next();
-    args.next()
+fn print_record(record: &Record) {
+    match record {
+        Record::A(addr) => {
+            let (a, b, c, d) = split_ipv4(*addr);
+            println!("Address: {}.{}.{}.{}", a, b, c, d);
+        }
+        Record::Aaaa(addr) => {
+            print!("IPv6 Address: ");
+            for (i, pair) in addr.chunks(2).enumerate() {
+                if i > 0 {
+                    print!(":");
+                }
+                print!("{:x}", u16::from_be_bytes([pair[0], pair[1]]));
+            }
+            println!("");
+        }
+        Record::Cname(name) => println!("canonical name = {}", name),
+        Record::Ptr(name) => println!("name = {}", name),
+        Record::Mx {
+            preference,
+            exchange,
+        } => println!("mail exchanger = {} {}", preference, exchange),
+        Record::Txt(strings) => {
+            for text in strings {
+                println!("text = \"{}\"", text);
+            }
+        }
+    }
 }
 
 fn print_usage() {
-    println!("Usage: nslookup <domain>");
+    println!("Usage: nslookup [-ns server] [-p port] [-4|-6] <domain>");
+    println!("       nslookup [-ns server] [-p port] -t A|AAAA|MX|TXT|PTR <domain>");
+    println!("       nslookup [-ns server] [-p port] -x <ip>");
     println!("Examples:");
     println!("  nslookup example.com");
-    println!("  nslookup google.com");
-    println!("  nslookup github.com");
+    println!("  nslookup -4 example.com");
+    println!("  nslookup -t MX gmail.com");
+    println!("  nslookup -x 8.8.8.8");
+    println!("  nslookup -ns 1.1.1.1 example.com");
 }
 
 fn split_ipv4(addr: u32) -> (u8, u8, u8, u8) {
@@ -2,28 +2,41 @@
 extern crate alloc;
 
 use alloc::{vec, vec::Vec};
+use core::net::Ipv4Addr;
+use ulib::sys::stat::IcmpEchoStats;
 use ulib::sys::Error;
-use ulib::{env, icmp_close, icmp_recvfrom, icmp_sendto, icmp_socket, print, println, sys};
+use ulib::{
+    env, print, println, raw_close, raw_echo_stats, raw_recv, raw_send_ttl, raw_socket, sys,
+};
 
 const PAYLOAD_SIZE: usize = 56;
 const ICMP_HEADER_LEN: usize = 8;
 const ICMP_ECHO_REQUEST: u8 = 8;
 const ICMP_ECHO_REPLY: u8 = 0;
+const ICMP_TIME_EXCEEDED: u8 = 11;
+const IPV4_MIN_HEADER_LEN: usize = 20;
+const IPPROTO_ICMP: u8 = 1;
 const REPLY_BUF_SIZE: usize = 256;
 const DEFAULT_COUNT: u16 = 2;
 const TIMEOUT_MS: u64 = 3000;
 const INTERVAL_MS: usize = 10;
+const DEFAULT_TTL: u8 = 64;
+
+struct Options {
+    dst: &'static str,
+    ttl: u8,
+}
 
 fn main() {
-    let Some(dst) = parse_dst() else {
+    let Some(Options { dst, ttl }) = parse_args() else {
         print_usage();
         return;
     };
 
-    let sock = match icmp_socket() {
+    let sock = match raw_socket(IPPROTO_ICMP) {
         Ok(sock) => sock,
         Err(e) => {
-            println!("icmp socket error: {:?}", e);
+            println!("raw socket error: {:?}", e);
             return;
         }
     };
@@ -33,13 +46,18 @@ fn main() {
     println!("PING {} ({}): {} data bytes", dst, dst, PAYLOAD_SIZE);
 
     for seq in 0..DEFAULT_COUNT {
-        if let Err(e) = ping_once(sock, dst, id, seq, &payload) {
+        if let Err(e) = ping_once(sock, dst, ttl, id, seq, &payload) {
             println!("recv error: {:?}", e);
         }
         sys::sleep(INTERVAL_MS).ok();
     }
 
-    let _ = icmp_close(sock);
+    // The kernel matched the replies to our requests; report its count.
+    match raw_echo_stats(sock) {
+        Ok(stats) => print_summary(dst, &stats),
+        Err(e) => println!("echo stats error: {:?}", e),
+    }
+    let _ = raw_close(sock);
 }
 
 fn build_payload() -> [u8; PAYLOAD_SIZE] {
@@ -59,67 +77,212 @@ fn build_echo_request(id: u16, seq: u16, payload: &[u8]) -> Vec<u8> {
     packet[4..6].copy_from_slice(&id.to_be_bytes());
     packet[6..8].copy_from_slice(&seq.to_be_bytes());
     packet[ICMP_HEADER_LEN..].copy_from_slice(payload);
+    let csum = checksum(&packet);
+    packet[2..4].copy_from_slice(&csum.to_be_bytes());
     packet
 }
 
-fn parse_echo_reply(buf: &[u8]) -> Option<(u16, u16, usize)> {
-    if buf.len() < ICMP_HEADER_LEN {
+// RFC 1071 one's complement sum; zero over a packet whose checksum is right.
+fn checksum(data: &[u8]) -> u16 {
+    let mut sum = data.chunks(2).fold(0u32, |sum, pair| {
+        let word = u16::from_be_bytes([pair[0], *pair.get(1).unwrap_or(&0)]);
+        sum + word as u32
+    });
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+struct Datagram<'a> {
+    src: u32,
+    ttl: u8,
+    icmp: &'a [u8],
+}
+
+// Splits what the raw socket returns into the IP fields ping prints and
+// the ICMP message behind them.
+fn parse_datagram(buf: &[u8]) -> Option<Datagram<'_>> {
+    if buf.len() < IPV4_MIN_HEADER_LEN {
         return None;
     }
-    if buf[0] != ICMP_ECHO_REPLY {
+    let ihl = ((buf[0] & 0x0f) as usize) * 4;
+    if ihl < IPV4_MIN_HEADER_LEN || buf.len() < ihl {
         return None;
     }
+    Some(Datagram {
+        src: u32::from_be_bytes([buf[12], buf[13], buf[14], buf[15]]),
+        ttl: buf[8],
+        icmp: &buf[ihl..],
+    })
+}
+
+enum Reply {
+    Echo {
+        id: u16,
+        seq: u16,
+        payload_len: usize,
+    },
+    TimeExceeded {
+        id: u16,
+        seq: u16,
+    },
+}
+
+fn echo_id_seq(buf: &[u8]) -> (u16, u16) {
     let id = u16::from_be_bytes([buf[4], buf[5]]);
     let seq = u16::from_be_bytes([buf[6], buf[7]]);
-    Some((id, seq, buf.len() - ICMP_HEADER_LEN))
+    (id, seq)
+}
+
+fn parse_reply(buf: &[u8]) -> Option<Reply> {
+    if buf.len() < ICMP_HEADER_LEN || checksum(buf) != 0 {
+        return None;
+    }
+    match buf[0] {
+        ICMP_ECHO_REPLY => {
+            let (id, seq) = echo_id_seq(buf);
+            Some(Reply::Echo {
+                id,
+                seq,
+                payload_len: buf.len() - ICMP_HEADER_LEN,
+            })
+        }
+        ICMP_TIME_EXCEEDED => {
+            let inner = &buf[ICMP_HEADER_LEN..];
+            if inner.len() < IPV4_MIN_HEADER_LEN {
+                return None;
+            }
+            let ihl = ((inner[0] & 0x0f) as usize) * 4;
+            if ihl < IPV4_MIN_HEADER_LEN || inner.len() < ihl + ICMP_HEADER_LEN {
+                return None;
+            }
+            let echo = &inner[ihl..];
+            if echo[0] != ICMP_ECHO_REQUEST {
+                return None;
+            }
+            let (id, seq) = echo_id_seq(echo);
+            Some(Reply::TimeExceeded { id, seq })
+        }
+        _ => None,
+    }
 }
 
 fn clock_us() -> u64 {
     sys::clocktime().unwrap_or(0) as u64
 }
 
-fn print_reply(dst: &str, seq: u16, payload_len: usize, start_us: u64) {
-    let end_us = clock_us();
-    let elapsed_us = end_us.saturating_sub(start_us);
+fn print_reply(dst: &str, seq: u16, ttl: u8, payload_len: usize, elapsed_us: u64) {
     let elapsed_ms = elapsed_us / 1000;
     let rem_us = elapsed_us % 1000;
     println!(
-        "{} bytes from {}: icmp_seq={} ttl=64 time={}.{:03} ms",
+        "{} bytes from {}: icmp_seq={} ttl={} time={}.{:03} ms",
         payload_len + ICMP_HEADER_LEN,
         dst,
         seq,
+        ttl,
         elapsed_ms,
         rem_us
     );
 }
 
+fn print_time_exceeded(src: u32, seq: u16) {
+    println!(
+        "From {} icmp_seq={} Time to live exceeded",
+        Ipv4Addr::from(src),
+        seq
+    );
+}
+
+fn format_ms(us: u64) -> (u64, u64) {
+    (us / 1000, us % 1000)
+}
+
+fn print_summary(dst: &str, stats: &IcmpEchoStats) {
+    let loss = if stats.sent == 0 {
+        0
+    } else {
+        (stats.sent - stats.received) * 100 / stats.sent
+    };
+    println!("--- {} ping statistics ---", dst);
+    println!(
+        "{} packets transmitted, {} packets received, {}% packet loss",
+        stats.sent, stats.received, loss
+    );
+    if stats.received == 0 {
+        return;
+    }
+    let (min_ms, min_us) = format_ms(stats.min_rtt_us);
+    let (avg_ms, avg_us) = format_ms(stats.sum_rtt_us / stats.received);
+    let (max_ms, max_us) = format_ms(stats.max_rtt_us);
+    println!(
+        "round-trip min/avg/max = {}.{:03}/{}.{:03}/{}.{:03} ms",
+        min_ms, min_us, avg_ms, avg_us, max_ms, max_us
+    );
+}
+
 fn print_usage() {
-    println!("usage: ping <ip address>");
+    println!("usage: ping [-t ttl] <ip address>");
 }
 
-fn parse_dst() -> Option<&'static str> {
+fn parse_args() -> Option<Options> {
     let mut args = env::args();
     let _prog = args.next();
-    args.next()
+
+    let mut ttl = DEFAULT_TTL;
+    let mut dst = None;
+    while let Some(arg) = args.next() {
+        match arg {
+            "-t" => {
+                ttl = args.next()?.parse::<u8>().ok().filter(|&t| t > 0)?;
+            }
+            _ if arg.starts_with('-') => return None,
+            _ => dst = Some(arg),
+        }
+    }
+
+    Some(Options { dst: dst?, ttl })
 }
 
-fn ping_once(sock: usize, dst: &str, id: u16, seq: u16, payload: &[u8]) -> Result<(), Error> {
+fn ping_once(
+    sock: usize,
+    dst: &str,
+    ttl: u8,
+    id: u16,
+    seq: u16,
+    payload: &[u8],
+) -> Result<(), Error> {
     let start_us = clock_us();
     let packet = build_echo_request(id, seq, payload);
-    icmp_sendto(sock, dst, &packet)?;
+    raw_send_ttl(sock, dst, &packet, ttl)?;
 
     let mut buf = [0u8; REPLY_BUF_SIZE];
-    let mut src: u32 = 0;
     let timeout_us = TIMEOUT_MS.saturating_mul(1000);
 
     loop {
-        match icmp_recvfrom(sock, &mut buf, &mut src) {
+        match raw_recv(sock, &mut buf) {
             Ok(n) => {
-                if let Some((reply_id, reply_seq, payload_len)) = parse_echo_reply(&buf[..n]) {
-                    if reply_id == id && reply_seq == seq {
-                        print_reply(dst, seq, payload_len, start_us);
+                let Some(datagram) = parse_datagram(&buf[..n]) else {
+                    continue;
+                };
+                match parse_reply(datagram.icmp) {
+                    Some(Reply::Echo {
+                        id: reply_id,
+                        seq: reply_seq,
+                        payload_len,
+                    }) if reply_id == id && reply_seq == seq => {
+                        let rtt_us = clock_us().saturating_sub(start_us);
+                        print_reply(dst, seq, datagram.ttl, payload_len, rtt_us);
+                        return Ok(());
+                    }
+                    Some(Reply::TimeExceeded {
+                        id: reply_id,
+                        seq: reply_seq,
+                    }) if reply_id == id && reply_seq == seq => {
+                        print_time_exceeded(datagram.src, seq);
                         return Ok(());
                     }
+                    _ => {}
                 }
             }
             Err(Error::WouldBlock) => {
@@ -0,0 +1,99 @@
+#![no_std]
+extern crate alloc;
+
+use alloc::vec::Vec;
+use ulib::http::{http_get_traced, parse_url};
+use ulib::io::Write;
+use ulib::stdio::stdout;
+use ulib::{env, eprint, eprintln, fs};
+
+const DEFAULT_MAX_REDIRECTS: u8 = 5;
+
+struct Options {
+    url: &'static str,
+    output: Option<&'static str>,
+    verbose: bool,
+    max_redirects: u8,
+}
+
+fn main() {
+    let Some(Options {
+        url,
+        output,
+        verbose,
+        max_redirects,
+    }) = parse_args()
+    else {
+        print_usage();
+        return;
+    };
+    let Some((host, port, path)) = parse_url(url) else {
+        eprintln!("wget: invalid url '{}'", url);
+        return;
+    };
+
+    let mut buf = Vec::new();
+    let on_redirect = |location: &str| {
+        if verbose {
+            eprintln!("wget: redirected to {}", location);
+        }
+    };
+    let response = match http_get_traced(host, port, path, max_redirects, &mut buf, on_redirect) {
+        Ok(response) => response,
+        Err(e) => {
+            eprintln!("wget: {}: {:?}", url, e);
+            return;
+        }
+    };
+
+    let status = response.status();
+    eprintln!(
+        "wget: {} {} ({} bytes)",
+        status.code(),
+        status.message(),
+        response.body().len()
+    );
+    if !status.is_success() {
+        return;
+    }
+
+    let written = match output {
+        Some(path) => fs::File::create(path).and_then(|mut f| f.write_all(response.body())),
+        None => stdout().write_all(response.body()),
+    };
+    if let Err(e) = written {
+        eprintln!("wget: write failed: {:?}", e);
+    }
+}
+
+fn parse_args() -> Option<Options> {
+    let mut args = env::args().skip(1);
+    let mut url = None;
+    let mut output = None;
+    let mut verbose = false;
+    let mut max_redirects = DEFAULT_MAX_REDIRECTS;
+
+    while let Some(arg) = args.next() {
+        match arg {
+            "-O" => output = Some(args.next()?),
+            "-v" => verbose = true,
+            "--max-redirect" => max_redirects = args.next()?.parse().ok()?,
+            _ if arg.starts_with('-') => return None,
+            _ if url.is_none() => url = Some(arg),
+            _ => return None,
+        }
+    }
+
+    Some(Options {
+        url: url?,
+        output,
+        verbose,
+        max_redirects,
+    })
+}
+
+fn print_usage() {
+    eprintln!("usage: wget [-v] [--max-redirect n] [-O file] <url>");
+    eprintln!("  -v: print each redirect followed");
+    eprintln!("  --max-redirect: redirects to follow (default: 5)");
+}
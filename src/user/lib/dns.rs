@@ -0,0 +1,160 @@
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordType {
+    A,
+    Aaaa,
+    Cname,
+    Ptr,
+    Mx,
+    Txt,
+}
+
+impl RecordType {
+    pub fn code(self) -> u16 {
+        match self {
+            RecordType::A => 1,
+            RecordType::Cname => 5,
+            RecordType::Ptr => 12,
+            RecordType::Mx => 15,
+            RecordType::Txt => 16,
+            RecordType::Aaaa => 28,
+        }
+    }
+
+    pub fn from_code(code: u16) -> Option<Self> {
+        match code {
+            1 => Some(RecordType::A),
+            5 => Some(RecordType::Cname),
+            12 => Some(RecordType::Ptr),
+            15 => Some(RecordType::Mx),
+            16 => Some(RecordType::Txt),
+            28 => Some(RecordType::Aaaa),
+            _ => None,
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_uppercase().as_str() {
+            "A" => Some(RecordType::A),
+            "AAAA" => Some(RecordType::Aaaa),
+            "CNAME" => Some(RecordType::Cname),
+            "PTR" => Some(RecordType::Ptr),
+            "MX" => Some(RecordType::Mx),
+            "TXT" => Some(RecordType::Txt),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Record {
+    A(u32),
+    Aaaa([u8; 16]),
+    Cname(String),
+    Ptr(String),
+    Mx { preference: u16, exchange: String },
+    Txt(Vec<String>),
+}
+
+/// Decodes the `type(u16) len(u16) data` entries written by the `dnsquery`
+/// system call.
+pub fn decode_records(buf: &[u8]) -> Vec<Record> {
+    let mut records = Vec::new();
+    let mut offset = 0;
+    while offset + 4 <= buf.len() {
+        let rtype = u16::from_be_bytes([buf[offset], buf[offset + 1]]);
+        let len = u16::from_be_bytes([buf[offset + 2], buf[offset + 3]]) as usize;
+        offset += 4;
+        let Some(data) = buf.get(offset..offset + len) else {
+            break;
+        };
+        offset += len;
+
+        let record = match RecordType::from_code(rtype) {
+            Some(RecordType::A) if len == 4 => {
+                Record::A(u32::from_be_bytes([data[0], data[1], data[2], data[3]]))
+            }
+            Some(RecordType::Aaaa) if len == 16 => {
+                let mut addr = [0u8; 16];
+                addr.copy_from_slice(data);
+                Record::Aaaa(addr)
+            }
+            Some(RecordType::Cname) => Record::Cname(String::from_utf8_lossy(data).into()),
+            Some(RecordType::Ptr) => Record::Ptr(String::from_utf8_lossy(data).into()),
+            Some(RecordType::Mx) if len >= 2 => Record::Mx {
+                preference: u16::from_be_bytes([data[0], data[1]]),
+                exchange: String::from_utf8_lossy(&data[2..]).into(),
+            },
+            Some(RecordType::Txt) => Record::Txt(decode_txt(data)),
+            _ => continue,
+        };
+        records.push(record);
+    }
+    records
+}
+
+fn decode_txt(data: &[u8]) -> Vec<String> {
+    let mut strings = Vec::new();
+    let mut offset = 0;
+    while offset < data.len() {
+        let len = data[offset] as usize;
+        let end = (offset + 1 + len).min(data.len());
+        strings.push(String::from_utf8_lossy(&data[offset + 1..end]).into());
+        offset = end;
+    }
+    strings
+}
+
+/// Parses a dotted-quad address, first octet in the high byte.
+pub fn parse_ipv4(s: &str) -> Option<u32> {
+    let mut addr = 0u32;
+    let mut parts = 0;
+    for part in s.split('.') {
+        addr = (addr << 8) | part.parse::<u8>().ok()? as u32;
+        parts += 1;
+    }
+    (parts == 4).then_some(addr)
+}
+
+/// Builds the `d.c.b.a.in-addr.arpa` name used for reverse lookups.
+pub fn reverse_name(addr: u32) -> String {
+    format!(
+        "{}.{}.{}.{}.in-addr.arpa",
+        addr & 0xFF,
+        (addr >> 8) & 0xFF,
+        (addr >> 16) & 0xFF,
+        (addr >> 24) & 0xFF
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reverse_name_of_parsed_address() {
+        let addr = parse_ipv4("8.8.8.8").unwrap();
+        assert_eq!(reverse_name(addr), "8.8.8.8.in-addr.arpa");
+        let addr = parse_ipv4("192.0.2.10").unwrap();
+        assert_eq!(reverse_name(addr), "10.2.0.192.in-addr.arpa");
+    }
+
+    #[test]
+    fn parse_ipv4_rejects_malformed() {
+        assert_eq!(parse_ipv4("8.8.8"), None);
+        assert_eq!(parse_ipv4("8.8.8.8.8"), None);
+        assert_eq!(parse_ipv4("8.8.8.256"), None);
+        assert_eq!(parse_ipv4("dns.google"), None);
+    }
+
+    #[test]
+    fn record_type_names() {
+        assert_eq!(RecordType::from_name("txt"), Some(RecordType::Txt));
+        assert_eq!(RecordType::Txt.code(), 16);
+        assert_eq!(RecordType::from_name("AAAA"), Some(RecordType::Aaaa));
+        assert_eq!(RecordType::from_name("SRV"), None);
+    }
+}
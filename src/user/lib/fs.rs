@@ -6,7 +6,7 @@ use crate::path::{Path, PathBuf};
 use crate::sys::{
     self,
     defs::AsBytes,
-    fcntl::{omode, FcntlCmd},
+    fcntl::{omode, whence, FcntlCmd},
     fs::DirEnt,
     stat::FileType,
     stat::Stat,
@@ -17,6 +17,13 @@ pub type Fd = usize;
 #[derive(Debug, PartialEq, Eq)]
 pub struct File(Fd);
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeekFrom {
+    Start(usize),
+    Current(isize),
+    End(isize),
+}
+
 impl File {
     pub fn open<P: AsRef<Path>>(path: P) -> sys::Result<File> {
         OpenOptions::new().read(true).open(path)
@@ -66,6 +73,15 @@ impl File {
     pub fn set_cloexec(&mut self) -> sys::Result<usize> {
         sys::fcntl(self.0, FcntlCmd::SetCloexec)
     }
+
+    // Returns the new offset from the start of the file.
+    pub fn seek(&mut self, pos: SeekFrom) -> sys::Result<usize> {
+        match pos {
+            SeekFrom::Start(off) => sys::lseek(self.0, off as isize, whence::SEEK_SET),
+            SeekFrom::Current(off) => sys::lseek(self.0, off, whence::SEEK_CUR),
+            SeekFrom::End(off) => sys::lseek(self.0, off, whence::SEEK_END),
+        }
+    }
 }
 
 impl Drop for File {
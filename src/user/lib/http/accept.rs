@@ -0,0 +1,45 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+
+// Splits an Accept header into media ranges and their q weights, in header
+// order. A missing or malformed q counts as 1.
+pub fn parse_accept_header(value: &str) -> Vec<(String, f32)> {
+    value
+        .split(',')
+        .filter_map(|item| {
+            let mut params = item.split(';');
+            let range = params.next()?.trim();
+            if range.is_empty() {
+                return None;
+            }
+            let q = params
+                .find_map(|p| p.trim().strip_prefix("q="))
+                .and_then(|q| q.trim().parse::<f32>().ok())
+                .unwrap_or(1.0)
+                .clamp(0.0, 1.0);
+            Some((range.to_ascii_lowercase(), q))
+        })
+        .collect()
+}
+
+// The weight of the most specific range matching `mime`: an exact type
+// beats type/*, which beats */*. A type no range matches gets 0.
+pub fn accept_quality(accepted: &[(String, f32)], mime: &str) -> f32 {
+    let kind = mime.split('/').next().unwrap_or(mime);
+    accepted
+        .iter()
+        .filter_map(|(range, q)| {
+            let specificity = if range == mime {
+                2
+            } else if range.strip_suffix("/*") == Some(kind) {
+                1
+            } else if range == "*/*" {
+                0
+            } else {
+                return None;
+            };
+            Some((specificity, *q))
+        })
+        .max_by_key(|(specificity, _)| *specificity)
+        .map_or(0.0, |(_, q)| q)
+}
@@ -0,0 +1,193 @@
+use crate::http::error::Error;
+use crate::http::response::HttpResponse;
+use crate::http::Result;
+use crate::{close, connect, dns_resolve, recv, send, socket, sys};
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::net::Ipv4Addr;
+
+const SEND_RETRY_TICKS: usize = 1;
+const DEFAULT_PORT: u16 = 80;
+
+// Sends a GET for `path` and reads the response into `buf` until the
+// server closes the connection. `host` may be a dotted IPv4 address or a
+// name to resolve. Up to `max_redirects` 3xx responses are followed.
+pub fn http_get(
+    host: &str,
+    port: u16,
+    path: &str,
+    max_redirects: u8,
+    buf: &mut Vec<u8>,
+) -> Result<HttpResponse> {
+    http_get_traced(host, port, path, max_redirects, buf, |_| {})
+}
+
+// Like http_get, but hands every Location followed to `on_redirect`.
+pub fn http_get_traced<F: FnMut(&str)>(
+    host: &str,
+    port: u16,
+    path: &str,
+    max_redirects: u8,
+    buf: &mut Vec<u8>,
+    mut on_redirect: F,
+) -> Result<HttpResponse> {
+    let mut host = String::from(host);
+    let mut port = port;
+    let mut path = String::from(path);
+
+    for _ in 0..=max_redirects {
+        let response = fetch(&host, port, &path, buf)?;
+        if !response.status().is_redirect() {
+            return Ok(response);
+        }
+
+        let location = response
+            .header("Location")
+            .ok_or(Error::InvalidHttpResponse)?;
+        on_redirect(location);
+        let absolute = if let Some(rest) = location.strip_prefix("//") {
+            Some(rest)
+        } else if location.contains("://") {
+            Some(location)
+        } else {
+            None
+        };
+        match absolute {
+            Some(url) => {
+                let (next_host, next_port, next_path) =
+                    parse_url(url).ok_or(Error::InvalidHttpResponse)?;
+                host = String::from(next_host);
+                port = next_port;
+                path = String::from(next_path);
+            }
+            None => path = resolve_path(&path, location),
+        }
+    }
+
+    Err(Error::TooManyRedirects)
+}
+
+// Splits an http:// URL into host, port and path; the scheme may be left
+// out. Other schemes are not understood.
+pub fn parse_url(url: &str) -> Option<(&str, u16, &str)> {
+    let rest = match url.split_once("://") {
+        Some(("http", rest)) => rest,
+        Some(_) => return None,
+        None => url,
+    };
+    let (authority, path) = match rest.find('/') {
+        Some(pos) => (&rest[..pos], &rest[pos..]),
+        None => (rest, "/"),
+    };
+    let (host, port) = match authority.split_once(':') {
+        Some((host, port)) => (host, port.parse().ok()?),
+        None => (authority, DEFAULT_PORT),
+    };
+    if host.is_empty() {
+        return None;
+    }
+    Some((host, port, path))
+}
+
+// Resolves a same-host reference against the current path (RFC 3986
+// 5.2): "/x" replaces it, "?q" keeps the path, anything else replaces the
+// last segment. Dot segments are then removed.
+fn resolve_path(base: &str, reference: &str) -> String {
+    let base = base.split(['?', '#']).next().unwrap_or("");
+    let reference = reference.split('#').next().unwrap_or("");
+    let merged = if reference.is_empty() {
+        String::from(base)
+    } else if reference.starts_with('/') {
+        String::from(reference)
+    } else if reference.starts_with('?') {
+        format!("{}{}", base, reference)
+    } else {
+        let dir = match base.rfind('/') {
+            Some(pos) => &base[..=pos],
+            None => "/",
+        };
+        format!("{}{}", dir, reference)
+    };
+
+    let (path, query) = match merged.find('?') {
+        Some(pos) => merged.split_at(pos),
+        None => (merged.as_str(), ""),
+    };
+    let mut segments: Vec<&str> = Vec::new();
+    let mut trailing = false;
+    for segment in path.split('/').skip(1) {
+        trailing = matches!(segment, "." | "..");
+        match segment {
+            "." => {}
+            ".." => {
+                segments.pop();
+            }
+            _ => segments.push(segment),
+        }
+    }
+    let mut out = String::new();
+    for segment in &segments {
+        out.push('/');
+        out.push_str(segment);
+    }
+    if trailing || out.is_empty() {
+        out.push('/');
+    }
+    out.push_str(query);
+    out
+}
+
+fn fetch(host: &str, port: u16, path: &str, buf: &mut Vec<u8>) -> Result<HttpResponse> {
+    let addr = resolve_host(host)?;
+    let path = if path.is_empty() { "/" } else { path };
+    let host_header = if port == DEFAULT_PORT {
+        String::from(host)
+    } else {
+        format!("{}:{}", host, port)
+    };
+    let request = format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+        path, host_header
+    );
+
+    let sock = socket().map_err(|_| Error::ConnectionFailed)?;
+    let result = exchange(sock, &addr, port, request.as_bytes(), buf);
+    let _ = close(sock);
+    result?;
+
+    HttpResponse::parse(buf)
+}
+
+fn resolve_host(host: &str) -> Result<String> {
+    if host.parse::<Ipv4Addr>().is_ok() {
+        return Ok(String::from(host));
+    }
+    let addr = dns_resolve(host).map_err(|_| Error::HostNotFound)?;
+    Ok(format!("{}", Ipv4Addr::from(addr)))
+}
+
+fn exchange(sock: usize, addr: &str, port: u16, request: &[u8], buf: &mut Vec<u8>) -> Result<()> {
+    connect(sock, addr, port, 0).map_err(|_| Error::ConnectionFailed)?;
+
+    let mut sent = 0;
+    while sent < request.len() {
+        match send(sock, &request[sent..]) {
+            Ok(0) | Err(sys::Error::BufferFull) | Err(sys::Error::WouldBlock) => {
+                let _ = sys::sleep(SEND_RETRY_TICKS);
+            }
+            Ok(n) => sent += n,
+            Err(_) => return Err(Error::ConnectionFailed),
+        }
+    }
+
+    buf.clear();
+    let mut tmp = [0u8; 512];
+    loop {
+        match recv(sock, &mut tmp) {
+            Ok(0) => return Ok(()),
+            Ok(n) => buf.extend_from_slice(&tmp[..n]),
+            Err(_) => return Err(Error::ConnectionFailed),
+        }
+    }
+}
@@ -3,4 +3,8 @@ pub enum Error {
     InvalidHttpRequest,
     UnsupportedMethod,
     UnsupportedVersion,
+    InvalidHttpResponse,
+    HostNotFound,
+    ConnectionFailed,
+    TooManyRedirects,
 }
@@ -1,4 +1,6 @@
-use alloc::string::String;
+use crate::http::error::Error;
+use crate::http::Result;
+use alloc::string::{String, ToString};
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct HttpHeader {
@@ -11,6 +13,11 @@ impl HttpHeader {
         Self { name, value }
     }
 
+    pub(crate) fn parse(line: &str) -> Result<Self> {
+        let (name, value) = line.split_once(':').ok_or(Error::InvalidHttpRequest)?;
+        Ok(Self::new(name.trim().to_string(), value.trim().to_string()))
+    }
+
     pub fn name(&self) -> &str {
         &self.name
     }
@@ -4,12 +4,22 @@ use crate::http::Result;
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum HttpMethod {
     Get,
+    Post,
+    Put,
+    Delete,
+    Head,
+    Options,
 }
 
 impl HttpMethod {
     pub fn from_str(s: &str) -> Result<Self> {
         match s {
             "GET" => Ok(HttpMethod::Get),
+            "POST" => Ok(HttpMethod::Post),
+            "PUT" => Ok(HttpMethod::Put),
+            "DELETE" => Ok(HttpMethod::Delete),
+            "HEAD" => Ok(HttpMethod::Head),
+            "OPTIONS" => Ok(HttpMethod::Options),
             _ => Err(Error::UnsupportedMethod),
         }
     }
@@ -17,6 +27,11 @@ impl HttpMethod {
     pub fn as_str(&self) -> &'static str {
         match self {
             HttpMethod::Get => "GET",
+            HttpMethod::Post => "POST",
+            HttpMethod::Put => "PUT",
+            HttpMethod::Delete => "DELETE",
+            HttpMethod::Head => "HEAD",
+            HttpMethod::Options => "OPTIONS",
         }
     }
 }
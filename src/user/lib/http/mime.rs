@@ -1,5 +1,38 @@
+use crate::mutex::Mutex;
+use crate::sys::sync::LazyLock;
+use alloc::vec;
+use alloc::vec::Vec;
+
+static MIME_REGISTRY: LazyLock<Mutex<Vec<(&'static str, &'static str)>>> = LazyLock::new(|| {
+    Mutex::new(vec![
+        ("wasm", "application/wasm"),
+        ("webmanifest", "application/manifest+json"),
+        ("avif", "image/avif"),
+    ])
+});
+
+pub fn register_mime_type(ext: &'static str, mime: &'static str) {
+    let ext = ext.trim_start_matches('.');
+    let mut registry = MIME_REGISTRY.lock();
+    match registry.iter_mut().find(|(e, _)| *e == ext) {
+        Some(entry) => entry.1 = mime,
+        None => registry.push((ext, mime)),
+    }
+}
+
+fn registered_mime_type(ext: &str) -> Option<&'static str> {
+    MIME_REGISTRY
+        .lock()
+        .iter()
+        .find(|(e, _)| *e == ext)
+        .map(|(_, mime)| *mime)
+}
+
 pub fn mime_type_from_path(path: &str) -> &'static str {
     let ext = path.rfind('.').map(|i| &path[i + 1..]);
+    if let Some(mime) = ext.and_then(registered_mime_type) {
+        return mime;
+    }
 
     match ext {
         Some("html") | Some("htm") => "text/html",
@@ -12,6 +45,19 @@ pub fn mime_type_from_path(path: &str) -> &'static str {
         Some("gif") => "image/gif",
         Some("svg") => "image/svg+xml",
         Some("ico") => "image/x-icon",
+        Some("webp") => "image/webp",
+        Some("wasm") => "application/wasm",
+        Some("pdf") => "application/pdf",
+        Some("xml") => "application/xml",
+        Some("yaml") | Some("yml") => "application/yaml",
+        Some("toml") => "application/toml",
+        Some("mp4") => "video/mp4",
+        Some("webm") => "video/webm",
+        Some("ogg") => "audio/ogg",
+        Some("woff") => "font/woff",
+        Some("woff2") => "font/woff2",
+        Some("ttf") => "font/ttf",
+        Some("eot") => "application/vnd.ms-fontobject",
         _ => "application/octet-stream",
     }
 }
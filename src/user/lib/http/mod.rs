@@ -1,5 +1,7 @@
 extern crate alloc;
 
+mod accept;
+mod client;
 mod error;
 mod header;
 mod method;
@@ -9,12 +11,14 @@ mod response;
 mod status;
 mod version;
 
+pub use accept::{accept_quality, parse_accept_header};
+pub use client::{http_get, http_get_traced, parse_url};
 pub use error::Error;
 pub use header::HttpHeader;
 pub use method::HttpMethod;
-pub use mime::mime_type_from_path;
+pub use mime::{mime_type_from_path, register_mime_type};
 pub use request::HttpRequest;
-pub use response::HttpResponse;
+pub use response::{ChunkedWriter, HttpResponse};
 pub use status::HttpStatus;
 pub use version::HttpVersion;
 
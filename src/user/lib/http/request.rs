@@ -3,6 +3,7 @@ use crate::http::header::HttpHeader;
 use crate::http::method::HttpMethod;
 use crate::http::version::HttpVersion;
 use crate::http::Result;
+use crate::util::base64::base64_decode;
 use alloc::string::{String, ToString};
 use alloc::vec::Vec;
 
@@ -12,11 +13,19 @@ pub struct HttpRequest {
     uri: String,
     version: HttpVersion,
     headers: Vec<HttpHeader>,
+    body: Vec<u8>,
+    credentials: Option<(String, String)>,
 }
 
 impl HttpRequest {
     pub fn parse(data: &[u8]) -> Result<Self> {
-        let text = core::str::from_utf8(data).map_err(|_| Error::InvalidHttpRequest)?;
+        // The body may be binary, so only the head has to be UTF-8.
+        let head_end = data
+            .windows(4)
+            .position(|window| window == b"\r\n\r\n")
+            .map(|pos| pos + 4);
+        let head = &data[..head_end.unwrap_or(data.len())];
+        let text = core::str::from_utf8(head).map_err(|_| Error::InvalidHttpRequest)?;
 
         let mut lines = text.split("\r\n");
 
@@ -28,15 +37,26 @@ impl HttpRequest {
             if line.is_empty() {
                 break;
             }
-            headers.push(Self::parse_header_line(line)?);
+            headers.push(HttpHeader::parse(line)?);
         }
 
-        Ok(Self {
+        let mut request = Self {
             method,
             uri,
             version,
             headers,
-        })
+            body: Vec::new(),
+            credentials: None,
+        };
+        request.credentials = request.decode_basic_auth();
+        // Whatever of the body arrived with the head is kept, up to
+        // Content-Length; a short body is the caller's to finish reading.
+        if let Some(start) = head_end {
+            let len = request.content_length().unwrap_or(0);
+            let end = data.len().min(start.saturating_add(len));
+            request.body = data[start..end].to_vec();
+        }
+        Ok(request)
     }
 
     fn parse_request_line(line: &str) -> Result<(HttpMethod, String, HttpVersion)> {
@@ -52,15 +72,6 @@ impl HttpRequest {
         Ok((method, uri.to_string(), version))
     }
 
-    fn parse_header_line(line: &str) -> Result<HttpHeader> {
-        let colon_pos = line.find(':').ok_or(Error::InvalidHttpRequest)?;
-
-        let name = line[..colon_pos].trim().to_string();
-        let value = line[colon_pos + 1..].trim().to_string();
-
-        Ok(HttpHeader::new(name, value))
-    }
-
     pub fn method(&self) -> HttpMethod {
         self.method
     }
@@ -73,10 +84,104 @@ impl HttpRequest {
         self.version
     }
 
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+
     pub fn header(&self, name: &str) -> Option<&str> {
         self.headers
             .iter()
             .find(|h| h.name_eq_ignore_case(name))
             .map(|h| h.value())
     }
+
+    pub fn content_length(&self) -> Option<usize> {
+        self.header("Content-Length")?.trim().parse().ok()
+    }
+
+    // HTTP/1.1 connections persist unless the client asks to close them;
+    // HTTP/1.0 ones only when it asks for keep-alive.
+    pub fn keep_alive(&self) -> bool {
+        let has_token = |token: &str| {
+            self.header("Connection").is_some_and(|value| {
+                value
+                    .split(',')
+                    .any(|t| t.trim().eq_ignore_ascii_case(token))
+            })
+        };
+        match self.version {
+            HttpVersion::Http11 => !has_token("close"),
+            HttpVersion::Http10 => has_token("keep-alive"),
+        }
+    }
+
+    // Decoded once at parse time so basic_auth can hand out borrows.
+    fn decode_basic_auth(&self) -> Option<(String, String)> {
+        let (scheme, encoded) = self.header("Authorization")?.split_once(' ')?;
+        if !scheme.eq_ignore_ascii_case("Basic") {
+            return None;
+        }
+        let decoded = String::from_utf8(base64_decode(encoded.trim())?).ok()?;
+        let (user, password) = decoded.split_once(':')?;
+        Some((user.to_string(), password.to_string()))
+    }
+
+    pub fn basic_auth(&self) -> Option<(&str, &str)> {
+        self.credentials
+            .as_ref()
+            .map(|(user, password)| (user.as_str(), password.as_str()))
+    }
+
+    // Weak validators (W/"...") compare equal to their strong form, as
+    // If-None-Match uses weak comparison.
+    pub fn etag_matches(&self, etag: &str) -> bool {
+        let Some(value) = self.header("If-None-Match") else {
+            return false;
+        };
+        let etag = etag.trim_start_matches("W/");
+        value
+            .split(',')
+            .map(|t| t.trim())
+            .any(|t| t == "*" || t.trim_start_matches("W/") == etag)
+    }
+
+    pub fn request_id(&self) -> Option<&str> {
+        self.header("X-Request-ID")
+            .map(|id| id.trim())
+            .filter(|id| !id.is_empty())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_with_id(value: &str) -> HttpRequest {
+        let data = alloc::format!("GET / HTTP/1.1\r\nX-Request-ID:{}\r\n\r\n", value);
+        HttpRequest::parse(data.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn request_id_is_trimmed() {
+        assert_eq!(parse_with_id(" abc-123 ").request_id(), Some("abc-123"));
+        assert_eq!(parse_with_id("abc-123").request_id(), Some("abc-123"));
+    }
+
+    #[test]
+    fn empty_request_id_is_none() {
+        assert_eq!(parse_with_id("").request_id(), None);
+        assert_eq!(parse_with_id("   ").request_id(), None);
+        let request = HttpRequest::parse(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(request.request_id(), None);
+    }
+
+    #[test]
+    fn oversized_content_length_keeps_what_arrived() {
+        let data = alloc::format!(
+            "POST / HTTP/1.1\r\nContent-Length: {}\r\n\r\nabc",
+            usize::MAX
+        );
+        let request = HttpRequest::parse(data.as_bytes()).unwrap();
+        assert_eq!(request.body(), b"abc");
+    }
 }
@@ -1,7 +1,12 @@
+use crate::http::error::Error;
 use crate::http::header::HttpHeader;
+use crate::http::method::HttpMethod;
 use crate::http::mime::mime_type_from_path;
 use crate::http::status::HttpStatus;
 use crate::http::version::HttpVersion;
+use crate::http::Result;
+use crate::io::Write;
+use crate::sys;
 use alloc::format;
 use alloc::string::{String, ToString};
 use alloc::vec::Vec;
@@ -23,50 +28,270 @@ impl HttpResponse {
         }
     }
 
+    // Parses a complete response as read off the wire. Chunked bodies are
+    // decoded; otherwise the body runs to Content-Length or to the end.
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let head_end = data
+            .windows(4)
+            .position(|window| window == b"\r\n\r\n")
+            .ok_or(Error::InvalidHttpResponse)?;
+        let text =
+            core::str::from_utf8(&data[..head_end]).map_err(|_| Error::InvalidHttpResponse)?;
+        let mut lines = text.split("\r\n");
+
+        let status_line = lines.next().ok_or(Error::InvalidHttpResponse)?;
+        let mut parts = status_line.split_whitespace();
+        let version = HttpVersion::from_str(parts.next().ok_or(Error::InvalidHttpResponse)?)?;
+        let code = parts
+            .next()
+            .and_then(|code| code.parse::<u16>().ok())
+            .ok_or(Error::InvalidHttpResponse)?;
+
+        let mut response = Self::new(HttpStatus::from_code(code));
+        response.version = version;
+        for line in lines {
+            let header = HttpHeader::parse(line).map_err(|_| Error::InvalidHttpResponse)?;
+            response.headers.push(header);
+        }
+
+        let rest = &data[head_end + 4..];
+        let chunked = response
+            .header("Transfer-Encoding")
+            .is_some_and(|v| v.eq_ignore_ascii_case("chunked"));
+        response.body = if chunked {
+            decode_chunked(rest)?
+        } else {
+            match response
+                .header("Content-Length")
+                .map(|v| v.parse::<usize>())
+            {
+                Some(Ok(len)) => rest.get(..len).ok_or(Error::InvalidHttpResponse)?.to_vec(),
+                Some(Err(_)) => return Err(Error::InvalidHttpResponse),
+                None => rest.to_vec(),
+            }
+        };
+        Ok(response)
+    }
+
+    pub fn status(&self) -> HttpStatus {
+        self.status
+    }
+
+    pub fn version(&self) -> HttpVersion {
+        self.version
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|h| h.name_eq_ignore_case(name))
+            .map(|h| h.value())
+    }
+
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+
     pub fn add_header(&mut self, name: String, value: String) {
         self.headers.push(HttpHeader::new(name, value));
     }
 
+    pub fn set_attachment(&mut self, filename: &str) -> &mut Self {
+        let mut quoted = String::with_capacity(filename.len());
+        for c in filename.chars() {
+            if c == '"' || c == '\\' {
+                quoted.push('\\');
+            }
+            quoted.push(c);
+        }
+        self.add_header(
+            "Content-Disposition".to_string(),
+            format!("attachment; filename=\"{}\"", quoted),
+        );
+        self
+    }
+
+    pub fn set_inline(&mut self) -> &mut Self {
+        self.add_header("Content-Disposition".to_string(), "inline".to_string());
+        self
+    }
+
+    pub fn set_request_id(&mut self, id: u64) -> &mut Self {
+        self.echo_request_id(&format!("{:016x}", id))
+    }
+
+    pub fn echo_request_id(&mut self, id: &str) -> &mut Self {
+        self.add_header("X-Request-ID".to_string(), id.to_string());
+        self
+    }
+
+    pub fn set_keep_alive(&mut self, keep_alive: bool) -> &mut Self {
+        self.headers
+            .retain(|h| !h.name_eq_ignore_case("Connection"));
+        let value = if keep_alive { "keep-alive" } else { "close" };
+        self.add_header("Connection".to_string(), value.to_string());
+        self
+    }
+
+    // Without an origin any site may read the response. `methods` and
+    // `headers` should list what the server actually accepts.
+    pub fn add_cors_headers(
+        &mut self,
+        origin: Option<&str>,
+        methods: &[HttpMethod],
+        headers: &[&str],
+    ) -> &mut Self {
+        let methods: Vec<&str> = methods.iter().map(HttpMethod::as_str).collect();
+        self.add_header(
+            "Access-Control-Allow-Origin".to_string(),
+            origin.unwrap_or("*").to_string(),
+        );
+        self.add_header(
+            "Access-Control-Allow-Methods".to_string(),
+            methods.join(", "),
+        );
+        self.add_header(
+            "Access-Control-Allow-Headers".to_string(),
+            headers.join(", "),
+        );
+        self
+    }
+
     pub fn set_body(&mut self, body: Vec<u8>) {
         self.body = body;
     }
 
+    pub fn estimated_size(&self) -> usize {
+        // "<version> <code> <message>\r\n", status codes are always three digits
+        let status_line = self.version.as_str().len() + 1 + 3 + 1 + self.status.message().len() + 2;
+        let headers: usize = self
+            .headers
+            .iter()
+            .map(|h| h.name().len() + 2 + h.value().len() + 2)
+            .sum();
+        status_line + headers + 2 + self.body.len()
+    }
+
     pub fn to_bytes(&self) -> Vec<u8> {
-        let mut result = Vec::new();
+        let mut result = Vec::with_capacity(self.estimated_size());
+        self.to_bytes_into(&mut result);
+        result
+    }
 
-        let status_line = format!(
-            "{} {} {}\r\n",
-            self.version.as_str(),
-            self.status.code(),
-            self.status.message()
-        );
-        result.extend_from_slice(status_line.as_bytes());
+    pub fn to_bytes_into(&self, buf: &mut Vec<u8>) {
+        self.head_into(buf, false);
+        buf.extend_from_slice(&self.body);
+    }
+
+    // Status line and headers only, as for a HEAD request.
+    pub fn head_bytes_into(&self, buf: &mut Vec<u8>) {
+        self.head_into(buf, false);
+    }
+
+    // Content-Length is dropped for chunked responses, which announce
+    // Transfer-Encoding instead.
+    fn head_into(&self, buf: &mut Vec<u8>, chunked: bool) {
+        let code = self.status.code();
+        let digits = [
+            b'0' + (code / 100 % 10) as u8,
+            b'0' + (code / 10 % 10) as u8,
+            b'0' + (code % 10) as u8,
+        ];
+
+        buf.extend_from_slice(self.version.as_str().as_bytes());
+        buf.push(b' ');
+        buf.extend_from_slice(&digits);
+        buf.push(b' ');
+        buf.extend_from_slice(self.status.message().as_bytes());
+        buf.extend_from_slice(b"\r\n");
 
         for header in &self.headers {
-            let header_line = format!("{}: {}\r\n", header.name(), header.value());
-            result.extend_from_slice(header_line.as_bytes());
+            if chunked && header.name_eq_ignore_case("Content-Length") {
+                continue;
+            }
+            buf.extend_from_slice(header.name().as_bytes());
+            buf.extend_from_slice(b": ");
+            buf.extend_from_slice(header.value().as_bytes());
+            buf.extend_from_slice(b"\r\n");
         }
+        if chunked {
+            buf.extend_from_slice(b"Transfer-Encoding: chunked\r\n");
+        }
+
+        buf.extend_from_slice(b"\r\n");
+    }
 
-        result.extend_from_slice(b"\r\n");
+    // Writes the status line and headers; the body follows through the
+    // returned writer, one chunk at a time. Any body already set is ignored.
+    pub fn write_chunked<W: Write>(&self, mut writer: W) -> sys::Result<ChunkedWriter<W>> {
+        let mut head = Vec::with_capacity(self.estimated_size());
+        self.head_into(&mut head, true);
+        writer.write_all(&head)?;
+        Ok(ChunkedWriter { writer })
+    }
 
-        result.extend_from_slice(&self.body);
+    pub fn with_body(status: HttpStatus, content_type: &str, body: Vec<u8>) -> Self {
+        let mut response = Self::new(status);
 
-        result
+        response.add_header("Content-Type".to_string(), content_type.to_string());
+        response.add_header("Content-Length".to_string(), body.len().to_string());
+        response.add_header("Connection".to_string(), "close".to_string());
+        response.add_header("Server".to_string(), "octox-httpd/0.1".to_string());
+        response.set_body(body);
+
+        response
+    }
+
+    // Headers for a body whose length is not known up front; send it with
+    // write_chunked.
+    pub fn streamed(status: HttpStatus, content_type: &str) -> Self {
+        let mut response = Self::new(status);
+
+        response.add_header("Content-Type".to_string(), content_type.to_string());
+        response.add_header("Connection".to_string(), "close".to_string());
+        response.add_header("Server".to_string(), "octox-httpd/0.1".to_string());
+
+        response
     }
 
     pub fn from_file_content(path: &str, content: Vec<u8>) -> Self {
-        let mut response = Self::new(HttpStatus::Ok);
+        Self::with_body(HttpStatus::Ok, mime_type_from_path(path), content)
+    }
 
-        let mime_type = mime_type_from_path(path);
-        response.add_header("Content-Type".to_string(), mime_type.to_string());
-        response.add_header("Content-Length".to_string(), content.len().to_string());
+    pub fn set_etag(&mut self, etag: &str) {
+        self.add_header("ETag".to_string(), etag.to_string());
+    }
+
+    pub fn not_modified(etag: &str) -> Self {
+        let mut response = Self::new(HttpStatus::NotModified);
+
+        response.add_header("ETag".to_string(), etag.to_string());
+        response.add_header("Connection".to_string(), "close".to_string());
+        response.add_header("Server".to_string(), "octox-httpd/0.1".to_string());
+
+        response
+    }
+
+    pub fn redirect(status: HttpStatus, location: &str) -> Self {
+        let mut response = Self::new(status);
+
+        response.add_header("Location".to_string(), location.to_string());
+        response.add_header("Content-Length".to_string(), "0".to_string());
         response.add_header("Connection".to_string(), "close".to_string());
         response.add_header("Server".to_string(), "octox-httpd/0.1".to_string());
-        response.set_body(content);
 
         response
     }
 
+    pub fn www_authenticate(realm: &str) -> Self {
+        let mut response = Self::error(HttpStatus::Unauthorized);
+        response.add_header(
+            "WWW-Authenticate".to_string(),
+            format!("Basic realm=\"{}\"", realm),
+        );
+        response
+    }
+
     pub fn validate_path(uri: &str) -> core::result::Result<String, HttpStatus> {
         if uri.contains("..") {
             return Err(HttpStatus::Forbidden);
@@ -111,3 +336,73 @@ impl HttpResponse {
         response
     }
 }
+
+fn decode_chunked(mut data: &[u8]) -> Result<Vec<u8>> {
+    let mut body = Vec::new();
+    loop {
+        let line_end = data
+            .windows(2)
+            .position(|window| window == b"\r\n")
+            .ok_or(Error::InvalidHttpResponse)?;
+        let line =
+            core::str::from_utf8(&data[..line_end]).map_err(|_| Error::InvalidHttpResponse)?;
+        // chunk extensions after ';' are ignored
+        let size = line.split(';').next().unwrap_or("").trim();
+        let size = usize::from_str_radix(size, 16).map_err(|_| Error::InvalidHttpResponse)?;
+        data = &data[line_end + 2..];
+        if size == 0 {
+            return Ok(body);
+        }
+        let end = size.checked_add(2).ok_or(Error::InvalidHttpResponse)?;
+        if data.len() < end || &data[size..end] != b"\r\n" {
+            return Err(Error::InvalidHttpResponse);
+        }
+        body.extend_from_slice(&data[..size]);
+        data = &data[end..];
+    }
+}
+
+pub struct ChunkedWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> ChunkedWriter<W> {
+    // An empty chunk would read as the terminator, so it is skipped.
+    pub fn write_chunk(&mut self, data: &[u8]) -> sys::Result<()> {
+        if data.is_empty() {
+            return Ok(());
+        }
+        self.writer
+            .write_all(format!("{:x}\r\n", data.len()).as_bytes())?;
+        self.writer.write_all(data)?;
+        self.writer.write_all(b"\r\n")
+    }
+
+    pub fn finish(mut self) -> sys::Result<()> {
+        self.writer.write_all(b"0\r\n\r\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_chunked_joins_chunks() {
+        let body = decode_chunked(b"3\r\nabc\r\n2;ext=1\r\nde\r\n0\r\n\r\n").unwrap();
+        assert_eq!(body, b"abcde");
+    }
+
+    #[test]
+    fn decode_chunked_rejects_oversized_chunk() {
+        let data = format!("{:x}\r\nabc\r\n0\r\n\r\n", usize::MAX);
+        assert_eq!(
+            decode_chunked(data.as_bytes()),
+            Err(Error::InvalidHttpResponse)
+        );
+        assert_eq!(
+            decode_chunked(b"10\r\nabc\r\n0\r\n\r\n"),
+            Err(Error::InvalidHttpResponse)
+        );
+    }
+}
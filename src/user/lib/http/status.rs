@@ -1,30 +1,88 @@
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum HttpStatus {
     Ok,
+    PartialContent,
+    MovedPermanently,
+    Found,
+    NotModified,
     BadRequest,
+    Unauthorized,
     Forbidden,
     NotFound,
+    MethodNotAllowed,
+    NotAcceptable,
+    PayloadTooLarge,
+    RangeNotSatisfiable,
     InternalServerError,
+    Other(u16),
 }
 
 impl HttpStatus {
     pub fn code(&self) -> u16 {
         match self {
             HttpStatus::Ok => 200,
+            HttpStatus::PartialContent => 206,
+            HttpStatus::MovedPermanently => 301,
+            HttpStatus::Found => 302,
+            HttpStatus::NotModified => 304,
             HttpStatus::BadRequest => 400,
+            HttpStatus::Unauthorized => 401,
             HttpStatus::Forbidden => 403,
             HttpStatus::NotFound => 404,
+            HttpStatus::MethodNotAllowed => 405,
+            HttpStatus::NotAcceptable => 406,
+            HttpStatus::PayloadTooLarge => 413,
+            HttpStatus::RangeNotSatisfiable => 416,
             HttpStatus::InternalServerError => 500,
+            HttpStatus::Other(code) => *code,
         }
     }
 
+    pub fn from_code(code: u16) -> Self {
+        match code {
+            200 => HttpStatus::Ok,
+            206 => HttpStatus::PartialContent,
+            301 => HttpStatus::MovedPermanently,
+            302 => HttpStatus::Found,
+            304 => HttpStatus::NotModified,
+            400 => HttpStatus::BadRequest,
+            401 => HttpStatus::Unauthorized,
+            403 => HttpStatus::Forbidden,
+            404 => HttpStatus::NotFound,
+            405 => HttpStatus::MethodNotAllowed,
+            406 => HttpStatus::NotAcceptable,
+            413 => HttpStatus::PayloadTooLarge,
+            416 => HttpStatus::RangeNotSatisfiable,
+            500 => HttpStatus::InternalServerError,
+            code => HttpStatus::Other(code),
+        }
+    }
+
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.code())
+    }
+
+    pub fn is_redirect(&self) -> bool {
+        matches!(self.code(), 301 | 302 | 303 | 307 | 308)
+    }
+
     pub fn message(&self) -> &'static str {
         match self {
             HttpStatus::Ok => "OK",
+            HttpStatus::PartialContent => "Partial Content",
+            HttpStatus::MovedPermanently => "Moved Permanently",
+            HttpStatus::Found => "Found",
+            HttpStatus::NotModified => "Not Modified",
             HttpStatus::BadRequest => "Bad Request",
+            HttpStatus::Unauthorized => "Unauthorized",
             HttpStatus::Forbidden => "Forbidden",
             HttpStatus::NotFound => "Not Found",
+            HttpStatus::MethodNotAllowed => "Method Not Allowed",
+            HttpStatus::NotAcceptable => "Not Acceptable",
+            HttpStatus::PayloadTooLarge => "Payload Too Large",
+            HttpStatus::RangeNotSatisfiable => "Range Not Satisfiable",
             HttpStatus::InternalServerError => "Internal Server Error",
+            HttpStatus::Other(_) => "Unknown",
         }
     }
 }
@@ -3,6 +3,7 @@
 #![allow(clippy::missing_safety_doc)]
 #![allow(internal_features)]
 
+pub mod dns;
 pub mod http;
 pub mod sys {
     use core::arch::asm;
@@ -15,7 +16,7 @@ pub mod sys {
     pub use kernel::fs;
     pub use kernel::stat;
     pub use kernel::sync;
-    use stat::Stat;
+    use stat::{IcmpEchoStats, NetDeviceStats, Stat, TcpBufInfo, TcpListenStats, TcpSocketStats};
     include!(concat!(env!("OUT_DIR"), "/usys.rs"));
 }
 pub extern crate alloc;
@@ -29,6 +30,7 @@ pub mod path;
 pub mod pipe;
 pub mod process;
 pub mod umalloc;
+pub mod util;
 //pub mod regex;
 
 use crate::env::ARGS;
@@ -63,20 +65,199 @@ pub fn icmp_sendto(sock: usize, dst: &str, data: &[u8]) -> sys::Result<usize> {
     sys::icmpsendto(sock, dst.as_bytes(), data)
 }
 
-pub fn icmp_recvfrom(sock: usize, buf: &mut [u8], src_addr: &mut u32) -> sys::Result<usize> {
-    sys::icmprecvfrom(sock, buf, src_addr)
+pub fn icmp_sendto_ttl(sock: usize, dst: &str, data: &[u8], ttl: u8) -> sys::Result<usize> {
+    sys::icmpsendtottl(sock, dst.as_bytes(), data, ttl)
+}
+
+pub fn icmp_recvfrom(
+    sock: usize,
+    buf: &mut [u8],
+    src_addr: &mut u32,
+    ttl: &mut u8,
+) -> sys::Result<usize> {
+    sys::icmprecvfrom(sock, buf, src_addr, ttl)
 }
 
 pub fn icmp_close(sock: usize) -> sys::Result<()> {
     sys::icmpclose(sock)
 }
 
+// Raw IP sockets: send builds only the IP header; recv returns whole
+// datagrams, header included.
+const RAW_DEFAULT_TTL: u8 = 64;
+
+pub fn raw_socket(protocol: u8) -> sys::Result<usize> {
+    sys::rawsocket(protocol)
+}
+
+pub fn raw_send(sock: usize, dst: &str, data: &[u8]) -> sys::Result<usize> {
+    sys::rawsend(sock, dst.as_bytes(), data, RAW_DEFAULT_TTL)
+}
+
+pub fn raw_send_ttl(sock: usize, dst: &str, data: &[u8], ttl: u8) -> sys::Result<usize> {
+    sys::rawsend(sock, dst.as_bytes(), data, ttl)
+}
+
+pub fn raw_recv(sock: usize, buf: &mut [u8]) -> sys::Result<usize> {
+    sys::rawrecv(sock, buf)
+}
+
+pub fn raw_close(sock: usize) -> sys::Result<()> {
+    sys::rawclose(sock)
+}
+
+// Echo request/reply round trips seen on an ICMP raw socket.
+pub fn raw_echo_stats(sock: usize) -> sys::Result<sys::stat::IcmpEchoStats> {
+    let mut st = sys::stat::IcmpEchoStats::default();
+    sys::rawechostats(sock, &mut st)?;
+    Ok(st)
+}
+
+// Devices are numbered in registration order; DeviceNotFound past the end.
+pub fn net_device_stats_by_index(index: usize) -> sys::Result<sys::stat::NetDeviceStats> {
+    let mut st = sys::stat::NetDeviceStats::default();
+    sys::netdevstats(index, &mut st)?;
+    Ok(st)
+}
+
+pub fn arp_set(addr: &str, mac: &[u8; 6]) -> sys::Result<()> {
+    sys::arpset(addr.as_bytes(), mac)
+}
+
+pub fn arp_del(addr: &str) -> sys::Result<()> {
+    sys::arpdel(addr.as_bytes())
+}
+
+pub fn icmp_echo_stats(sock: usize) -> sys::Result<sys::stat::IcmpEchoStats> {
+    let mut st = sys::stat::IcmpEchoStats::default();
+    sys::icmpechostats(sock, &mut st)?;
+    Ok(st)
+}
+
+// Binds to `port`, or an ephemeral one when it is 0.
+pub fn udp_socket(port: u16) -> sys::Result<usize> {
+    sys::udpsocket(port)
+}
+
+pub fn udp_connect(sock: usize, addr: &str, port: u16) -> sys::Result<()> {
+    sys::udpconnect(sock, addr.as_bytes(), port)
+}
+
+pub fn udp_send(sock: usize, data: &[u8]) -> sys::Result<usize> {
+    sys::udpsend(sock, data)
+}
+
+pub fn udp_recv(sock: usize, buf: &mut [u8]) -> sys::Result<usize> {
+    sys::udprecv(sock, buf)
+}
+
+// Length and source of the next datagram without taking it off the queue;
+// WouldBlock when nothing is queued.
+pub fn udp_peekfrom(sock: usize, src_addr: &mut u32, src_port: &mut u16) -> sys::Result<usize> {
+    sys::udppeekfrom(sock, src_addr, src_port)
+}
+
+pub fn udp_recv_ready(sock: usize) -> sys::Result<bool> {
+    sys::udprecvready(sock).map(|ready| ready != 0)
+}
+
+pub fn udp_disconnect(sock: usize) -> sys::Result<()> {
+    sys::udpdisconnect(sock)
+}
+
+pub fn udp_close(sock: usize) -> sys::Result<()> {
+    sys::udpclose(sock)
+}
+
 pub fn dns_resolve(domain: &str) -> sys::Result<u32> {
     let mut addr: u32 = 0;
     sys::dnsresolve(domain.as_bytes(), &mut addr)?;
     Ok(addr)
 }
 
+pub fn dns_query(
+    domain: &str,
+    rtype: dns::RecordType,
+) -> sys::Result<alloc::vec::Vec<dns::Record>> {
+    let mut buf = [0u8; 512];
+    let len = sys::dnsquery(domain.as_bytes(), rtype.code(), &mut buf)?;
+    Ok(dns::decode_records(&buf[..len]))
+}
+
+pub fn dns_query_with_server(
+    domain: &str,
+    rtype: dns::RecordType,
+    server: &str,
+    port: u16,
+) -> sys::Result<alloc::vec::Vec<dns::Record>> {
+    let mut buf = [0u8; 512];
+    let len = sys::dnsqueryserver(
+        domain.as_bytes(),
+        rtype.code(),
+        server.as_bytes(),
+        port,
+        &mut buf,
+    )?;
+    Ok(dns::decode_records(&buf[..len]))
+}
+
+pub fn dns_search_domains() -> sys::Result<alloc::vec::Vec<alloc::string::String>> {
+    let mut buf = [0u8; 256];
+    let len = sys::dnssearchdomains(&mut buf)?;
+    let text = core::str::from_utf8(&buf[..len]).or(Err(sys::Error::Utf8Error))?;
+    Ok(text
+        .split_whitespace()
+        .map(alloc::string::String::from)
+        .collect())
+}
+
+// Expands `domain` with the kernel's search list and returns the name that
+// had records of one of `rtypes`, together with what it answered. An empty
+// server or a zero port keeps the kernel default.
+pub fn dns_search_with_server(
+    domain: &str,
+    rtypes: &[dns::RecordType],
+    server: &str,
+    port: u16,
+) -> sys::Result<(alloc::string::String, alloc::vec::Vec<dns::Record>)> {
+    let qtypes: alloc::vec::Vec<usize> = rtypes.iter().map(|t| t.code() as usize).collect();
+    let mut name = [0u8; 256];
+    let mut buf = [0u8; 512];
+    let len = sys::dnssearch(
+        domain.as_bytes(),
+        &qtypes,
+        server.as_bytes(),
+        port,
+        &mut name,
+        &mut buf,
+    )?;
+    let name_len = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+    let name = core::str::from_utf8(&name[..name_len]).or(Err(sys::Error::Utf8Error))?;
+    Ok((name.into(), dns::decode_records(&buf[..len])))
+}
+
+pub fn dns_resolve_all(domain: &str) -> sys::Result<alloc::vec::Vec<u32>> {
+    let records = dns_query(domain, dns::RecordType::A)?;
+    Ok(records
+        .into_iter()
+        .filter_map(|r| match r {
+            dns::Record::A(addr) => Some(addr),
+            _ => None,
+        })
+        .collect())
+}
+
+pub fn dns_resolve_ptr(addr: u32) -> sys::Result<alloc::string::String> {
+    let records = dns_query(&dns::reverse_name(addr), dns::RecordType::Ptr)?;
+    records
+        .into_iter()
+        .find_map(|r| match r {
+            dns::Record::Ptr(name) => Some(name),
+            _ => None,
+        })
+        .ok_or(sys::Error::NotFound)
+}
+
 pub fn socket() -> sys::Result<usize> {
     sys::tcpsocket()
 }
@@ -89,22 +270,102 @@ pub fn listen(sock: usize, port: u16) -> sys::Result<()> {
     sys::tcplisten(sock, port)
 }
 
+pub fn listen_with_backlog(sock: usize, port: u16, backlog: usize) -> sys::Result<()> {
+    sys::tcplistenbacklog(sock, port, backlog)
+}
+
+pub fn set_reuse_port(sock: usize, enable: bool) -> sys::Result<()> {
+    sys::tcpsetreuseport(sock, enable as usize)
+}
+
+pub fn socket_set_reuseaddr(sock: usize, enable: bool) -> sys::Result<()> {
+    sys::tcpsetreuseaddr(sock, enable as usize)
+}
+
+pub fn set_nonblocking(sock: usize, nonblocking: bool) -> sys::Result<()> {
+    sys::tcpsetnonblocking(sock, nonblocking as usize)
+}
+
+// `None` (or zero) lets recv/send block indefinitely again.
+pub fn socket_set_recv_timeout(sock: usize, timeout_ms: Option<u64>) -> sys::Result<()> {
+    sys::tcpsetrecvtimeout(sock, timeout_ms.unwrap_or(0) as usize)
+}
+
+pub fn socket_set_send_timeout(sock: usize, timeout_ms: Option<u64>) -> sys::Result<()> {
+    sys::tcpsetsendtimeout(sock, timeout_ms.unwrap_or(0) as usize)
+}
+
+// Socket buffer sizes, 512 bytes to 1 MiB. Set before listen or connect
+// to size the advertised window scale.
+pub fn socket_set_rcvbuf(sock: usize, size: usize) -> sys::Result<()> {
+    sys::tcpsetbuf(sock, 0, size)
+}
+
+pub fn socket_set_sndbuf(sock: usize, size: usize) -> sys::Result<()> {
+    sys::tcpsetbuf(sock, 1, size)
+}
+
 pub fn accept(sock: usize) -> sys::Result<usize> {
     sys::tcpaccept(sock)
 }
 
+pub fn listen_stats(sock: usize) -> sys::Result<sys::stat::TcpListenStats> {
+    let mut st = sys::stat::TcpListenStats::default();
+    sys::tcplistenstats(sock, &mut st)?;
+    Ok(st)
+}
+
 pub fn send(sock: usize, data: &[u8]) -> sys::Result<usize> {
     sys::tcpsend(sock, data)
 }
 
+pub fn wait_send_space(sock: usize, timeout_ms: usize) -> sys::Result<()> {
+    sys::tcpwaitsendspace(sock, timeout_ms)
+}
+
+pub fn tcp_buf_info(sock: usize) -> sys::Result<sys::stat::TcpBufInfo> {
+    let mut info = sys::stat::TcpBufInfo::default();
+    sys::tcpbufinfo(sock, &mut info)?;
+    Ok(info)
+}
+
+pub fn socket_stats(sock: usize) -> sys::Result<sys::stat::TcpSocketStats> {
+    let mut st = sys::stat::TcpSocketStats::default();
+    sys::tcpsockstats(sock, &mut st)?;
+    Ok(st)
+}
+
 pub fn recv(sock: usize, buf: &mut [u8]) -> sys::Result<usize> {
     sys::tcprecv(sock, buf)
 }
 
+pub fn select(
+    read_socks: &[usize],
+    write_socks: &[usize],
+    timeout_ms: u64,
+) -> sys::Result<(alloc::vec::Vec<usize>, alloc::vec::Vec<usize>)> {
+    let mut ready = alloc::vec![0u8; read_socks.len() + write_socks.len()];
+    sys::tcpselect(read_socks, write_socks, &mut ready, timeout_ms as usize)?;
+    let (read_ready, write_ready) = ready.split_at(read_socks.len());
+    let pick = |socks: &[usize], flags: &[u8]| {
+        socks
+            .iter()
+            .zip(flags)
+            .filter(|(_, &f)| f != 0)
+            .map(|(&s, _)| s)
+            .collect()
+    };
+    Ok((pick(read_socks, read_ready), pick(write_socks, write_ready)))
+}
+
 pub fn close(sock: usize) -> sys::Result<()> {
     sys::tcpclose(sock)
 }
 
+pub fn shutdown_write(sock: usize) -> sys::Result<()> {
+    sys::tcpshutdown(sock)
+}
+
 pub enum ExitCode {
     SUCCESS = 0x0isize,
     FAILURE = 0x1isize,